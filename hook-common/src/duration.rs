@@ -0,0 +1,149 @@
+//! # Duration
+//!
+//! Parse durations set in config, either as a bare number or in human friendly forms like `500ms`, `30s`, `5m`,
+//! or `1h30m`.
+use std::str::FromStr;
+use std::time;
+
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("{0} is not a valid duration, expected a number with a unit like 500ms, 30s, 5m or 1h")]
+pub struct ParseDurationError(String);
+
+/// Parse a duration made of one or more numbers each followed by a unit, one of `ms`, `s`, `m`, `h`, or `d`.
+/// A bare number without a unit is a number of `bare_unit`s, to keep supporting configs set before units were.
+pub fn parse_duration(
+    s: &str,
+    bare_unit: time::Duration,
+) -> Result<time::Duration, ParseDurationError> {
+    let error = || ParseDurationError(s.to_owned());
+    let trimmed = s.trim();
+    if trimmed.is_empty() {
+        return Err(error());
+    }
+    if let Ok(count) = trimmed.parse::<u64>() {
+        return times(bare_unit, count).ok_or_else(error);
+    }
+
+    let mut rest = trimmed;
+    let mut duration = time::Duration::ZERO;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).ok_or_else(error)?;
+        let count: u64 = rest[..digits].parse().map_err(|_| error())?;
+        rest = &rest[digits..];
+
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let unit = match &rest[..unit_len] {
+            "ms" => time::Duration::from_millis(1),
+            "s" => time::Duration::from_secs(1),
+            "m" => time::Duration::from_secs(60),
+            "h" => time::Duration::from_secs(60 * 60),
+            "d" => time::Duration::from_secs(24 * 60 * 60),
+            _ => return Err(error()),
+        };
+        rest = &rest[unit_len..];
+
+        duration = times(unit, count)
+            .and_then(|part| duration.checked_add(part))
+            .ok_or_else(error)?;
+    }
+
+    Ok(duration)
+}
+
+/// Multiply `unit` by `count`, or `None` on overflow.
+fn times(unit: time::Duration, count: u64) -> Option<time::Duration> {
+    let secs = unit.as_secs().checked_mul(count)?;
+    let nanos = (unit.subsec_nanos() as u64).checked_mul(count)?;
+
+    time::Duration::from_secs(secs).checked_add(time::Duration::from_nanos(nanos))
+}
+
+/// A duration set in config, where a bare number is a number of milliseconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EnvMsDuration(pub time::Duration);
+
+impl FromStr for EnvMsDuration {
+    type Err = ParseDurationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_duration(s, time::Duration::from_millis(1)).map(EnvMsDuration)
+    }
+}
+
+/// A duration set in config, where a bare number is a number of seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EnvSecsDuration(pub time::Duration);
+
+impl FromStr for EnvSecsDuration {
+    type Err = ParseDurationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_duration(s, time::Duration::from_secs(1)).map(EnvSecsDuration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration_units() {
+        for (s, expected) in [
+            ("500ms", time::Duration::from_millis(500)),
+            ("30s", time::Duration::from_secs(30)),
+            ("5m", time::Duration::from_secs(5 * 60)),
+            ("2h", time::Duration::from_secs(2 * 60 * 60)),
+            ("1d", time::Duration::from_secs(24 * 60 * 60)),
+            ("1m30s", time::Duration::from_secs(90)),
+            (" 1s500ms ", time::Duration::from_millis(1500)),
+            ("0s", time::Duration::ZERO),
+        ] {
+            assert_eq!(
+                s.parse::<EnvMsDuration>(),
+                Ok(EnvMsDuration(expected)),
+                "{}",
+                s
+            );
+            assert_eq!(
+                s.parse::<EnvSecsDuration>(),
+                Ok(EnvSecsDuration(expected)),
+                "{}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_bare_numbers_in_default_unit() {
+        assert_eq!(
+            "250".parse(),
+            Ok(EnvMsDuration(time::Duration::from_millis(250)))
+        );
+        assert_eq!(
+            "250".parse(),
+            Ok(EnvSecsDuration(time::Duration::from_secs(250)))
+        );
+        assert_eq!("0".parse(), Ok(EnvMsDuration(time::Duration::ZERO)));
+    }
+
+    #[test]
+    fn test_parse_invalid_durations() {
+        for s in ["", "s", "10x", "5 m", "1.5s", "-1s", "m5", "10sm"] {
+            assert_eq!(
+                s.parse::<EnvMsDuration>(),
+                Err(ParseDurationError(s.to_owned())),
+                "{}",
+                s
+            );
+        }
+
+        assert_eq!(
+            "10x".parse::<EnvMsDuration>().unwrap_err().to_string(),
+            "10x is not a valid duration, expected a number with a unit like 500ms, 30s, 5m or 1h"
+        );
+    }
+}
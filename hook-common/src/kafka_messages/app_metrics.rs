@@ -22,6 +22,8 @@ pub enum ErrorType {
     ConnectionError,
     BadHttpStatus(u16),
     ParseError,
+    ExpiredError,
+    InternalError,
 }
 
 // NOTE: This is stored in Postgres and deserialized by the cleanup/janitor process, so this
@@ -131,6 +133,8 @@ where
         ErrorType::TimeoutError => "Timeout Error".to_owned(),
         ErrorType::BadHttpStatus(s) => format!("Bad HTTP Status: {}", s),
         ErrorType::ParseError => "Parse Error".to_owned(),
+        ErrorType::ExpiredError => "Expired Error".to_owned(),
+        ErrorType::InternalError => "Internal Error".to_owned(),
     };
     serializer.serialize_str(&error_type)
 }
@@ -150,6 +154,8 @@ where
                     ErrorType::BadHttpStatus(status.parse().map_err(serde::de::Error::custom)?)
                 }
                 "Parse Error" => ErrorType::ParseError,
+                "Expired Error" => ErrorType::ExpiredError,
+                "Internal Error" => ErrorType::InternalError,
                 _ => {
                     return Err(serde::de::Error::unknown_variant(
                         &s,
@@ -158,6 +164,8 @@ where
                             "Timeout Error",
                             "Bad HTTP Status: <status>",
                             "Parse Error",
+                            "Expired Error",
+                            "Internal Error",
                         ],
                     ))
                 }
@@ -0,0 +1,26 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{deserialize_datetime, serialize_datetime};
+
+/// A summary of a single cleanup run of the janitor, produced even if nothing was cleaned up so it
+/// can be used as a heartbeat.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+pub struct JanitorRun {
+    #[serde(
+        serialize_with = "serialize_datetime",
+        deserialize_with = "deserialize_datetime"
+    )]
+    pub timestamp: DateTime<Utc>,
+    pub queue: String,
+    /// The number of completed, failed and discarded rows observed by the run.
+    pub rows_scanned: u64,
+    pub completed_rows_deleted: u64,
+    pub failed_rows_deleted: u64,
+    #[serde(default)]
+    pub discarded_rows_deleted: u64,
+    pub duration_ms: u64,
+    /// The error that made the run fail, if any. Nothing is deleted by a failed run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
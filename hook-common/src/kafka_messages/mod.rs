@@ -1,4 +1,5 @@
 pub mod app_metrics;
+pub mod janitor_runs;
 pub mod plugin_logs;
 
 use chrono::{DateTime, NaiveDateTime, Utc};
@@ -1,3 +1,5 @@
+use std::str::FromStr;
+
 use chrono::{DateTime, Utc};
 use serde::{Serialize, Serializer};
 use uuid::Uuid;
@@ -13,7 +15,7 @@ pub enum PluginLogEntrySource {
 }
 
 #[allow(dead_code)]
-#[derive(Serialize)]
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PluginLogEntryType {
     Debug,
     Log,
@@ -22,6 +24,24 @@ pub enum PluginLogEntryType {
     Error,
 }
 
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParsePluginLogEntryTypeError(String);
+
+impl FromStr for PluginLogEntryType {
+    type Err = ParsePluginLogEntryTypeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_ref() {
+            "DEBUG" => Ok(PluginLogEntryType::Debug),
+            "LOG" => Ok(PluginLogEntryType::Log),
+            "INFO" => Ok(PluginLogEntryType::Info),
+            "WARN" => Ok(PluginLogEntryType::Warn),
+            "ERROR" => Ok(PluginLogEntryType::Error),
+            invalid => Err(ParsePluginLogEntryTypeError(invalid.to_owned())),
+        }
+    }
+}
+
 #[derive(Serialize)]
 pub struct PluginLogEntry {
     #[serde(serialize_with = "serialize_source")]
@@ -65,7 +85,7 @@ where
     serializer.serialize_str(type_str)
 }
 
-fn serialize_message<S>(msg: &String, serializer: S) -> Result<S::Ok, S::Error>
+fn serialize_message<S>(msg: &str, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
@@ -1,5 +1,8 @@
+pub mod duration;
 pub mod kafka_messages;
+pub mod logging;
 pub mod metrics;
+pub mod pairs;
 pub mod pgqueue;
 pub mod retry;
 pub mod webhook;
@@ -0,0 +1,218 @@
+//! # Logging
+//!
+//! Set up the `tracing` subscriber that writes our logs, in a format fit for where they are collected.
+use std::str::FromStr;
+
+use envconfig::Envconfig;
+use thiserror::Error;
+use tracing::Subscriber;
+use tracing_subscriber::filter::{EnvFilter, LevelFilter, ParseError};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::util::{SubscriberInitExt, TryInitError};
+
+/// The formats logs can be written in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line, for log pipelines.
+    Json,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("{0} is not a valid log format")]
+pub struct ParseLogFormatError(String);
+
+impl FromStr for LogFormat {
+    type Err = ParseLogFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_ref() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            invalid => Err(ParseLogFormatError(invalid.to_owned())),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum LoggingError {
+    #[error("invalid log level: {0}")]
+    InvalidLogLevel(#[from] ParseError),
+    #[error("a global subscriber or logger was already set")]
+    AlreadyInitialized(#[from] TryInitError),
+}
+
+/// How our logs are written.
+#[derive(Envconfig, Clone, Debug, Default)]
+pub struct LoggingConfig {
+    /// Either `text` or `json`.
+    #[envconfig(default = "text")]
+    pub log_format: LogFormat,
+
+    /// The minimum level of logs written, or any directives as in `RUST_LOG`, like `info,hook_consumer=debug`.
+    /// Set to "" to use `RUST_LOG`, or `info` if that's not set either.
+    #[envconfig(default = "")]
+    pub log_level: String,
+}
+
+impl LoggingConfig {
+    /// Build a subscriber writing logs with `make_writer` as configured.
+    pub fn subscriber<W>(
+        &self,
+        make_writer: W,
+    ) -> Result<Box<dyn Subscriber + Send + Sync>, LoggingError>
+    where
+        W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+    {
+        let filter = if self.log_level.is_empty() {
+            EnvFilter::builder()
+                .with_default_directive(LevelFilter::INFO.into())
+                .from_env_lossy()
+        } else {
+            EnvFilter::builder().parse(&self.log_level)?
+        };
+        let builder = tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_writer(make_writer);
+
+        Ok(match self.log_format {
+            LogFormat::Text => Box::new(builder.finish()),
+            LogFormat::Json => Box::new(builder.json().finish()),
+        })
+    }
+
+    /// Set a subscriber writing logs to stdout as configured as the global default.
+    /// Records of the `log` crate, used by dependencies like rdkafka, reqwest and sqlx, are written by it too.
+    pub fn init(&self) -> Result<(), LoggingError> {
+        self.init_with_writer(std::io::stdout)
+    }
+
+    fn init_with_writer<W>(&self, make_writer: W) -> Result<(), LoggingError>
+    where
+        W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+    {
+        self.subscriber(make_writer)?.try_init()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// A writer collecting everything written to it, shared by every clone.
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Log a single event with a subscriber built from `config`, returning what it wrote.
+    fn log_with(config: &LoggingConfig) -> String {
+        let buffer = Buffer::default();
+        let subscriber = config
+            .subscriber({
+                let buffer = buffer.clone();
+                move || buffer.clone()
+            })
+            .expect("failed to build subscriber");
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug!("not written");
+            tracing::info!(job_id = 42, "webhook delivered");
+        });
+
+        let written = buffer.0.lock().unwrap().clone();
+        String::from_utf8(written).expect("logs are not valid UTF-8")
+    }
+
+    #[test]
+    fn test_json_format_writes_json_lines() {
+        let logs = log_with(&LoggingConfig {
+            log_format: LogFormat::Json,
+            log_level: "info".to_owned(),
+        });
+
+        let lines: Vec<&str> = logs.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let line: serde_json::Value = serde_json::from_str(lines[0]).expect("log is not JSON");
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["fields"]["message"], "webhook delivered");
+        assert_eq!(line["fields"]["job_id"], 42);
+    }
+
+    #[test]
+    fn test_text_format_writes_text_lines() {
+        let logs = log_with(&LoggingConfig {
+            log_format: LogFormat::Text,
+            log_level: "info".to_owned(),
+        });
+
+        let lines: Vec<&str> = logs.lines().collect();
+        assert_eq!(lines.len(), 1);
+        assert!(serde_json::from_str::<serde_json::Value>(lines[0]).is_err());
+        // Field names may be wrapped in ANSI styles.
+        assert!(lines[0].contains("webhook delivered"));
+        assert!(lines[0].contains("job_id"));
+    }
+
+    #[test]
+    fn test_init_writes_log_records() {
+        let buffer = Buffer::default();
+        let config = LoggingConfig {
+            log_format: LogFormat::Json,
+            log_level: "info".to_owned(),
+        };
+        config
+            .init_with_writer({
+                let buffer = buffer.clone();
+                move || buffer.clone()
+            })
+            .expect("failed to initialize logging");
+
+        log::debug!(target: "sqlx::query", "not written");
+        log::info!(target: "sqlx::query", "a log record");
+
+        let written = buffer.0.lock().unwrap().clone();
+        let logs = String::from_utf8(written).expect("logs are not valid UTF-8");
+        let lines: Vec<&str> = logs.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let line: serde_json::Value = serde_json::from_str(lines[0]).expect("log is not JSON");
+        assert_eq!(line["fields"]["message"], "a log record");
+        assert_eq!(line["target"], "sqlx::query");
+
+        assert!(matches!(
+            config.init_with_writer(io::sink),
+            Err(LoggingError::AlreadyInitialized(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_logging_options() {
+        assert_eq!("json".parse(), Ok(LogFormat::Json));
+        assert_eq!(" TEXT ".parse(), Ok(LogFormat::Text));
+        assert!("yaml".parse::<LogFormat>().is_err());
+
+        let config = LoggingConfig {
+            log_format: LogFormat::Text,
+            log_level: "info,hook_consumer=notalevel".to_owned(),
+        };
+        assert!(matches!(
+            config.subscriber(io::sink),
+            Err(LoggingError::InvalidLogLevel(_))
+        ));
+    }
+}
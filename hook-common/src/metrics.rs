@@ -18,7 +18,12 @@ pub async fn serve(router: Router, bind: &str) -> Result<(), std::io::Error> {
 
 /// Build a Router for a metrics endpoint.
 pub fn setup_metrics_router() -> Router {
-    let recorder_handle = setup_metrics_recorder();
+    setup_metrics_router_with_labels(&[])
+}
+
+/// Build a Router for a metrics endpoint, attaching `global_labels` to all metrics.
+pub fn setup_metrics_router_with_labels(global_labels: &[(String, String)]) -> Router {
+    let recorder_handle = setup_metrics_recorder_with_labels(global_labels);
 
     Router::new()
         .route("/metrics", get(recorder_handle.render()))
@@ -26,15 +31,25 @@ pub fn setup_metrics_router() -> Router {
 }
 
 pub fn setup_metrics_recorder() -> PrometheusHandle {
+    setup_metrics_recorder_with_labels(&[])
+}
+
+/// Install a Prometheus recorder that attaches `global_labels`, like a deployment region, to all metrics.
+pub fn setup_metrics_recorder_with_labels(global_labels: &[(String, String)]) -> PrometheusHandle {
+    metrics_builder(global_labels).install_recorder().unwrap()
+}
+
+fn metrics_builder(global_labels: &[(String, String)]) -> PrometheusBuilder {
     const EXPONENTIAL_SECONDS: &[f64] = &[
         0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
     ];
 
-    PrometheusBuilder::new()
-        .set_buckets(EXPONENTIAL_SECONDS)
-        .unwrap()
-        .install_recorder()
-        .unwrap()
+    global_labels.iter().fold(
+        PrometheusBuilder::new()
+            .set_buckets(EXPONENTIAL_SECONDS)
+            .unwrap(),
+        |builder, (key, value)| builder.add_global_label(key, value),
+    )
 }
 
 /// Middleware to record some common HTTP metrics
@@ -68,3 +83,35 @@ pub async fn track_metrics(req: Request<Body>, next: Next) -> impl IntoResponse
 
     response
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use metrics::{Key, Label, Recorder};
+
+    #[test]
+    fn test_metrics_carry_global_labels() {
+        let recorder = metrics_builder(&[
+            ("region".to_owned(), "us-east-1".to_owned()),
+            ("zone".to_owned(), "us-east-1a".to_owned()),
+        ])
+        .build_recorder();
+        let handle = recorder.handle();
+
+        let key = Key::from_parts(
+            "webhook_jobs_completed",
+            vec![Label::new("queue", "default")],
+        );
+        recorder.register_counter(&key).increment(1);
+
+        let rendered = handle.render();
+        let line = rendered
+            .lines()
+            .find(|line| line.starts_with("webhook_jobs_completed{"))
+            .expect("counter was not rendered");
+
+        assert!(line.contains(r#"queue="default""#));
+        assert!(line.contains(r#"region="us-east-1""#));
+        assert!(line.contains(r#"zone="us-east-1a""#));
+    }
+}
@@ -0,0 +1,74 @@
+//! # Pairs
+//!
+//! Split lists of pairs set in config, like `1=3,2=5` to configure something for specific teams, or
+//! `default:...;retries:...` to configure something for specific queues.
+use std::str::FromStr;
+
+/// Split comma-separated `key=value` pairs, parsing the key and the value of each with their `FromStr`.
+/// Whitespace around pairs, keys, and values is ignored, as are empty pairs.
+/// Yields `Err` with any pair that has no `=`, an empty key, or a key or value that fails to parse.
+pub fn key_value_pairs<K: FromStr, V: FromStr>(
+    s: &str,
+) -> impl Iterator<Item = Result<(K, V), &str>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => {
+                let key = key.trim().parse::<K>().map_err(|_| pair)?;
+                let value = value.trim().parse::<V>().map_err(|_| pair)?;
+                Ok((key, value))
+            }
+            _ => Err(pair),
+        })
+}
+
+/// Split semicolon-separated `queue:value` pairs, as used to configure something for specific queues.
+/// Yields `Err` with any pair that has no queue.
+pub fn queue_pairs(s: &str) -> impl Iterator<Item = Result<(&str, &str), &str>> {
+    s.split(';')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once(':') {
+            Some((queue, value)) if !queue.trim().is_empty() => Ok((queue.trim(), value.trim())),
+            _ => Err(pair),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_value_pairs() {
+        let pairs: Result<Vec<(u32, String)>, &str> =
+            key_value_pairs(" 1 = a ,, 2=b=c, ").collect();
+
+        assert_eq!(pairs, Ok(vec![(1, "a".to_owned()), (2, "b=c".to_owned())]));
+        assert_eq!(key_value_pairs::<u32, u32>("").count(), 0);
+    }
+
+    #[test]
+    fn test_key_value_pairs_errors() {
+        let first_error = |s| {
+            key_value_pairs::<String, u32>(s)
+                .find_map(Result::err)
+                .map(str::to_owned)
+        };
+
+        assert_eq!(first_error("a=1, b"), Some("b".to_owned()));
+        assert_eq!(first_error("a=1, =2"), Some("=2".to_owned()));
+        assert_eq!(first_error("a=1, b=x"), Some("b=x".to_owned()));
+        assert_eq!(first_error("a=1, b=2"), None);
+    }
+
+    #[test]
+    fn test_queue_pairs() {
+        let pairs: Vec<_> = queue_pairs(" default: a=1 ;; retries:b ; :c").collect();
+
+        assert_eq!(
+            pairs,
+            vec![Ok(("default", "a=1")), Ok(("retries", "b")), Err(":c")]
+        );
+    }
+}
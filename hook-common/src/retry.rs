@@ -1,16 +1,36 @@
 //! # Retry
 //!
 //! Module providing a `RetryPolicy` struct to configure job retrying.
+use std::ops::RangeInclusive;
+use std::str::FromStr;
 use std::time;
 
-#[derive(Clone, Debug)]
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// The longest interval any `RetryPolicy` retries after, whatever its `maximum_interval`, so that intervals of
+/// policies without one still fit in a PostgreSQL interval, and `NOW()` plus them in a timestamp.
+pub const MAXIMUM_RETRY_INTERVAL: time::Duration = time::Duration::from_secs(365 * 24 * 60 * 60);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 /// A retry policy to determine retry parameters for a job.
+/// Policies can be loaded from config files, with intervals given in (possibly fractional) seconds and any missing
+/// field taking the value of `RetryPolicy::default`.
 pub struct RetryPolicy {
+    /// How the interval grows with every past attempt, and whether it's randomized.
+    pub backoff_strategy: BackoffStrategy,
     /// Coefficient to multiply initial_interval with for every past attempt.
     pub backoff_coefficient: u32,
     /// The backoff interval for the first retry.
+    #[serde(with = "duration_secs")]
     pub initial_interval: time::Duration,
+    /// The minimum possible backoff between retries, overriding the backoff of early attempts.
+    #[serde(with = "optional_duration_secs")]
+    pub minimum_interval: Option<time::Duration>,
     /// The maximum possible backoff between retries.
+    #[serde(with = "optional_duration_secs")]
     pub maximum_interval: Option<time::Duration>,
     /// An optional queue to send WebhookJob retries to.
     pub queue: Option<String>,
@@ -24,15 +44,85 @@ impl RetryPolicy {
 
     /// Determine interval for retrying at a given attempt number.
     /// If not `None`, this method will respect `preferred_retry_interval` as long as it falls within `candidate_interval <= preferred_retry_interval <= maximum_interval`.
+    /// The candidate interval is raised to `minimum_interval` before being clamped to `maximum_interval`, so
+    /// `maximum_interval` wins if both are set and the minimum is the larger one.
+    /// The interval is never longer than `MAXIMUM_RETRY_INTERVAL`.
+    /// Jittered backoff strategies are randomized with the thread-local random number generator.
     pub fn retry_interval(
         &self,
         attempt: u32,
         preferred_retry_interval: Option<time::Duration>,
     ) -> time::Duration {
-        let candidate_interval =
-            self.initial_interval * self.backoff_coefficient.pow(attempt.saturating_sub(1));
+        self.retry_interval_with_rng(attempt, preferred_retry_interval, &mut rand::thread_rng())
+    }
+
+    /// Determine interval for retrying at a given attempt number like `retry_interval`, randomizing jittered
+    /// backoff strategies with `rng`. Seeding `rng` makes the interval deterministic.
+    pub fn retry_interval_with_rng(
+        &self,
+        attempt: u32,
+        preferred_retry_interval: Option<time::Duration>,
+        rng: &mut impl Rng,
+    ) -> time::Duration {
+        self.multiplied_retry_interval_with_rng(attempt, preferred_retry_interval, 1, rng)
+    }
+
+    /// Determine interval for retrying at a given attempt number like `retry_interval`, with the backoff multiplied
+    /// by `interval_multiplier`, e.g. to back off more from a destination that asked us to slow down.
+    /// The backoff is multiplied before it's clamped to `minimum_interval` and `maximum_interval`, and
+    /// `preferred_retry_interval` is not multiplied at all, as it's what the destination asked for.
+    pub fn multiplied_retry_interval(
+        &self,
+        attempt: u32,
+        preferred_retry_interval: Option<time::Duration>,
+        interval_multiplier: u32,
+    ) -> time::Duration {
+        self.multiplied_retry_interval_with_rng(
+            attempt,
+            preferred_retry_interval,
+            interval_multiplier,
+            &mut rand::thread_rng(),
+        )
+    }
 
-        match (preferred_retry_interval, self.maximum_interval) {
+    fn multiplied_retry_interval_with_rng(
+        &self,
+        attempt: u32,
+        preferred_retry_interval: Option<time::Duration>,
+        interval_multiplier: u32,
+        rng: &mut impl Rng,
+    ) -> time::Duration {
+        let initial_interval = self
+            .initial_interval
+            .checked_mul(interval_multiplier)
+            .unwrap_or(time::Duration::MAX);
+        // The interval saturates instead of overflowing after many attempts, e.g. consecutive connection failures.
+        let exponential_interval = self
+            .backoff_coefficient
+            .checked_pow(attempt.saturating_sub(1))
+            .and_then(|multiplier| initial_interval.checked_mul(multiplier))
+            .unwrap_or(time::Duration::MAX);
+        // Jitter is applied to the capped interval, so that jittered intervals don't all end up at the maximum.
+        let capped_interval = match self.maximum_interval {
+            Some(max_interval) => std::cmp::min(exponential_interval, max_interval),
+            None => exponential_interval,
+        };
+        let candidate_interval = match self.backoff_strategy {
+            BackoffStrategy::Fixed => initial_interval,
+            BackoffStrategy::Exponential => exponential_interval,
+            BackoffStrategy::ExponentialFullJitter => {
+                rng.gen_range(time::Duration::ZERO..=capped_interval)
+            }
+            BackoffStrategy::ExponentialEqualJitter => {
+                capped_interval / 2 + rng.gen_range(time::Duration::ZERO..=capped_interval / 2)
+            }
+        };
+        let candidate_interval = match self.minimum_interval {
+            Some(min_interval) => std::cmp::max(candidate_interval, min_interval),
+            None => candidate_interval,
+        };
+
+        let interval = match (preferred_retry_interval, self.maximum_interval) {
             (Some(duration), Some(max_interval)) => {
                 let min_interval_allowed = std::cmp::min(candidate_interval, max_interval);
 
@@ -45,7 +135,13 @@ impl RetryPolicy {
             (Some(duration), None) => std::cmp::max(candidate_interval, duration),
             (None, Some(max_interval)) => std::cmp::min(candidate_interval, max_interval),
             (None, None) => candidate_interval,
-        }
+        };
+
+        let interval = std::cmp::min(interval, MAXIMUM_RETRY_INTERVAL);
+
+        // Jittered intervals have nanoseconds, but PostgreSQL intervals only go down to microseconds, and sqlx refuses
+        // to bind a duration it would have to round.
+        time::Duration::new(interval.as_secs(), interval.subsec_micros() * 1_000)
     }
 
     /// Determine the queue to be used for retrying.
@@ -66,13 +162,58 @@ impl Default for RetryPolicy {
     }
 }
 
+/// How the interval between retries grows with every past attempt.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackoffStrategy {
+    /// Always wait `initial_interval`.
+    Fixed,
+    /// Multiply `initial_interval` by `backoff_coefficient` for every past attempt.
+    #[default]
+    Exponential,
+    /// Wait a random interval between 0 and the exponential interval, capped to `maximum_interval`, to spread out
+    /// retries of jobs that failed together.
+    ExponentialFullJitter,
+    /// Wait half of the exponential interval, capped to `maximum_interval`, plus a random interval up to the other
+    /// half, which spreads out retries while still backing off.
+    ExponentialEqualJitter,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("{0} is not a valid BackoffStrategy")]
+pub struct ParseBackoffStrategyError(String);
+
+impl FromStr for BackoffStrategy {
+    type Err = ParseBackoffStrategyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_ref() {
+            "fixed" => Ok(BackoffStrategy::Fixed),
+            "exponential" => Ok(BackoffStrategy::Exponential),
+            "exponential_full_jitter" => Ok(BackoffStrategy::ExponentialFullJitter),
+            "exponential_equal_jitter" => Ok(BackoffStrategy::ExponentialEqualJitter),
+            invalid => Err(ParseBackoffStrategyError(invalid.to_owned())),
+        }
+    }
+}
+
 /// Builder pattern struct to provide a `RetryPolicy`.
+/// Serialized like `RetryPolicy`, so a config file can be loaded into a builder to override some of its fields.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
 pub struct RetryPolicyBuilder {
+    /// How the interval grows with every past attempt, and whether it's randomized.
+    pub backoff_strategy: BackoffStrategy,
     /// Coefficient to multiply initial_interval with for every past attempt.
     pub backoff_coefficient: u32,
     /// The backoff interval for the first retry.
+    #[serde(with = "duration_secs")]
     pub initial_interval: time::Duration,
+    /// The minimum possible backoff between retries, overriding the backoff of early attempts.
+    #[serde(with = "optional_duration_secs")]
+    pub minimum_interval: Option<time::Duration>,
     /// The maximum possible backoff between retries.
+    #[serde(with = "optional_duration_secs")]
     pub maximum_interval: Option<time::Duration>,
     /// An optional queue to send WebhookJob retries to.
     pub queue: Option<String>,
@@ -81,8 +222,10 @@ pub struct RetryPolicyBuilder {
 impl Default for RetryPolicyBuilder {
     fn default() -> Self {
         Self {
+            backoff_strategy: BackoffStrategy::default(),
             backoff_coefficient: 2,
             initial_interval: time::Duration::from_secs(1),
+            minimum_interval: None,
             maximum_interval: None,
             queue: None,
         }
@@ -98,6 +241,16 @@ impl RetryPolicyBuilder {
         }
     }
 
+    pub fn backoff_strategy(mut self, strategy: BackoffStrategy) -> RetryPolicyBuilder {
+        self.backoff_strategy = strategy;
+        self
+    }
+
+    pub fn minimum_interval(mut self, interval: time::Duration) -> RetryPolicyBuilder {
+        self.minimum_interval = Some(interval);
+        self
+    }
+
     pub fn maximum_interval(mut self, interval: time::Duration) -> RetryPolicyBuilder {
         self.maximum_interval = Some(interval);
         self
@@ -111,17 +264,282 @@ impl RetryPolicyBuilder {
     /// Provide a `RetryPolicy` according to build parameters provided thus far.
     pub fn provide(&self) -> RetryPolicy {
         RetryPolicy {
+            backoff_strategy: self.backoff_strategy,
             backoff_coefficient: self.backoff_coefficient,
             initial_interval: self.initial_interval,
+            minimum_interval: self.minimum_interval,
             maximum_interval: self.maximum_interval,
             queue: self.queue.clone(),
         }
     }
 }
 
+/// What to do with a job after a request fails with a given HTTP status code.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RetryAction {
+    /// Retry the job, multiplying the backoff of the `RetryPolicy` by `interval_multiplier`, see
+    /// `RetryPolicy::multiplied_retry_interval`.
+    Retry { interval_multiplier: u32 },
+    /// Fail the job without retrying it.
+    Fail,
+    /// Drop the job without retrying it or reporting it as failed, e.g. when a destination rejects
+    /// duplicates of requests it already received.
+    Discard,
+}
+
+impl RetryAction {
+    /// Retry with the interval given by the `RetryPolicy`, unchanged.
+    pub const RETRY: RetryAction = RetryAction::Retry {
+        interval_multiplier: 1,
+    };
+}
+
+/// A rule assigning a `RetryAction` to a range of HTTP status codes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatusRule {
+    pub statuses: RangeInclusive<u16>,
+    pub action: RetryAction,
+}
+
+/// Classify failed requests by HTTP status code to decide whether their jobs are retried.
+///
+/// Rules are checked in the order they were added, and the first one matching a status code wins.
+/// Status codes not matched by any rule keep the default behavior: 429 and 5XX are retried, and
+/// everything else fails.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RetryClassifier {
+    rules: Vec<StatusRule>,
+}
+
+impl RetryClassifier {
+    /// Add a rule assigning `action` to `statuses`, checked after all rules added before.
+    pub fn rule(mut self, statuses: RangeInclusive<u16>, action: RetryAction) -> Self {
+        self.rules.push(StatusRule { statuses, action });
+        self
+    }
+
+    /// Determine what to do with a job after a request failed with `status`.
+    pub fn classify(&self, status: u16) -> RetryAction {
+        self.rules
+            .iter()
+            .find(|rule| rule.statuses.contains(&status))
+            .map(|rule| rule.action)
+            .unwrap_or_else(|| default_action(status))
+    }
+}
+
+/// The default action for `status`: retry on 429 and 5XX, fail on anything else.
+fn default_action(status: u16) -> RetryAction {
+    if status == 429 || (500..600).contains(&status) {
+        RetryAction::RETRY
+    } else {
+        RetryAction::Fail
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("{0} is not a valid status rule")]
+pub struct ParseRetryClassifierError(String);
+
+/// Parse a `RetryClassifier` from comma-separated rules of the form `statuses=action`.
+/// `statuses` is a status code or an inclusive range like `400-499`, and `action` is one of `fail`,
+/// `discard`, `retry`, or `retry*N` to multiply the retry interval by `N`.
+/// For example: `429=retry*4,418=discard,400-499=fail`.
+impl FromStr for RetryClassifier {
+    type Err = ParseRetryClassifierError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut classifier = RetryClassifier::default();
+
+        for rule in s.split(',').map(str::trim).filter(|rule| !rule.is_empty()) {
+            let error = || ParseRetryClassifierError(rule.to_owned());
+            let (statuses, action) = rule.split_once('=').ok_or_else(error)?;
+
+            let statuses = parse_statuses(statuses).ok_or_else(error)?;
+
+            let action = match action.trim().to_ascii_lowercase().as_str() {
+                "fail" => RetryAction::Fail,
+                "discard" => RetryAction::Discard,
+                "retry" => RetryAction::RETRY,
+                action => {
+                    let interval_multiplier = action
+                        .strip_prefix("retry*")
+                        .and_then(|multiplier| multiplier.trim().parse().ok())
+                        .ok_or_else(error)?;
+                    RetryAction::Retry {
+                        interval_multiplier,
+                    }
+                }
+            };
+
+            classifier = classifier.rule(statuses, action);
+        }
+
+        Ok(classifier)
+    }
+}
+
+/// Parse a status code, or an inclusive range of them like `400-499`.
+fn parse_statuses(statuses: &str) -> Option<RangeInclusive<u16>> {
+    match statuses.trim().split_once('-') {
+        Some((start, end)) => Some(start.trim().parse().ok()?..=end.trim().parse().ok()?),
+        None => {
+            let status = statuses.trim().parse().ok()?;
+            Some(status..=status)
+        }
+    }
+}
+
+/// A rule deciding whether responses with a status code in `statuses`, and a body containing `body_contains` if
+/// set, mean a webhook was delivered.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SuccessRule {
+    pub statuses: RangeInclusive<u16>,
+    pub body_contains: Option<String>,
+    pub success: bool,
+}
+
+/// Decide by status code, and optionally by body, whether a response means a webhook was delivered, for
+/// destinations that respond with a 200 and an error in the body, or with a 202 for webhooks they haven't
+/// processed yet.
+///
+/// Rules are checked in the order they were added, and the first one matching a response wins.
+/// Responses not matched by any rule keep the default behavior: any 2XX is a success.
+/// Responses that aren't a success are then classified by a `RetryClassifier`, like failing ones.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SuccessCriteria {
+    rules: Vec<SuccessRule>,
+}
+
+impl SuccessCriteria {
+    /// Add a rule deciding whether responses with `statuses`, and a body containing `body_contains` if set, are a
+    /// success, checked after all rules added before.
+    pub fn rule(
+        mut self,
+        statuses: RangeInclusive<u16>,
+        body_contains: Option<&str>,
+        success: bool,
+    ) -> Self {
+        self.rules.push(SuccessRule {
+            statuses,
+            body_contains: body_contains.map(str::to_owned),
+            success,
+        });
+        self
+    }
+
+    /// Whether the body of a response with `status` has to be read to tell if it's a success.
+    pub fn inspects_body(&self, status: u16) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| rule.body_contains.is_some() && rule.statuses.contains(&status))
+    }
+
+    /// Whether a response with `status` and `body` is a success. Rules on the body don't match responses whose
+    /// body wasn't read.
+    pub fn is_success(&self, status: u16, body: Option<&str>) -> bool {
+        self.rules
+            .iter()
+            .find(|rule| {
+                rule.statuses.contains(&status)
+                    && match (&rule.body_contains, body) {
+                        (Some(text), Some(body)) => body.contains(text.as_str()),
+                        (Some(_), None) => false,
+                        (None, _) => true,
+                    }
+            })
+            .map(|rule| rule.success)
+            .unwrap_or((200..300).contains(&status))
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("{0} is not a valid success rule")]
+pub struct ParseSuccessCriteriaError(String);
+
+/// Parse `SuccessCriteria` from comma-separated rules of the form `statuses=outcome` or
+/// `statuses~text=outcome`, for responses whose body contains `text`.
+/// `statuses` is a status code or an inclusive range like `200-299`, and `outcome` is `success` or `failure`.
+/// `text` can't contain commas. For example: `200~"status":"error"=failure,202=failure`.
+impl FromStr for SuccessCriteria {
+    type Err = ParseSuccessCriteriaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut criteria = SuccessCriteria::default();
+
+        for rule in s.split(',').map(str::trim).filter(|rule| !rule.is_empty()) {
+            let error = || ParseSuccessCriteriaError(rule.to_owned());
+            let (condition, outcome) = rule.rsplit_once('=').ok_or_else(error)?;
+            let (statuses, body_contains) = match condition.split_once('~') {
+                Some((statuses, text)) if !text.is_empty() => (statuses, Some(text)),
+                Some(_) => return Err(error()),
+                None => (condition, None),
+            };
+
+            let statuses = parse_statuses(statuses).ok_or_else(error)?;
+            let success = match outcome.trim().to_ascii_lowercase().as_str() {
+                "success" => true,
+                "failure" => false,
+                _ => return Err(error()),
+            };
+
+            criteria = criteria.rule(statuses, body_contains, success);
+        }
+
+        Ok(criteria)
+    }
+}
+
+/// (De)serialize a `time::Duration` as a number of seconds, which may be fractional.
+mod duration_secs {
+    use std::time;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        duration: &time::Duration,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<time::Duration, D::Error> {
+        let secs = f64::deserialize(deserializer)?;
+        time::Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom)
+    }
+}
+
+/// (De)serialize an optional `time::Duration` as a number of seconds like `duration_secs`, or null.
+mod optional_duration_secs {
+    use std::time;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        duration: &Option<time::Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => serializer.serialize_some(&duration.as_secs_f64()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<time::Duration>, D::Error> {
+        Option::<f64>::deserialize(deserializer)?
+            .map(|secs| time::Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::SeedableRng;
 
     #[test]
     fn test_constant_retry_interval() {
@@ -151,6 +569,58 @@ mod tests {
         assert_eq!(fourth_interval, time::Duration::from_secs(4));
     }
 
+    #[test]
+    fn test_retry_interval_saturates_after_many_attempts() {
+        let retry_policy = RetryPolicy::build(2, time::Duration::from_secs(2))
+            .maximum_interval(time::Duration::from_secs(60))
+            .provide();
+
+        assert_eq!(
+            retry_policy.retry_interval(100, None),
+            time::Duration::from_secs(60)
+        );
+        assert_eq!(
+            retry_policy.retry_interval(u32::MAX, None),
+            time::Duration::from_secs(60)
+        );
+
+        let retry_policy = RetryPolicy::build(2, time::Duration::from_secs(2)).provide();
+        assert_eq!(
+            retry_policy.retry_interval(1000, None),
+            MAXIMUM_RETRY_INTERVAL
+        );
+        assert_eq!(
+            RetryPolicy::default().retry_interval(u32::MAX, None),
+            MAXIMUM_RETRY_INTERVAL
+        );
+    }
+
+    #[test]
+    fn test_multiplied_retry_interval() {
+        let retry_policy = RetryPolicy::build(2, time::Duration::from_secs(1))
+            .maximum_interval(time::Duration::from_secs(10))
+            .provide();
+
+        assert_eq!(
+            retry_policy.multiplied_retry_interval(2, None, 4),
+            time::Duration::from_secs(8)
+        );
+        // The multiplied interval is still capped.
+        assert_eq!(
+            retry_policy.multiplied_retry_interval(3, None, 4),
+            time::Duration::from_secs(10)
+        );
+        // The preferred interval is not multiplied.
+        assert_eq!(
+            retry_policy.multiplied_retry_interval(1, Some(time::Duration::from_secs(5)), 4),
+            time::Duration::from_secs(5)
+        );
+        assert_eq!(
+            retry_policy.multiplied_retry_interval(1, None, 1),
+            retry_policy.retry_interval(1, None)
+        );
+    }
+
     #[test]
     fn test_retry_interval_increases_with_coefficient() {
         let retry_policy = RetryPolicy::build(2, time::Duration::from_secs(2)).provide();
@@ -163,6 +633,171 @@ mod tests {
         assert_eq!(third_interval, time::Duration::from_secs(8));
     }
 
+    #[test]
+    fn test_retry_interval_never_below_minimum() {
+        let retry_policy = RetryPolicy::build(2, time::Duration::from_millis(100))
+            .minimum_interval(time::Duration::from_secs(1))
+            .maximum_interval(time::Duration::from_secs(4))
+            .provide();
+        let first_interval = retry_policy.retry_interval(1, None);
+        let fourth_interval = retry_policy.retry_interval(4, None);
+        let fifth_interval = retry_policy.retry_interval(5, None);
+        let sixth_interval = retry_policy.retry_interval(6, None);
+        let small_preferred_interval =
+            retry_policy.retry_interval(1, Some(time::Duration::from_millis(200)));
+
+        assert_eq!(first_interval, time::Duration::from_secs(1));
+        assert_eq!(fourth_interval, time::Duration::from_secs(1));
+        assert_eq!(fifth_interval, time::Duration::from_millis(1600));
+        assert_eq!(sixth_interval, time::Duration::from_millis(3200));
+        assert_eq!(small_preferred_interval, time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_retry_interval_maximum_wins_over_minimum() {
+        let retry_policy = RetryPolicy::build(1, time::Duration::from_secs(1))
+            .minimum_interval(time::Duration::from_secs(10))
+            .maximum_interval(time::Duration::from_secs(5))
+            .provide();
+
+        assert_eq!(
+            retry_policy.retry_interval(1, None),
+            time::Duration::from_secs(5)
+        );
+    }
+
+    /// Sample the retry interval of `retry_policy` at `attempt` from a seeded random number generator.
+    fn sample_intervals(
+        retry_policy: &RetryPolicy,
+        attempt: u32,
+        seed: u64,
+    ) -> Vec<time::Duration> {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+
+        (0..1000)
+            .map(|_| retry_policy.retry_interval_with_rng(attempt, None, &mut rng))
+            .collect()
+    }
+
+    /// The mean of `intervals`, in seconds.
+    fn mean_secs(intervals: &[time::Duration]) -> f64 {
+        intervals
+            .iter()
+            .map(time::Duration::as_secs_f64)
+            .sum::<f64>()
+            / intervals.len() as f64
+    }
+
+    #[test]
+    fn test_fixed_backoff_ignores_coefficient() {
+        let retry_policy = RetryPolicy::build(3, time::Duration::from_secs(2))
+            .backoff_strategy(BackoffStrategy::Fixed)
+            .provide();
+
+        for attempt in 1..5 {
+            assert_eq!(
+                retry_policy.retry_interval(attempt, None),
+                time::Duration::from_secs(2)
+            );
+        }
+    }
+
+    #[test]
+    fn test_full_jitter_backoff_is_bounded_by_exponential_interval() {
+        let retry_policy = RetryPolicy::build(2, time::Duration::from_secs(1))
+            .backoff_strategy(BackoffStrategy::ExponentialFullJitter)
+            .provide();
+
+        // The exponential interval of a third attempt is 4 seconds.
+        let intervals = sample_intervals(&retry_policy, 3, 42);
+        assert!(intervals
+            .iter()
+            .all(|interval| *interval <= time::Duration::from_secs(4)));
+        assert!(intervals
+            .iter()
+            .any(|interval| *interval < time::Duration::from_secs(1)));
+        assert!((mean_secs(&intervals) - 2.0).abs() < 0.2);
+    }
+
+    #[test]
+    fn test_equal_jitter_backoff_waits_at_least_half_the_exponential_interval() {
+        let retry_policy = RetryPolicy::build(2, time::Duration::from_secs(1))
+            .backoff_strategy(BackoffStrategy::ExponentialEqualJitter)
+            .provide();
+
+        let intervals = sample_intervals(&retry_policy, 3, 42);
+        assert!(intervals.iter().all(|interval| {
+            time::Duration::from_secs(2) <= *interval && *interval <= time::Duration::from_secs(4)
+        }));
+        assert!((mean_secs(&intervals) - 3.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_jitter_backoff_respects_minimum_and_maximum() {
+        let retry_policy = RetryPolicy::build(2, time::Duration::from_secs(1))
+            .backoff_strategy(BackoffStrategy::ExponentialFullJitter)
+            .minimum_interval(time::Duration::from_millis(500))
+            .maximum_interval(time::Duration::from_secs(10))
+            .provide();
+
+        // Jitter applies to the capped interval, so retries late in the backoff don't cluster at the maximum.
+        let intervals = sample_intervals(&retry_policy, 20, 42);
+        assert!(intervals.iter().all(|interval| {
+            time::Duration::from_millis(500) <= *interval
+                && *interval <= time::Duration::from_secs(10)
+        }));
+        assert!((mean_secs(&intervals) - 5.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn test_jitter_backoff_is_rounded_to_microseconds() {
+        let retry_policy = RetryPolicy::build(2, time::Duration::from_secs(1))
+            .backoff_strategy(BackoffStrategy::ExponentialFullJitter)
+            .provide();
+
+        for interval in sample_intervals(&retry_policy, 3, 7) {
+            assert_eq!(interval.subsec_nanos() % 1_000, 0);
+        }
+    }
+
+    #[test]
+    fn test_jitter_backoff_is_deterministic_when_seeded() {
+        let retry_policy = RetryPolicy::build(2, time::Duration::from_secs(1))
+            .backoff_strategy(BackoffStrategy::ExponentialFullJitter)
+            .provide();
+
+        assert_eq!(
+            sample_intervals(&retry_policy, 3, 7),
+            sample_intervals(&retry_policy, 3, 7)
+        );
+        assert_ne!(
+            sample_intervals(&retry_policy, 3, 7),
+            sample_intervals(&retry_policy, 3, 8)
+        );
+    }
+
+    #[test]
+    fn test_parse_backoff_strategy() {
+        assert_eq!("fixed".parse(), Ok(BackoffStrategy::Fixed));
+        assert_eq!("Exponential".parse(), Ok(BackoffStrategy::Exponential));
+        assert_eq!(
+            "exponential_full_jitter".parse(),
+            Ok(BackoffStrategy::ExponentialFullJitter)
+        );
+        assert_eq!(
+            "exponential_equal_jitter".parse(),
+            Ok(BackoffStrategy::ExponentialEqualJitter)
+        );
+        assert!("linear".parse::<BackoffStrategy>().is_err());
+
+        let policy: RetryPolicy =
+            serde_json::from_str(r#"{"backoff_strategy": "exponential_equal_jitter"}"#).unwrap();
+        assert_eq!(
+            policy.backoff_strategy,
+            BackoffStrategy::ExponentialEqualJitter
+        );
+    }
+
     #[test]
     fn test_retry_interval_respects_preferred() {
         let retry_policy = RetryPolicy::build(1, time::Duration::from_secs(2)).provide();
@@ -222,4 +857,175 @@ mod tests {
 
         assert_eq!(retry_policy.retry_queue(&current_queue), current_queue);
     }
+
+    #[test]
+    fn test_default_retry_classifier() {
+        let classifier = RetryClassifier::default();
+
+        assert_eq!(classifier.classify(400), RetryAction::Fail);
+        assert_eq!(classifier.classify(418), RetryAction::Fail);
+        assert_eq!(classifier.classify(429), RetryAction::RETRY);
+        assert_eq!(classifier.classify(503), RetryAction::RETRY);
+    }
+
+    #[test]
+    fn test_retry_classifier_rules() {
+        let classifier = RetryClassifier::default()
+            .rule(
+                429..=429,
+                RetryAction::Retry {
+                    interval_multiplier: 4,
+                },
+            )
+            .rule(418..=418, RetryAction::Discard)
+            .rule(400..=499, RetryAction::RETRY);
+
+        assert_eq!(
+            classifier.classify(429),
+            RetryAction::Retry {
+                interval_multiplier: 4
+            }
+        );
+        assert_eq!(classifier.classify(418), RetryAction::Discard);
+        // Matched by the last rule, as the first ones don't apply.
+        assert_eq!(classifier.classify(400), RetryAction::RETRY);
+        // Not matched by any rule, so the default applies.
+        assert_eq!(classifier.classify(503), RetryAction::RETRY);
+        assert_eq!(classifier.classify(301), RetryAction::Fail);
+    }
+
+    #[test]
+    fn test_parse_retry_classifier() {
+        let classifier: RetryClassifier = "429=retry*4, 418=discard,400-499=fail,500-599=retry"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            classifier,
+            RetryClassifier::default()
+                .rule(
+                    429..=429,
+                    RetryAction::Retry {
+                        interval_multiplier: 4
+                    }
+                )
+                .rule(418..=418, RetryAction::Discard)
+                .rule(400..=499, RetryAction::Fail)
+                .rule(500..=599, RetryAction::RETRY)
+        );
+        assert_eq!(
+            "".parse::<RetryClassifier>(),
+            Ok(RetryClassifier::default())
+        );
+        assert!("418".parse::<RetryClassifier>().is_err());
+        assert!("418=ignore".parse::<RetryClassifier>().is_err());
+        assert!("4xx=fail".parse::<RetryClassifier>().is_err());
+        assert!("429=retry*fast".parse::<RetryClassifier>().is_err());
+    }
+
+    #[test]
+    fn test_success_criteria() {
+        let criteria = SuccessCriteria::default();
+        assert!(criteria.is_success(200, None));
+        assert!(criteria.is_success(202, None));
+        assert!(!criteria.is_success(404, None));
+        assert!(!criteria.inspects_body(200));
+
+        let criteria: SuccessCriteria = r#"200~"status":"error"=failure, 202=failure,404=success"#
+            .parse()
+            .unwrap();
+        assert_eq!(
+            criteria,
+            SuccessCriteria::default()
+                .rule(200..=200, Some(r#""status":"error""#), false)
+                .rule(202..=202, None, false)
+                .rule(404..=404, None, true)
+        );
+        assert!(criteria.inspects_body(200));
+        assert!(!criteria.inspects_body(201));
+
+        assert!(!criteria.is_success(200, Some(r#"{"status":"error","message":"bad token"}"#)));
+        assert!(criteria.is_success(200, Some(r#"{"status":"ok"}"#)));
+        assert!(criteria.is_success(200, None));
+        assert!(!criteria.is_success(202, None));
+        assert!(criteria.is_success(404, None));
+        assert!(criteria.is_success(201, None));
+        assert!(!criteria.is_success(500, None));
+
+        assert_eq!(
+            "".parse::<SuccessCriteria>(),
+            Ok(SuccessCriteria::default())
+        );
+        assert_eq!(
+            "200~a=b=failure".parse::<SuccessCriteria>(),
+            Ok(SuccessCriteria::default().rule(200..=200, Some("a=b"), false))
+        );
+        assert!("202".parse::<SuccessCriteria>().is_err());
+        assert!("202=retry".parse::<SuccessCriteria>().is_err());
+        assert!("200~=failure".parse::<SuccessCriteria>().is_err());
+        assert!("2xx=success".parse::<SuccessCriteria>().is_err());
+    }
+
+    #[test]
+    fn test_deserialize_retry_policy() {
+        let policy: RetryPolicy = serde_json::from_str(
+            r#"{
+                "backoff_coefficient": 3,
+                "initial_interval": 0.5,
+                "minimum_interval": 1,
+                "maximum_interval": 10,
+                "queue": "webhooks-degraded"
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(policy.retry_interval(1, None), time::Duration::from_secs(1));
+        assert_eq!(
+            policy.retry_interval(2, None),
+            time::Duration::from_millis(1500)
+        );
+        assert_eq!(
+            policy.retry_interval(5, None),
+            time::Duration::from_secs(10)
+        );
+        assert_eq!(policy.retry_queue("webhooks"), "webhooks-degraded");
+
+        let serialized = serde_json::to_value(&policy).unwrap();
+        assert_eq!(
+            serialized,
+            serde_json::json!({
+                "backoff_strategy": "exponential",
+                "backoff_coefficient": 3,
+                "initial_interval": 0.5,
+                "minimum_interval": 1.0,
+                "maximum_interval": 10.0,
+                "queue": "webhooks-degraded"
+            })
+        );
+        let round_tripped: RetryPolicy = serde_json::from_value(serialized.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&round_tripped).unwrap(), serialized);
+    }
+
+    #[test]
+    fn test_deserialize_retry_policies_by_queue() {
+        let policies: std::collections::HashMap<String, RetryPolicy> = serde_json::from_str(
+            r#"{"webhooks": {"maximum_interval": 60}, "webhooks-degraded": {"initial_interval": 30}}"#,
+        )
+        .unwrap();
+        let default_policy = RetryPolicy::default();
+
+        let policy = &policies["webhooks"];
+        assert_eq!(
+            policy.backoff_coefficient,
+            default_policy.backoff_coefficient
+        );
+        assert_eq!(policy.initial_interval, default_policy.initial_interval);
+        assert_eq!(policy.maximum_interval, Some(time::Duration::from_secs(60)));
+        assert_eq!(
+            policies["webhooks-degraded"].retry_interval(1, None),
+            time::Duration::from_secs(30)
+        );
+
+        assert!(serde_json::from_str::<RetryPolicy>(r#"{"initial_interval": -1}"#).is_err());
+    }
 }
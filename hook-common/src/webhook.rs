@@ -4,16 +4,20 @@ use std::fmt;
 use std::str::FromStr;
 
 use serde::{de::Visitor, Deserialize, Serialize};
+use thiserror::Error;
 
 use crate::kafka_messages::app_metrics;
-use crate::pgqueue::PgQueueError;
+use crate::pairs::key_value_pairs;
+use crate::pgqueue::{PgQueueError, Recurrence};
 
 /// Supported HTTP methods for webhooks.
-#[derive(Debug, PartialEq, Clone, Copy)]
+/// POST is the default, as it's what most webhooks use.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
 pub enum HttpMethod {
     DELETE,
     GET,
     PATCH,
+    #[default]
     POST,
     PUT,
 }
@@ -80,6 +84,15 @@ impl<'de> Deserialize<'de> for HttpMethod {
     }
 }
 
+/// Deserialize an `HttpMethod` that may be `null`, defaulting to POST.
+/// Combined with `#[serde(default)]` this also covers a missing method.
+fn deserialize_optional_method<'de, D>(deserializer: D) -> Result<HttpMethod, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(Option::<HttpMethod>::deserialize(deserializer)?.unwrap_or_default())
+}
+
 /// Serialize required to write `HttpMethod` to database.
 impl Serialize for HttpMethod {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -122,12 +135,205 @@ impl From<&HttpMethod> for http::Method {
 /// implement. See: https://github.com/PostHog/plugin-scaffold/blob/main/src/types.ts#L15.
 #[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
 pub struct WebhookJobParameters {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<WebhookAuth>,
     pub body: String,
+    /// URLs to try, in order, if the request to `url` fails with a connection error or a retryable status.
+    /// The job only counts as a failed attempt if the requests to all of these URLs fail too.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallback_urls: Vec<String>,
+    /// URLs the webhook is delivered to as well as `url`, each of them independently of the others. A retry only
+    /// sends the webhook again to the URLs it wasn't delivered to, see `WebhookJobMetadata::delivered_urls`.
+    /// `fallback_urls` only apply to `url`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fan_out_urls: Vec<String>,
     pub headers: collections::HashMap<String, String>,
+    /// The HTTP method of the request, POST if missing or `null`.
+    #[serde(default, deserialize_with = "deserialize_optional_method")]
     pub method: HttpMethod,
+    /// Parts sent as a `multipart/form-data` body instead of `body`, which must be empty if any are set.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub multipart: Vec<MultipartPart>,
+    /// A timeout in milliseconds for the request, overriding the consumer's own request timeout for this job only.
+    /// Consumers may clamp it to a maximum.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
     pub url: String,
 }
 
+/// A part of a `multipart/form-data` webhook body.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+pub struct MultipartPart {
+    /// The name of the form field.
+    pub name: String,
+    pub content: String,
+    /// The file name of a file-style part, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_name: Option<String>,
+    /// The Content-Type of the part. Destinations usually take parts without one as `text/plain`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+}
+
+/// Enumeration of errors found when validating a webhook job before enqueueing it.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ValidationError {
+    #[error("body too large")]
+    BodyTooLarge,
+    #[error("target must not be empty")]
+    EmptyTarget,
+    #[error("{0}")]
+    InvalidRecurrence(String),
+    #[error("{0} is not a valid url")]
+    InvalidUrl(String),
+    #[error("{0} is not a valid header name")]
+    InvalidHeaderName(String),
+    #[error("header {0} has an invalid value")]
+    InvalidHeaderValue(String),
+    #[error("{0} is not a valid number of max attempts")]
+    InvalidMaxAttempts(i64),
+    #[error("a webhook can't have both a body and multipart parts")]
+    MultipartWithBody,
+    #[error("multipart part {0} has an invalid content type")]
+    InvalidPartContentType(String),
+}
+
+impl WebhookJobParameters {
+    /// Check that these parameters can be used to send a webhook, so that a job that's bound to fail is never enqueued.
+    /// URLs may be relative if the job's team has a `base_url` they are resolved against, see `resolve_url`.
+    /// The HTTP method is not checked, as only supported methods can be deserialized into an `HttpMethod`.
+    pub fn validate(&self, base_url: Option<&reqwest::Url>) -> Result<(), ValidationError> {
+        for url in std::iter::once(&self.url)
+            .chain(self.fallback_urls.iter())
+            .chain(self.fan_out_urls.iter())
+        {
+            match resolve_url(url, base_url) {
+                Ok(resolved) if resolved.has_host() => (),
+                _ => return Err(ValidationError::InvalidUrl(url.to_owned())),
+            }
+        }
+
+        for (name, value) in self.headers.iter() {
+            http::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| ValidationError::InvalidHeaderName(name.to_owned()))?;
+            http::HeaderValue::from_str(value)
+                .map_err(|_| ValidationError::InvalidHeaderValue(name.to_owned()))?;
+        }
+
+        if !self.multipart.is_empty() && !self.body.is_empty() {
+            return Err(ValidationError::MultipartWithBody);
+        }
+        for part in self.multipart.iter() {
+            if let Some(content_type) = &part.content_type {
+                content_type
+                    .parse::<mime::Mime>()
+                    .map_err(|_| ValidationError::InvalidPartContentType(part.name.to_owned()))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Base URLs per team id, as parsed from a comma-separated list of `team_id=url` pairs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TenantBaseUrls(pub collections::HashMap<u32, reqwest::Url>);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseTenantBaseUrlsError(String);
+
+impl FromStr for TenantBaseUrls {
+    type Err = ParseTenantBaseUrlsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        key_value_pairs::<u32, reqwest::Url>(s)
+            .collect::<Result<_, _>>()
+            .map(TenantBaseUrls)
+            .map_err(|pair| ParseTenantBaseUrlsError(pair.to_owned()))
+    }
+}
+
+impl TenantBaseUrls {
+    /// Resolve a webhook job's `url` against the base URL of the job's team, if it has one, see `resolve_url`.
+    pub fn resolve(&self, url: &str, team_id: u32) -> Result<reqwest::Url, ResolveUrlError> {
+        resolve_url(url, self.0.get(&team_id))
+    }
+}
+
+/// Enumeration of errors found when resolving a webhook URL to the absolute URL a request is sent to.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ResolveUrlError {
+    #[error("error parsing webhook url")]
+    ParseError(url::ParseError),
+    #[error("{0} is not within the base URL of its team")]
+    EscapedBaseUrl(String),
+}
+
+/// Resolve a webhook `url` to the absolute URL we send a request to.
+/// Absolute URLs are used as they are. Relative URLs are a path appended to `base_url`, keeping the base URL's own
+/// path, and fail to parse if there is no `base_url`.
+/// Relative URLs that leave the base URL's path once their `..` segments are resolved are rejected.
+pub fn resolve_url(
+    url: &str,
+    base_url: Option<&reqwest::Url>,
+) -> Result<reqwest::Url, ResolveUrlError> {
+    match reqwest::Url::parse(url) {
+        Ok(parsed) => Ok(parsed),
+        Err(url::ParseError::RelativeUrlWithoutBase) => {
+            let base_url = base_url.ok_or(ResolveUrlError::ParseError(
+                url::ParseError::RelativeUrlWithoutBase,
+            ))?;
+            let base = base_url.as_str().trim_end_matches('/');
+            let path = url.trim_start_matches('/');
+            let resolved = reqwest::Url::parse(&format!("{}/{}", base, path))
+                .map_err(ResolveUrlError::ParseError)?;
+
+            let base_path = format!("{}/", base_url.path().trim_end_matches('/'));
+            if resolved.origin() != base_url.origin() || !resolved.path().starts_with(&base_path) {
+                return Err(ResolveUrlError::EscapedBaseUrl(url.to_owned()));
+            }
+
+            Ok(resolved)
+        }
+        Err(error) => Err(ResolveUrlError::ParseError(error)),
+    }
+}
+
+/// Check that a webhook job can be attempted at least once with `max_attempts`.
+pub fn validate_max_attempts(max_attempts: i64) -> Result<(), ValidationError> {
+    if max_attempts > 0 && max_attempts <= i32::MAX as i64 {
+        Ok(())
+    } else {
+        Err(ValidationError::InvalidMaxAttempts(max_attempts))
+    }
+}
+
+/// Authentication for a webhook request, converted to an `Authorization` header when the webhook is sent.
+/// Credentials are kept out of `WebhookJobParameters::headers`, so that they can be redacted from `Debug` output.
+#[derive(Deserialize, Serialize, PartialEq, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum WebhookAuth {
+    Bearer { token: String },
+    Basic { username: String, password: String },
+}
+
+/// Implement `std::fmt::Debug` by hand to avoid leaking credentials into logs.
+impl fmt::Debug for WebhookAuth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WebhookAuth::Bearer { .. } => f
+                .debug_struct("Bearer")
+                .field("token", &"<redacted>")
+                .finish(),
+            WebhookAuth::Basic { username, .. } => f
+                .debug_struct("Basic")
+                .field("username", username)
+                .field("password", &"<redacted>")
+                .finish(),
+        }
+    }
+}
+
 /// `JobMetadata` required for the `WebhookConsumer` to execute a webhook.
 /// These should be set if the Webhook is associated with a plugin `composeWebhook` invocation.
 #[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
@@ -135,6 +341,55 @@ pub struct WebhookJobMetadata {
     pub team_id: u32,
     pub plugin_id: u32,
     pub plugin_config_id: u32,
+    /// A condition on the version of the resource the webhook updates, so that chained webhooks are applied in
+    /// order by destinations that support conditional requests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub precondition: Option<WebhookPrecondition>,
+    /// The URLs a webhook with `fan_out_urls` was already delivered to in previous attempts, including `url`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub delivered_urls: Vec<String>,
+    /// How the webhook recurs, e.g. for periodic health pings. Its next occurrence is enqueued once it's completed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recurrence: Option<Recurrence>,
+    /// W3C trace context (`traceparent` and `tracestate`) of whatever enqueued the webhook, so that its delivery is
+    /// traced as part of the same trace.
+    #[serde(default, skip_serializing_if = "collections::HashMap::is_empty")]
+    pub trace_context: collections::HashMap<String, String>,
+}
+
+impl WebhookJobMetadata {
+    /// Check that this metadata can be used to run a webhook, so that a recurring job that would never recur is
+    /// never enqueued.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if let Some(recurrence) = &self.recurrence {
+            recurrence
+                .next_at(chrono::offset::Utc::now())
+                .map_err(|error| ValidationError::InvalidRecurrence(error.to_string()))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A conditional request header sent with a webhook, typically with an ETag captured from a prior response.
+/// Destinations that support them reject the webhook with a 412 if the condition doesn't hold.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WebhookPrecondition {
+    /// Only apply the webhook if the resource's current ETag is `etag`, sent as `If-Match`.
+    IfMatch { etag: String },
+    /// Only apply the webhook if the resource's current ETag isn't `etag`, sent as `If-None-Match`.
+    IfNoneMatch { etag: String },
+}
+
+impl WebhookPrecondition {
+    /// The name and value of the header this precondition is sent as.
+    pub fn header(&self) -> (http::HeaderName, &str) {
+        match self {
+            WebhookPrecondition::IfMatch { etag } => (http::header::IF_MATCH, etag),
+            WebhookPrecondition::IfNoneMatch { etag } => (http::header::IF_NONE_MATCH, etag),
+        }
+    }
 }
 
 /// An error originating during a Webhook Job invocation.
@@ -143,6 +398,109 @@ pub struct WebhookJobMetadata {
 pub struct WebhookJobError {
     pub r#type: app_metrics::ErrorType,
     pub details: app_metrics::ErrorDetails,
+    /// The beginning of the response body returned by the destination, if any, to help debugging failures.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<String>,
+    /// A digest of the response body returned by the destination, kept instead of the body itself if configured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_digest: Option<ResponseDigest>,
+    /// The kind of failure of the request, if the error comes from one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_error_kind: Option<WebhookRequestErrorKind>,
+    /// The status code the destination responded with, if it responded with an error status code.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_code: Option<u16>,
+    /// When the error happened. Errors stored before this was added have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failed_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// The kind of failure of a webhook request.
+/// This is more precise than `app_metrics::ErrorType`, to tell apart transient destination issues (like DNS
+/// failures) from persistent ones (like TLS misconfigurations) when debugging.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookRequestErrorKind {
+    /// The destination's hostname couldn't be resolved.
+    Dns,
+    /// A connection to the destination couldn't be established.
+    Connect,
+    /// The TLS handshake with the destination failed.
+    Tls,
+    /// The request timed out.
+    Timeout,
+    /// The request or response body couldn't be sent or read.
+    Body,
+    /// The destination responded with an error status code.
+    Status,
+    /// Any other error, like a redirect loop.
+    Other,
+}
+
+impl WebhookRequestErrorKind {
+    /// A short name for this kind, used as a metric label.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookRequestErrorKind::Dns => "dns",
+            WebhookRequestErrorKind::Connect => "connect",
+            WebhookRequestErrorKind::Tls => "tls",
+            WebhookRequestErrorKind::Timeout => "timeout",
+            WebhookRequestErrorKind::Body => "body",
+            WebhookRequestErrorKind::Status => "status",
+            WebhookRequestErrorKind::Other => "other",
+        }
+    }
+}
+
+/// An error resolving the hostname of a webhook's URL.
+/// Resolvers return it so that resolution failures can be told apart from other connection errors, which are often
+/// `std::io::Error`s as well.
+#[derive(Error, Debug)]
+#[error("failed to resolve {host}: {error}")]
+pub struct ResolveError {
+    pub host: String,
+    #[source]
+    pub error: std::io::Error,
+}
+
+/// Classify a `reqwest::Error` with the is_* methods provided by reqwest.
+/// Connection errors are told apart by looking at their sources: resolution failures come from a `ResolveError`,
+/// so only clients with a resolver returning them report `Dns` errors, and TLS failures come from `native_tls`.
+impl From<&reqwest::Error> for WebhookRequestErrorKind {
+    fn from(error: &reqwest::Error) -> Self {
+        if error.is_timeout() {
+            WebhookRequestErrorKind::Timeout
+        } else if error.is_status() {
+            WebhookRequestErrorKind::Status
+        } else if error.is_body() || error.is_decode() {
+            WebhookRequestErrorKind::Body
+        } else if error.is_connect() {
+            let mut source = std::error::Error::source(error);
+
+            while let Some(cause) = source {
+                if cause.is::<native_tls::Error>() {
+                    return WebhookRequestErrorKind::Tls;
+                }
+                if cause.is::<ResolveError>() {
+                    return WebhookRequestErrorKind::Dns;
+                }
+                source = cause.source();
+            }
+
+            WebhookRequestErrorKind::Connect
+        } else {
+            WebhookRequestErrorKind::Other
+        }
+    }
+}
+
+/// A digest of a response body, enabling change-detection and integrity checks without storing the body.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
+pub struct ResponseDigest {
+    /// The hex-encoded SHA-256 digest of the whole body.
+    pub sha256: String,
+    /// The length of the whole body in bytes.
+    pub length: u64,
 }
 
 /// Webhook jobs boil down to an HTTP request, so it's useful to have a way to convert from &reqwest::Error.
@@ -150,7 +508,7 @@ pub struct WebhookJobError {
 /// Some precision may be lost as our app_metrics::ErrorType does not support the same number of variants.
 impl From<&reqwest::Error> for WebhookJobError {
     fn from(error: &reqwest::Error) -> Self {
-        if error.is_timeout() {
+        let webhook_job_error = if error.is_timeout() {
             WebhookJobError::new_timeout(&error.to_string())
         } else if error.is_status() {
             WebhookJobError::new_http_status(
@@ -162,7 +520,9 @@ impl From<&reqwest::Error> for WebhookJobError {
             // Not all of `reqwest::Error` may strictly be connection errors, so our supported error types may need an extension
             // depending on how strict error reporting has to be.
             WebhookJobError::new_connection(&error.to_string())
-        }
+        };
+
+        webhook_job_error.with_request_error_kind(Some(error.into()))
     }
 }
 
@@ -178,6 +538,11 @@ impl WebhookJobError {
             details: app_metrics::ErrorDetails {
                 error: error_details,
             },
+            response: None,
+            response_digest: None,
+            request_error_kind: None,
+            status_code: None,
+            failed_at: Some(chrono::Utc::now()),
         }
     }
 
@@ -192,6 +557,11 @@ impl WebhookJobError {
             details: app_metrics::ErrorDetails {
                 error: error_details,
             },
+            response: None,
+            response_digest: None,
+            request_error_kind: None,
+            status_code: None,
+            failed_at: Some(chrono::Utc::now()),
         }
     }
 
@@ -206,9 +576,35 @@ impl WebhookJobError {
             details: app_metrics::ErrorDetails {
                 error: error_details,
             },
+            response: None,
+            response_digest: None,
+            request_error_kind: None,
+            status_code: Some(status_code),
+            failed_at: Some(chrono::Utc::now()),
         }
     }
 
+    /// Attach the (possibly truncated) response body returned by the destination to this error.
+    pub fn with_response(mut self, response: Option<String>) -> Self {
+        self.response = response;
+        self
+    }
+
+    /// Attach the kind of failure of the request to this error.
+    pub fn with_request_error_kind(
+        mut self,
+        request_error_kind: Option<WebhookRequestErrorKind>,
+    ) -> Self {
+        self.request_error_kind = request_error_kind;
+        self
+    }
+
+    /// Attach a digest of the response body returned by the destination to this error.
+    pub fn with_response_digest(mut self, response_digest: Option<ResponseDigest>) -> Self {
+        self.response_digest = response_digest;
+        self
+    }
+
     pub fn new_parse(message: &str) -> Self {
         let error_details = app_metrics::Error {
             name: "Parse Error".to_owned(),
@@ -220,6 +616,416 @@ impl WebhookJobError {
             details: app_metrics::ErrorDetails {
                 error: error_details,
             },
+            response: None,
+            response_digest: None,
+            request_error_kind: None,
+            status_code: None,
+            failed_at: Some(chrono::Utc::now()),
         }
     }
+
+    pub fn new_expired(message: &str) -> Self {
+        let error_details = app_metrics::Error {
+            name: "Expired Error".to_owned(),
+            message: Some(message.to_owned()),
+            stack: None,
+        };
+        Self {
+            r#type: app_metrics::ErrorType::ExpiredError,
+            details: app_metrics::ErrorDetails {
+                error: error_details,
+            },
+            response: None,
+            response_digest: None,
+            request_error_kind: None,
+            status_code: None,
+            failed_at: Some(chrono::Utc::now()),
+        }
+    }
+
+    /// Build the error of a job that failed because of a bug of ours, like its processing panicking.
+    pub fn new_internal(message: &str) -> Self {
+        let error_details = app_metrics::Error {
+            name: "Internal Error".to_owned(),
+            message: Some(message.to_owned()),
+            stack: None,
+        };
+        Self {
+            r#type: app_metrics::ErrorType::InternalError,
+            details: app_metrics::ErrorDetails {
+                error: error_details,
+            },
+            response: None,
+            response_digest: None,
+            request_error_kind: None,
+            status_code: None,
+            failed_at: Some(chrono::Utc::now()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_webhook_auth_debug_is_redacted() {
+        let bearer = WebhookAuth::Bearer {
+            token: "a-secret-token".to_owned(),
+        };
+        let basic = WebhookAuth::Basic {
+            username: "user".to_owned(),
+            password: "a-secret-password".to_owned(),
+        };
+
+        assert_eq!(format!("{:?}", bearer), r#"Bearer { token: "<redacted>" }"#);
+        assert_eq!(
+            format!("{:?}", basic),
+            r#"Basic { username: "user", password: "<redacted>" }"#
+        );
+
+        let parameters = WebhookJobParameters {
+            auth: Some(bearer),
+            body: "".to_owned(),
+            fallback_urls: Vec::new(),
+            fan_out_urls: Vec::new(),
+            headers: collections::HashMap::new(),
+            method: HttpMethod::POST,
+            multipart: Vec::new(),
+            timeout_ms: None,
+            url: "http://example.com".to_owned(),
+        };
+        assert!(!format!("{:?}", parameters).contains("a-secret-token"));
+    }
+
+    fn valid_parameters() -> WebhookJobParameters {
+        WebhookJobParameters {
+            auth: None,
+            body: "".to_owned(),
+            fallback_urls: vec!["https://fallback.example.com/hook".to_owned()],
+            fan_out_urls: Vec::new(),
+            headers: collections::HashMap::from([(
+                "Content-Type".to_owned(),
+                "application/json".to_owned(),
+            )]),
+            method: HttpMethod::POST,
+            multipart: Vec::new(),
+            timeout_ms: None,
+            url: "https://example.com/hook".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_validate_valid_parameters() {
+        assert_eq!(valid_parameters().validate(None), Ok(()));
+        assert_eq!(validate_max_attempts(1), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_invalid_url() {
+        for url in ["", "invalid", "mailto:someone@example.com"] {
+            let mut parameters = valid_parameters();
+            parameters.url = url.to_owned();
+
+            assert_eq!(
+                parameters.validate(None),
+                Err(ValidationError::InvalidUrl(url.to_owned()))
+            );
+        }
+
+        let mut parameters = valid_parameters();
+        parameters.fallback_urls.push("invalid".to_owned());
+        assert_eq!(
+            parameters.validate(None),
+            Err(ValidationError::InvalidUrl("invalid".to_owned()))
+        );
+
+        let mut parameters = valid_parameters();
+        parameters.fan_out_urls.push("invalid".to_owned());
+        assert_eq!(
+            parameters.validate(None),
+            Err(ValidationError::InvalidUrl("invalid".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_validate_relative_url() {
+        let base_url = reqwest::Url::parse("https://tenant.example.com/hooks/").unwrap();
+        let mut parameters = valid_parameters();
+        parameters.url = "/events".to_owned();
+        parameters.fallback_urls.push("fallback".to_owned());
+
+        assert_eq!(parameters.validate(Some(&base_url)), Ok(()));
+        assert_eq!(
+            parameters.validate(None),
+            Err(ValidationError::InvalidUrl("/events".to_owned()))
+        );
+
+        parameters.url = "../admin".to_owned();
+        assert_eq!(
+            parameters.validate(Some(&base_url)),
+            Err(ValidationError::InvalidUrl("../admin".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_parse_tenant_base_urls() {
+        assert_eq!("".parse::<TenantBaseUrls>(), Ok(TenantBaseUrls::default()));
+        assert!("1".parse::<TenantBaseUrls>().is_err());
+        assert!("team=https://example.com"
+            .parse::<TenantBaseUrls>()
+            .is_err());
+        assert!("1=not a url".parse::<TenantBaseUrls>().is_err());
+    }
+
+    #[test]
+    fn test_validate_invalid_headers() {
+        let mut parameters = valid_parameters();
+        parameters
+            .headers
+            .insert("Not A Token".to_owned(), "value".to_owned());
+        assert_eq!(
+            parameters.validate(None),
+            Err(ValidationError::InvalidHeaderName("Not A Token".to_owned()))
+        );
+
+        let mut parameters = valid_parameters();
+        parameters
+            .headers
+            .insert("X-Header".to_owned(), "line\nbreak".to_owned());
+        assert_eq!(
+            parameters.validate(None),
+            Err(ValidationError::InvalidHeaderValue("X-Header".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_validate_multipart_parts() {
+        let part = MultipartPart {
+            name: "file".to_owned(),
+            content: "a,b".to_owned(),
+            file_name: Some("export.csv".to_owned()),
+            content_type: Some("text/csv".to_owned()),
+        };
+
+        let mut parameters = valid_parameters();
+        parameters.multipart.push(part.clone());
+        assert_eq!(parameters.validate(None), Ok(()));
+
+        let mut parameters = valid_parameters();
+        parameters.body = "a body".to_owned();
+        parameters.multipart.push(part.clone());
+        assert_eq!(
+            parameters.validate(None),
+            Err(ValidationError::MultipartWithBody)
+        );
+
+        let mut parameters = valid_parameters();
+        parameters.multipart.push(MultipartPart {
+            content_type: Some("not a content type".to_owned()),
+            ..part
+        });
+        assert_eq!(
+            parameters.validate(None),
+            Err(ValidationError::InvalidPartContentType("file".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_validate_invalid_method() {
+        let parameters = serde_json::from_str::<WebhookJobParameters>(
+            r#"{"body": "", "headers": {}, "method": "TRACE", "url": "http://example.com"}"#,
+        );
+        assert!(parameters.is_err());
+    }
+
+    #[test]
+    fn test_validate_invalid_max_attempts() {
+        assert_eq!(
+            validate_max_attempts(0),
+            Err(ValidationError::InvalidMaxAttempts(0))
+        );
+        assert_eq!(
+            validate_max_attempts(-1),
+            Err(ValidationError::InvalidMaxAttempts(-1))
+        );
+        assert!(validate_max_attempts(i32::MAX as i64 + 1).is_err());
+    }
+
+    #[test]
+    fn test_webhook_auth_deserialize() {
+        let auth: WebhookAuth =
+            serde_json::from_str(r#"{"type": "bearer", "token": "a-token"}"#).unwrap();
+        assert_eq!(
+            auth,
+            WebhookAuth::Bearer {
+                token: "a-token".to_owned()
+            }
+        );
+
+        let parameters: WebhookJobParameters = serde_json::from_str(
+            r#"{"body": "", "headers": {}, "method": "POST", "url": "http://example.com"}"#,
+        )
+        .unwrap();
+        assert_eq!(parameters.auth, None);
+    }
+
+    #[test]
+    fn test_webhook_precondition_deserialize() {
+        let metadata: WebhookJobMetadata = serde_json::from_str(
+            r#"{"team_id": 1, "plugin_id": 2, "plugin_config_id": 3, "precondition": {"type": "if_match", "etag": "\"v3\""}}"#,
+        )
+        .unwrap();
+        let precondition = metadata.precondition.expect("precondition is missing");
+        assert_eq!(
+            precondition,
+            WebhookPrecondition::IfMatch {
+                etag: r#""v3""#.to_owned()
+            }
+        );
+        assert_eq!(precondition.header(), (http::header::IF_MATCH, r#""v3""#));
+
+        let metadata: WebhookJobMetadata =
+            serde_json::from_str(r#"{"team_id": 1, "plugin_id": 2, "plugin_config_id": 3}"#)
+                .unwrap();
+        assert_eq!(metadata.precondition, None);
+        assert_eq!(
+            serde_json::to_value(&metadata).unwrap(),
+            serde_json::json!({"team_id": 1, "plugin_id": 2, "plugin_config_id": 3})
+        );
+    }
+
+    #[test]
+    fn test_method_defaults_to_post() {
+        let parameters: WebhookJobParameters =
+            serde_json::from_str(r#"{"body": "", "headers": {}, "url": "http://example.com"}"#)
+                .unwrap();
+        assert_eq!(parameters.method, HttpMethod::POST);
+
+        let parameters: WebhookJobParameters = serde_json::from_str(
+            r#"{"body": "", "headers": {}, "method": null, "url": "http://example.com"}"#,
+        )
+        .unwrap();
+        assert_eq!(parameters.method, HttpMethod::POST);
+
+        let parameters: WebhookJobParameters = serde_json::from_str(
+            r#"{"body": "", "headers": {}, "method": "patch", "url": "http://example.com"}"#,
+        )
+        .unwrap();
+        assert_eq!(parameters.method, HttpMethod::PATCH);
+    }
+
+    /// Serve `response` as raw bytes to every connection on a random local port, returning its address.
+    /// Connections are closed right after if not `keep_open`, so that clients can't wait for anything missing.
+    async fn serve_raw(response: &'static [u8], keep_open: bool) -> std::net::SocketAddr {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut request = [0; 4096];
+                    let _ = stream.read(&mut request).await;
+                    let _ = stream.write_all(response).await;
+                    if keep_open {
+                        tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+                    }
+                });
+            }
+        });
+
+        addr
+    }
+
+    /// A resolver that never resolves any hostname.
+    struct FailingResolver;
+
+    impl reqwest::dns::Resolve for FailingResolver {
+        fn resolve(&self, name: hyper::client::connect::dns::Name) -> reqwest::dns::Resolving {
+            let error = ResolveError {
+                host: name.as_str().to_owned(),
+                error: std::io::Error::new(std::io::ErrorKind::NotFound, "no such host"),
+            };
+            Box::pin(async move { Err(error.into()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_error_kind() {
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_millis(200))
+            .dns_resolver(std::sync::Arc::new(FailingResolver))
+            .build()
+            .unwrap();
+        let kind = |error: reqwest::Error| WebhookRequestErrorKind::from(&error);
+
+        let error = client
+            .get("http://webhooks.example.com/")
+            .send()
+            .await
+            .unwrap_err();
+        assert_eq!(kind(error), WebhookRequestErrorKind::Dns);
+
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let error = client
+            .get(format!("http://{}/", closed))
+            .send()
+            .await
+            .unwrap_err();
+        assert_eq!(kind(error), WebhookRequestErrorKind::Connect);
+
+        let incomplete = b"HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\nhello";
+        let ok = serve_raw(incomplete, true).await;
+        let error = client
+            .get(format!("https://{}/", ok))
+            .send()
+            .await
+            .unwrap_err();
+        assert_eq!(kind(error), WebhookRequestErrorKind::Tls);
+
+        let response = client.get(format!("http://{}/", ok)).send().await.unwrap();
+        let error = response.bytes().await.unwrap_err();
+        assert_eq!(kind(error), WebhookRequestErrorKind::Timeout);
+
+        let hanging = serve_raw(b"", true).await;
+        let error = client
+            .get(format!("http://{}/", hanging))
+            .send()
+            .await
+            .unwrap_err();
+        assert_eq!(kind(error), WebhookRequestErrorKind::Timeout);
+
+        let truncated = serve_raw(incomplete, false).await;
+        let response = client
+            .get(format!("http://{}/", truncated))
+            .send()
+            .await
+            .unwrap();
+        let error = response.bytes().await.unwrap_err();
+        assert_eq!(kind(error), WebhookRequestErrorKind::Body);
+
+        let status = serve_raw(
+            b"HTTP/1.1 500 Internal Server Error\r\ncontent-length: 0\r\n\r\n",
+            false,
+        )
+        .await;
+        let error = client
+            .get(format!("http://{}/", status))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap_err();
+        assert_eq!(kind(error), WebhookRequestErrorKind::Status);
+
+        let error = client.get("http://").send().await.unwrap_err();
+        assert_eq!(kind(error), WebhookRequestErrorKind::Other);
+    }
 }
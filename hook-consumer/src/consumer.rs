@@ -1,24 +1,47 @@
+use std::borrow::Cow;
 use std::collections;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time;
 
+use futures::FutureExt;
 use hook_common::{
-    pgqueue::{Job, PgJob, PgJobError, PgQueue, PgQueueError, PgQueueJob, PgTransactionJob},
-    retry::RetryPolicy,
-    webhook::{HttpMethod, WebhookJobError, WebhookJobMetadata, WebhookJobParameters},
+    pairs::key_value_pairs,
+    pgqueue::{
+        Job, JobResponse, PgJob, PgJobError, PgQueue, PgQueueJob, PgQueueListener, PgTransactionJob,
+    },
+    retry::{RetryAction, RetryClassifier, RetryPolicy, SuccessCriteria},
+    webhook::{
+        MultipartPart, ResolveUrlError, ResponseDigest, TenantBaseUrls, WebhookAuth,
+        WebhookJobError, WebhookJobMetadata, WebhookJobParameters, WebhookPrecondition,
+    },
 };
 use http::StatusCode;
 use reqwest::header;
+use sha2::{Digest, Sha256};
 use tokio::sync;
+use tracing::{debug, error, info, warn, Instrument};
 
-use crate::error::{ConsumerError, WebhookError};
+use crate::dns::SystemResolver;
+use crate::error::{
+    CapturedResponse, ClientIdentityError, ConsumerError, DisallowedRedirectError, ResponsePhase,
+    TooManyRedirectsError, WebhookError,
+};
 
 /// A WebhookJob is any `PgQueueJob` with `WebhookJobParameters` and `WebhookJobMetadata`.
 trait WebhookJob: PgQueueJob + std::marker::Send {
+    /// Whether the job was dequeued in a transaction, which is rolled back if the job is dropped unfinalized.
+    const TRANSACTIONAL: bool;
+
     fn parameters(&self) -> &WebhookJobParameters;
     fn metadata(&self) -> &WebhookJobMetadata;
     fn job(&self) -> &Job<WebhookJobParameters, WebhookJobMetadata>;
 
+    fn id(&self) -> i64 {
+        self.job().id
+    }
+
     fn attempt(&self) -> i32 {
         self.job().attempt
     }
@@ -33,6 +56,8 @@ trait WebhookJob: PgQueueJob + std::marker::Send {
 }
 
 impl WebhookJob for PgTransactionJob<'_, WebhookJobParameters, WebhookJobMetadata> {
+    const TRANSACTIONAL: bool = true;
+
     fn parameters(&self) -> &WebhookJobParameters {
         &self.job.parameters
     }
@@ -47,6 +72,8 @@ impl WebhookJob for PgTransactionJob<'_, WebhookJobParameters, WebhookJobMetadat
 }
 
 impl WebhookJob for PgJob<WebhookJobParameters, WebhookJobMetadata> {
+    const TRANSACTIONAL: bool = false;
+
     fn parameters(&self) -> &WebhookJobParameters {
         &self.job.parameters
     }
@@ -60,504 +87,6870 @@ impl WebhookJob for PgJob<WebhookJobParameters, WebhookJobMetadata> {
     }
 }
 
-/// A consumer to poll `PgQueue` and spawn tasks to process webhooks when a job becomes available.
-pub struct WebhookConsumer<'p> {
-    /// An identifier for this consumer. Used to mark jobs we have consumed.
-    name: String,
-    /// The queue we will be dequeuing jobs from.
-    queue: &'p PgQueue,
-    /// The interval for polling the queue.
-    poll_interval: time::Duration,
-    /// The client used for HTTP requests.
-    client: reqwest::Client,
-    /// Maximum number of concurrent jobs being processed.
-    max_concurrent_jobs: usize,
-    /// The retry policy used to calculate retry intervals when a job fails with a retryable error.
-    retry_policy: RetryPolicy,
+/// Options that control how the HTTP request of every webhook job is built and its response handled.
+#[derive(Clone, Debug)]
+pub struct RequestOptions {
+    /// A header used to send an idempotency key derived from the job's id on every attempt.
+    /// No idempotency key is sent if `None`.
+    pub idempotency_key_header: Option<String>,
+    /// The maximum number of bytes of an error response body to store with a job's errors.
+    /// Successful response bodies read within `response_body_timeout` are capped to the same size.
+    pub max_response_body_size: usize,
+    /// What to keep of an error response body.
+    pub response_capture: ResponseCapture,
+    /// How long to wait for the response headers, to fail fast on a destination that hangs.
+    pub response_headers_timeout: Option<time::Duration>,
+    /// How long to wait for the whole response body once headers are received, to tolerate large but slow bodies.
+    pub response_body_timeout: Option<time::Duration>,
+    /// Base URLs that relative webhook URLs are resolved against, keyed by the team id in the job's metadata.
+    pub tenant_base_urls: TenantBaseUrls,
+    /// Decides, by status code, whether a job is retried after its destination responds with an error.
+    pub retry_classifier: RetryClassifier,
+    /// Decides, by status code and body, whether a response means the webhook was delivered. Responses that don't
+    /// are classified by `retry_classifier`.
+    /// Used for jobs in any queue without criteria in `queue_success_criteria`.
+    pub success_criteria: SuccessCriteria,
+    /// Success criteria to use instead of `success_criteria` for jobs in specific queues, keyed by queue name.
+    pub queue_success_criteria: collections::HashMap<String, SuccessCriteria>,
+    /// Headers sent with every webhook request, unless the job sets a header with the same name.
+    pub default_headers: DefaultHeaders,
+    /// Fields of the job's metadata sent as headers with every webhook request.
+    pub metadata_headers: MetadataHeaders,
+    /// The hosts webhooks can be sent to. Webhooks to any other host are failed without sending them.
+    pub allowed_hosts: AllowedHosts,
+    /// The maximum request timeout a job can set in its `timeout_ms`. Higher timeouts are clamped to this one.
+    /// Not clamped if `None`.
+    pub max_request_timeout: Option<time::Duration>,
+    /// A response header whose value is stored with completed jobs as the id of the destination's response.
+    /// Only the response status is stored if `None`.
+    pub response_id_header: Option<String>,
+    /// The Content-Type sent with webhooks whose body is a JSON object or array, if the job doesn't set one.
+    /// No Content-Type is added if `None`.
+    pub json_content_type: Option<String>,
+    /// The maximum size in bytes of a webhook body, as sent: multipart bodies count their boundaries and part
+    /// headers too. Webhooks with larger bodies are failed without sending them. Not limited if `None`.
+    pub max_body_bytes: Option<usize>,
+    /// Timeouts adapted to how fast each host responds, lowering the timeout of requests to hosts that usually
+    /// respond fast. Requests use the job's or the client's timeout if `None`.
+    pub adaptive_timeouts: Option<Arc<AdaptiveTimeouts>>,
 }
 
-impl<'p> WebhookConsumer<'p> {
-    pub fn new(
-        name: &str,
-        queue: &'p PgQueue,
-        poll_interval: time::Duration,
-        request_timeout: time::Duration,
-        max_concurrent_jobs: usize,
-        retry_policy: RetryPolicy,
-    ) -> Self {
-        let mut headers = header::HeaderMap::new();
-        headers.insert(
-            header::CONTENT_TYPE,
-            header::HeaderValue::from_static("application/json"),
-        );
-
-        let client = reqwest::Client::builder()
-            .default_headers(headers)
-            .timeout(request_timeout)
-            .build()
-            .expect("failed to construct reqwest client for webhook consumer");
-
+impl Default for RequestOptions {
+    fn default() -> Self {
         Self {
-            name: name.to_owned(),
-            queue,
-            poll_interval,
-            client,
-            max_concurrent_jobs,
-            retry_policy,
+            idempotency_key_header: None,
+            max_response_body_size: 1024,
+            response_capture: ResponseCapture::default(),
+            response_headers_timeout: None,
+            response_body_timeout: None,
+            tenant_base_urls: TenantBaseUrls::default(),
+            retry_classifier: RetryClassifier::default(),
+            success_criteria: SuccessCriteria::default(),
+            queue_success_criteria: collections::HashMap::new(),
+            default_headers: DefaultHeaders::default(),
+            metadata_headers: MetadataHeaders::default(),
+            allowed_hosts: AllowedHosts::default(),
+            max_request_timeout: None,
+            response_id_header: None,
+            json_content_type: Some("application/json".to_owned()),
+            max_body_bytes: None,
+            adaptive_timeouts: None,
         }
     }
+}
 
-    /// Wait until a job becomes available in our queue.
-    async fn wait_for_job<'a>(
-        &self,
-    ) -> Result<PgJob<WebhookJobParameters, WebhookJobMetadata>, ConsumerError> {
-        let mut interval = tokio::time::interval(self.poll_interval);
+/// The modes to capture an error response body with, to store it with a job's errors.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResponseCapture {
+    /// Keep the beginning of the body, up to `RequestOptions::max_response_body_size` bytes.
+    #[default]
+    Body,
+    /// Keep only a SHA-256 digest and the length of the body, computed at constant memory.
+    Digest,
+}
 
-        loop {
-            interval.tick().await;
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseResponseCaptureError(String);
 
-            if let Some(job) = self.queue.dequeue(&self.name).await? {
-                return Ok(job);
-            }
+impl FromStr for ResponseCapture {
+    type Err = ParseResponseCaptureError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_ref() {
+            "body" => Ok(ResponseCapture::Body),
+            "digest" => Ok(ResponseCapture::Digest),
+            invalid => Err(ParseResponseCaptureError(invalid.to_owned())),
         }
     }
+}
 
-    /// Wait until a job becomes available in our queue in transactional mode.
-    async fn wait_for_job_tx<'a>(
+/// How webhook requests follow redirects to other URLs, as parsed from `none` or `limited(N)`.
+/// Webhooks don't follow redirects by default, as a redirect could send them on to any host.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RedirectPolicy {
+    /// Don't follow redirects. Redirect responses fail the job, unless the `RetryClassifier` says otherwise.
+    #[default]
+    None,
+    /// Follow up to this many redirects. Requests redirected more times than this fail the job.
+    Limited(usize),
+}
+
+impl RedirectPolicy {
+    /// Set a `reqwest::ClientBuilder` to follow redirects according to this policy.
+    /// Every redirect is checked against `allowed_hosts` before it's followed, like the webhook's own URL, so that an
+    /// allowed destination can't redirect webhooks on to any other host. Redirects to absolute URLs are resolved by
+    /// reqwest, so tenant base URLs don't apply to them.
+    pub fn apply(
         &self,
-    ) -> Result<PgTransactionJob<'a, WebhookJobParameters, WebhookJobMetadata>, ConsumerError> {
-        let mut interval = tokio::time::interval(self.poll_interval);
+        builder: reqwest::ClientBuilder,
+        allowed_hosts: &AllowedHosts,
+    ) -> reqwest::ClientBuilder {
+        match *self {
+            RedirectPolicy::None => builder.redirect(reqwest::redirect::Policy::none()),
+            RedirectPolicy::Limited(max) => {
+                let allowed_hosts = allowed_hosts.clone();
 
-        loop {
-            interval.tick().await;
+                builder.redirect(reqwest::redirect::Policy::custom(move |attempt| {
+                    // The original request counts as a previous one too.
+                    if attempt.previous().len() > max {
+                        return attempt.error(TooManyRedirectsError(max));
+                    }
 
-            if let Some(job) = self.queue.dequeue_tx(&self.name).await? {
-                return Ok(job);
+                    match attempt.url().host_str() {
+                        Some(host) if !allowed_hosts.allows(host) => {
+                            let host = host.to_owned();
+                            attempt.error(DisallowedRedirectError(host))
+                        }
+                        _ => attempt.follow(),
+                    }
+                }))
             }
         }
     }
+}
 
-    /// Run this consumer to continuously process any jobs that become available.
-    pub async fn run(&self, transactional: bool) -> Result<(), ConsumerError> {
-        let semaphore = Arc::new(sync::Semaphore::new(self.max_concurrent_jobs));
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseRedirectPolicyError(String);
 
-        if transactional {
-            loop {
-                let webhook_job = self.wait_for_job_tx().await?;
-                spawn_webhook_job_processing_task(
-                    self.client.clone(),
-                    semaphore.clone(),
-                    self.retry_policy.clone(),
-                    webhook_job,
-                )
-                .await;
-            }
-        } else {
-            loop {
-                let webhook_job = self.wait_for_job().await?;
+impl FromStr for RedirectPolicy {
+    type Err = ParseRedirectPolicyError;
 
-                spawn_webhook_job_processing_task(
-                    self.client.clone(),
-                    semaphore.clone(),
-                    self.retry_policy.clone(),
-                    webhook_job,
-                )
-                .await;
-            }
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let policy = s.trim().to_ascii_lowercase();
+
+        if policy == "none" {
+            return Ok(RedirectPolicy::None);
         }
+
+        policy
+            .strip_prefix("limited(")
+            .and_then(|max| max.strip_suffix(')'))
+            .and_then(|max| max.trim().parse::<usize>().ok())
+            .map(RedirectPolicy::Limited)
+            .ok_or(ParseRedirectPolicyError(policy))
     }
 }
 
-/// Spawn a Tokio task to process a Webhook Job once we successfully acquire a permit.
-///
-/// # Arguments
-///
-/// * `client`: An HTTP client to execute the webhook job request.
-/// * `semaphore`: A semaphore used for rate limiting purposes. This function will panic if this semaphore is closed.
-/// * `retry_policy`: The retry policy used to set retry parameters if a job fails and has remaining attempts.
-/// * `webhook_job`: The webhook job to process as dequeued from `hook_common::pgqueue::PgQueue`.
-async fn spawn_webhook_job_processing_task<W: WebhookJob + 'static>(
-    client: reqwest::Client,
-    semaphore: Arc<sync::Semaphore>,
-    retry_policy: RetryPolicy,
-    webhook_job: W,
-) -> tokio::task::JoinHandle<Result<(), ConsumerError>> {
-    let permit = semaphore
-        .acquire_owned()
-        .await
-        .expect("semaphore has been closed");
+/// Headers to send with every request, as parsed from a comma-separated list of `name=value` pairs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DefaultHeaders(pub collections::HashMap<String, String>);
 
-    let labels = [
-        ("queue", webhook_job.queue()),
-        ("target", webhook_job.target()),
-    ];
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseDefaultHeadersError(String);
 
-    metrics::increment_counter!("webhook_jobs_total", &labels);
+impl FromStr for DefaultHeaders {
+    type Err = ParseDefaultHeadersError;
 
-    tokio::spawn(async move {
-        let result = process_webhook_job(client, webhook_job, &retry_policy).await;
-        drop(permit);
-        result
-    })
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Values are parsed as a `HeaderValue` to validate them, which only allows visible ASCII, so they convert back
+        // to a string losslessly.
+        key_value_pairs::<header::HeaderName, header::HeaderValue>(s)
+            .map(|pair| {
+                pair.map(|(name, value)| {
+                    (
+                        name.to_string(),
+                        String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                    )
+                })
+            })
+            .collect::<Result<_, _>>()
+            .map(DefaultHeaders)
+            .map_err(|pair| ParseDefaultHeadersError(pair.to_owned()))
+    }
 }
 
-/// Process a webhook job by transitioning it to its appropriate state after its request is sent.
-/// After we finish, the webhook job will be set as completed (if the request was successful), retryable (if the request
-/// was unsuccessful but we can still attempt a retry), or failed (if the request was unsuccessful and no more retries
-/// may be attempted).
-///
-/// A webhook job is considered retryable after a failing request if:
-/// 1. The job has attempts remaining (i.e. hasn't reached `max_attempts`), and...
-/// 2. The status code indicates retrying at a later point could resolve the issue. This means: 429 and any 5XX.
-///
-/// # Arguments
-///
-/// * `client`: An HTTP client to execute the webhook job request.
-/// * `webhook_job`: The webhook job to process as dequeued from `hook_common::pgqueue::PgQueue`.
-/// * `retry_policy`: The retry policy used to set retry parameters if a job fails and has remaining attempts.
-async fn process_webhook_job<W: WebhookJob>(
-    client: reqwest::Client,
-    webhook_job: W,
-    retry_policy: &RetryPolicy,
-) -> Result<(), ConsumerError> {
-    let parameters = webhook_job.parameters();
+/// Fields of a job's metadata to send as headers, as parsed from a comma-separated list of `field=name` pairs, like
+/// `team_id=X-PostHog-Team-Id`, so that destinations get context without it being duplicated into the parameters.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetadataHeaders(pub Vec<(String, header::HeaderName)>);
 
-    let labels = [
-        ("queue", webhook_job.queue()),
-        ("target", webhook_job.target()),
-    ];
+impl MetadataHeaders {
+    /// Add the headers mapped from fields of `metadata` to `headers`. Any header with the same name set by the job is
+    /// replaced, so that a job can't claim to belong to another team. Fields that are missing, that aren't a string,
+    /// a number, or a boolean, or whose value isn't a valid header value are skipped.
+    pub fn apply(
+        &self,
+        metadata: &WebhookJobMetadata,
+        headers: &mut collections::HashMap<String, String>,
+    ) {
+        if self.0.is_empty() {
+            return;
+        }
+        let Ok(serde_json::Value::Object(fields)) = serde_json::to_value(metadata) else {
+            return;
+        };
 
-    let now = tokio::time::Instant::now();
+        for (field, name) in &self.0 {
+            let value = match fields.get(field) {
+                Some(serde_json::Value::String(value)) => value.to_owned(),
+                Some(value @ (serde_json::Value::Number(_) | serde_json::Value::Bool(_))) => {
+                    value.to_string()
+                }
+                _ => continue,
+            };
+            if header::HeaderValue::from_str(&value).is_err() {
+                warn!(field, header = %name, "metadata field is not a valid header value");
+                continue;
+            }
 
-    let send_result = send_webhook(
-        client,
-        &parameters.method,
-        &parameters.url,
-        &parameters.headers,
-        parameters.body.clone(),
-    )
-    .await;
+            headers.retain(|key, _| !key.eq_ignore_ascii_case(name.as_str()));
+            headers.insert(name.to_string(), value);
+        }
+    }
+}
 
-    let elapsed = now.elapsed().as_secs_f64();
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseMetadataHeadersError(String);
 
-    match send_result {
-        Ok(_) => {
-            webhook_job
-                .complete()
-                .await
-                .map_err(|error| ConsumerError::PgJobError(error.to_string()))?;
+impl FromStr for MetadataHeaders {
+    type Err = ParseMetadataHeadersError;
 
-            metrics::increment_counter!("webhook_jobs_completed", &labels);
-            metrics::histogram!("webhook_jobs_processing_duration_seconds", elapsed, &labels);
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        key_value_pairs::<String, header::HeaderName>(s)
+            .collect::<Result<_, _>>()
+            .map(MetadataHeaders)
+            .map_err(|pair| ParseMetadataHeadersError(pair.to_owned()))
+    }
+}
 
-            Ok(())
-        }
-        Err(WebhookError::ParseHeadersError(e)) => {
-            webhook_job
-                .fail(WebhookJobError::new_parse(&e.to_string()))
-                .await
-                .map_err(|job_error| ConsumerError::PgJobError(job_error.to_string()))?;
+/// A Content-Type, validated as a MIME type. Empty if `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContentType(pub Option<String>);
 
-            metrics::increment_counter!("webhook_jobs_failed", &labels);
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseContentTypeError(String);
 
-            Ok(())
-        }
-        Err(WebhookError::ParseHttpMethodError(e)) => {
-            webhook_job
-                .fail(WebhookJobError::new_parse(&e))
-                .await
-                .map_err(|job_error| ConsumerError::PgJobError(job_error.to_string()))?;
+impl FromStr for ContentType {
+    type Err = ParseContentTypeError;
 
-            metrics::increment_counter!("webhook_jobs_failed", &labels);
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "" => Ok(ContentType(None)),
+            content_type => {
+                content_type
+                    .parse::<mime::Mime>()
+                    .map_err(|_| ParseContentTypeError(content_type.to_owned()))?;
 
-            Ok(())
+                Ok(ContentType(Some(content_type.to_owned())))
+            }
         }
-        Err(WebhookError::ParseUrlError(e)) => {
-            webhook_job
-                .fail(WebhookJobError::new_parse(&e.to_string()))
-                .await
-                .map_err(|job_error| ConsumerError::PgJobError(job_error.to_string()))?;
+    }
+}
 
-            metrics::increment_counter!("webhook_jobs_failed", &labels);
+/// Domains that webhooks can be sent to, as parsed from a comma-separated list.
+/// A domain allows itself and all of its subdomains. All hosts are allowed if there are no domains.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AllowedHosts(pub Vec<String>);
 
-            Ok(())
-        }
-        Err(WebhookError::RetryableRequestError { error, retry_after }) => {
-            let retry_interval =
-                retry_policy.retry_interval(webhook_job.attempt() as u32, retry_after);
-            let current_queue = webhook_job.queue();
-            let retry_queue = retry_policy.retry_queue(&current_queue);
+impl AllowedHosts {
+    /// Return true if webhooks can be sent to `host`.
+    pub fn allows(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
 
-            match webhook_job
-                .retry(WebhookJobError::from(&error), retry_interval, retry_queue)
-                .await
-            {
-                Ok(_) => {
-                    metrics::increment_counter!("webhook_jobs_retried", &labels);
+        self.0.is_empty()
+            || self.0.iter().any(|domain| {
+                host == *domain
+                    || host
+                        .strip_suffix(domain.as_str())
+                        .is_some_and(|subdomain| subdomain.ends_with('.'))
+            })
+    }
+}
 
-                    Ok(())
-                }
-                Err(PgJobError::RetryInvalidError {
-                    job: webhook_job, ..
-                }) => {
-                    webhook_job
-                        .fail(WebhookJobError::from(&error))
-                        .await
-                        .map_err(|job_error| ConsumerError::PgJobError(job_error.to_string()))?;
+impl FromStr for AllowedHosts {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(AllowedHosts(
+            s.split(',')
+                .map(|domain| domain.trim().trim_matches('.').to_ascii_lowercase())
+                .filter(|domain| !domain.is_empty())
+                .collect(),
+        ))
+    }
+}
 
-                    metrics::increment_counter!("webhook_jobs_failed", &labels);
+impl RequestOptions {
+    /// Select the options for a job in `queue`, using its queue's success criteria if it has any.
+    /// The options are only copied for queues with their own success criteria.
+    fn for_queue(&self, queue: &str) -> Cow<'_, RequestOptions> {
+        match self.queue_success_criteria.get(queue) {
+            Some(success_criteria) => Cow::Owned(RequestOptions {
+                success_criteria: success_criteria.clone(),
+                ..self.clone()
+            }),
+            None => Cow::Borrowed(self),
+        }
+    }
 
-                    Ok(())
+    /// Check that webhooks can be sent to the host of the absolute `url`, as resolved by `resolve_url`.
+    /// URLs that fail to parse are left for `send_webhook` to report.
+    fn check_allowed_host(&self, url: &str) -> Result<(), WebhookError> {
+        match reqwest::Url::parse(url) {
+            Ok(url) => match url.host_str() {
+                Some(host) if !self.allowed_hosts.allows(host) => {
+                    Err(WebhookError::DisallowedHostError(host.to_owned()))
                 }
-                Err(job_error) => Err(ConsumerError::PgJobError(job_error.to_string())),
+                _ => Ok(()),
+            },
+            Err(_) => Ok(()),
+        }
+    }
+
+    /// Resolve a webhook job's `url` to the absolute URL we send a request to, against the base URL of the job's
+    /// team if it's relative, see `hook_common::webhook::resolve_url`.
+    fn resolve_url(&self, url: &str, team_id: u32) -> Result<String, WebhookError> {
+        match self.tenant_base_urls.resolve(url, team_id) {
+            Ok(resolved) => Ok(resolved.into()),
+            Err(ResolveUrlError::ParseError(error)) => Err(WebhookError::ParseUrlError(error)),
+            Err(ResolveUrlError::EscapedBaseUrl(url)) => {
+                Err(WebhookError::EscapedBaseUrlError(url))
             }
         }
-        Err(WebhookError::NonRetryableRetryableRequestError(error)) => {
-            webhook_job
-                .fail(WebhookJobError::from(&error))
-                .await
-                .map_err(|job_error| ConsumerError::PgJobError(job_error.to_string()))?;
+    }
 
-            metrics::increment_counter!("webhook_jobs_failed", &labels);
+    /// Determine the timeout of a webhook job's request from the job's `timeout_ms`, clamped to `max_request_timeout`.
+    /// Returns `None` if the job doesn't set a timeout, so the client's own timeout applies.
+    fn request_timeout(&self, timeout_ms: Option<u64>) -> Option<time::Duration> {
+        let timeout = time::Duration::from_millis(timeout_ms?);
 
-            Ok(())
+        match self.max_request_timeout {
+            Some(max_request_timeout) => Some(timeout.min(max_request_timeout)),
+            None => Some(timeout),
         }
     }
-}
 
-/// Make an HTTP request to a webhook endpoint.
-///
-/// # Arguments
-///
-/// * `client`: An HTTP client to execute the HTTP request.
-/// * `method`: The HTTP method to use in the HTTP request.
-/// * `url`: The URL we are targetting with our request. Parsing this URL fail.
-/// * `headers`: Key, value pairs of HTTP headers in a `std::collections::HashMap`. Can fail if headers are not valid.
-/// * `body`: The body of the request. Ownership is required.
-async fn send_webhook(
-    client: reqwest::Client,
-    method: &HttpMethod,
-    url: &str,
-    headers: &collections::HashMap<String, String>,
-    body: String,
-) -> Result<reqwest::Response, WebhookError> {
-    let method: http::Method = method.into();
-    let url: reqwest::Url = (url).parse().map_err(WebhookError::ParseUrlError)?;
-    let headers: reqwest::header::HeaderMap = (headers)
-        .try_into()
-        .map_err(WebhookError::ParseHeadersError)?;
-    let body = reqwest::Body::from(body);
+    /// Build the headers for a webhook job's request, adding any headers these options require to the job's own.
+    /// Headers set by the job take precedence over any headers added here.
+    fn headers(
+        &self,
+        job_id: i64,
+        headers: &collections::HashMap<String, String>,
+        body: &str,
+    ) -> collections::HashMap<String, String> {
+        let mut headers = headers.clone();
 
-    let response = client
-        .request(method, url)
-        .headers(headers)
-        .body(body)
-        .send()
-        .await
-        .map_err(|e| WebhookError::RetryableRequestError {
-            error: e,
-            retry_after: None,
-        })?;
+        if let Some(header_name) = &self.idempotency_key_header {
+            if !headers
+                .keys()
+                .any(|key| key.eq_ignore_ascii_case(header_name))
+            {
+                headers.insert(header_name.to_owned(), idempotency_key(job_id));
+            }
+        }
 
-    let retry_after = parse_retry_after_header(response.headers());
+        for (name, value) in &self.default_headers.0 {
+            if !headers.keys().any(|key| key.eq_ignore_ascii_case(name)) {
+                headers.insert(name.to_owned(), value.to_owned());
+            }
+        }
 
-    match response.error_for_status() {
-        Ok(response) => Ok(response),
-        Err(err) => {
-            if is_retryable_status(
-                err.status()
-                    .expect("status code is set as error is generated from a response"),
-            ) {
-                Err(WebhookError::RetryableRequestError {
-                    error: err,
-                    retry_after,
-                })
-            } else {
-                Err(WebhookError::NonRetryableRetryableRequestError(err))
+        if let Some(content_type) = &self.json_content_type {
+            if !headers
+                .keys()
+                .any(|key| key.eq_ignore_ascii_case(header::CONTENT_TYPE.as_str()))
+                && is_json(body)
+            {
+                headers.insert(header::CONTENT_TYPE.to_string(), content_type.to_owned());
             }
         }
+
+        headers
+    }
+
+    /// Build what is stored with a job completed after its destination responded with `response`.
+    fn job_response(&self, response: &reqwest::Response) -> JobResponse {
+        let id = self
+            .response_id_header
+            .as_ref()
+            .and_then(|header_name| response.headers().get(header_name))
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+
+        JobResponse {
+            status: response.status().as_u16(),
+            id,
+        }
     }
 }
 
-fn is_retryable_status(status: StatusCode) -> bool {
-    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+/// Add the header a webhook job's `precondition` is sent as to its `headers`. Any header with the same name set by
+/// the job is replaced, as chained webhooks rely on the precondition to be applied in order.
+fn set_precondition_header(
+    headers: &mut collections::HashMap<String, String>,
+    precondition: &WebhookPrecondition,
+) {
+    let (name, value) = precondition.header();
+
+    headers.retain(|key, _| !key.eq_ignore_ascii_case(name.as_str()));
+    headers.insert(name.to_string(), value.to_owned());
 }
 
-/// Attempt to parse a chrono::Duration from a Retry-After header, returning None if not possible.
-/// Retry-After header can specify a date in RFC2822 or a number of seconds; we try to parse both.
-/// If a Retry-After header is not present in the provided `header_map`, `None` is returned.
-///
-/// # Arguments
+/// Check whether a webhook's `body` is a JSON object or array. Other JSON values, like strings, are too
+/// ambiguous to be sent as JSON when the job doesn't say so.
+fn is_json(body: &str) -> bool {
+    let body = body.trim_start();
+
+    (body.starts_with('{') || body.starts_with('['))
+        && serde_json::from_str::<serde::de::IgnoredAny>(body).is_ok()
+}
+
+/// Derive an idempotency key for the job identified by `job_id`.
+/// The key is stable across attempts of the same job, so destinations can use it to deduplicate deliveries.
+fn idempotency_key(job_id: i64) -> String {
+    format!("rusty-hook-{}", job_id)
+}
+
+/// The estimated memory used by a single connection to a destination, including its buffers, TLS state and
+/// the request and response headers, on top of the request and response bodies.
+const CONNECTION_OVERHEAD_BYTES: usize = 64 * 1024;
+
+/// Permits of a `MemoryBudget` are counted in KiB, so that budgets larger than 4 GiB fit in a `u32`.
+const MEMORY_PERMIT_BYTES: usize = 1024;
+
+/// Admission control limiting the memory estimated to be used by all webhook jobs in flight.
 ///
-/// * `header_map`: A `&reqwest::HeaderMap` of response headers that could contain Retry-After.
-fn parse_retry_after_header(header_map: &reqwest::header::HeaderMap) -> Option<time::Duration> {
-    let retry_after_header = header_map.get(reqwest::header::RETRY_AFTER);
+/// Every job reserves the size of its request body, the response body we may buffer, and a per-connection
+/// overhead, for as long as it's being processed. A job larger than the whole budget can still run, but only
+/// once no other jobs are in flight.
+pub struct MemoryBudget {
+    semaphore: Arc<sync::Semaphore>,
+    permits: u32,
+}
 
-    let retry_after = match retry_after_header {
-        Some(header_value) => match header_value.to_str() {
-            Ok(s) => s,
-            Err(_) => {
-                return None;
-            }
-        },
-        None => {
-            return None;
+impl MemoryBudget {
+    pub fn new(max_memory_bytes: usize) -> Self {
+        let permits = (max_memory_bytes / MEMORY_PERMIT_BYTES).clamp(1, u32::MAX as usize) as u32;
+
+        Self {
+            semaphore: Arc::new(sync::Semaphore::new(permits as usize)),
+            permits,
         }
-    };
+    }
 
-    if let Ok(u) = retry_after.parse::<u64>() {
-        let duration = time::Duration::from_secs(u);
-        return Some(duration);
+    /// Estimate the memory in bytes used to process a webhook job with a request body of `body_size` bytes.
+    fn estimate(body_size: usize, request_options: &RequestOptions) -> usize {
+        let response_size = match request_options.response_capture {
+            ResponseCapture::Body => request_options.max_response_body_size,
+            // Digests are computed as the body streams in, so no body is kept around.
+            ResponseCapture::Digest => 0,
+        };
+
+        body_size + response_size + CONNECTION_OVERHEAD_BYTES
     }
 
-    if let Ok(dt) = chrono::DateTime::parse_from_rfc2822(retry_after) {
-        let duration =
-            chrono::DateTime::<chrono::offset::Utc>::from(dt) - chrono::offset::Utc::now();
+    /// Wait until `bytes` fit in this budget, and reserve them until the returned permit is dropped.
+    /// Waits are counted in a metric labelled with `labels`.
+    async fn reserve(
+        &self,
+        bytes: usize,
+        labels: &[(&'static str, String)],
+    ) -> sync::OwnedSemaphorePermit {
+        let permits = bytes
+            .div_ceil(MEMORY_PERMIT_BYTES)
+            .clamp(1, self.permits as usize) as u32;
 
-        // This can only fail when negative, in which case we return None.
-        return duration.to_std().ok();
+        if self.semaphore.available_permits() < permits as usize {
+            metrics::increment_counter!("webhook_memory_budget_waits_total", labels);
+        }
+
+        self.semaphore
+            .clone()
+            .acquire_many_owned(permits)
+            .await
+            .expect("memory budget semaphore has been closed")
     }
+}
 
-    None
+/// Limits on the number of webhook jobs in flight per team, so that a single team can't take up the whole consumer.
+/// Jobs of teams at their limit are left in the queue, to be dequeued once one of their jobs in flight is done.
+pub struct TeamConcurrency {
+    /// The limit of teams without one in `limits`, if any.
+    default_limit: Option<usize>,
+    /// Limits overriding `default_limit` for specific teams, keyed by team id. A limit of 0 means no limit.
+    limits: collections::HashMap<u32, usize>,
+    /// The number of jobs in flight of every team that has any.
+    in_flight: Arc<std::sync::Mutex<collections::HashMap<u32, usize>>>,
 }
 
-mod tests {
-    use super::*;
-    // Note we are ignoring some warnings in this module.
+impl TeamConcurrency {
+    pub fn new(default_limit: Option<usize>, limits: collections::HashMap<u32, usize>) -> Self {
+        Self {
+            default_limit,
+            limits,
+            in_flight: Arc::new(std::sync::Mutex::new(collections::HashMap::new())),
+        }
+    }
+
+    /// The maximum number of jobs of `team_id` in flight, if limited.
+    fn limit(&self, team_id: u32) -> Option<usize> {
+        match self.limits.get(&team_id) {
+            Some(0) => None,
+            Some(limit) => Some(*limit),
+            None => self.default_limit,
+        }
+    }
+
+    /// The teams that have as many jobs in flight as their limit, whose jobs shouldn't be dequeued now.
+    fn saturated_teams(&self) -> Vec<u32> {
+        let in_flight = self
+            .in_flight
+            .lock()
+            .expect("team concurrency lock poisoned");
+
+        in_flight
+            .iter()
+            .filter(|(team_id, jobs)| self.limit(**team_id).is_some_and(|limit| **jobs >= limit))
+            .map(|(team_id, _)| *team_id)
+            .collect()
+    }
+
+    /// Count a job of `team_id` as in flight until the returned permit is dropped.
+    fn acquire(&self, team_id: u32) -> TeamPermit {
+        *self
+            .in_flight
+            .lock()
+            .expect("team concurrency lock poisoned")
+            .entry(team_id)
+            .or_insert(0) += 1;
+
+        TeamPermit {
+            team_id,
+            in_flight: self.in_flight.clone(),
+        }
+    }
+}
+
+/// A job of a team counted as in flight by `TeamConcurrency`, until dropped.
+struct TeamPermit {
+    team_id: u32,
+    in_flight: Arc<std::sync::Mutex<collections::HashMap<u32, usize>>>,
+}
+
+impl Drop for TeamPermit {
+    fn drop(&mut self) {
+        let mut in_flight = self
+            .in_flight
+            .lock()
+            .expect("team concurrency lock poisoned");
+
+        if let Some(jobs) = in_flight.get_mut(&self.team_id) {
+            *jobs -= 1;
+            if *jobs == 0 {
+                in_flight.remove(&self.team_id);
+            }
+        }
+    }
+}
+
+/// Backpressure letting fewer jobs in flight while most webhook requests fail, like during an outage of their
+/// destinations, so that retries don't pile up and amplify the load. Only timeouts, connection errors, and 429 or 5xx
+/// responses count as failed requests, see `WebhookError::is_overload`.
+///
+/// The outcomes of the last `window` requests are tracked. Once more than `threshold` of them failed, only as
+/// many jobs are let in flight as the share of requests that succeeded allows, down to a single job. The limit
+/// recovers as requests succeed again.
+pub struct Backpressure {
+    /// The share of failed requests above which fewer jobs are let in flight.
+    threshold: f64,
+    /// The number of most recent requests the failure rate is computed over.
+    window: usize,
+    /// The number of jobs let in flight without backpressure.
+    max_concurrent_jobs: usize,
+    /// Whether each of the most recent requests succeeded, oldest first.
+    outcomes: std::sync::Mutex<collections::VecDeque<bool>>,
+}
+
+impl Backpressure {
+    pub fn new(threshold: f64, window: usize, max_concurrent_jobs: usize) -> Self {
+        Self {
+            threshold,
+            window: window.max(1),
+            max_concurrent_jobs,
+            outcomes: std::sync::Mutex::new(collections::VecDeque::new()),
+        }
+    }
+
+    /// Record whether a webhook request succeeded.
+    fn record(&self, succeeded: bool) {
+        let mut outcomes = self.outcomes.lock().expect("backpressure lock poisoned");
+
+        if outcomes.len() == self.window {
+            outcomes.pop_front();
+        }
+        outcomes.push_back(succeeded);
+    }
+
+    /// The number of jobs to let in flight given the outcomes of recent requests.
+    /// There is no backpressure until `window` requests have been made.
+    pub fn concurrency(&self) -> usize {
+        let outcomes = self.outcomes.lock().expect("backpressure lock poisoned");
+        if outcomes.len() < self.window {
+            return self.max_concurrent_jobs;
+        }
+
+        let succeeded = outcomes.iter().filter(|succeeded| **succeeded).count();
+        let failure_rate = 1.0 - succeeded as f64 / outcomes.len() as f64;
+        if failure_rate <= self.threshold {
+            return self.max_concurrent_jobs;
+        }
+
+        ((self.max_concurrent_jobs * succeeded).div_ceil(outcomes.len())).max(1)
+    }
+
+    /// Wait until fewer jobs than `concurrency` are in flight, checking every `interval`.
+    /// Jobs in flight are the permits taken from `semaphore`, out of `max_concurrent_jobs`.
+    async fn wait_for_capacity(&self, semaphore: &sync::Semaphore, interval: time::Duration) {
+        loop {
+            let concurrency = self.concurrency();
+            metrics::gauge!("webhook_backpressure_concurrency", concurrency as f64);
+
+            let in_flight = self.max_concurrent_jobs - semaphore.available_permits();
+            if in_flight < concurrency {
+                return;
+            }
+
+            tokio::time::sleep(interval).await;
+        }
+    }
+}
+
+/// The number of most recent response times of a host its adaptive timeout is computed from.
+const ADAPTIVE_TIMEOUT_WINDOW: usize = 100;
+
+/// The number of response times of a host needed before its timeout is adapted to them.
+const ADAPTIVE_TIMEOUT_MIN_SAMPLES: usize = 20;
+
+/// The maximum number of hosts response times are tracked for. Requests to any other host use the regular timeout.
+const ADAPTIVE_TIMEOUT_MAX_HOSTS: usize = 10_000;
+
+/// Request timeouts adapted to how fast each host usually responds, so that requests to a host that suddenly
+/// slows down fail faster and free up their slots for other jobs.
+///
+/// The timeout of a host is `multiplier` times the 95th percentile of its most recent response times, kept
+/// between `min` and `max`. Hosts with too few response times yet use the regular timeout.
+#[derive(Debug)]
+pub struct AdaptiveTimeouts {
+    /// What the 95th percentile response time of a host is multiplied by to get its timeout.
+    multiplier: f64,
+    /// The lowest timeout a host can get.
+    min: time::Duration,
+    /// The highest timeout a host can get, like the client's own timeout.
+    max: time::Duration,
+    /// The most recent response times of each host, oldest first.
+    latencies:
+        std::sync::Mutex<collections::HashMap<String, collections::VecDeque<time::Duration>>>,
+}
+
+impl AdaptiveTimeouts {
+    pub fn new(multiplier: f64, min: time::Duration, max: time::Duration) -> Self {
+        Self {
+            multiplier,
+            min,
+            max: max.max(min),
+            latencies: std::sync::Mutex::new(collections::HashMap::new()),
+        }
+    }
+
+    /// Record how long `host` took to respond to a request, or how long we waited before timing out.
+    fn record(&self, host: &str, latency: time::Duration) {
+        let mut latencies = self
+            .latencies
+            .lock()
+            .expect("adaptive timeouts lock poisoned");
+
+        if !latencies.contains_key(host) && latencies.len() >= ADAPTIVE_TIMEOUT_MAX_HOSTS {
+            return;
+        }
+        let host_latencies = latencies.entry(host.to_owned()).or_default();
+        if host_latencies.len() == ADAPTIVE_TIMEOUT_WINDOW {
+            host_latencies.pop_front();
+        }
+        host_latencies.push_back(latency);
+    }
+
+    /// The 95th percentile of the most recent response times of `host`, if enough of them were recorded.
+    fn p95(&self, host: &str) -> Option<time::Duration> {
+        let latencies = self
+            .latencies
+            .lock()
+            .expect("adaptive timeouts lock poisoned");
+        let host_latencies = latencies.get(host)?;
+        if host_latencies.len() < ADAPTIVE_TIMEOUT_MIN_SAMPLES {
+            return None;
+        }
+
+        let mut sorted: Vec<time::Duration> = host_latencies.iter().copied().collect();
+        sorted.sort_unstable();
+        let rank = (sorted.len() as f64 * 0.95).ceil() as usize;
+
+        sorted.get(rank.saturating_sub(1)).copied()
+    }
+
+    /// The timeout of requests to `host`, or `None` to use the regular timeout.
+    pub fn timeout(&self, host: &str) -> Option<time::Duration> {
+        let p95 = self.p95(host)?;
+
+        Some(p95.mul_f64(self.multiplier).clamp(self.min, self.max))
+    }
+}
+
+/// An adaptive interval between polls of a queue.
+/// The interval doubles after every poll that finds no jobs, up to a maximum, so that an idle queue is polled
+/// less often. A new `PollInterval` is started every time we wait for a job, so polling goes back to the
+/// minimum interval as soon as a job is found, without delaying a busy queue.
+#[derive(Debug)]
+struct PollInterval {
+    max: time::Duration,
+    current: time::Duration,
+}
+
+impl PollInterval {
+    /// Create a `PollInterval` starting at `min`. A `max` lower than `min` disables backing off.
+    fn new(min: time::Duration, max: time::Duration) -> Self {
+        Self {
+            max: max.max(min),
+            current: min,
+        }
+    }
+
+    /// The duration to wait until the next poll.
+    fn current(&self) -> time::Duration {
+        self.current
+    }
+
+    /// Back off after a poll that found no jobs.
+    fn empty(&mut self) {
+        self.current = self.current.saturating_mul(2).min(self.max);
+    }
+}
+
+/// Decides when a queue is drained, see `WebhookConsumer::run_until_drained`.
+struct Drain {
+    /// The number of consecutive idle polls after which the queue is drained.
+    idle_polls: usize,
+    /// The semaphore held by every job in flight.
+    semaphore: Arc<sync::Semaphore>,
+    /// The number of permits of `semaphore`.
+    max_concurrent_jobs: usize,
+}
+
+impl Drain {
+    /// Whether a poll that found no jobs now is idle, i.e. no jobs are in flight either.
+    fn is_idle(&self) -> bool {
+        self.semaphore.available_permits() == self.max_concurrent_jobs
+    }
+}
+
+/// A consumer to poll `PgQueue` and spawn tasks to process webhooks when a job becomes available.
+pub struct WebhookConsumer<'p> {
+    /// An identifier for this consumer. Used to mark jobs we have consumed.
+    name: String,
+    /// The queue we will be dequeuing jobs from.
+    queue: &'p PgQueue,
+    /// The interval for polling the queue.
+    poll_interval: time::Duration,
+    /// The interval polling backs off to while the queue is empty. Polling doesn't back off if not higher than
+    /// `poll_interval`.
+    max_poll_interval: time::Duration,
+    /// The client used for HTTP requests.
+    client: reqwest::Client,
+    /// Maximum number of concurrent jobs being processed.
+    max_concurrent_jobs: usize,
+    /// Maximum number of concurrent HTTP requests in flight, independent of `max_concurrent_jobs`.
+    max_concurrent_requests: usize,
+    /// The retry policy used to calculate retry intervals when a job fails with a retryable error.
+    /// Used for jobs in any queue without a policy in `queue_retry_policies`.
+    retry_policy: RetryPolicy,
+    /// Retry policies to use instead of `retry_policy` for jobs in specific queues, keyed by queue name.
+    queue_retry_policies: collections::HashMap<String, RetryPolicy>,
+    /// Options used to build the HTTP request of every webhook job.
+    request_options: Arc<RequestOptions>,
+    /// Whether our queue was paused the last time we checked.
+    paused: AtomicBool,
+    /// The target memory used by all jobs in flight, if any.
+    max_memory_bytes: Option<usize>,
+    /// The retry policy used to back off while the database can't be reached.
+    connection_retry_policy: RetryPolicy,
+    /// Whether to listen for jobs enqueued into our queue, to pick them up without waiting for the next poll.
+    listen_for_jobs: bool,
+    /// The maximum number of jobs of any one team in flight, if limited.
+    max_concurrent_jobs_per_team: Option<usize>,
+    /// Limits overriding `max_concurrent_jobs_per_team` for specific teams, keyed by team id.
+    team_max_concurrent_jobs: collections::HashMap<u32, usize>,
+    /// The share of failed requests above which fewer jobs are let in flight, if any. See `Backpressure`.
+    failure_rate_threshold: Option<f64>,
+    /// The number of most recent requests the failure rate is computed over.
+    failure_rate_window: usize,
+}
+
+impl<'p> WebhookConsumer<'p> {
+    pub fn new(
+        name: &str,
+        queue: &'p PgQueue,
+        poll_interval: time::Duration,
+        request_timeout: time::Duration,
+        max_concurrent_jobs: usize,
+        retry_policy: RetryPolicy,
+    ) -> Self {
+        let client = client_builder(request_timeout)
+            .build()
+            .expect("failed to construct reqwest client for webhook consumer");
+
+        Self {
+            name: name.to_owned(),
+            queue,
+            poll_interval,
+            max_poll_interval: poll_interval,
+            client,
+            max_concurrent_jobs,
+            max_concurrent_requests: max_concurrent_jobs,
+            retry_policy,
+            queue_retry_policies: collections::HashMap::new(),
+            request_options: Arc::new(RequestOptions::default()),
+            paused: AtomicBool::new(false),
+            max_memory_bytes: None,
+            connection_retry_policy: RetryPolicy::build(2, time::Duration::from_millis(100))
+                .maximum_interval(time::Duration::from_secs(10))
+                .provide(),
+            listen_for_jobs: false,
+            max_concurrent_jobs_per_team: None,
+            team_max_concurrent_jobs: collections::HashMap::new(),
+            failure_rate_threshold: None,
+            failure_rate_window: 100,
+        }
+    }
+
+    /// Back off polling while the queue is empty, doubling the interval after every empty poll up to
+    /// `max_poll_interval`. Polling goes back to `poll_interval` as soon as a job is found.
+    pub fn max_poll_interval(mut self, max_poll_interval: time::Duration) -> Self {
+        self.max_poll_interval = max_poll_interval;
+        self
+    }
+
+    /// Listen for jobs enqueued into our queue, to pick them up as soon as they are instead of at the next poll.
+    /// Polling carries on as usual, to pick up jobs retried or enqueued without notifying us.
+    pub fn listen_for_jobs(mut self, listen_for_jobs: bool) -> Self {
+        self.listen_for_jobs = listen_for_jobs;
+        self
+    }
+
+    /// Limit the number of jobs of any one team in flight. Jobs of a team at its limit are left in the queue
+    /// until one of its jobs in flight is done, so that other teams' jobs are processed in the meantime.
+    pub fn max_concurrent_jobs_per_team(mut self, max_concurrent_jobs_per_team: usize) -> Self {
+        self.max_concurrent_jobs_per_team = Some(max_concurrent_jobs_per_team);
+        self
+    }
+
+    /// Set limits overriding `max_concurrent_jobs_per_team` for specific teams, keyed by team id.
+    /// Teams can be limited this way even if `max_concurrent_jobs_per_team` isn't set, and a limit of 0 exempts
+    /// a team from it.
+    pub fn team_max_concurrent_jobs(
+        mut self,
+        team_max_concurrent_jobs: collections::HashMap<u32, usize>,
+    ) -> Self {
+        self.team_max_concurrent_jobs = team_max_concurrent_jobs;
+        self
+    }
+
+    /// Let fewer jobs in flight once more than `threshold` of the last `window` requests failed, so that retries
+    /// don't amplify the load on failing destinations. See `Backpressure`.
+    pub fn failure_rate_backpressure(mut self, threshold: f64, window: usize) -> Self {
+        self.failure_rate_threshold = Some(threshold);
+        self.failure_rate_window = window;
+        self
+    }
+
+    /// Set the client used for HTTP requests, replacing the one built in `new`.
+    /// Use `client_builder` to start from the same defaults.
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Set the maximum number of concurrent HTTP requests in flight.
+    /// If not set, defaults to `max_concurrent_jobs`.
+    pub fn max_concurrent_requests(mut self, max_concurrent_requests: usize) -> Self {
+        self.max_concurrent_requests = max_concurrent_requests;
+        self
+    }
+
+    /// Set retry policies for jobs in specific queues, keyed by queue name.
+    /// Jobs in any other queue use the retry policy passed to `new`.
+    pub fn queue_retry_policies(
+        mut self,
+        queue_retry_policies: collections::HashMap<String, RetryPolicy>,
+    ) -> Self {
+        self.queue_retry_policies = queue_retry_policies;
+        self
+    }
+
+    /// Select the retry policy for a job in `queue`, falling back to our default retry policy.
+    fn retry_policy_for(&self, queue: &str) -> &RetryPolicy {
+        self.queue_retry_policies
+            .get(queue)
+            .unwrap_or(&self.retry_policy)
+    }
+
+    /// Set the options used to build the HTTP request of every webhook job.
+    pub fn request_options(mut self, request_options: RequestOptions) -> Self {
+        self.request_options = Arc::new(request_options);
+        self
+    }
+
+    /// Set a target memory budget for all jobs in flight, on top of `max_concurrent_jobs`.
+    /// No more jobs are dequeued while the estimated memory used by jobs in flight would exceed this budget.
+    pub fn max_memory_bytes(mut self, max_memory_bytes: usize) -> Self {
+        self.max_memory_bytes = Some(max_memory_bytes);
+        self
+    }
+
+    /// Set the retry policy used to back off while the database can't be reached, both when waiting for a
+    /// connection on startup and when polling for jobs.
+    pub fn connection_retry_policy(mut self, connection_retry_policy: RetryPolicy) -> Self {
+        self.connection_retry_policy = connection_retry_policy;
+        self
+    }
+
+    /// Wait until a connection to the database can be acquired, backing off following our connection retry
+    /// policy for up to `max_attempts` attempts. Returns the last connection error if all of them failed.
+    pub async fn wait_for_connection(&self, max_attempts: u32) -> Result<(), ConsumerError> {
+        let mut attempt = 0;
+
+        loop {
+            attempt += 1;
+
+            match self.queue.check_connection().await {
+                Ok(()) => return Ok(()),
+                Err(error) if attempt < max_attempts => {
+                    let interval = self.connection_retry_policy.retry_interval(attempt, None);
+                    warn!(
+                        attempt,
+                        error = %error,
+                        "failed to connect to the database, retrying in {:?}",
+                        interval
+                    );
+                    tokio::time::sleep(interval).await;
+                }
+                Err(error) => return Err(error.into()),
+            }
+        }
+    }
+
+    /// Back off following our connection retry policy after `failures` consecutive connection errors.
+    async fn back_off_connection(&self, failures: u32, error: &ConsumerError) {
+        let interval = self.connection_retry_policy.retry_interval(failures, None);
+        warn!(
+            failures,
+            error = %error,
+            "lost connection to the database, retrying in {:?}",
+            interval
+        );
+        tokio::time::sleep(interval).await;
+    }
+
+    /// Check whether our queue is paused, logging any change since the last check.
+    async fn is_paused(&self) -> Result<bool, ConsumerError> {
+        let paused = self.queue.is_paused().await?;
+
+        if self.paused.swap(paused, Ordering::Relaxed) != paused {
+            if paused {
+                info!("queue is paused, no jobs will be dequeued until it is resumed");
+            } else {
+                info!("queue was resumed, dequeuing jobs");
+            }
+        }
+
+        Ok(paused)
+    }
+
+    /// Wait until a job becomes available in our queue, or until our queue is drained as decided by
+    /// `drain`, if set, returning `None` then.
+    /// No jobs are dequeued while our queue is paused.
+    /// Connection errors are backed off from until the database can be reached again, instead of being returned.
+    async fn wait_for_job(
+        &self,
+        drain: Option<&Drain>,
+        mut listener: Option<&mut PgQueueListener>,
+        team_concurrency: Option<&TeamConcurrency>,
+    ) -> Result<Option<PgJob<WebhookJobParameters, WebhookJobMetadata>>, ConsumerError> {
+        let mut interval = PollInterval::new(self.poll_interval, self.max_poll_interval);
+        let mut connection_failures = 0;
+        let mut idle_polls = 0;
+
+        loop {
+            let (polled, paused) = match self.is_paused().await {
+                Ok(false) => (
+                    self.queue
+                        .dequeue_excluding_teams(
+                            &self.name,
+                            &team_concurrency
+                                .map(TeamConcurrency::saturated_teams)
+                                .unwrap_or_default(),
+                        )
+                        .await
+                        .map_err(ConsumerError::from),
+                    false,
+                ),
+                Ok(true) => (Ok(None), true),
+                Err(error) => (Err(error), false),
+            };
+
+            match polled {
+                Ok(Some(job)) => return Ok(Some(job)),
+                Ok(None) => {
+                    connection_failures = 0;
+
+                    if let Some(drain) = drain {
+                        idle_polls = if !paused && drain.is_idle() {
+                            idle_polls + 1
+                        } else {
+                            0
+                        };
+                        if idle_polls >= drain.idle_polls {
+                            return Ok(None);
+                        }
+                    }
+                }
+                Err(error) if error.is_connection_error() => {
+                    connection_failures += 1;
+                    self.back_off_connection(connection_failures, &error).await;
+                    continue;
+                }
+                Err(error) => return Err(error),
+            }
+
+            wait_for_poll(listener.as_deref_mut(), interval.current()).await;
+            interval.empty();
+        }
+    }
+
+    /// Wait until a job becomes available in our queue in transactional mode, or until our queue is drained as decided by
+    /// `drain`, if set, returning `None` then.
+    /// No jobs are dequeued while our queue is paused.
+    /// Connection errors are backed off from until the database can be reached again, instead of being returned.
+    async fn wait_for_job_tx<'a>(
+        &self,
+        drain: Option<&Drain>,
+        mut listener: Option<&mut PgQueueListener>,
+        team_concurrency: Option<&TeamConcurrency>,
+    ) -> Result<Option<PgTransactionJob<'a, WebhookJobParameters, WebhookJobMetadata>>, ConsumerError>
+    {
+        let mut interval = PollInterval::new(self.poll_interval, self.max_poll_interval);
+        let mut connection_failures = 0;
+        let mut idle_polls = 0;
+
+        loop {
+            let (polled, paused) = match self.is_paused().await {
+                Ok(false) => (
+                    self.queue
+                        .dequeue_tx_excluding_teams(
+                            &self.name,
+                            &team_concurrency
+                                .map(TeamConcurrency::saturated_teams)
+                                .unwrap_or_default(),
+                        )
+                        .await
+                        .map_err(ConsumerError::from),
+                    false,
+                ),
+                Ok(true) => (Ok(None), true),
+                Err(error) => (Err(error), false),
+            };
+
+            match polled {
+                Ok(Some(job)) => return Ok(Some(job)),
+                Ok(None) => {
+                    connection_failures = 0;
+
+                    if let Some(drain) = drain {
+                        idle_polls = if !paused && drain.is_idle() {
+                            idle_polls + 1
+                        } else {
+                            0
+                        };
+                        if idle_polls >= drain.idle_polls {
+                            return Ok(None);
+                        }
+                    }
+                }
+                Err(error) if error.is_connection_error() => {
+                    connection_failures += 1;
+                    self.back_off_connection(connection_failures, &error).await;
+                    continue;
+                }
+                Err(error) => return Err(error),
+            }
+
+            wait_for_poll(listener.as_deref_mut(), interval.current()).await;
+            interval.empty();
+        }
+    }
+
+    /// Run this consumer to continuously process any jobs that become available.
+    pub async fn run(&self, transactional: bool) -> Result<(), ConsumerError> {
+        let semaphore = Arc::new(sync::Semaphore::new(self.max_concurrent_jobs));
+        self.process_jobs(
+            transactional,
+            None,
+            None,
+            semaphore,
+            never_signaled(),
+            never_signaled(),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Run this consumer like `run` until `shutdown` resolves, then stop dequeuing and wait for the jobs in
+    /// flight to finish, returning once they have.
+    ///
+    /// Jobs still in flight after `grace_period` have their requests aborted, and are retried as if they
+    /// had timed out, so that a destination that hangs can't prevent shutdown.
+    pub async fn run_until(
+        &self,
+        transactional: bool,
+        shutdown: impl std::future::Future<Output = ()>,
+        grace_period: time::Duration,
+    ) -> Result<(), ConsumerError> {
+        let semaphore = Arc::new(sync::Semaphore::new(self.max_concurrent_jobs));
+        let (stop, stopped) = sync::watch::channel(false);
+        let (cancel, cancelled) = sync::watch::channel(false);
+
+        let drain = async {
+            self.process_jobs(
+                transactional,
+                None,
+                None,
+                semaphore.clone(),
+                stopped,
+                cancelled,
+            )
+            .await?;
+
+            // Every job in flight holds a permit, so all of them are done once we can take every permit.
+            let _permits = semaphore
+                .acquire_many(self.max_concurrent_jobs as u32)
+                .await
+                .expect("semaphore has been closed");
+
+            Ok(())
+        };
+        let deadline = async {
+            shutdown.await;
+            info!("shutting down, waiting for jobs in flight to finish");
+            let _ = stop.send(true);
+
+            tokio::time::sleep(grace_period).await;
+            info!("shutdown grace period is over, aborting jobs in flight");
+            let _ = cancel.send(true);
+
+            std::future::pending::<()>().await
+        };
+
+        tokio::select! {
+            result = drain => result,
+            _ = deadline => unreachable!("deadline never completes"),
+        }
+    }
+
+    /// Run this consumer like `run` until our queue is drained, returning once the jobs in flight are done.
+    ///
+    /// The queue is drained after `idle_polls` consecutive polls (at least 1) found no available job while no jobs
+    /// were in flight, as jobs in flight could still be retried right away. Jobs scheduled to run in the future,
+    /// like retries of failed jobs, are left in the queue. This is intended for one-off deployments clearing a
+    /// backlog, or for a planned decommission.
+    pub async fn run_until_drained(
+        &self,
+        transactional: bool,
+        idle_polls: usize,
+    ) -> Result<(), ConsumerError> {
+        let semaphore = Arc::new(sync::Semaphore::new(self.max_concurrent_jobs));
+        self.process_jobs(
+            transactional,
+            None,
+            Some(idle_polls),
+            semaphore.clone(),
+            never_signaled(),
+            never_signaled(),
+        )
+        .await?;
+
+        // Every job in flight holds a permit, so all of them are done once we can take every permit.
+        let _permits = semaphore
+            .acquire_many(self.max_concurrent_jobs as u32)
+            .await
+            .expect("semaphore has been closed");
+
+        Ok(())
+    }
+
+    /// Run this consumer to process `n` jobs, returning once all of them have been processed.
+    /// Jobs are processed concurrently as in `run`. A job that is retried counts again when it's dequeued for
+    /// its next attempt, so this is intended for batch deployments and tests rather than to drain a queue.
+    pub async fn run_n(&self, transactional: bool, n: usize) -> Result<(), ConsumerError> {
+        let semaphore = Arc::new(sync::Semaphore::new(self.max_concurrent_jobs));
+        let handles = self
+            .process_jobs(
+                transactional,
+                Some(n),
+                None,
+                semaphore,
+                never_signaled(),
+                never_signaled(),
+            )
+            .await?;
+
+        for handle in handles {
+            handle
+                .await
+                .expect("webhook job processing task panicked")?;
+        }
+
+        Ok(())
+    }
+
+    /// Dequeue jobs and spawn a task to process each of them, stopping after `limit` jobs if set, once the queue is
+    /// drained after `idle_polls` if set, or once `stopped` is signaled. Spawned tasks abort their requests once
+    /// `cancelled` is signaled.
+    /// Returns the handles of the spawned tasks, which are only kept if there is a `limit`.
+    ///
+    /// # Arguments
+    ///
+    /// * `transactional`: Whether to dequeue jobs in transactional mode.
+    /// * `limit`: The number of jobs to process, if any.
+    /// * `idle_polls`: The number of idle polls after which the queue is drained, see `run_until_drained`.
+    /// * `semaphore`: A semaphore with `max_concurrent_jobs` permits, one of which is held by every job in flight.
+    /// * `stopped`: Signals that no more jobs should be dequeued.
+    /// * `cancelled`: Signals that jobs in flight should abort their requests.
+    async fn process_jobs(
+        &self,
+        transactional: bool,
+        limit: Option<usize>,
+        idle_polls: Option<usize>,
+        semaphore: Arc<sync::Semaphore>,
+        mut stopped: sync::watch::Receiver<bool>,
+        cancelled: sync::watch::Receiver<bool>,
+    ) -> Result<Vec<tokio::task::JoinHandle<Result<(), ConsumerError>>>, ConsumerError> {
+        let request_semaphore = Arc::new(sync::Semaphore::new(self.max_concurrent_requests));
+        let memory_budget = self
+            .max_memory_bytes
+            .map(|max_memory_bytes| Arc::new(MemoryBudget::new(max_memory_bytes)));
+        let drain = idle_polls.map(|idle_polls| Drain {
+            idle_polls,
+            semaphore: semaphore.clone(),
+            max_concurrent_jobs: self.max_concurrent_jobs,
+        });
+        let team_concurrency = (self.max_concurrent_jobs_per_team.is_some()
+            || !self.team_max_concurrent_jobs.is_empty())
+        .then(|| {
+            Arc::new(TeamConcurrency::new(
+                self.max_concurrent_jobs_per_team,
+                self.team_max_concurrent_jobs.clone(),
+            ))
+        });
+        let backpressure = self.failure_rate_threshold.map(|threshold| {
+            Arc::new(Backpressure::new(
+                threshold,
+                self.failure_rate_window,
+                self.max_concurrent_jobs,
+            ))
+        });
+        let mut listener = if self.listen_for_jobs {
+            Some(self.queue.listen().await?)
+        } else {
+            None
+        };
+        let context = JobProcessingContext {
+            client: self.client.clone(),
+            semaphore: semaphore.clone(),
+            request_semaphore,
+            memory_budget,
+            team_concurrency: team_concurrency.clone(),
+            backpressure: backpressure.clone(),
+            request_options: self.request_options.clone(),
+            cancelled,
+            queue: Some(self.queue.clone()),
+        };
+        let mut handles = Vec::new();
+        let mut processed = 0;
+
+        while !matches!(limit, Some(limit) if processed >= limit) {
+            if let Some(backpressure) = &backpressure {
+                tokio::select! {
+                    _ = backpressure.wait_for_capacity(&semaphore, self.poll_interval) => {},
+                    _ = wait_for_signal(&mut stopped) => break,
+                }
+            }
+
+            let handle = if transactional {
+                let webhook_job = tokio::select! {
+                    webhook_job = self.wait_for_job_tx(drain.as_ref(), listener.as_mut(), team_concurrency.as_deref()) => webhook_job?,
+                    _ = wait_for_signal(&mut stopped) => break,
+                };
+                let Some(webhook_job) = webhook_job else {
+                    break;
+                };
+                let retry_policy = self.retry_policy_for(&webhook_job.job.queue).clone();
+
+                spawn_webhook_job_processing_task(&context, retry_policy, webhook_job).await
+            } else {
+                let webhook_job = tokio::select! {
+                    webhook_job = self.wait_for_job(drain.as_ref(), listener.as_mut(), team_concurrency.as_deref()) => webhook_job?,
+                    _ = wait_for_signal(&mut stopped) => break,
+                };
+                let Some(webhook_job) = webhook_job else {
+                    break;
+                };
+                let retry_policy = self.retry_policy_for(&webhook_job.job.queue).clone();
+
+                spawn_webhook_job_processing_task(&context, retry_policy, webhook_job).await
+            };
+
+            processed += 1;
+            if limit.is_some() {
+                handles.push(handle);
+            }
+        }
+
+        Ok(handles)
+    }
+}
+
+/// A receiver for a signal that is never sent, as its sender is already dropped.
+fn never_signaled() -> sync::watch::Receiver<bool> {
+    sync::watch::channel(false).1
+}
+
+/// Wait until `signal` is set, never returning if its sender is dropped without setting it.
+async fn wait_for_signal(signal: &mut sync::watch::Receiver<bool>) {
+    if signal.wait_for(|signaled| *signaled).await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Wait `interval` until the next poll, or until `listener`, if any, is notified of a job enqueued into our queue.
+/// Errors waiting for notifications are only logged, as polling picks up jobs regardless.
+async fn wait_for_poll(listener: Option<&mut PgQueueListener>, interval: time::Duration) {
+    let Some(listener) = listener else {
+        return tokio::time::sleep(interval).await;
+    };
+
+    if let Err(error) = listener.wait(interval).await {
+        warn!("failed to wait for job notifications: {}", error);
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Initialize a `reqwest::ClientBuilder` with the defaults used by a `WebhookConsumer`'s client.
+///
+/// # Arguments
+///
+/// * `request_timeout`: The timeout applied to every HTTP request.
+///
+/// Redirects are not followed, use `RedirectPolicy::apply` to change that.
+/// Hostnames are resolved with a `SystemResolver`, so that resolution failures are told apart from other connection
+/// errors. Any resolver set with `dns_resolver` should fail with a `ResolveError` too.
+pub fn client_builder(request_timeout: time::Duration) -> reqwest::ClientBuilder {
+    let builder = reqwest::Client::builder()
+        .dns_resolver(Arc::new(SystemResolver))
+        .timeout(request_timeout);
+
+    RedirectPolicy::default().apply(builder, &AllowedHosts::default())
+}
+
+/// Build the proxies for a `WebhookConsumer`'s client to send requests through, if any.
+/// Proxy URLs are only used for requests of their scheme, and no proxy is used for any host in `no_proxy`,
+/// a comma-separated list as in the `NO_PROXY` environment variable. Empty URLs are skipped.
+pub fn proxies(
+    http_proxy: &str,
+    https_proxy: &str,
+    no_proxy: &str,
+) -> reqwest::Result<Vec<reqwest::Proxy>> {
+    let mut proxies = Vec::new();
+
+    if !http_proxy.is_empty() {
+        proxies.push(reqwest::Proxy::http(http_proxy)?);
+    }
+    if !https_proxy.is_empty() {
+        proxies.push(reqwest::Proxy::https(https_proxy)?);
+    }
+
+    Ok(proxies
+        .into_iter()
+        .map(|proxy| proxy.no_proxy(reqwest::NoProxy::from_string(no_proxy)))
+        .collect())
+}
+
+/// Load the client certificate a `WebhookConsumer`'s client presents to destinations that require mutual TLS, from
+/// the PEM files at `cert_path` and `key_path`. The key must be in PKCS #8 format.
+/// No certificate is presented if both paths are empty.
+pub fn client_identity(
+    cert_path: &str,
+    key_path: &str,
+) -> Result<Option<reqwest::Identity>, ClientIdentityError> {
+    match (cert_path.is_empty(), key_path.is_empty()) {
+        (true, true) => return Ok(None),
+        (false, false) => (),
+        _ => return Err(ClientIdentityError::IncompleteError),
+    }
+
+    let read = |path: &str| {
+        std::fs::read(path).map_err(|error| ClientIdentityError::ReadError {
+            path: path.to_owned(),
+            error,
+        })
+    };
+    let identity = reqwest::Identity::from_pkcs8_pem(&read(cert_path)?, &read(key_path)?)?;
+
+    Ok(Some(identity))
+}
+
+/// The state shared by the tasks processing the webhook jobs dequeued by a `WebhookConsumer`.
+#[derive(Clone)]
+struct JobProcessingContext {
+    /// An HTTP client to execute webhook job requests.
+    client: reqwest::Client,
+    /// A semaphore used for rate limiting purposes. Spawning a task will panic if this semaphore is closed.
+    semaphore: Arc<sync::Semaphore>,
+    /// A semaphore limiting the number of HTTP requests in flight. Acquired only while sending.
+    request_semaphore: Arc<sync::Semaphore>,
+    /// An optional budget limiting the memory used by jobs in flight. Reserved before spawning.
+    memory_budget: Option<Arc<MemoryBudget>>,
+    /// Optional limits on the jobs in flight per team. Jobs are counted before spawning.
+    team_concurrency: Option<Arc<TeamConcurrency>>,
+    /// Optional backpressure the outcome of every webhook job's request is recorded with.
+    backpressure: Option<Arc<Backpressure>>,
+    /// Options used to build webhook job HTTP requests.
+    request_options: Arc<RequestOptions>,
+    /// Signals that webhook job requests should be aborted, and the jobs retried.
+    cancelled: sync::watch::Receiver<bool>,
+    /// The queue jobs were dequeued from, used to fail jobs that panic.
+    queue: Option<PgQueue>,
+}
+
+/// Spawn a Tokio task to process a Webhook Job once we successfully acquire a permit.
+///
+/// # Arguments
+///
+/// * `context`: The state shared with the tasks processing other webhook jobs.
+/// * `retry_policy`: The retry policy used to set retry parameters if a job fails and has remaining attempts.
+/// * `webhook_job`: The webhook job to process as dequeued from `hook_common::pgqueue::PgQueue`.
+async fn spawn_webhook_job_processing_task<W: WebhookJob + 'static>(
+    context: &JobProcessingContext,
+    retry_policy: RetryPolicy,
+    webhook_job: W,
+) -> tokio::task::JoinHandle<Result<(), ConsumerError>> {
+    let JobProcessingContext {
+        client,
+        semaphore,
+        request_semaphore,
+        memory_budget,
+        team_concurrency,
+        backpressure,
+        request_options,
+        cancelled,
+        queue,
+    } = context.clone();
+    let permit = semaphore
+        .acquire_owned()
+        .await
+        .expect("semaphore has been closed");
+
+    let labels = [
+        ("queue", webhook_job.queue()),
+        ("target", webhook_job.target()),
+    ];
+
+    metrics::increment_counter!("webhook_jobs_total", &labels);
+
+    let memory_permit = match memory_budget {
+        Some(memory_budget) => {
+            let bytes =
+                MemoryBudget::estimate(webhook_job.parameters().body.len(), &request_options);
+            Some(
+                memory_budget
+                    .reserve(bytes, &[("queue", webhook_job.queue())])
+                    .await,
+            )
+        }
+        None => None,
+    };
+    let team_permit = team_concurrency
+        .map(|team_concurrency| team_concurrency.acquire(webhook_job.metadata().team_id));
+
+    let job_id = webhook_job.id();
+    let span = job_span(&webhook_job);
+
+    // The job's span is a child of the current span, so that any fields set on it (like the deployment region) are
+    // kept in the task's logs too.
+    tokio::spawn(
+        async move {
+            let result = isolate_panics(
+                queue.as_ref(),
+                W::TRANSACTIONAL,
+                job_id,
+                &labels,
+                process_webhook_job(
+                    client,
+                    webhook_job,
+                    &request_semaphore,
+                    &retry_policy,
+                    &request_options,
+                    cancelled,
+                    backpressure.as_deref(),
+                ),
+            )
+            .await;
+            drop(team_permit);
+            drop(memory_permit);
+            drop(permit);
+            result
+        }
+        .instrument(span),
+    )
+}
+
+/// Open a span for processing `webhook_job`, so that every log emitted while processing it carries its context.
+fn job_span(webhook_job: &impl WebhookJob) -> tracing::Span {
+    tracing::info_span!(
+        "webhook_job",
+        job_id = webhook_job.id(),
+        queue = webhook_job.queue(),
+        target = webhook_job.target(),
+        attempt = webhook_job.attempt(),
+    )
+}
+
+/// Run the `processing` of the webhook job `job_id`, catching any panic so that it doesn't take down the consumer.
+/// If `queue` is set, the job is failed after a panic, so that it isn't left stranded in `'running'`. Jobs dequeued
+/// in a transaction are failed once it's rolled back, which happens when the job is dropped: rolling back undoes
+/// the job's attempt, so a job that always panics would otherwise be dequeued again forever.
+async fn isolate_panics(
+    queue: Option<&PgQueue>,
+    transactional: bool,
+    job_id: i64,
+    labels: &[(&'static str, String)],
+    processing: impl std::future::Future<Output = Result<(), ConsumerError>>,
+) -> Result<(), ConsumerError> {
+    let panic = match std::panic::AssertUnwindSafe(processing)
+        .catch_unwind()
+        .await
+    {
+        Ok(result) => return result,
+        Err(panic) => panic,
+    };
+
+    let message = panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_owned());
+    error!(job_id, panic = message, "webhook job processing panicked");
+
+    if let Some(queue) = queue {
+        let job_error =
+            WebhookJobError::new_internal(&format!("job processing panicked: {}", message));
+        let failed = if transactional {
+            queue.fail_available(job_id, job_error).await?
+        } else {
+            queue.fail_running(job_id, job_error).await?
+        };
+        if failed {
+            metrics::increment_counter!("webhook_jobs_failed", labels);
+        }
+    }
+
+    Ok(())
+}
+
+/// Process a webhook job by transitioning it to its appropriate state after its request is sent.
+/// After we finish, the webhook job will be set as completed (if the request was successful), retryable (if the request
+/// was unsuccessful but we can still attempt a retry), or failed (if the request was unsuccessful and no more retries
+/// may be attempted).
+///
+/// The decision is made by `job_disposition`, see there for when a webhook job is considered retryable.
+///
+/// # Arguments
+///
+/// * `client`: An HTTP client to execute the webhook job request.
+/// * `webhook_job`: The webhook job to process as dequeued from `hook_common::pgqueue::PgQueue`.
+/// * `request_semaphore`: A semaphore limiting the number of HTTP requests in flight. This function will panic if this semaphore is closed.
+/// * `retry_policy`: The retry policy used to set retry parameters if a job fails and has remaining attempts.
+/// * `request_options`: Options used to build the webhook job's HTTP request.
+/// * `cancelled`: Signals that the webhook job's request should be aborted, and the job retried.
+/// * `backpressure`: Records whether the request failed from overload, if set. Jobs failed without sending a request
+///   aren't.
+async fn process_webhook_job<W: WebhookJob>(
+    client: reqwest::Client,
+    webhook_job: W,
+    request_semaphore: &sync::Semaphore,
+    retry_policy: &RetryPolicy,
+    request_options: &RequestOptions,
+    mut cancelled: sync::watch::Receiver<bool>,
+    backpressure: Option<&Backpressure>,
+) -> Result<(), ConsumerError> {
+    let request_options = &*request_options.for_queue(&webhook_job.queue());
+    let parameters = webhook_job.parameters();
+    let mut headers =
+        request_options.headers(webhook_job.id(), &parameters.headers, &parameters.body);
+    request_options
+        .metadata_headers
+        .apply(webhook_job.metadata(), &mut headers);
+    if let Some(precondition) = &webhook_job.metadata().precondition {
+        set_precondition_header(&mut headers, precondition);
+    }
+
+    let labels = [
+        ("queue", webhook_job.queue()),
+        ("target", webhook_job.target()),
+    ];
+
+    record_queue_latency(webhook_job.job());
+
+    // Expired jobs are not worth delivering anymore, and retrying them would only deliver them later.
+    if webhook_job.job().is_expired() {
+        let failed_job = webhook_job
+            .fail(WebhookJobError::new_expired(
+                "job expired before it could be delivered",
+            ))
+            .await?;
+        record_stored_errors(&failed_job.queue, failed_job.errors);
+
+        metrics::increment_counter!("webhook_jobs_expired", &labels);
+
+        return Ok(());
+    }
+
+    let request_permit = request_semaphore
+        .acquire()
+        .await
+        .expect("request semaphore has been closed");
+
+    #[cfg(feature = "otel")]
+    let delivery_cx = crate::otel::start_delivery_span(&webhook_job.metadata().trace_context);
+    #[cfg(feature = "otel")]
+    let headers = crate::otel::inject_trace_context(&delivery_cx, headers);
+    let now = tokio::time::Instant::now();
+
+    // Check for cancellation first, so that a job processed after a shutdown deadline isn't sent at all.
+    let send_result = tokio::select! {
+        biased;
+        _ = wait_for_signal(&mut cancelled) => None,
+        send_result = send_webhook_fan_out(
+            client,
+            parameters,
+            webhook_job.metadata(),
+            &headers,
+            request_options,
+        ) => Some(send_result),
+    };
+
+    let elapsed = now.elapsed().as_secs_f64();
+    drop(request_permit);
+
+    let Some((outcome, delivered_urls)) = send_result else {
+        info!(
+            job_id = webhook_job.id(),
+            "webhook request was aborted on shutdown"
+        );
+
+        let retry_interval = retry_policy.retry_interval(webhook_job.attempt() as u32, None);
+        return retry_webhook_job(
+            webhook_job,
+            retry_policy,
+            retry_interval,
+            &labels,
+            None,
+            || WebhookJobError::new_timeout("request was aborted on shutdown"),
+        )
+        .await;
+    };
+
+    let send_result = match outcome {
+        FanOutOutcome::Sent(send_result) => send_result,
+        FanOutOutcome::AlreadyDelivered => {
+            info!(
+                job_id = webhook_job.id(),
+                "webhook was already delivered to every url in previous attempts"
+            );
+
+            record_dwell_time(webhook_job.job());
+            webhook_job.complete().await?;
+
+            metrics::increment_counter!("webhook_jobs_completed", &labels);
+
+            return Ok(());
+        }
+    };
+
+    if let Some(kind) = send_result
+        .as_ref()
+        .err()
+        .and_then(WebhookError::request_error_kind)
+    {
+        let error_labels = [
+            ("queue", webhook_job.queue()),
+            ("kind", kind.as_str().to_owned()),
+        ];
+        metrics::increment_counter!("webhook_request_errors_total", &error_labels);
+    }
+
+    // Only errors from an overloaded destination count as failures: a destination rejecting bad payloads with a 4xx
+    // is up, and letting fewer jobs in flight wouldn't help it.
+    if let Some(backpressure) = backpressure {
+        match &send_result {
+            Ok(_) => backpressure.record(true),
+            Err(error) if error.request_error_kind().is_some() => {
+                backpressure.record(!error.is_overload())
+            }
+            Err(_) => {}
+        }
+    }
+
+    #[cfg(feature = "otel")]
+    crate::otel::emit_delivery_log(
+        &delivery_cx,
+        &crate::otel::Delivery {
+            job_id: webhook_job.id(),
+            team_id: webhook_job.metadata().team_id,
+            url: &parameters.url,
+            attempt: webhook_job.attempt(),
+            duration: time::Duration::from_secs_f64(elapsed),
+            result: &send_result,
+        },
+    );
+
+    let disposition = job_disposition(
+        &send_result,
+        webhook_job.job(),
+        retry_policy,
+        &request_options.retry_classifier,
+    );
+
+    match disposition {
+        JobDisposition::Complete => {
+            let response = send_result
+                .as_ref()
+                .expect("only successful requests are completed");
+            record_dwell_time(webhook_job.job());
+            webhook_job
+                .complete_with_response(request_options.job_response(response))
+                .await?;
+
+            metrics::increment_counter!("webhook_jobs_completed", &labels);
+            metrics::histogram!("webhook_jobs_processing_duration_seconds", elapsed, &labels);
+
+            Ok(())
+        }
+        JobDisposition::Retry(retry_interval) => {
+            let error = send_result
+                .as_ref()
+                .expect_err("only failed requests are retried");
+            // Keep track of the URLs a fan-out webhook was delivered to, so that they aren't sent it again.
+            let metadata = (!parameters.fan_out_urls.is_empty()).then(|| WebhookJobMetadata {
+                delivered_urls,
+                ..webhook_job.metadata().clone()
+            });
+
+            retry_webhook_job(
+                webhook_job,
+                retry_policy,
+                retry_interval,
+                &labels,
+                metadata,
+                || job_error(error),
+            )
+            .await
+        }
+        JobDisposition::Fail(job_error) => {
+            record_remaining_attempts(webhook_job.job());
+            record_dwell_time(webhook_job.job());
+            let failed_job = webhook_job.fail(job_error).await?;
+            record_stored_errors(&failed_job.queue, failed_job.errors);
+
+            metrics::increment_counter!("webhook_jobs_failed", &labels);
+
+            Ok(())
+        }
+        JobDisposition::Discard => {
+            let status = send_result
+                .as_ref()
+                .err()
+                .and_then(WebhookError::status)
+                .expect("status code is set as the response was classified");
+            record_dwell_time(webhook_job.job());
+            webhook_job
+                .discard_with_response(JobResponse {
+                    status: status.as_u16(),
+                    id: None,
+                })
+                .await?;
+
+            metrics::increment_counter!("webhook_jobs_discarded", &labels);
+
+            Ok(())
+        }
+    }
+}
+
+/// What to do with a webhook job after its request was sent.
+#[derive(Debug)]
+pub enum JobDisposition {
+    /// The webhook was delivered, so the job is completed.
+    Complete,
+    /// The webhook could not be delivered but it could be later, so the job is retried after the given interval.
+    Retry(time::Duration),
+    /// The webhook could not be delivered and it won't ever be, so the job is failed with the given error.
+    Fail(WebhookJobError),
+    /// The destination rejected the webhook with a status code classified as `RetryAction::Discard`, so the job
+    /// is completed without being reported as failed.
+    Discard,
+}
+
+/// Decide what to do with `job` given the `result` of its request. This doesn't execute the decision, so it can be
+/// done without a database or network.
+///
+/// A job is retried after a failing request if:
+/// 1. The job has attempts remaining (i.e. hasn't reached `max_attempts`), and...
+/// 2. The request timed out, failed without a response, or `retry_classifier` classifies its status code as
+///    `RetryAction::Retry`. By default, this means: 429 and any 5XX. Responses that don't meet the success
+///    criteria are classified by status code too, and failed by default.
+///
+/// # Arguments
+///
+/// * `result`: The result of the job's request, as returned by `send_webhook_fan_out`.
+/// * `job`: The job the request was sent for.
+/// * `retry_policy`: The retry policy used to calculate the retry interval.
+/// * `retry_classifier`: Classifies failing status codes to decide whether the job is retried.
+pub fn job_disposition(
+    result: &Result<reqwest::Response, WebhookError>,
+    job: &Job<WebhookJobParameters, WebhookJobMetadata>,
+    retry_policy: &RetryPolicy,
+    retry_classifier: &RetryClassifier,
+) -> JobDisposition {
+    let error = match result {
+        Ok(_) => return JobDisposition::Complete,
+        Err(error) => error,
+    };
+
+    let (retry_after, interval_multiplier) = match error {
+        WebhookError::RetryableRequestError {
+            error: request_error,
+            retry_after,
+            ..
+        } => {
+            let interval_multiplier = match request_error
+                .status()
+                .map(|status| retry_classifier.classify(status.as_u16()))
+            {
+                Some(RetryAction::Retry {
+                    interval_multiplier,
+                }) => interval_multiplier,
+                _ => 1,
+            };
+
+            (*retry_after, interval_multiplier)
+        }
+        WebhookError::RetryableTimeoutError { .. } => (None, 1),
+        WebhookError::RedirectError { status, .. }
+        | WebhookError::UnsuccessfulResponseError { status, .. } => {
+            match retry_classifier.classify(status.as_u16()) {
+                RetryAction::Retry {
+                    interval_multiplier,
+                } => (None, interval_multiplier),
+                RetryAction::Discard => return JobDisposition::Discard,
+                RetryAction::Fail => return JobDisposition::Fail(job_error(error)),
+            }
+        }
+        WebhookError::NonRetryableRequestError { .. }
+            if error.status().is_some_and(|status| {
+                retry_classifier.classify(status.as_u16()) == RetryAction::Discard
+            }) =>
+        {
+            return JobDisposition::Discard
+        }
+        _ => return JobDisposition::Fail(job_error(error)),
+    };
+
+    if job.is_gte_max_attempts() {
+        return JobDisposition::Fail(job_error(error));
+    }
+
+    JobDisposition::Retry(retry_policy.multiplied_retry_interval(
+        job.attempt as u32,
+        retry_after,
+        interval_multiplier,
+    ))
+}
+
+/// Retry a webhook job after `retry_interval` in the queue given by `retry_policy`, or fail it if it has no attempts
+/// remaining.
+///
+/// # Arguments
+///
+/// * `webhook_job`: The webhook job that failed with a retryable error.
+/// * `retry_policy`: The retry policy used to select the queue to retry in.
+/// * `retry_interval`: The interval to wait before the next attempt.
+/// * `labels`: The labels of the metrics recorded for the job.
+/// * `metadata`: The metadata to replace the job's metadata with, if any.
+/// * `job_error`: Builds the error stored with the job's errors.
+async fn retry_webhook_job<W: WebhookJob>(
+    webhook_job: W,
+    retry_policy: &RetryPolicy,
+    retry_interval: time::Duration,
+    labels: &[(&'static str, String)],
+    metadata: Option<WebhookJobMetadata>,
+    job_error: impl Fn() -> WebhookJobError,
+) -> Result<(), ConsumerError> {
+    let current_queue = webhook_job.queue();
+    let retry_queue = retry_policy.retry_queue(&current_queue);
+
+    let retried = match metadata {
+        Some(metadata) => {
+            webhook_job
+                .retry_with_metadata(job_error(), retry_interval, retry_queue, metadata)
+                .await
+        }
+        None => {
+            webhook_job
+                .retry(job_error(), retry_interval, retry_queue)
+                .await
+        }
+    };
+
+    match retried {
+        Ok(retried_job) => {
+            debug!(
+                job_id = retried_job.id,
+                next_attempt_at = %retried_job.next_attempt_at,
+                "retrying webhook job"
+            );
+            metrics::increment_counter!("webhook_jobs_retried", labels);
+            record_stored_errors(&retried_job.queue, retried_job.errors);
+
+            Ok(())
+        }
+        Err(PgJobError::RetryInvalidError {
+            job: webhook_job, ..
+        }) => {
+            record_remaining_attempts(webhook_job.job());
+            record_dwell_time(webhook_job.job());
+            let failed_job = webhook_job.fail(job_error()).await?;
+            record_stored_errors(&failed_job.queue, failed_job.errors);
+
+            metrics::increment_counter!("webhook_jobs_failed", labels);
+
+            Ok(())
+        }
+        Err(job_error) => Err(job_error.into()),
+    }
+}
+
+/// Record how long a job waited in its queue before being dequeued for this attempt.
+///
+/// The time since the job was created is only recorded on its first attempt, so that it measures how long jobs
+/// wait before their first delivery. The time since the job was scheduled is recorded on every attempt, and
+/// measures how far behind schedule we are, including for retries.
+fn record_queue_latency(job: &Job<WebhookJobParameters, WebhookJobMetadata>) {
+    let labels = [("queue", job.queue.to_owned())];
+    // `attempted_at` is set by the database on dequeue, so we don't depend on our own clock here.
+    let attempted_at = job.attempted_at.unwrap_or_else(chrono::offset::Utc::now);
+    let seconds = |duration: chrono::Duration| duration.to_std().unwrap_or_default().as_secs_f64();
+
+    if job.attempt == 1 {
+        metrics::histogram!(
+            "webhook_job_queue_latency_seconds",
+            seconds(job.age(attempted_at)),
+            &labels
+        );
+    }
+    metrics::histogram!(
+        "webhook_job_scheduling_lag_seconds",
+        seconds(job.time_since_scheduled(attempted_at)),
+        &labels
+    );
+}
+
+/// Record how long a job spent in the queue it finished in, from when it entered that queue until it's completed or
+/// failed, labelled by whether it finished on its first attempt in its original queue or on a retry.
+/// Jobs finishing in a retry queue only count the time since they were retried into it.
+///
+/// A job is on a retry once it's past its first attempt, or once it was moved out of the queue it was created in,
+/// so that retries are told apart even when they are sent to the same queue.
+fn record_dwell_time(job: &Job<WebhookJobParameters, WebhookJobMetadata>) {
+    let queue_kind = if job.attempt > 1 || job.entered_queue_at.is_some() {
+        "retry"
+    } else {
+        "primary"
+    };
+    let dwell_time = job
+        .time_in_queue(chrono::offset::Utc::now())
+        .to_std()
+        .unwrap_or_default();
+
+    metrics::histogram!(
+        "webhook_job_dwell_time_seconds",
+        dwell_time.as_secs_f64(),
+        &[
+            ("queue", job.queue.to_owned()),
+            ("queue_kind", queue_kind.to_owned())
+        ]
+    );
+}
+
+/// Record how many attempts a job had left when it failed, to tell apart jobs failing on their first attempt from
+/// jobs failing after exhausting their retries.
+fn record_remaining_attempts(job: &Job<WebhookJobParameters, WebhookJobMetadata>) {
+    metrics::histogram!(
+        "webhook_jobs_failed_remaining_attempts",
+        job.remaining_attempts() as f64,
+        &[("queue", job.queue.to_owned())]
+    );
+}
+
+/// Record how many errors a job has stored after being retried or failed, to tell how large the `errors` of jobs
+/// grow, and whether `max_errors` needs to bound them.
+fn record_stored_errors(queue: &str, errors: usize) {
+    metrics::histogram!(
+        "webhook_job_stored_errors",
+        errors as f64,
+        &[("queue", queue.to_owned())]
+    );
+}
+
+/// Build the `WebhookJobError` stored with a job's errors from a request error and the response captured with it.
+fn webhook_job_error(
+    error: &reqwest::Error,
+    response: Option<&CapturedResponse>,
+) -> WebhookJobError {
+    with_captured_response(WebhookJobError::from(error), response)
+}
+
+/// Store the part of an error response that was captured, if any, with `webhook_job_error`.
+fn with_captured_response(
+    webhook_job_error: WebhookJobError,
+    response: Option<&CapturedResponse>,
+) -> WebhookJobError {
+    match response {
+        Some(CapturedResponse::Body(body)) => webhook_job_error.with_response(Some(body.clone())),
+        Some(CapturedResponse::Digest(digest)) => {
+            webhook_job_error.with_response_digest(Some(digest.clone()))
+        }
+        None => webhook_job_error,
+    }
+}
+
+/// Build the `WebhookJobError` stored with a job's errors from the error its request failed with.
+fn job_error(error: &WebhookError) -> WebhookJobError {
+    match error {
+        WebhookError::ParseHeadersError(e) => WebhookJobError::new_parse(&e.to_string()),
+        WebhookError::ParseHttpMethodError(e) => WebhookJobError::new_parse(e),
+        WebhookError::InvalidContentTypeError(content_type) => {
+            WebhookJobError::new_parse(&format!("{} is not a valid content type", content_type))
+        }
+        WebhookError::ParseUrlError(e) => WebhookJobError::new_parse(&e.to_string()),
+        WebhookError::EscapedBaseUrlError(_) => WebhookJobError::new_parse(&error.to_string()),
+        WebhookError::DisallowedHostError(host) => WebhookJobError::new_connection(&format!(
+            "{} is not an allowed webhook destination",
+            host
+        )),
+        WebhookError::BodyTooLargeError { .. } | WebhookError::MultipartWithBodyError => {
+            WebhookJobError::new_parse(&error.to_string())
+        }
+        WebhookError::RedirectError { status, .. } => {
+            WebhookJobError::new_http_status(status.as_u16(), &error.to_string())
+                .with_request_error_kind(error.request_error_kind())
+        }
+        WebhookError::RetryableRequestError {
+            error, response, ..
+        }
+        | WebhookError::NonRetryableRequestError { error, response } => {
+            webhook_job_error(error, response.as_ref())
+        }
+        WebhookError::UnsuccessfulResponseError { status, response } => with_captured_response(
+            WebhookJobError::new_http_status(status.as_u16(), &error.to_string())
+                .with_request_error_kind(error.request_error_kind()),
+            response.as_ref(),
+        ),
+        WebhookError::RetryableTimeoutError { .. } => {
+            WebhookJobError::new_timeout(&error.to_string())
+                .with_request_error_kind(error.request_error_kind())
+        }
+    }
+}
+
+/// The outcome of delivering a webhook to every URL it fans out to, see `send_webhook_fan_out`.
+enum FanOutOutcome {
+    /// The result of the first request that failed, or that of the last one if none did.
+    Sent(Result<reqwest::Response, WebhookError>),
+    /// Every URL was delivered to in previous attempts, so no request was made.
+    AlreadyDelivered,
+}
+
+/// Deliver a webhook to its `url` and each of its `fan_out_urls`, skipping the URLs in the job's `delivered_urls`
+/// it was already delivered to in previous attempts. Every URL is sent the webhook regardless of whether the
+/// others fail, and only `url` fails over to `fallback_urls`.
+/// Returns the outcome of the deliveries along with all the URLs the webhook was delivered to so far. Webhooks
+/// without `fan_out_urls` are only sent to `url`, as usual.
+///
+/// # Arguments
+///
+/// * `client`: An HTTP client to execute the HTTP requests.
+/// * `parameters`: The parameters of the webhook job, including the URLs to deliver to.
+/// * `metadata`: The metadata of the webhook job, including the URLs it was already delivered to.
+/// * `headers`: Key, value pairs of HTTP headers, as built for the webhook job.
+/// * `request_options`: Options used to resolve URLs and to capture an error response body.
+async fn send_webhook_fan_out(
+    client: reqwest::Client,
+    parameters: &WebhookJobParameters,
+    metadata: &WebhookJobMetadata,
+    headers: &collections::HashMap<String, String>,
+    request_options: &RequestOptions,
+) -> (FanOutOutcome, Vec<String>) {
+    if parameters.fan_out_urls.is_empty() {
+        let result = send_webhook_with_fallbacks(
+            client,
+            parameters,
+            metadata.team_id,
+            headers,
+            request_options,
+        )
+        .await;
+        return (FanOutOutcome::Sent(result), Vec::new());
+    }
+
+    let mut delivered_urls = metadata.delivered_urls.clone();
+    let mut first_error = None;
+    let mut last_response = None;
+
+    for url in std::iter::once(&parameters.url).chain(parameters.fan_out_urls.iter()) {
+        if delivered_urls.contains(url) {
+            continue;
+        }
+
+        let result = if url == &parameters.url {
+            send_webhook_with_fallbacks(
+                client.clone(),
+                parameters,
+                metadata.team_id,
+                headers,
+                request_options,
+            )
+            .await
+        } else {
+            send_webhook_to_url(
+                client.clone(),
+                parameters,
+                url,
+                metadata.team_id,
+                headers,
+                request_options,
+            )
+            .await
+        };
+
+        match result {
+            Ok(response) => {
+                delivered_urls.push(url.to_owned());
+                last_response = Some(response);
+            }
+            Err(error) => {
+                info!("fan-out request to {} failed: {}", url, error);
+                first_error.get_or_insert(error);
+            }
+        }
+    }
+
+    let outcome = match (first_error, last_response) {
+        (Some(error), _) => FanOutOutcome::Sent(Err(error)),
+        (None, Some(response)) => FanOutOutcome::Sent(Ok(response)),
+        (None, None) => FanOutOutcome::AlreadyDelivered,
+    };
+
+    (outcome, delivered_urls)
+}
+
+/// Make an HTTP request to a webhook's `url`, failing over to each of its `fallback_urls` in order.
+/// We only fail over on errors that are retryable, as any other error would happen with a fallback URL too.
+/// Returns the result of the first request that doesn't fail with a retryable error, or that of the last request.
+///
+/// # Arguments
+///
+/// * `client`: An HTTP client to execute the HTTP requests.
+/// * `parameters`: The parameters of the webhook job, including the URLs to try.
+/// * `team_id`: The team of the webhook job, used to resolve relative URLs.
+/// * `headers`: Key, value pairs of HTTP headers, as built for the webhook job.
+/// * `request_options`: Options used to resolve URLs and to capture an error response body.
+async fn send_webhook_with_fallbacks(
+    client: reqwest::Client,
+    parameters: &WebhookJobParameters,
+    team_id: u32,
+    headers: &collections::HashMap<String, String>,
+    request_options: &RequestOptions,
+) -> Result<reqwest::Response, WebhookError> {
+    let mut urls = std::iter::once(&parameters.url).chain(parameters.fallback_urls.iter());
+    let mut url = urls.next().expect("there is always a primary url");
+
+    loop {
+        let result = send_webhook_to_url(
+            client.clone(),
+            parameters,
+            url,
+            team_id,
+            headers,
+            request_options,
+        )
+        .await;
+
+        match (result, urls.next()) {
+            (
+                Err(
+                    error @ (WebhookError::RetryableRequestError { .. }
+                    | WebhookError::RetryableTimeoutError { .. }),
+                ),
+                Some(fallback_url),
+            ) => {
+                info!(
+                    "request to {} failed, failing over to {}: {}",
+                    url, fallback_url, error
+                );
+                url = fallback_url;
+            }
+            (result, _) => return result,
+        }
+    }
+}
+
+/// Make an HTTP request with a webhook's `parameters` to `url`, once resolved and checked against the allowed hosts.
+async fn send_webhook_to_url(
+    client: reqwest::Client,
+    parameters: &WebhookJobParameters,
+    url: &str,
+    team_id: u32,
+    headers: &collections::HashMap<String, String>,
+    request_options: &RequestOptions,
+) -> Result<reqwest::Response, WebhookError> {
+    let resolved_url = request_options.resolve_url(url, team_id)?;
+    request_options.check_allowed_host(&resolved_url)?;
+
+    send_webhook(client, parameters, &resolved_url, headers, request_options).await
+}
+
+/// Build a `multipart/form-data` body out of a webhook's `parts`.
+fn multipart_form(parts: &[MultipartPart]) -> Result<reqwest::multipart::Form, WebhookError> {
+    let mut form = reqwest::multipart::Form::new();
+
+    for part in parts {
+        let mut form_part = reqwest::multipart::Part::text(part.content.clone());
+        if let Some(file_name) = &part.file_name {
+            form_part = form_part.file_name(file_name.clone());
+        }
+        if let Some(content_type) = &part.content_type {
+            form_part = form_part
+                .mime_str(content_type)
+                .map_err(|_| WebhookError::InvalidContentTypeError(content_type.to_owned()))?;
+        }
+        form = form.part(part.name.clone(), form_part);
+    }
+
+    Ok(form)
+}
+
+/// The size in bytes of the body `request` sends, as encoded. Multipart bodies are streamed, so their size is the
+/// Content-Length reqwest computes for them, which counts their boundaries and part headers too.
+fn body_size(request: &reqwest::Request) -> usize {
+    match request.body().and_then(reqwest::Body::as_bytes) {
+        Some(body) => body.len(),
+        None => request
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|content_length| content_length.to_str().ok())
+            .and_then(|content_length| content_length.parse().ok())
+            .unwrap_or_default(),
+    }
+}
+
+/// Check that the Content-Type of a webhook request, if any, is a valid MIME type.
+/// Destinations would reject the request on every attempt otherwise, so it's failed without sending it.
+fn check_content_type(headers: &reqwest::header::HeaderMap) -> Result<(), WebhookError> {
+    match headers.get(header::CONTENT_TYPE) {
+        Some(content_type)
+            if content_type
+                .to_str()
+                .ok()
+                .and_then(|content_type| content_type.parse::<mime::Mime>().ok())
+                .is_none() =>
+        {
+            Err(WebhookError::InvalidContentTypeError(
+                String::from_utf8_lossy(content_type.as_bytes()).into_owned(),
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Make an HTTP request to a webhook endpoint.
+///
+/// # Arguments
+///
+/// * `client`: An HTTP client to execute the HTTP request.
+/// * `parameters`: The webhook job's parameters, which set the method, body, multipart parts, authentication and timeout.
+/// * `url`: The URL we are targetting with our request, which may differ from `parameters.url`. Parsing this URL fail.
+/// * `headers`: Key, value pairs of HTTP headers in a `std::collections::HashMap`. Can fail if headers are not valid.
+/// * `request_options`: Options used to capture an error response body, to bound the request's size and to time it out.
+async fn send_webhook(
+    client: reqwest::Client,
+    parameters: &WebhookJobParameters,
+    url: &str,
+    headers: &collections::HashMap<String, String>,
+    request_options: &RequestOptions,
+) -> Result<reqwest::Response, WebhookError> {
+    let body = parameters.body.clone();
+    let multipart = &parameters.multipart;
+    let auth = parameters.auth.as_ref();
+    let timeout = request_options.request_timeout(parameters.timeout_ms);
+    let method: http::Method = (&parameters.method).into();
+    let url: reqwest::Url = (url).parse().map_err(WebhookError::ParseUrlError)?;
+    let host = url.host_str().unwrap_or_default().to_owned();
+    let mut headers: reqwest::header::HeaderMap = (headers)
+        .try_into()
+        .map_err(WebhookError::ParseHeadersError)?;
+    check_content_type(&headers)?;
+
+    if auth.is_some() {
+        headers.remove(header::AUTHORIZATION);
+    }
+
+    let mut request = if multipart.is_empty() {
+        client
+            .request(method, url)
+            .headers(headers)
+            .body(reqwest::Body::from(body))
+    } else {
+        if !body.is_empty() {
+            return Err(WebhookError::MultipartWithBodyError);
+        }
+        // The Content-Type of a multipart body carries its boundary, and its Content-Length is computed once it's
+        // encoded, so neither can be set by the job.
+        headers.remove(header::CONTENT_TYPE);
+        headers.remove(header::CONTENT_LENGTH);
+
+        client
+            .request(method, url)
+            .headers(headers)
+            .multipart(multipart_form(multipart)?)
+    };
+
+    // reqwest marks these headers as sensitive, so they are not leaked by `Debug` either.
+    request = match auth {
+        Some(WebhookAuth::Bearer { token }) => request.bearer_auth(token),
+        Some(WebhookAuth::Basic { username, password }) => {
+            request.basic_auth(username, Some(password))
+        }
+        None => request,
+    };
+    // An adaptive timeout only ever lowers the timeout set by the job.
+    let adaptive_timeout = request_options
+        .adaptive_timeouts
+        .as_ref()
+        .and_then(|adaptive_timeouts| adaptive_timeouts.timeout(&host));
+    let timeout = match (timeout, adaptive_timeout) {
+        (Some(timeout), Some(adaptive_timeout)) => Some(timeout.min(adaptive_timeout)),
+        (timeout, adaptive_timeout) => timeout.or(adaptive_timeout),
+    };
+    if let Some(timeout) = timeout {
+        request = request.timeout(timeout);
+    }
+
+    let (client, request) = request.build_split();
+    let request = request.map_err(WebhookError::from_request_error)?;
+    if let Some(max_body_bytes) = request_options.max_body_bytes {
+        let size = body_size(&request);
+        if size > max_body_bytes {
+            return Err(WebhookError::BodyTooLargeError {
+                size,
+                max_body_bytes,
+            });
+        }
+    }
+
+    let started = time::Instant::now();
+    let response = match request_options.response_headers_timeout {
+        Some(timeout) => tokio::time::timeout(timeout, client.execute(request))
+            .await
+            .map_err(|_| WebhookError::RetryableTimeoutError {
+                phase: ResponsePhase::Headers,
+                timeout,
+            }),
+        None => Ok(client.execute(request).await),
+    };
+    if let Some(adaptive_timeouts) = &request_options.adaptive_timeouts {
+        // Timeouts are recorded too, so that the timeout of a host that slowed down for good grows back.
+        let measured = match &response {
+            Ok(Ok(_)) | Err(_) => true,
+            Ok(Err(error)) => error.is_timeout(),
+        };
+        if measured {
+            adaptive_timeouts.record(&host, started.elapsed());
+        }
+    }
+    let response = response?.map_err(WebhookError::from_request_error)?;
+
+    // Redirects are only returned when the client doesn't follow them.
+    if response.status().is_redirection() {
+        return Err(WebhookError::RedirectError {
+            status: response.status(),
+            location: response
+                .headers()
+                .get(header::LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .map(str::to_owned),
+        });
+    }
+
+    let retry_after = parse_retry_after_header(response.headers());
+    let status = response.status();
+
+    // Only read the body to check it against the success criteria if some rule needs it.
+    let (response, body) = if request_options
+        .success_criteria
+        .inspects_body(status.as_u16())
+    {
+        let (response, body) = drain_response_body(
+            response,
+            request_options.response_body_timeout,
+            request_options.max_response_body_size,
+        )
+        .await?;
+        (response, Some(String::from_utf8_lossy(&body).into_owned()))
+    } else {
+        (response, None)
+    };
+
+    if request_options
+        .success_criteria
+        .is_success(status.as_u16(), body.as_deref())
+    {
+        return match (body, request_options.response_body_timeout) {
+            (None, Some(timeout)) => drain_response_body(
+                response,
+                Some(timeout),
+                request_options.max_response_body_size,
+            )
+            .await
+            .map(|(response, _)| response),
+            _ => Ok(response),
+        };
+    }
+
+    match response.error_for_status_ref() {
+        Ok(_) => Err(WebhookError::UnsuccessfulResponseError {
+            status,
+            response: capture_response(response, request_options).await,
+        }),
+        Err(err) => {
+            let response = capture_response(response, request_options).await;
+
+            if is_retryable_status(&request_options.retry_classifier, status) {
+                Err(WebhookError::RetryableRequestError {
+                    error: err,
+                    response,
+                    retry_after,
+                })
+            } else {
+                Err(WebhookError::NonRetryableRequestError {
+                    error: err,
+                    response,
+                })
+            }
+        }
+    }
+}
+
+/// Capture what `request_options` keep of an error response body, to store it with the job's errors.
+/// The status is enough to report the error, so a body that takes too long is just not captured.
+async fn capture_response(
+    response: reqwest::Response,
+    request_options: &RequestOptions,
+) -> Option<CapturedResponse> {
+    let capture = async {
+        match request_options.response_capture {
+            ResponseCapture::Body => {
+                read_response_body(response, request_options.max_response_body_size)
+                    .await
+                    .map(CapturedResponse::Body)
+            }
+            ResponseCapture::Digest => digest_response_body(response)
+                .await
+                .map(CapturedResponse::Digest),
+        }
+    };
+
+    match request_options.response_body_timeout {
+        Some(timeout) => tokio::time::timeout(timeout, capture).await.unwrap_or(None),
+        None => capture.await,
+    }
+}
+
+/// Read a whole response body within `timeout`, returning a response with the same status and headers serving
+/// only the first `max_size` bytes of it, along with those bytes.
+/// The rest of the body is discarded as it is streamed, so destinations echoing large payloads back don't
+/// cause memory spikes.
+///
+/// # Arguments
+///
+/// * `response`: The response to read the body from.
+/// * `timeout`: How long to wait for the whole body, if at all.
+/// * `max_size`: The maximum number of bytes of the body to keep.
+async fn drain_response_body(
+    mut response: reqwest::Response,
+    timeout: Option<time::Duration>,
+    max_size: usize,
+) -> Result<(reqwest::Response, Vec<u8>), WebhookError> {
+    let status = response.status();
+    let version = response.version();
+    let headers = response.headers().clone();
+
+    let drain = async {
+        let mut body = Vec::new();
+
+        while let Some(chunk) = response.chunk().await? {
+            let remaining = max_size.saturating_sub(body.len());
+            body.extend_from_slice(&chunk[..chunk.len().min(remaining)]);
+        }
+
+        Ok::<_, reqwest::Error>(body)
+    };
+    let body = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, drain).await.map_err(|_| {
+            WebhookError::RetryableTimeoutError {
+                phase: ResponsePhase::Body,
+                timeout,
+            }
+        })?,
+        None => drain.await,
+    }
+    .map_err(WebhookError::from_request_error)?;
+
+    let mut buffered = http::Response::new(body.clone());
+    *buffered.status_mut() = status;
+    *buffered.version_mut() = version;
+    *buffered.headers_mut() = headers;
+
+    Ok((reqwest::Response::from(buffered), body))
+}
+
+/// Read up to `max_size` bytes of a response body, discarding the rest.
+/// The body is read in chunks so that a huge body is never fully buffered in memory.
+/// Returns `None` if the body is empty or couldn't be read.
+///
+/// # Arguments
+///
+/// * `response`: The response to read the body from. The response is consumed, so its connection can be released.
+/// * `max_size`: The maximum number of bytes to read.
+async fn read_response_body(mut response: reqwest::Response, max_size: usize) -> Option<String> {
+    let mut body = Vec::new();
+
+    while body.len() < max_size {
+        match response.chunk().await {
+            Ok(Some(chunk)) => {
+                let remaining = max_size - body.len();
+                body.extend_from_slice(&chunk[..chunk.len().min(remaining)]);
+            }
+            Ok(None) => break,
+            Err(_) => break,
+        }
+    }
+
+    if body.is_empty() {
+        None
+    } else {
+        Some(String::from_utf8_lossy(&body).into_owned())
+    }
+}
+
+/// Compute a SHA-256 digest and the length of a response body.
+/// The body is hashed as it is streamed, so it is never kept in memory.
+/// Returns `None` if the body couldn't be read.
+///
+/// # Arguments
+///
+/// * `response`: The response to digest the body of.
+async fn digest_response_body(mut response: reqwest::Response) -> Option<ResponseDigest> {
+    let mut hasher = Sha256::new();
+    let mut length = 0;
+
+    loop {
+        match response.chunk().await {
+            Ok(Some(chunk)) => {
+                hasher.update(&chunk);
+                length += chunk.len() as u64;
+            }
+            Ok(None) => break,
+            Err(_) => return None,
+        }
+    }
+
+    Some(ResponseDigest {
+        sha256: format!("{:x}", hasher.finalize()),
+        length,
+    })
+}
+
+fn is_retryable_status(retry_classifier: &RetryClassifier, status: StatusCode) -> bool {
+    matches!(
+        retry_classifier.classify(status.as_u16()),
+        RetryAction::Retry { .. }
+    )
+}
+
+/// Attempt to parse a chrono::Duration from a Retry-After header, returning None if not possible.
+/// Retry-After header can specify a date in RFC2822 or a number of seconds; we try to parse both.
+/// If a Retry-After header is not present in the provided `header_map`, `None` is returned.
+///
+/// # Arguments
+///
+/// * `header_map`: A `&reqwest::HeaderMap` of response headers that could contain Retry-After.
+fn parse_retry_after_header(header_map: &reqwest::header::HeaderMap) -> Option<time::Duration> {
+    let retry_after_header = header_map.get(reqwest::header::RETRY_AFTER);
+
+    let retry_after = match retry_after_header {
+        Some(header_value) => match header_value.to_str() {
+            Ok(s) => s,
+            Err(_) => {
+                return None;
+            }
+        },
+        None => {
+            return None;
+        }
+    };
+
+    if let Ok(u) = retry_after.parse::<u64>() {
+        let duration = time::Duration::from_secs(u);
+        return Some(duration);
+    }
+
+    if let Ok(dt) = chrono::DateTime::parse_from_rfc2822(retry_after) {
+        let duration =
+            chrono::DateTime::<chrono::offset::Utc>::from(dt) - chrono::offset::Utc::now();
+
+        // This can only fail when negative, in which case we return None.
+        return duration.to_std().ok();
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    // Note we are ignoring some warnings in this module.
     // This is due to a long-standing cargo bug that reports imports and helper functions as unused.
     // See: https://github.com/rust-lang/rust/issues/46379.
     #[allow(unused_imports)]
-    use hook_common::pgqueue::{JobStatus, NewJob};
+    use hook_common::pgqueue::{JobStatus, NewJob, PgQueueError};
+    use hook_common::{
+        kafka_messages::app_metrics,
+        webhook::{HttpMethod, WebhookRequestErrorKind},
+    };
     #[allow(unused_imports)]
     use sqlx::PgPool;
+    #[allow(unused_imports)]
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Use process id as a worker id for tests.
+    fn worker_id() -> String {
+        std::process::id().to_string()
+    }
+
+    async fn enqueue_job(
+        queue: &PgQueue,
+        max_attempts: i32,
+        job_parameters: WebhookJobParameters,
+        job_metadata: WebhookJobMetadata,
+    ) -> Result<(), PgQueueError> {
+        let job_target = job_parameters.url.to_owned();
+        let new_job = NewJob::new(max_attempts, job_metadata, job_parameters, &job_target);
+        queue.enqueue(new_job).await?;
+        Ok(())
+    }
+
+    /// Serve a mock destination `Router` on a random local port, returning its base URL.
+    async fn serve_mock_destination(router: axum::Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("failed to bind mock destination");
+        let addr = listener
+            .local_addr()
+            .expect("failed to get mock destination address");
+
+        tokio::spawn(async move {
+            axum::serve(listener, router)
+                .await
+                .expect("failed to serve mock destination");
+        });
+
+        format!("http://{}", addr)
+    }
+
+    fn webhook_job_metadata() -> WebhookJobMetadata {
+        WebhookJobMetadata {
+            team_id: 1,
+            plugin_id: 2,
+            plugin_config_id: 3,
+            precondition: None,
+            delivered_urls: Vec::new(),
+            recurrence: None,
+            trace_context: Default::default(),
+        }
+    }
+
+    fn webhook_job_parameters(url: &str) -> WebhookJobParameters {
+        WebhookJobParameters {
+            auth: None,
+            body: "a webhook job body. much wow.".to_owned(),
+            fallback_urls: Vec::new(),
+            fan_out_urls: Vec::new(),
+            headers: collections::HashMap::new(),
+            method: HttpMethod::POST,
+            multipart: Vec::new(),
+            timeout_ms: None,
+            url: url.to_owned(),
+        }
+    }
+
+    /// A context to process webhook jobs one at a time with default options, failing jobs that panic in `queue`.
+    fn job_processing_context(queue: Option<PgQueue>) -> JobProcessingContext {
+        JobProcessingContext {
+            client: reqwest::Client::new(),
+            semaphore: Arc::new(sync::Semaphore::new(1)),
+            request_semaphore: Arc::new(sync::Semaphore::new(1)),
+            memory_budget: None,
+            team_concurrency: None,
+            backpressure: None,
+            request_options: Arc::new(RequestOptions::default()),
+            cancelled: never_signaled(),
+            queue,
+        }
+    }
+
+    /// A webhook job on its `attempt` out of `max_attempts`, as dequeued for `job_disposition`.
+    fn attempted_job(
+        attempt: i32,
+        max_attempts: i32,
+    ) -> Job<WebhookJobParameters, WebhookJobMetadata> {
+        Job {
+            id: 1,
+            attempt,
+            attempted_at: Some(chrono::Utc::now()),
+            attempted_by: vec![worker_id()],
+            created_at: chrono::Utc::now(),
+            expires_at: None,
+            max_attempts,
+            metadata: sqlx::types::Json(webhook_job_metadata()),
+            parameters: sqlx::types::Json(webhook_job_parameters("http://localhost:18081")),
+            queue: "webhooks".to_owned(),
+            scheduled_at: chrono::Utc::now(),
+            status: JobStatus::Running,
+            target: "http://localhost:18081".to_owned(),
+            priority: 0,
+            entered_queue_at: None,
+        }
+    }
+
+    /// The result of a request that got a response with `status`, classified like `send_webhook` does.
+    fn request_result(
+        status: u16,
+        retry_after: Option<time::Duration>,
+    ) -> Result<reqwest::Response, WebhookError> {
+        let response = reqwest::Response::from(
+            http::Response::builder()
+                .status(status)
+                .body("")
+                .expect("failed to build response"),
+        );
+
+        match response.error_for_status() {
+            Ok(response) => Ok(response),
+            Err(error)
+                if is_retryable_status(&RetryClassifier::default(), error.status().unwrap()) =>
+            {
+                Err(WebhookError::RetryableRequestError {
+                    error,
+                    response: None,
+                    retry_after,
+                })
+            }
+            Err(error) => Err(WebhookError::NonRetryableRequestError {
+                error,
+                response: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_job_disposition() {
+        let retry_policy = RetryPolicy::build(2, time::Duration::from_secs(1)).provide();
+        let classifier = RetryClassifier::default();
+        let disposition = |result: &Result<reqwest::Response, WebhookError>, attempt| {
+            job_disposition(
+                result,
+                &attempted_job(attempt, 3),
+                &retry_policy,
+                &classifier,
+            )
+        };
+
+        // 2XX
+        for status in [200, 201, 204] {
+            assert!(matches!(
+                disposition(&request_result(status, None), 1),
+                JobDisposition::Complete
+            ));
+        }
+
+        // 429, backing off with the attempt and respecting Retry-After, until attempts run out.
+        let result = request_result(429, None);
+        assert!(matches!(
+            disposition(&result, 1),
+            JobDisposition::Retry(interval) if interval == time::Duration::from_secs(1)
+        ));
+        assert!(matches!(
+            disposition(&result, 2),
+            JobDisposition::Retry(interval) if interval == time::Duration::from_secs(2)
+        ));
+        assert!(matches!(
+            disposition(&request_result(429, Some(time::Duration::from_secs(30))), 1),
+            JobDisposition::Retry(interval) if interval == time::Duration::from_secs(30)
+        ));
+        assert!(matches!(
+            disposition(&result, 3),
+            JobDisposition::Fail(WebhookJobError {
+                r#type: app_metrics::ErrorType::BadHttpStatus(429),
+                ..
+            })
+        ));
+
+        // 5XX
+        for status in [500, 502, 503] {
+            let result = request_result(status, None);
+            assert!(matches!(
+                disposition(&result, 1),
+                JobDisposition::Retry(interval) if interval == time::Duration::from_secs(1)
+            ));
+            assert!(matches!(
+                disposition(&result, 3),
+                JobDisposition::Fail(WebhookJobError {
+                    r#type: app_metrics::ErrorType::BadHttpStatus(s),
+                    ..
+                }) if s == status
+            ));
+        }
+
+        // 4XX, failing even with attempts remaining.
+        for status in [400, 404, 409] {
+            assert!(matches!(
+                disposition(&request_result(status, None), 1),
+                JobDisposition::Fail(WebhookJobError {
+                    r#type: app_metrics::ErrorType::BadHttpStatus(s),
+                    ..
+                }) if s == status
+            ));
+        }
+
+        // Network errors, without a response.
+        let result = Err(WebhookError::RetryableRequestError {
+            error: reqwest::Client::new()
+                .get("not a url")
+                .build()
+                .expect_err("request should be invalid"),
+            response: None,
+            retry_after: None,
+        });
+        assert!(matches!(
+            disposition(&result, 1),
+            JobDisposition::Retry(interval) if interval == time::Duration::from_secs(1)
+        ));
+        assert!(matches!(
+            disposition(&result, 3),
+            JobDisposition::Fail(WebhookJobError {
+                r#type: app_metrics::ErrorType::ConnectionError,
+                ..
+            })
+        ));
+
+        let result = Err(WebhookError::RetryableTimeoutError {
+            phase: ResponsePhase::Headers,
+            timeout: time::Duration::from_secs(5),
+        });
+        assert!(matches!(
+            disposition(&result, 2),
+            JobDisposition::Retry(interval) if interval == time::Duration::from_secs(2)
+        ));
+        assert!(matches!(
+            disposition(&result, 3),
+            JobDisposition::Fail(WebhookJobError {
+                r#type: app_metrics::ErrorType::TimeoutError,
+                request_error_kind: Some(WebhookRequestErrorKind::Timeout),
+                ..
+            })
+        ));
+
+        // Errors that happen before sending a request.
+        let result = Err(WebhookError::DisallowedHostError("localhost".to_owned()));
+        assert!(matches!(
+            disposition(&result, 1),
+            JobDisposition::Fail(WebhookJobError {
+                r#type: app_metrics::ErrorType::ConnectionError,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_job_disposition_follows_classifier() {
+        let retry_policy = RetryPolicy::build(2, time::Duration::from_secs(1)).provide();
+        let classifier = RetryClassifier::default()
+            .rule(409..=409, RetryAction::Discard)
+            .rule(
+                503..=503,
+                RetryAction::Retry {
+                    interval_multiplier: 4,
+                },
+            );
+        let disposition = |result: &Result<reqwest::Response, WebhookError>, attempt| {
+            job_disposition(
+                result,
+                &attempted_job(attempt, 3),
+                &retry_policy,
+                &classifier,
+            )
+        };
+
+        assert!(matches!(
+            disposition(&request_result(409, None), 1),
+            JobDisposition::Discard
+        ));
+        // Discarded jobs are not retried, so they are discarded on their last attempt too.
+        assert!(matches!(
+            disposition(&request_result(409, None), 3),
+            JobDisposition::Discard
+        ));
+        assert!(matches!(
+            disposition(&request_result(503, None), 2),
+            JobDisposition::Retry(interval) if interval == time::Duration::from_secs(8)
+        ));
+
+        // The multiplied interval is still capped, and Retry-After is followed as it is.
+        let retry_policy = RetryPolicy::build(2, time::Duration::from_secs(1))
+            .maximum_interval(time::Duration::from_secs(60))
+            .provide();
+        let disposition = |result: &Result<reqwest::Response, WebhookError>, attempt| {
+            job_disposition(
+                result,
+                &attempted_job(attempt, 10),
+                &retry_policy,
+                &classifier,
+            )
+        };
+        assert!(matches!(
+            disposition(&request_result(503, None), 8),
+            JobDisposition::Retry(interval) if interval == time::Duration::from_secs(60)
+        ));
+        assert!(matches!(
+            disposition(&request_result(503, Some(time::Duration::from_secs(30))), 1),
+            JobDisposition::Retry(interval) if interval == time::Duration::from_secs(30)
+        ));
+    }
+
+    #[test]
+    fn test_is_retryable_status() {
+        let classifier = RetryClassifier::default();
+
+        assert!(!is_retryable_status(
+            &classifier,
+            http::StatusCode::FORBIDDEN
+        ));
+        assert!(!is_retryable_status(
+            &classifier,
+            http::StatusCode::BAD_REQUEST
+        ));
+        assert!(is_retryable_status(
+            &classifier,
+            http::StatusCode::TOO_MANY_REQUESTS
+        ));
+        assert!(is_retryable_status(
+            &classifier,
+            http::StatusCode::INTERNAL_SERVER_ERROR
+        ));
+
+        let classifier = classifier.rule(418..=418, RetryAction::RETRY);
+
+        assert!(is_retryable_status(
+            &classifier,
+            http::StatusCode::IM_A_TEAPOT
+        ));
+    }
+
+    #[test]
+    fn test_parse_retry_after_header() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(reqwest::header::RETRY_AFTER, "120".parse().unwrap());
+
+        let duration = parse_retry_after_header(&headers).unwrap();
+        assert_eq!(duration, time::Duration::from_secs(120));
+
+        headers.remove(reqwest::header::RETRY_AFTER);
+
+        let duration = parse_retry_after_header(&headers);
+        assert_eq!(duration, None);
+
+        headers.insert(
+            reqwest::header::RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+
+        let duration = parse_retry_after_header(&headers);
+        assert_eq!(duration, None);
+    }
+
+    #[test]
+    fn test_resolve_url_with_tenant_base_url() {
+        let request_options = RequestOptions {
+            tenant_base_urls:
+                "1=https://tenant-one.example.com/hooks/, 2=https://tenant-two.example.com"
+                    .parse()
+                    .unwrap(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            request_options.resolve_url("/events", 1).unwrap(),
+            "https://tenant-one.example.com/hooks/events"
+        );
+        assert_eq!(
+            request_options.resolve_url("events?a=b", 2).unwrap(),
+            "https://tenant-two.example.com/events?a=b"
+        );
+        // Absolute URLs are not resolved against the team's base URL.
+        assert_eq!(
+            request_options
+                .resolve_url("https://example.com/hook", 1)
+                .unwrap(),
+            "https://example.com/hook"
+        );
+        assert!(matches!(
+            request_options.resolve_url("/events", 3),
+            Err(WebhookError::ParseUrlError(
+                url::ParseError::RelativeUrlWithoutBase
+            ))
+        ));
+
+        // Relative URLs can't leave the base URL's path.
+        assert_eq!(
+            request_options.resolve_url("events/../other", 1).unwrap(),
+            "https://tenant-one.example.com/hooks/other"
+        );
+        for url in [
+            "../admin",
+            "/events/../../admin",
+            "%2e%2e/admin",
+            "..\\admin",
+            "..",
+        ] {
+            assert!(
+                matches!(
+                    request_options.resolve_url(url, 1),
+                    Err(WebhookError::EscapedBaseUrlError(_))
+                ),
+                "{} was resolved to {:?}",
+                url,
+                request_options.resolve_url(url, 1)
+            );
+        }
+    }
+
+    #[test]
+    fn test_allowed_hosts() {
+        let allowed_hosts: AllowedHosts = "example.com, .hooks.test".parse().unwrap();
+
+        assert!(allowed_hosts.allows("example.com"));
+        assert!(allowed_hosts.allows("webhooks.EXAMPLE.com"));
+        assert!(allowed_hosts.allows("a.b.hooks.test."));
+        assert!(!allowed_hosts.allows("notexample.com"));
+        assert!(!allowed_hosts.allows("example.com.evil.test"));
+        assert!(!allowed_hosts.allows("127.0.0.1"));
+
+        assert!(AllowedHosts::default().allows("127.0.0.1"));
+        assert_eq!("".parse::<AllowedHosts>().unwrap(), AllowedHosts::default());
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_disallowed_host_is_failed(db: PgPool) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let router = axum::Router::new().route(
+            "/",
+            axum::routing::post({
+                let requests = requests.clone();
+                move || async move {
+                    requests.fetch_add(1, Ordering::SeqCst);
+                }
+            }),
+        );
+        let url = serve_mock_destination(router).await;
+        let queue = PgQueue::new_from_pool("test_disallowed_host_is_failed", db.clone())
+            .await
+            .expect("failed to connect to PG");
+        enqueue_job(
+            &queue,
+            3,
+            webhook_job_parameters(&url),
+            webhook_job_metadata(),
+        )
+        .await
+        .expect("failed to enqueue job");
+        let webhook_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = queue
+            .dequeue(&worker_id())
+            .await
+            .expect("failed to dequeue job")
+            .expect("didn't find a job to dequeue");
+        let job_id = webhook_job.id();
+
+        process_webhook_job(
+            reqwest::Client::new(),
+            webhook_job,
+            &sync::Semaphore::new(1),
+            &RetryPolicy::default(),
+            &RequestOptions {
+                allowed_hosts: "example.com".parse().unwrap(),
+                ..Default::default()
+            },
+            never_signaled(),
+            None,
+        )
+        .await
+        .expect("failed to process webhook job");
+
+        let status: JobStatus = sqlx::query_scalar("SELECT status FROM job_queue WHERE id = $1")
+            .bind(job_id)
+            .fetch_one(&db)
+            .await
+            .expect("failed to fetch job status");
+
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+        // The job had attempts left, but it must not be retried.
+        assert_eq!(status, JobStatus::Failed);
+    }
+
+    #[tokio::test]
+    async fn test_sends_requests_through_proxy() {
+        // Requests sent through a proxy use the absolute URL of the destination, which is routed by its path.
+        let router =
+            axum::Router::new().route("/hook", axum::routing::post(|| async { "proxied" }));
+        let proxy_url = serve_mock_destination(router).await;
+
+        let mut builder = client_builder(time::Duration::from_secs(5));
+        for proxy in proxies(&proxy_url, "", "").expect("failed to build proxies") {
+            builder = builder.proxy(proxy);
+        }
+        let client = builder.build().expect("failed to build client");
+
+        let response = client
+            .post("http://webhooks.invalid/hook")
+            .send()
+            .await
+            .expect("failed to send request through proxy");
+        assert_eq!(response.text().await.unwrap(), "proxied");
+
+        // Hosts in `no_proxy` are not proxied, so they have to resolve, and this one doesn't.
+        let mut builder = client_builder(time::Duration::from_secs(5));
+        for proxy in proxies(&proxy_url, "", "webhooks.invalid").expect("failed to build proxies") {
+            builder = builder.proxy(proxy);
+        }
+        let client = builder.build().expect("failed to build client");
+        assert!(client
+            .post("http://webhooks.invalid/hook")
+            .send()
+            .await
+            .is_err());
+
+        assert!(proxies("not a url", "", "").is_err());
+        assert!(proxies("", "", "").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_builds_client_with_client_identity() {
+        let fixture = |name: &str| format!("{}/src/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+        let (cert_path, key_path) = (fixture("client.crt"), fixture("client.key"));
+
+        let identity = client_identity(&cert_path, &key_path)
+            .expect("failed to load client identity")
+            .expect("no client identity was loaded");
+        client_builder(time::Duration::from_secs(5))
+            .identity(identity)
+            .build()
+            .expect("failed to build client with client identity");
+
+        assert!(client_identity("", "").unwrap().is_none());
+        assert!(matches!(
+            client_identity(&cert_path, ""),
+            Err(ClientIdentityError::IncompleteError)
+        ));
+        assert!(matches!(
+            client_identity(&fixture("missing.crt"), &key_path),
+            Err(ClientIdentityError::ReadError { .. })
+        ));
+        // A certificate is not a key.
+        assert!(matches!(
+            client_identity(&cert_path, &cert_path),
+            Err(ClientIdentityError::InvalidIdentityError(_))
+        ));
+    }
+
+    #[test]
+    fn test_poll_interval_backs_off_and_resets() {
+        let mut interval = PollInterval::new(
+            time::Duration::from_millis(100),
+            time::Duration::from_millis(1000),
+        );
+        assert_eq!(interval.current(), time::Duration::from_millis(100));
+
+        let mut intervals = Vec::new();
+        for _ in 0..5 {
+            interval.empty();
+            intervals.push(interval.current().as_millis());
+        }
+        assert_eq!(intervals, vec![200, 400, 800, 1000, 1000]);
+
+        // A maximum that's not higher than the minimum keeps the interval fixed.
+        let mut interval = PollInterval::new(
+            time::Duration::from_millis(100),
+            time::Duration::from_millis(0),
+        );
+        interval.empty();
+        assert_eq!(interval.current(), time::Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_request_timeout_is_clamped() {
+        let request_options = RequestOptions {
+            max_request_timeout: Some(time::Duration::from_secs(10)),
+            ..Default::default()
+        };
+
+        assert_eq!(request_options.request_timeout(None), None);
+        assert_eq!(
+            request_options.request_timeout(Some(500)),
+            Some(time::Duration::from_millis(500))
+        );
+        assert_eq!(
+            request_options.request_timeout(Some(60_000)),
+            Some(time::Duration::from_secs(10))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_job_request_timeout_overrides_client_timeout() {
+        let url =
+            serve_delayed_destination(time::Duration::from_secs(2), time::Duration::ZERO).await;
+        let client = client_builder(time::Duration::from_secs(30))
+            .build()
+            .expect("failed to build client");
+        let parameters = WebhookJobParameters {
+            timeout_ms: Some(100),
+            ..webhook_job_parameters(&url)
+        };
+
+        let start = tokio::time::Instant::now();
+        let result = send_webhook_with_fallbacks(
+            client,
+            &parameters,
+            1,
+            &parameters.headers,
+            &RequestOptions::default(),
+        )
+        .await;
+
+        match result {
+            Err(WebhookError::RetryableRequestError { error, .. }) => assert!(error.is_timeout()),
+            result => panic!("unexpected result: {:?}", result.map(|r| r.status())),
+        }
+        assert!(start.elapsed() < time::Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_adaptive_timeout_tracks_p95() {
+        let adaptive_timeouts = AdaptiveTimeouts::new(
+            3.0,
+            time::Duration::from_millis(10),
+            time::Duration::from_secs(1),
+        );
+
+        // Not enough response times were recorded to tell yet.
+        for _ in 0..ADAPTIVE_TIMEOUT_MIN_SAMPLES - 1 {
+            adaptive_timeouts.record("a.example.com", time::Duration::from_millis(50));
+        }
+        assert_eq!(adaptive_timeouts.timeout("a.example.com"), None);
+
+        for ms in 1..=100 {
+            adaptive_timeouts.record("a.example.com", time::Duration::from_millis(ms));
+        }
+        assert_eq!(
+            adaptive_timeouts.p95("a.example.com"),
+            Some(time::Duration::from_millis(95))
+        );
+        assert_eq!(
+            adaptive_timeouts.timeout("a.example.com"),
+            Some(time::Duration::from_millis(285))
+        );
+
+        // Older response times are forgotten as the host slows down.
+        for _ in 0..ADAPTIVE_TIMEOUT_WINDOW {
+            adaptive_timeouts.record("a.example.com", time::Duration::from_millis(200));
+        }
+        assert_eq!(
+            adaptive_timeouts.timeout("a.example.com"),
+            Some(time::Duration::from_millis(600))
+        );
+        for _ in 0..ADAPTIVE_TIMEOUT_WINDOW {
+            adaptive_timeouts.record("a.example.com", time::Duration::from_millis(500));
+        }
+        assert_eq!(
+            adaptive_timeouts.timeout("a.example.com"),
+            Some(time::Duration::from_secs(1))
+        );
+
+        // Every host has its own timeout.
+        for _ in 0..ADAPTIVE_TIMEOUT_MIN_SAMPLES {
+            adaptive_timeouts.record("b.example.com", time::Duration::from_millis(1));
+        }
+        assert_eq!(
+            adaptive_timeouts.timeout("b.example.com"),
+            Some(time::Duration::from_millis(10))
+        );
+        assert_eq!(adaptive_timeouts.timeout("c.example.com"), None);
+    }
+
+    #[tokio::test]
+    async fn test_adaptive_timeout_fails_slow_requests_faster() {
+        let url =
+            serve_delayed_destination(time::Duration::from_secs(2), time::Duration::ZERO).await;
+        let client = client_builder(time::Duration::from_secs(30))
+            .build()
+            .expect("failed to build client");
+        let adaptive_timeouts = Arc::new(AdaptiveTimeouts::new(
+            2.0,
+            time::Duration::from_millis(100),
+            time::Duration::from_secs(30),
+        ));
+        for _ in 0..ADAPTIVE_TIMEOUT_MIN_SAMPLES {
+            adaptive_timeouts.record("127.0.0.1", time::Duration::from_millis(20));
+        }
+        let request_options = RequestOptions {
+            adaptive_timeouts: Some(adaptive_timeouts.clone()),
+            ..Default::default()
+        };
+        let parameters = webhook_job_parameters(&url);
+
+        let start = tokio::time::Instant::now();
+        let result = send_webhook_with_fallbacks(
+            client,
+            &parameters,
+            1,
+            &parameters.headers,
+            &request_options,
+        )
+        .await;
+
+        match result {
+            Err(WebhookError::RetryableRequestError { error, .. }) => assert!(error.is_timeout()),
+            result => panic!("unexpected result: {:?}", result.map(|r| r.status())),
+        }
+        assert!(start.elapsed() < time::Duration::from_secs(1));
+        // The time waited until timing out is recorded, so that a host that slowed down for good gets more time.
+        let latencies = adaptive_timeouts.latencies.lock().unwrap();
+        let host_latencies = &latencies["127.0.0.1"];
+        assert_eq!(host_latencies.len(), ADAPTIVE_TIMEOUT_MIN_SAMPLES + 1);
+        assert!(host_latencies.back() >= Some(&time::Duration::from_millis(100)));
+    }
+
+    #[tokio::test]
+    async fn test_send_webhook_with_multipart_parts() {
+        let received = Arc::new(std::sync::Mutex::new(None));
+        let router = axum::Router::new().route(
+            "/",
+            axum::routing::post({
+                let received = received.clone();
+                move |headers: axum::http::HeaderMap, body: String| async move {
+                    let content_type = headers
+                        .get(axum::http::header::CONTENT_TYPE)
+                        .and_then(|content_type| content_type.to_str().ok())
+                        .map(str::to_owned);
+                    *received.lock().unwrap() = Some((content_type, body));
+                    axum::http::StatusCode::OK
+                }
+            }),
+        );
+        let url = serve_mock_destination(router).await;
+        let headers = collections::HashMap::from([(
+            "Content-Type".to_owned(),
+            "application/json".to_owned(),
+        )]);
+        let parts = vec![
+            MultipartPart {
+                name: "metadata".to_owned(),
+                content: r#"{"team_id": 1}"#.to_owned(),
+                file_name: None,
+                content_type: None,
+            },
+            MultipartPart {
+                name: "file".to_owned(),
+                content: "a,b\n1,2\n".to_owned(),
+                file_name: Some("export.csv".to_owned()),
+                content_type: Some("text/csv".to_owned()),
+            },
+        ];
+
+        send_webhook(
+            reqwest::Client::new(),
+            &WebhookJobParameters {
+                body: String::new(),
+                multipart: parts,
+                ..webhook_job_parameters(&url)
+            },
+            &url,
+            &headers,
+            &RequestOptions::default(),
+        )
+        .await
+        .expect("send_webhook failed");
+
+        let (content_type, body) = received
+            .lock()
+            .unwrap()
+            .clone()
+            .expect("destination received no request");
+        let content_type = content_type.expect("request has no Content-Type");
+        let boundary = content_type
+            .strip_prefix("multipart/form-data; boundary=")
+            .expect("request is not multipart/form-data");
+        assert_eq!(
+            body,
+            format!(
+                "--{boundary}\r\n\
+                 Content-Disposition: form-data; name=\"metadata\"\r\n\
+                 \r\n\
+                 {{\"team_id\": 1}}\r\n\
+                 --{boundary}\r\n\
+                 Content-Disposition: form-data; name=\"file\"; filename=\"export.csv\"\r\n\
+                 Content-Type: text/csv\r\n\
+                 \r\n\
+                 a,b\n1,2\n\r\n\
+                 --{boundary}--\r\n"
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_webhook_with_multipart_parts_and_body_fails() {
+        let parts = vec![MultipartPart {
+            name: "file".to_owned(),
+            content: "content".to_owned(),
+            file_name: None,
+            content_type: None,
+        }];
+
+        let result = send_webhook(
+            reqwest::Client::new(),
+            &WebhookJobParameters {
+                body: "a body".to_owned(),
+                multipart: parts,
+                ..webhook_job_parameters("http://localhost:18081/echo")
+            },
+            "http://localhost:18081/echo",
+            &collections::HashMap::new(),
+            &RequestOptions::default(),
+        )
+        .await;
+
+        assert!(matches!(result, Err(WebhookError::MultipartWithBodyError)));
+    }
+
+    #[test]
+    fn test_default_headers_are_merged_with_job_headers() {
+        let request_options = RequestOptions {
+            default_headers: "User-Agent=rusty-hook/1.0, X-Deployment-Id=us-east-1"
+                .parse()
+                .unwrap(),
+            ..Default::default()
+        };
+
+        let headers = request_options.headers(
+            1,
+            &collections::HashMap::from([("Content-Type".to_owned(), "text/plain".to_owned())]),
+            "",
+        );
+        assert_eq!(
+            headers.get("user-agent").map(String::as_str),
+            Some("rusty-hook/1.0")
+        );
+        assert_eq!(
+            headers.get("x-deployment-id").map(String::as_str),
+            Some("us-east-1")
+        );
+        assert_eq!(
+            headers.get("Content-Type").map(String::as_str),
+            Some("text/plain")
+        );
+
+        // Headers set by the job take precedence, regardless of case.
+        let headers = request_options.headers(
+            1,
+            &collections::HashMap::from([("USER-AGENT".to_owned(), "my-agent".to_owned())]),
+            "",
+        );
+        assert_eq!(
+            headers.get("USER-AGENT").map(String::as_str),
+            Some("my-agent")
+        );
+        assert_eq!(headers.get("user-agent"), None);
+    }
+
+    #[test]
+    fn test_parse_default_headers() {
+        assert_eq!("".parse::<DefaultHeaders>(), Ok(DefaultHeaders::default()));
+        assert!("User-Agent".parse::<DefaultHeaders>().is_err());
+        assert!("Bad Header=value".parse::<DefaultHeaders>().is_err());
+        assert!("X-Header=bad\nvalue".parse::<DefaultHeaders>().is_err());
+    }
+
+    #[test]
+    fn test_metadata_fields_are_sent_as_headers() {
+        let request_options = RequestOptions {
+            metadata_headers: "team_id=X-PostHog-Team-Id, plugin_config_id=X-PostHog-Plugin-Config-Id, delivered_urls=X-Delivered"
+                .parse()
+                .unwrap(),
+            ..Default::default()
+        };
+        let metadata = WebhookJobMetadata {
+            team_id: 42,
+            plugin_id: 2,
+            plugin_config_id: 3,
+            precondition: None,
+            delivered_urls: Vec::new(),
+            recurrence: None,
+            trace_context: Default::default(),
+        };
+
+        // Metadata headers replace headers set by the job, regardless of case.
+        let mut headers =
+            collections::HashMap::from([("x-posthog-team-id".to_owned(), "1".to_owned())]);
+        request_options
+            .metadata_headers
+            .apply(&metadata, &mut headers);
+
+        assert_eq!(
+            headers,
+            collections::HashMap::from([
+                ("x-posthog-team-id".to_owned(), "42".to_owned()),
+                ("x-posthog-plugin-config-id".to_owned(), "3".to_owned()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_metadata_headers() {
+        assert_eq!(
+            "".parse::<MetadataHeaders>(),
+            Ok(MetadataHeaders::default())
+        );
+        assert_eq!(
+            " team_id = X-PostHog-Team-Id ".parse::<MetadataHeaders>(),
+            Ok(MetadataHeaders(vec![(
+                "team_id".to_owned(),
+                header::HeaderName::from_static("x-posthog-team-id")
+            )]))
+        );
+        assert!("team_id".parse::<MetadataHeaders>().is_err());
+        assert!("=X-PostHog-Team-Id".parse::<MetadataHeaders>().is_err());
+        assert!("team_id=Bad Header".parse::<MetadataHeaders>().is_err());
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_sends_precondition_header_from_metadata(db: PgPool) {
+        let received = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let router = axum::Router::new().route(
+            "/",
+            axum::routing::post({
+                let received = received.clone();
+                move |headers: axum::http::HeaderMap| async move {
+                    let values = headers
+                        .get_all("If-Match")
+                        .iter()
+                        .map(|value| value.to_str().unwrap().to_owned())
+                        .collect::<Vec<_>>();
+                    received.lock().unwrap().push(values);
+                }
+            }),
+        );
+        let url = serve_mock_destination(router).await;
+        let queue = PgQueue::new_from_pool("test_sends_precondition_header_from_metadata", db)
+            .await
+            .expect("failed to connect to PG");
+
+        // The precondition replaces a conditional header set by the job itself.
+        let mut parameters = webhook_job_parameters(&url);
+        parameters.headers =
+            collections::HashMap::from([("if-match".to_owned(), r#""v2""#.to_owned())]);
+        let metadata = WebhookJobMetadata {
+            precondition: Some(WebhookPrecondition::IfMatch {
+                etag: r#""v3""#.to_owned(),
+            }),
+            ..webhook_job_metadata()
+        };
+        enqueue_job(&queue, 1, parameters, metadata)
+            .await
+            .expect("failed to enqueue job");
+        enqueue_job(
+            &queue,
+            1,
+            webhook_job_parameters(&url),
+            webhook_job_metadata(),
+        )
+        .await
+        .expect("failed to enqueue job");
+
+        // Process jobs one at a time, so that they are received in order.
+        let consumer = WebhookConsumer::new(
+            &worker_id(),
+            &queue,
+            time::Duration::from_millis(10),
+            time::Duration::from_secs(5),
+            1,
+            RetryPolicy::default(),
+        );
+        consumer
+            .run_n(false, 2)
+            .await
+            .expect("failed to run consumer");
+
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![vec![r#""v3""#.to_owned()], vec![]]
+        );
+    }
+
+    #[test]
+    fn test_json_content_type_is_added_to_json_bodies() {
+        let request_options = RequestOptions::default();
+        let no_headers = collections::HashMap::new();
+
+        for body in [r#"{"event": "$pageview"}"#, " [1, 2, 3]"] {
+            let headers = request_options.headers(1, &no_headers, body);
+            assert_eq!(
+                headers.get("content-type").map(String::as_str),
+                Some("application/json")
+            );
+        }
+
+        for body in [
+            "",
+            "a webhook job body. much wow.",
+            r#""a string""#,
+            "{not json",
+        ] {
+            let headers = request_options.headers(1, &no_headers, body);
+            assert_eq!(headers.get("content-type"), None);
+        }
+
+        // A Content-Type set by the job is kept, regardless of case.
+        let headers = request_options.headers(
+            1,
+            &collections::HashMap::from([("CONTENT-TYPE".to_owned(), "text/plain".to_owned())]),
+            "{}",
+        );
+        assert_eq!(
+            headers.get("CONTENT-TYPE").map(String::as_str),
+            Some("text/plain")
+        );
+        assert_eq!(headers.get("content-type"), None);
+    }
+
+    #[tokio::test]
+    async fn test_json_content_type_is_the_only_default_content_type() {
+        let received = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let router = axum::Router::new().route(
+            "/",
+            axum::routing::post({
+                let received = received.clone();
+                move |headers: axum::http::HeaderMap| async move {
+                    let content_type = headers
+                        .get(axum::http::header::CONTENT_TYPE)
+                        .and_then(|content_type| content_type.to_str().ok())
+                        .map(str::to_owned);
+                    received.lock().unwrap().push(content_type);
+                    axum::http::StatusCode::OK
+                }
+            }),
+        );
+        let url = serve_mock_destination(router).await;
+        let client = client_builder(time::Duration::from_secs(5))
+            .build()
+            .expect("failed to build client");
+        let no_json_content_type = RequestOptions {
+            json_content_type: "".parse::<ContentType>().unwrap().0,
+            ..Default::default()
+        };
+
+        for (request_options, body) in [
+            (&RequestOptions::default(), "{}"),
+            (&RequestOptions::default(), "a webhook job body. much wow."),
+            (&no_json_content_type, "{}"),
+        ] {
+            let headers = request_options.headers(1, &collections::HashMap::new(), body);
+            send_webhook(
+                client.clone(),
+                &WebhookJobParameters {
+                    body: body.to_owned(),
+                    ..webhook_job_parameters(&url)
+                },
+                &url,
+                &headers,
+                request_options,
+            )
+            .await
+            .expect("send_webhook failed");
+        }
+
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![Some("application/json".to_owned()), None, None]
+        );
+    }
+
+    #[test]
+    fn test_json_content_type_can_be_overridden() {
+        let request_options = RequestOptions {
+            json_content_type: "application/vnd.api+json".parse::<ContentType>().unwrap().0,
+            ..Default::default()
+        };
+        let headers = request_options.headers(1, &collections::HashMap::new(), "{}");
+        assert_eq!(
+            headers.get("content-type").map(String::as_str),
+            Some("application/vnd.api+json")
+        );
+
+        let request_options = RequestOptions {
+            json_content_type: "".parse::<ContentType>().unwrap().0,
+            ..Default::default()
+        };
+        let headers = request_options.headers(1, &collections::HashMap::new(), "{}");
+        assert_eq!(headers.get("content-type"), None);
+    }
+
+    #[test]
+    fn test_parse_redirect_policy() {
+        assert_eq!("none".parse(), Ok(RedirectPolicy::None));
+        assert_eq!(" Limited(3) ".parse(), Ok(RedirectPolicy::Limited(3)));
+        assert_eq!("limited(0)".parse(), Ok(RedirectPolicy::Limited(0)));
+
+        for invalid in [
+            "",
+            "all",
+            "limited",
+            "limited()",
+            "limited(-1)",
+            "limited(3",
+        ] {
+            assert!(invalid.parse::<RedirectPolicy>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_content_type() {
+        assert_eq!(
+            "application/json; charset=utf-8".parse::<ContentType>(),
+            Ok(ContentType(Some(
+                "application/json; charset=utf-8".to_owned()
+            )))
+        );
+        assert_eq!("".parse::<ContentType>(), Ok(ContentType(None)));
+        assert!("json".parse::<ContentType>().is_err());
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_invalid_content_type_fails_job(db: PgPool) {
+        let received = Arc::new(AtomicUsize::new(0));
+        let router = axum::Router::new().route(
+            "/",
+            axum::routing::post({
+                let received = received.clone();
+                move || async move {
+                    received.fetch_add(1, Ordering::SeqCst);
+                }
+            }),
+        );
+        let url = serve_mock_destination(router).await;
+        let queue_name = "test_invalid_content_type_fails_job";
+        let queue = PgQueue::new_from_pool(queue_name, db.clone())
+            .await
+            .expect("failed to connect to PG");
+
+        let mut parameters = webhook_job_parameters(&url);
+        parameters.headers =
+            collections::HashMap::from([("Content-Type".to_owned(), "json".to_owned())]);
+        enqueue_job(&queue, 3, parameters, webhook_job_metadata())
+            .await
+            .expect("failed to enqueue job");
+
+        let consumer = WebhookConsumer::new(
+            &worker_id(),
+            &queue,
+            time::Duration::from_millis(10),
+            time::Duration::from_secs(5),
+            10,
+            RetryPolicy::default(),
+        );
+        consumer
+            .run_n(false, 1)
+            .await
+            .expect("failed to run consumer");
+
+        let (status, attempt): (JobStatus, i32) =
+            sqlx::query_as("SELECT status, attempt FROM job_queue WHERE queue = $1")
+                .bind(queue_name)
+                .fetch_one(&db)
+                .await
+                .expect("failed to fetch job row");
+        assert_eq!(status, JobStatus::Failed);
+        assert_eq!(attempt, 1);
+        assert_eq!(received.load(Ordering::SeqCst), 0);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_body_over_max_body_bytes_fails_job(db: PgPool) {
+        let received = Arc::new(AtomicUsize::new(0));
+        let router = axum::Router::new().route(
+            "/",
+            axum::routing::post({
+                let received = received.clone();
+                move || async move {
+                    received.fetch_add(1, Ordering::SeqCst);
+                }
+            }),
+        );
+        let url = serve_mock_destination(router).await;
+        let queue_name = "test_body_over_max_body_bytes_fails_job";
+        let queue = PgQueue::new_from_pool(queue_name, db.clone())
+            .await
+            .expect("failed to connect to PG");
+
+        let mut parameters = webhook_job_parameters(&url);
+        parameters.body = "a".repeat(1025);
+        enqueue_job(&queue, 3, parameters, webhook_job_metadata())
+            .await
+            .expect("failed to enqueue job");
+
+        let consumer = WebhookConsumer::new(
+            &worker_id(),
+            &queue,
+            time::Duration::from_millis(10),
+            time::Duration::from_secs(5),
+            10,
+            RetryPolicy::default(),
+        )
+        .request_options(RequestOptions {
+            max_body_bytes: Some(1024),
+            ..RequestOptions::default()
+        });
+        consumer
+            .run_n(false, 1)
+            .await
+            .expect("failed to run consumer");
+
+        let (status, errors): (JobStatus, Vec<sqlx::types::Json<WebhookJobError>>) =
+            sqlx::query_as("SELECT status, errors FROM job_queue WHERE queue = $1")
+                .bind(queue_name)
+                .fetch_one(&db)
+                .await
+                .expect("failed to fetch job row");
+        // The job had attempts left, but it's failed without being sent.
+        assert_eq!(status, JobStatus::Failed);
+        assert_eq!(received.load(Ordering::SeqCst), 0);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].details.error.message.as_deref(),
+            Some("webhook body of 1025 bytes is larger than the maximum of 1024 bytes")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_multipart_body_size_counts_its_encoding() {
+        let router = axum::Router::new().route("/", axum::routing::post(|| async { "OK" }));
+        let url = serve_mock_destination(router).await;
+        let parameters = WebhookJobParameters {
+            body: String::new(),
+            multipart: vec![MultipartPart {
+                name: "file".to_owned(),
+                content: "a".repeat(1000),
+                file_name: Some("export.csv".to_owned()),
+                content_type: Some("text/csv".to_owned()),
+            }],
+            ..webhook_job_parameters(&url)
+        };
+        let send = |max_body_bytes| {
+            let parameters = parameters.clone();
+            let url = url.clone();
+            async move {
+                send_webhook(
+                    reqwest::Client::new(),
+                    &parameters,
+                    &url,
+                    &collections::HashMap::new(),
+                    &RequestOptions {
+                        max_body_bytes: Some(max_body_bytes),
+                        ..Default::default()
+                    },
+                )
+                .await
+            }
+        };
+
+        // The part's content fits, but not once the boundaries and part headers are added.
+        let size = match send(1024).await {
+            Err(WebhookError::BodyTooLargeError { size, .. }) => size,
+            result => panic!("body wasn't too large: {:?}", result),
+        };
+        assert!(size > 1024, "{}", size);
+
+        let response = send(size).await.expect("send_webhook failed");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(matches!(
+            send(size - 1).await,
+            Err(WebhookError::BodyTooLargeError { .. })
+        ));
+    }
+
+    /// Serve a destination that redirects `/` to `/redirected`, counting the requests `/redirected` receives.
+    async fn serve_redirecting_destination(received: Arc<AtomicUsize>) -> String {
+        let router = axum::Router::new()
+            .route(
+                "/",
+                axum::routing::post(|| async {
+                    axum::response::Redirect::temporary("/redirected")
+                }),
+            )
+            .route(
+                "/redirected",
+                axum::routing::post(move || async move {
+                    received.fetch_add(1, Ordering::SeqCst);
+                }),
+            );
+
+        serve_mock_destination(router).await
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_redirect_fails_job_by_default(db: PgPool) {
+        let received = Arc::new(AtomicUsize::new(0));
+        let url = serve_redirecting_destination(received.clone()).await;
+        let queue_name = "test_redirect_fails_job_by_default";
+        let queue = PgQueue::new_from_pool(queue_name, db.clone())
+            .await
+            .expect("failed to connect to PG");
+
+        enqueue_job(
+            &queue,
+            3,
+            webhook_job_parameters(&url),
+            webhook_job_metadata(),
+        )
+        .await
+        .expect("failed to enqueue job");
+
+        let consumer = WebhookConsumer::new(
+            &worker_id(),
+            &queue,
+            time::Duration::from_millis(10),
+            time::Duration::from_secs(5),
+            10,
+            RetryPolicy::default(),
+        );
+        consumer
+            .run_n(false, 1)
+            .await
+            .expect("failed to run consumer");
+
+        let (status, errors): (JobStatus, Vec<sqlx::types::Json<WebhookJobError>>) =
+            sqlx::query_as("SELECT status, errors FROM job_queue WHERE queue = $1")
+                .bind(queue_name)
+                .fetch_one(&db)
+                .await
+                .expect("failed to fetch job row");
+        // The job had attempts left, but the redirect is not retried.
+        assert_eq!(status, JobStatus::Failed);
+        assert_eq!(received.load(Ordering::SeqCst), 0);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].r#type, app_metrics::ErrorType::BadHttpStatus(307));
+        assert_eq!(
+            errors[0].details.error.message.as_deref(),
+            Some("webhook destination redirected with 307 Temporary Redirect to /redirected, but the redirect was not followed")
+        );
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_limited_redirects_are_followed(db: PgPool) {
+        let received = Arc::new(AtomicUsize::new(0));
+        let url = serve_redirecting_destination(received.clone()).await;
+        let queue_name = "test_limited_redirects_are_followed";
+        let queue = PgQueue::new_from_pool(queue_name, db.clone())
+            .await
+            .expect("failed to connect to PG");
+
+        enqueue_job(
+            &queue,
+            1,
+            webhook_job_parameters(&url),
+            webhook_job_metadata(),
+        )
+        .await
+        .expect("failed to enqueue job");
+
+        let client = RedirectPolicy::Limited(1)
+            .apply(
+                client_builder(time::Duration::from_secs(5)),
+                &AllowedHosts::default(),
+            )
+            .build()
+            .expect("failed to build client");
+        let consumer = WebhookConsumer::new(
+            &worker_id(),
+            &queue,
+            time::Duration::from_millis(10),
+            time::Duration::from_secs(5),
+            10,
+            RetryPolicy::default(),
+        )
+        .client(client);
+        consumer
+            .run_n(false, 1)
+            .await
+            .expect("failed to run consumer");
+
+        let status: JobStatus = sqlx::query_scalar("SELECT status FROM job_queue WHERE queue = $1")
+            .bind(queue_name)
+            .fetch_one(&db)
+            .await
+            .expect("failed to fetch job row");
+        assert_eq!(status, JobStatus::Completed);
+        assert_eq!(received.load(Ordering::SeqCst), 1);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_redirects_to_disallowed_hosts_are_not_followed(db: PgPool) {
+        let router = axum::Router::new().route(
+            "/",
+            axum::routing::post(|| async {
+                axum::response::Redirect::temporary("http://169.254.169.254/latest/meta-data/")
+            }),
+        );
+        let url = serve_mock_destination(router).await;
+        let queue_name = "test_redirects_to_disallowed_hosts_are_not_followed";
+        let queue = PgQueue::new_from_pool(queue_name, db.clone())
+            .await
+            .expect("failed to connect to PG");
+
+        enqueue_job(
+            &queue,
+            3,
+            webhook_job_parameters(&url),
+            webhook_job_metadata(),
+        )
+        .await
+        .expect("failed to enqueue job");
+
+        let allowed_hosts: AllowedHosts = "127.0.0.1".parse().unwrap();
+        let client = RedirectPolicy::Limited(3)
+            .apply(client_builder(time::Duration::from_secs(5)), &allowed_hosts)
+            .build()
+            .expect("failed to build client");
+        let consumer = WebhookConsumer::new(
+            &worker_id(),
+            &queue,
+            time::Duration::from_millis(10),
+            time::Duration::from_secs(5),
+            10,
+            RetryPolicy::default(),
+        )
+        .client(client)
+        .request_options(RequestOptions {
+            allowed_hosts,
+            ..Default::default()
+        });
+        consumer
+            .run_n(false, 1)
+            .await
+            .expect("failed to run consumer");
+
+        let (status, errors): (JobStatus, Vec<sqlx::types::Json<WebhookJobError>>) =
+            sqlx::query_as("SELECT status, errors FROM job_queue WHERE queue = $1")
+                .bind(queue_name)
+                .fetch_one(&db)
+                .await
+                .expect("failed to fetch job row");
+        // The redirect is failed like a webhook sent to the disallowed host in the first place.
+        assert_eq!(status, JobStatus::Failed);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0]
+            .details
+            .error
+            .message
+            .as_deref()
+            .is_some_and(|message| message.contains("169.254.169.254")));
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_sends_relative_url_to_tenant_base_url(db: PgPool) {
+        let router = axum::Router::new().route(
+            "/tenant/events",
+            axum::routing::post(|| async { axum::http::StatusCode::OK }),
+        );
+        let base_url = format!("{}/tenant/", serve_mock_destination(router).await);
+
+        let worker_id = worker_id();
+        let queue =
+            PgQueue::new_from_pool("test_sends_relative_url_to_tenant_base_url", db.clone())
+                .await
+                .expect("failed to connect to PG");
+
+        enqueue_job(
+            &queue,
+            1,
+            webhook_job_parameters("/events"),
+            webhook_job_metadata(),
+        )
+        .await
+        .expect("failed to enqueue job");
+
+        let webhook_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = queue
+            .dequeue(&worker_id)
+            .await
+            .expect("failed to dequeue job")
+            .expect("didn't find a job to dequeue");
+        let job_id = webhook_job.job.id;
+
+        let request_options = RequestOptions {
+            tenant_base_urls: format!("{}={}", webhook_job_metadata().team_id, base_url)
+                .parse()
+                .unwrap(),
+            ..Default::default()
+        };
+        process_webhook_job(
+            reqwest::Client::new(),
+            webhook_job,
+            &sync::Semaphore::new(1),
+            &RetryPolicy::default(),
+            &request_options,
+            never_signaled(),
+            None,
+        )
+        .await
+        .expect("failed to process webhook job");
+
+        let status: JobStatus = sqlx::query_scalar("SELECT status FROM job_queue WHERE id = $1")
+            .bind(job_id)
+            .fetch_one(&db)
+            .await
+            .expect("failed to fetch job");
+        assert_eq!(status, JobStatus::Completed);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_wait_for_job(db: PgPool) {
+        let worker_id = worker_id();
+        let queue_name = "test_wait_for_job".to_string();
+        let queue = PgQueue::new_from_pool(&queue_name, db)
+            .await
+            .expect("failed to connect to PG");
+
+        let webhook_job_parameters = WebhookJobParameters {
+            auth: None,
+            body: "a webhook job body. much wow.".to_owned(),
+            fallback_urls: Vec::new(),
+            fan_out_urls: Vec::new(),
+            headers: collections::HashMap::new(),
+            method: HttpMethod::POST,
+            multipart: Vec::new(),
+            timeout_ms: None,
+            url: "localhost".to_owned(),
+        };
+        let webhook_job_metadata = WebhookJobMetadata {
+            team_id: 1,
+            plugin_id: 2,
+            plugin_config_id: 3,
+            precondition: None,
+            delivered_urls: Vec::new(),
+            recurrence: None,
+            trace_context: Default::default(),
+        };
+        // enqueue takes ownership of the job enqueued to avoid bugs that can cause duplicate jobs.
+        // Normally, a separate application would be enqueueing jobs for us to consume, so no ownership
+        // conflicts would arise. However, in this test we need to do the enqueueing ourselves.
+        // So, we clone the job to keep it around and assert the values returned by wait_for_job.
+        enqueue_job(
+            &queue,
+            1,
+            webhook_job_parameters.clone(),
+            webhook_job_metadata,
+        )
+        .await
+        .expect("failed to enqueue job");
+        let consumer = WebhookConsumer::new(
+            &worker_id,
+            &queue,
+            time::Duration::from_millis(100),
+            time::Duration::from_millis(5000),
+            10,
+            RetryPolicy::default(),
+        );
+
+        let consumed_job = consumer
+            .wait_for_job(None, None, None)
+            .await
+            .expect("failed to wait and read job")
+            .expect("didn't find a job to dequeue");
+
+        assert_eq!(consumed_job.job.attempt, 1);
+        assert!(consumed_job.job.attempted_by.contains(&worker_id));
+        assert_eq!(consumed_job.job.attempted_by.len(), 1);
+        assert_eq!(consumed_job.job.max_attempts, 1);
+        assert_eq!(
+            *consumed_job.job.parameters.as_ref(),
+            webhook_job_parameters
+        );
+        assert_eq!(consumed_job.job.status, JobStatus::Running);
+        assert_eq!(consumed_job.job.target, webhook_job_parameters.url);
+
+        consumed_job
+            .complete()
+            .await
+            .expect("job not successfully completed");
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_wait_for_job_skips_paused_queue(db: PgPool) {
+        let worker_id = worker_id();
+        let queue = PgQueue::new_from_pool("test_wait_for_job_skips_paused_queue", db)
+            .await
+            .expect("failed to connect to PG");
+
+        enqueue_job(
+            &queue,
+            1,
+            webhook_job_parameters("localhost"),
+            webhook_job_metadata(),
+        )
+        .await
+        .expect("failed to enqueue job");
+        queue.pause().await.expect("failed to pause queue");
+
+        let consumer = WebhookConsumer::new(
+            &worker_id,
+            &queue,
+            time::Duration::from_millis(10),
+            time::Duration::from_millis(5000),
+            10,
+            RetryPolicy::default(),
+        );
+
+        let waited = tokio::time::timeout(
+            time::Duration::from_millis(200),
+            consumer.wait_for_job(None, None, None),
+        )
+        .await;
+        assert!(waited.is_err(), "dequeued a job from a paused queue");
+
+        queue.resume().await.expect("failed to resume queue");
+
+        let consumed_job = tokio::time::timeout(
+            time::Duration::from_millis(1000),
+            consumer.wait_for_job(None, None, None),
+        )
+        .await
+        .expect("timed out waiting for job after resuming")
+        .expect("failed to wait and read job")
+        .expect("didn't find a job to dequeue");
+
+        assert_eq!(consumed_job.job.attempt, 1);
+        consumed_job
+            .complete()
+            .await
+            .expect("job not successfully completed");
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_send_webhook(_: PgPool) {
+        let method = HttpMethod::POST;
+        let url = "http://localhost:18081/echo";
+        let headers = collections::HashMap::new();
+        let body = "a very relevant request body";
+        let client = reqwest::Client::new();
+
+        let response = send_webhook(
+            client,
+            &WebhookJobParameters {
+                method,
+                body: body.to_owned(),
+                ..webhook_job_parameters(url)
+            },
+            url,
+            &headers,
+            &RequestOptions::default(),
+        )
+        .await
+        .expect("send_webhook failed");
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.text().await.expect("failed to read response body"),
+            body.to_owned(),
+        );
+    }
+
+    /// Serve a destination that waits `headers_delay` before responding with headers, and `body_delay` more
+    /// before sending the body, returning its URL.
+    async fn serve_delayed_destination(
+        headers_delay: time::Duration,
+        body_delay: time::Duration,
+    ) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("failed to bind delayed destination");
+        let addr = listener
+            .local_addr()
+            .expect("failed to get delayed destination address");
+
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.expect("failed to accept");
+                tokio::spawn(async move {
+                    let mut request = [0; 4096];
+                    let _ = stream.read(&mut request).await;
+
+                    tokio::time::sleep(headers_delay).await;
+                    let _ = stream
+                        .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\n")
+                        .await;
+                    let _ = stream.flush().await;
+
+                    tokio::time::sleep(body_delay).await;
+                    let _ = stream.write_all(b"hello").await;
+                });
+            }
+        });
+
+        format!("http://{}/", addr)
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_send_webhook_response_headers_timeout(_: PgPool) {
+        let url =
+            serve_delayed_destination(time::Duration::from_secs(2), time::Duration::ZERO).await;
+        let request_options = RequestOptions {
+            response_headers_timeout: Some(time::Duration::from_millis(100)),
+            response_body_timeout: Some(time::Duration::from_secs(5)),
+            ..Default::default()
+        };
+
+        let result = send_webhook(
+            reqwest::Client::new(),
+            &WebhookJobParameters {
+                body: String::new(),
+                ..webhook_job_parameters(&url)
+            },
+            &url,
+            &collections::HashMap::new(),
+            &request_options,
+        )
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(WebhookError::RetryableTimeoutError {
+                phase: ResponsePhase::Headers,
+                ..
+            })
+        ));
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_send_webhook_response_body_timeout(_: PgPool) {
+        let url =
+            serve_delayed_destination(time::Duration::ZERO, time::Duration::from_millis(300)).await;
+
+        let request_options = RequestOptions {
+            response_headers_timeout: Some(time::Duration::from_millis(100)),
+            response_body_timeout: Some(time::Duration::from_millis(100)),
+            ..Default::default()
+        };
+        let result = send_webhook(
+            reqwest::Client::new(),
+            &WebhookJobParameters {
+                body: String::new(),
+                ..webhook_job_parameters(&url)
+            },
+            &url,
+            &collections::HashMap::new(),
+            &request_options,
+        )
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(WebhookError::RetryableTimeoutError {
+                phase: ResponsePhase::Body,
+                ..
+            })
+        ));
+
+        // A slow body is tolerated if it arrives within the body timeout, even past the headers timeout.
+        let request_options = RequestOptions {
+            response_headers_timeout: Some(time::Duration::from_millis(100)),
+            response_body_timeout: Some(time::Duration::from_secs(5)),
+            ..Default::default()
+        };
+        let response = send_webhook(
+            reqwest::Client::new(),
+            &WebhookJobParameters {
+                body: String::new(),
+                ..webhook_job_parameters(&url)
+            },
+            &url,
+            &collections::HashMap::new(),
+            &request_options,
+        )
+        .await
+        .expect("send_webhook failed");
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.text().await.expect("failed to read response body"),
+            "hello"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_webhook_caps_large_success_body() {
+        let router = axum::Router::new().route(
+            "/",
+            axum::routing::post(|| async { vec![b'a'; 8 * 1024 * 1024] }),
+        );
+        let url = serve_mock_destination(router).await;
+
+        let request_options = RequestOptions {
+            max_response_body_size: 100,
+            response_body_timeout: Some(time::Duration::from_secs(5)),
+            ..Default::default()
+        };
+        let response = send_webhook(
+            reqwest::Client::new(),
+            &WebhookJobParameters {
+                body: String::new(),
+                ..webhook_job_parameters(&url)
+            },
+            &url,
+            &collections::HashMap::new(),
+            &request_options,
+        )
+        .await
+        .expect("send_webhook failed");
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response
+                .bytes()
+                .await
+                .expect("failed to read response body")
+                .len(),
+            100
+        );
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_max_concurrent_jobs_per_team(db: PgPool) {
+        let started = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let in_flight = Arc::new(std::sync::Mutex::new(
+            collections::HashMap::<u32, usize>::new(),
+        ));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+
+        let router = axum::Router::new().route(
+            "/:team_id",
+            axum::routing::post({
+                let started = started.clone();
+                let in_flight = in_flight.clone();
+                let max_in_flight = max_in_flight.clone();
+                move |axum::extract::Path(team_id): axum::extract::Path<u32>| async move {
+                    started.lock().unwrap().push(team_id);
+                    {
+                        let mut in_flight = in_flight.lock().unwrap();
+                        let current = in_flight.entry(team_id).or_insert(0);
+                        *current += 1;
+                        max_in_flight.fetch_max(*current, Ordering::SeqCst);
+                    }
+                    tokio::time::sleep(time::Duration::from_millis(50)).await;
+                    *in_flight.lock().unwrap().get_mut(&team_id).unwrap() -= 1;
+                }
+            }),
+        );
+        let url = serve_mock_destination(router).await;
+        let queue_name = "test_max_concurrent_jobs_per_team";
+        let queue = PgQueue::new_from_pool(queue_name, db.clone())
+            .await
+            .expect("failed to connect to PG");
+
+        // All of team 1's jobs are ahead of team 2's in the queue.
+        for team_id in [1, 1, 1, 2, 2, 2] {
+            enqueue_job(
+                &queue,
+                1,
+                webhook_job_parameters(&format!("{}/{}", url, team_id)),
+                WebhookJobMetadata {
+                    team_id,
+                    ..webhook_job_metadata()
+                },
+            )
+            .await
+            .expect("failed to enqueue job");
+        }
+
+        let consumer = WebhookConsumer::new(
+            &worker_id(),
+            &queue,
+            time::Duration::from_millis(10),
+            time::Duration::from_secs(5),
+            10,
+            RetryPolicy::default(),
+        )
+        .max_concurrent_jobs_per_team(1);
+        consumer
+            .run_n(false, 6)
+            .await
+            .expect("failed to run consumer");
+
+        // Team 2 didn't wait for all of team 1's jobs: both teams' first jobs were processed together.
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 1);
+        let mut first_started = started.lock().unwrap()[..2].to_vec();
+        first_started.sort();
+        assert_eq!(first_started, vec![1, 2]);
+
+        let statuses: Vec<JobStatus> =
+            sqlx::query_scalar("SELECT status FROM job_queue WHERE queue = $1")
+                .bind(queue_name)
+                .fetch_all(&db)
+                .await
+                .expect("failed to fetch job statuses");
+        assert!(statuses
+            .iter()
+            .all(|status| *status == JobStatus::Completed));
+    }
+
+    #[test]
+    fn test_team_concurrency_limits() {
+        let team_concurrency =
+            TeamConcurrency::new(Some(2), collections::HashMap::from([(2, 1), (3, 0)]));
+
+        let permits: Vec<TeamPermit> = [1, 1, 2, 3, 3, 3]
+            .into_iter()
+            .map(|team_id| team_concurrency.acquire(team_id))
+            .collect();
+        let mut saturated = team_concurrency.saturated_teams();
+        saturated.sort();
+        // Team 1 reached the default limit, team 2 its own, and team 3 isn't limited.
+        assert_eq!(saturated, vec![1, 2]);
+
+        drop(permits);
+        assert!(team_concurrency.saturated_teams().is_empty());
+    }
+
+    #[test]
+    fn test_backpressure_shrinks_concurrency_with_failures() {
+        let backpressure = Backpressure::new(0.5, 4, 8);
+
+        // Not enough requests were made to tell yet.
+        for _ in 0..3 {
+            backpressure.record(false);
+        }
+        assert_eq!(backpressure.concurrency(), 8);
+
+        backpressure.record(false);
+        assert_eq!(backpressure.concurrency(), 1);
+
+        // Concurrency recovers in proportion to the requests that succeed.
+        backpressure.record(true);
+        assert_eq!(backpressure.concurrency(), 2);
+        backpressure.record(true);
+        assert_eq!(backpressure.concurrency(), 8);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_backpressure_limits_jobs_in_flight_while_failing(db: PgPool) {
+        let started = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let in_flight = Arc::new(AtomicUsize::new(0));
+
+        let router = axum::Router::new().route(
+            "/",
+            axum::routing::post({
+                let started = started.clone();
+                let in_flight = in_flight.clone();
+                move || async move {
+                    let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    started.lock().unwrap().push(current);
+                    tokio::time::sleep(time::Duration::from_millis(50)).await;
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    axum::http::StatusCode::SERVICE_UNAVAILABLE
+                }
+            }),
+        );
+        let url = serve_mock_destination(router).await;
+        let queue = PgQueue::new_from_pool("test_backpressure_limits_jobs_in_flight", db)
+            .await
+            .expect("failed to connect to PG");
+
+        for _ in 0..12 {
+            enqueue_job(
+                &queue,
+                1,
+                webhook_job_parameters(&url),
+                webhook_job_metadata(),
+            )
+            .await
+            .expect("failed to enqueue job");
+        }
+
+        let consumer = WebhookConsumer::new(
+            &worker_id(),
+            &queue,
+            time::Duration::from_millis(10),
+            time::Duration::from_secs(5),
+            4,
+            RetryPolicy::default(),
+        )
+        .failure_rate_backpressure(0.5, 4);
+        consumer
+            .run_n(false, 12)
+            .await
+            .expect("failed to run consumer");
+
+        let started = started.lock().unwrap().clone();
+        assert_eq!(started.len(), 12);
+        // The first jobs all run together, but once they've failed the rest run one at a time. Jobs dequeued
+        // before the failures were recorded may still overlap.
+        assert_eq!(started[..4].iter().max(), Some(&4));
+        assert!(started[8..].iter().all(|in_flight| *in_flight == 1));
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_backpressure_ignores_client_errors(db: PgPool) {
+        let router = axum::Router::new()
+            .route(
+                "/bad-request",
+                axum::routing::post(|| async { axum::http::StatusCode::BAD_REQUEST }),
+            )
+            .route(
+                "/unavailable",
+                axum::routing::post(|| async { axum::http::StatusCode::SERVICE_UNAVAILABLE }),
+            );
+        let url = serve_mock_destination(router).await;
+        let queue = PgQueue::new_from_pool("test_backpressure_ignores_client_errors", db)
+            .await
+            .expect("failed to connect to PG");
+        let backpressure = Backpressure::new(0.5, 4, 8);
+
+        let process_jobs = |path: &'static str| {
+            let queue = queue.clone();
+            let url = format!("{}{}", url, path);
+            let backpressure = &backpressure;
+            async move {
+                for _ in 0..4 {
+                    enqueue_job(
+                        &queue,
+                        1,
+                        webhook_job_parameters(&url),
+                        webhook_job_metadata(),
+                    )
+                    .await
+                    .expect("failed to enqueue job");
+                    let webhook_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = queue
+                        .dequeue(&worker_id())
+                        .await
+                        .expect("failed to dequeue job")
+                        .expect("didn't find a job to dequeue");
+
+                    process_webhook_job(
+                        reqwest::Client::new(),
+                        webhook_job,
+                        &sync::Semaphore::new(1),
+                        &RetryPolicy::default(),
+                        &RequestOptions::default(),
+                        never_signaled(),
+                        Some(backpressure),
+                    )
+                    .await
+                    .expect("failed to process webhook job");
+                }
+            }
+        };
+
+        // A destination rejecting bad payloads is up, so jobs are let in flight as usual.
+        process_jobs("/bad-request").await;
+        assert_eq!(backpressure.concurrency(), 8);
+
+        process_jobs("/unavailable").await;
+        assert_eq!(backpressure.concurrency(), 1);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_max_concurrent_requests(db: PgPool) {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+
+        let router = axum::Router::new().route(
+            "/slow",
+            axum::routing::post({
+                let in_flight = in_flight.clone();
+                let max_in_flight = max_in_flight.clone();
+                move || async move {
+                    let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_in_flight.fetch_max(current, Ordering::SeqCst);
+                    tokio::time::sleep(time::Duration::from_millis(50)).await;
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                }
+            }),
+        );
+        let url = format!("{}/slow", serve_mock_destination(router).await);
+
+        let worker_id = worker_id();
+        let queue = PgQueue::new_from_pool("test_max_concurrent_requests", db)
+            .await
+            .expect("failed to connect to PG");
+
+        for _ in 0..10 {
+            enqueue_job(
+                &queue,
+                1,
+                webhook_job_parameters(&url),
+                webhook_job_metadata(),
+            )
+            .await
+            .expect("failed to enqueue job");
+        }
+
+        let context = JobProcessingContext {
+            semaphore: Arc::new(sync::Semaphore::new(10)),
+            request_semaphore: Arc::new(sync::Semaphore::new(2)),
+            ..job_processing_context(Some(queue.clone()))
+        };
+        let mut handles = Vec::new();
+
+        for _ in 0..10 {
+            let webhook_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = queue
+                .dequeue(&worker_id)
+                .await
+                .expect("failed to dequeue job")
+                .expect("didn't find a job to dequeue");
+
+            handles.push(
+                spawn_webhook_job_processing_task(&context, RetryPolicy::default(), webhook_job)
+                    .await,
+            );
+        }
+
+        for handle in handles {
+            handle
+                .await
+                .expect("webhook job processing task panicked")
+                .expect("failed to process webhook job");
+        }
+
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_panicking_job_is_failed(db: PgPool) {
+        let worker_id = worker_id();
+        let queue = PgQueue::new_from_pool("test_panicking_job_is_failed", db.clone())
+            .await
+            .expect("failed to connect to PG");
+
+        enqueue_job(
+            &queue,
+            3,
+            webhook_job_parameters("http://localhost"),
+            webhook_job_metadata(),
+        )
+        .await
+        .expect("failed to enqueue job");
+        let webhook_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = queue
+            .dequeue(&worker_id)
+            .await
+            .expect("failed to dequeue job")
+            .expect("didn't find a job to dequeue");
+        let job_id = webhook_job.id();
+        // Processing a job panics if the request semaphore is closed.
+        let request_semaphore = Arc::new(sync::Semaphore::new(1));
+        request_semaphore.close();
+
+        let handle = spawn_webhook_job_processing_task(
+            &JobProcessingContext {
+                request_semaphore,
+                ..job_processing_context(Some(queue.clone()))
+            },
+            RetryPolicy::default(),
+            webhook_job,
+        )
+        .await;
+
+        // The panic is caught within the task, so the worker running it survives.
+        handle
+            .await
+            .expect("webhook job processing task panicked")
+            .expect("failed to process webhook job");
+
+        let (status, errors): (JobStatus, Vec<sqlx::types::Json<WebhookJobError>>) =
+            sqlx::query_as("SELECT status, errors FROM job_queue WHERE id = $1")
+                .bind(job_id)
+                .fetch_one(&db)
+                .await
+                .expect("failed to fetch job row");
+
+        // The job had attempts left, but it's failed instead of being stranded in running.
+        assert_eq!(status, JobStatus::Failed);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].r#type,
+            hook_common::kafka_messages::app_metrics::ErrorType::InternalError
+        );
+        assert_eq!(errors[0].details.error.name, "Internal Error");
+        assert_eq!(
+            errors[0].details.error.message.as_deref(),
+            Some("job processing panicked: request semaphore has been closed: AcquireError(())")
+        );
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_panicking_transactional_job_is_failed(db: PgPool) {
+        let worker_id = worker_id();
+        let queue =
+            PgQueue::new_from_pool("test_panicking_transactional_job_is_failed", db.clone())
+                .await
+                .expect("failed to connect to PG");
+
+        enqueue_job(
+            &queue,
+            3,
+            webhook_job_parameters("http://localhost"),
+            webhook_job_metadata(),
+        )
+        .await
+        .expect("failed to enqueue job");
+        let webhook_job: PgTransactionJob<WebhookJobParameters, WebhookJobMetadata> = queue
+            .dequeue_tx(&worker_id)
+            .await
+            .expect("failed to dequeue job")
+            .expect("didn't find a job to dequeue");
+        let job_id = webhook_job.id();
+        let request_semaphore = Arc::new(sync::Semaphore::new(1));
+        request_semaphore.close();
+
+        let handle = spawn_webhook_job_processing_task(
+            &JobProcessingContext {
+                request_semaphore,
+                ..job_processing_context(Some(queue.clone()))
+            },
+            RetryPolicy::default(),
+            webhook_job,
+        )
+        .await;
+
+        handle
+            .await
+            .expect("webhook job processing task panicked")
+            .expect("failed to process webhook job");
+
+        // Rolling back the transaction undid the attempt, but the job is failed instead of being dequeued again.
+        let (status, errors): (JobStatus, Vec<sqlx::types::Json<WebhookJobError>>) =
+            sqlx::query_as("SELECT status, errors FROM job_queue WHERE id = $1")
+                .bind(job_id)
+                .fetch_one(&db)
+                .await
+                .expect("failed to fetch job row");
+        assert_eq!(status, JobStatus::Failed);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].details.error.message.as_deref(),
+            Some("job processing panicked: request semaphore has been closed: AcquireError(())")
+        );
+        assert!(queue
+            .dequeue_tx::<WebhookJobParameters, WebhookJobMetadata>(&worker_id)
+            .await
+            .expect("failed to dequeue job")
+            .is_none());
+    }
+
+    /// A `tracing` layer keeping the fields of every span named `name` that's opened.
+    struct SpanFieldsLayer {
+        name: &'static str,
+        fields: Arc<std::sync::Mutex<Vec<collections::HashMap<String, String>>>>,
+    }
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for SpanFieldsLayer {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            _: &tracing::span::Id,
+            _: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            struct Visitor(collections::HashMap<String, String>);
+
+            impl tracing::field::Visit for Visitor {
+                fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+                    self.0.insert(field.name().to_owned(), value.to_owned());
+                }
+
+                fn record_debug(
+                    &mut self,
+                    field: &tracing::field::Field,
+                    value: &dyn std::fmt::Debug,
+                ) {
+                    self.0
+                        .insert(field.name().to_owned(), format!("{:?}", value));
+                }
+            }
+
+            if attrs.metadata().name() == self.name {
+                let mut visitor = Visitor(collections::HashMap::new());
+                attrs.record(&mut visitor);
+                self.fields.lock().unwrap().push(visitor.0);
+            }
+        }
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_job_is_processed_in_span_with_job_fields(db: PgPool) {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let fields = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let subscriber = tracing_subscriber::registry().with(SpanFieldsLayer {
+            name: "webhook_job",
+            fields: fields.clone(),
+        });
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let router = axum::Router::new().route("/", axum::routing::post(|| async { "OK" }));
+        let url = serve_mock_destination(router).await;
+        let queue = PgQueue::new_from_pool("test_job_is_processed_in_span_with_job_fields", db)
+            .await
+            .expect("failed to connect to PG");
+
+        enqueue_job(
+            &queue,
+            1,
+            webhook_job_parameters(&url),
+            webhook_job_metadata(),
+        )
+        .await
+        .expect("failed to enqueue job");
+        let webhook_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = queue
+            .dequeue(&worker_id())
+            .await
+            .expect("failed to dequeue job")
+            .expect("didn't find a job to dequeue");
+        let job_id = webhook_job.id();
+
+        spawn_webhook_job_processing_task(
+            &job_processing_context(None),
+            RetryPolicy::default(),
+            webhook_job,
+        )
+        .await
+        .await
+        .expect("webhook job processing task panicked")
+        .expect("failed to process webhook job");
+
+        let fields = fields.lock().unwrap();
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields[0].get("job_id"), Some(&job_id.to_string()));
+        assert_eq!(
+            fields[0].get("queue").map(String::as_str),
+            Some("test_job_is_processed_in_span_with_job_fields")
+        );
+        assert_eq!(fields[0].get("target"), Some(&url));
+        assert_eq!(fields[0].get("attempt").map(String::as_str), Some("1"));
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_max_memory_bytes(db: PgPool) {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let delivered = Arc::new(AtomicUsize::new(0));
+
+        let router = axum::Router::new().route(
+            "/slow",
+            axum::routing::post({
+                let in_flight = in_flight.clone();
+                let max_in_flight = max_in_flight.clone();
+                let delivered = delivered.clone();
+                move || async move {
+                    let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_in_flight.fetch_max(current, Ordering::SeqCst);
+                    tokio::time::sleep(time::Duration::from_millis(50)).await;
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    delivered.fetch_add(1, Ordering::SeqCst);
+                }
+            }),
+        );
+        let url = format!("{}/slow", serve_mock_destination(router).await);
+
+        let worker_id = worker_id();
+        let queue = PgQueue::new_from_pool("test_max_memory_bytes", db)
+            .await
+            .expect("failed to connect to PG");
+
+        // Each job is estimated at 100 KiB of body, 1 KiB of response and 64 KiB of connection overhead,
+        // so only two of them fit in a 400 KiB budget.
+        for _ in 0..6 {
+            let mut parameters = webhook_job_parameters(&url);
+            parameters.body = "a".repeat(100 * 1024);
+            enqueue_job(&queue, 1, parameters, webhook_job_metadata())
+                .await
+                .expect("failed to enqueue job");
+        }
+
+        let consumer = WebhookConsumer::new(
+            &worker_id,
+            &queue,
+            time::Duration::from_millis(1),
+            time::Duration::from_millis(5000),
+            10,
+            RetryPolicy::default(),
+        )
+        .max_memory_bytes(400 * 1024);
+
+        let run = consumer.run(false);
+        tokio::pin!(run);
+
+        let all_delivered = async {
+            while delivered.load(Ordering::SeqCst) < 6 {
+                tokio::time::sleep(time::Duration::from_millis(10)).await;
+            }
+        };
+
+        tokio::select! {
+            result = &mut run => panic!("consumer stopped running: {:?}", result),
+            _ = tokio::time::timeout(time::Duration::from_secs(5), all_delivered) => {}
+        }
+
+        assert_eq!(delivered.load(Ordering::SeqCst), 6);
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_fails_over_to_fallback_url(db: PgPool) {
+        let router = axum::Router::new()
+            .route(
+                "/primary",
+                axum::routing::post(|| async { axum::http::StatusCode::SERVICE_UNAVAILABLE }),
+            )
+            .route(
+                "/fallback",
+                axum::routing::post(|| async { axum::http::StatusCode::OK }),
+            );
+        let base_url = serve_mock_destination(router).await;
+
+        let worker_id = worker_id();
+        let queue = PgQueue::new_from_pool("test_fails_over_to_fallback_url", db.clone())
+            .await
+            .expect("failed to connect to PG");
+
+        let mut parameters = webhook_job_parameters(&format!("{}/primary", base_url));
+        parameters.fallback_urls = vec![
+            format!("{}/fallback", base_url),
+            format!("{}/unused", base_url),
+        ];
+        enqueue_job(&queue, 1, parameters, webhook_job_metadata())
+            .await
+            .expect("failed to enqueue job");
+
+        let webhook_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = queue
+            .dequeue(&worker_id)
+            .await
+            .expect("failed to dequeue job")
+            .expect("didn't find a job to dequeue");
+        let job_id = webhook_job.job.id;
+
+        process_webhook_job(
+            reqwest::Client::new(),
+            webhook_job,
+            &sync::Semaphore::new(1),
+            &RetryPolicy::default(),
+            &RequestOptions::default(),
+            never_signaled(),
+            None,
+        )
+        .await
+        .expect("failed to process webhook job");
+
+        let (status, attempt): (JobStatus, i32) =
+            sqlx::query_as("SELECT status, attempt FROM job_queue WHERE id = $1")
+                .bind(job_id)
+                .fetch_one(&db)
+                .await
+                .expect("failed to fetch job");
+        assert_eq!(status, JobStatus::Completed);
+        assert_eq!(attempt, 1);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_idempotency_key_is_stable_across_attempts(db: PgPool) {
+        let keys = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        // Fail the first attempt so that the job is retried.
+        let router = axum::Router::new().route(
+            "/flaky",
+            axum::routing::post({
+                let keys = keys.clone();
+                move |headers: axum::http::HeaderMap| async move {
+                    let mut keys = keys.lock().unwrap();
+                    keys.push(
+                        headers
+                            .get("Idempotency-Key")
+                            .map(|value| value.to_str().unwrap().to_owned()),
+                    );
+
+                    if keys.len() == 1 {
+                        axum::http::StatusCode::INTERNAL_SERVER_ERROR
+                    } else {
+                        axum::http::StatusCode::OK
+                    }
+                }
+            }),
+        );
+        let url = format!("{}/flaky", serve_mock_destination(router).await);
+        let worker_id = worker_id();
+        let queue = PgQueue::new_from_pool("test_idempotency_key_is_stable_across_attempts", db)
+            .await
+            .expect("failed to connect to PG");
+
+        enqueue_job(
+            &queue,
+            2,
+            webhook_job_parameters(&url),
+            webhook_job_metadata(),
+        )
+        .await
+        .expect("failed to enqueue job");
+
+        let retry_policy = RetryPolicy::build(1, time::Duration::from_secs(0)).provide();
+        let request_options = RequestOptions {
+            idempotency_key_header: Some("Idempotency-Key".to_owned()),
+            ..RequestOptions::default()
+        };
+        let request_semaphore = sync::Semaphore::new(1);
+        let mut job_ids = Vec::new();
+
+        for _ in 0..2 {
+            let webhook_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = queue
+                .dequeue(&worker_id)
+                .await
+                .expect("failed to dequeue job")
+                .expect("didn't find a job to dequeue");
+            job_ids.push(webhook_job.id());
+
+            // The first attempt is expected to fail and be retried.
+            let _ = process_webhook_job(
+                reqwest::Client::new(),
+                webhook_job,
+                &request_semaphore,
+                &retry_policy,
+                &request_options,
+                never_signaled(),
+                None,
+            )
+            .await;
+        }
+
+        let keys = keys.lock().unwrap();
+        assert_eq!(job_ids[0], job_ids[1]);
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0], Some(idempotency_key(job_ids[0])));
+        assert_eq!(keys[0], keys[1]);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_fan_out_retries_only_failed_urls(db: PgPool) {
+        let healthy_requests = Arc::new(AtomicUsize::new(0));
+        let flaky_requests = Arc::new(AtomicUsize::new(0));
+        let router = axum::Router::new()
+            .route(
+                "/healthy",
+                axum::routing::post({
+                    let healthy_requests = healthy_requests.clone();
+                    move || async move {
+                        healthy_requests.fetch_add(1, Ordering::SeqCst);
+                    }
+                }),
+            )
+            .route(
+                "/flaky",
+                axum::routing::post({
+                    let flaky_requests = flaky_requests.clone();
+                    move || async move {
+                        // Fail the first request only.
+                        if flaky_requests.fetch_add(1, Ordering::SeqCst) == 0 {
+                            axum::http::StatusCode::SERVICE_UNAVAILABLE
+                        } else {
+                            axum::http::StatusCode::OK
+                        }
+                    }
+                }),
+            );
+        let base_url = serve_mock_destination(router).await;
+        let healthy_url = format!("{}/healthy", base_url);
+        let flaky_url = format!("{}/flaky", base_url);
+        let worker_id = worker_id();
+        let queue = PgQueue::new_from_pool("test_fan_out_retries_only_failed_urls", db.clone())
+            .await
+            .expect("failed to connect to PG");
+
+        let mut parameters = webhook_job_parameters(&healthy_url);
+        parameters.fan_out_urls = vec![flaky_url.clone()];
+        enqueue_job(&queue, 2, parameters, webhook_job_metadata())
+            .await
+            .expect("failed to enqueue job");
+
+        let retry_policy = RetryPolicy::build(1, time::Duration::from_secs(0)).provide();
+        let mut job_ids = Vec::new();
+
+        for _ in 0..2 {
+            let webhook_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = queue
+                .dequeue(&worker_id)
+                .await
+                .expect("failed to dequeue job")
+                .expect("didn't find a job to dequeue");
+            job_ids.push(webhook_job.id());
+
+            process_webhook_job(
+                reqwest::Client::new(),
+                webhook_job,
+                &sync::Semaphore::new(1),
+                &retry_policy,
+                &RequestOptions::default(),
+                never_signaled(),
+                None,
+            )
+            .await
+            .expect("failed to process webhook job");
+
+            if job_ids.len() == 1 {
+                // The URL the webhook was delivered to is kept across attempts.
+                let metadata: sqlx::types::Json<WebhookJobMetadata> =
+                    sqlx::query_scalar("SELECT metadata FROM job_queue WHERE id = $1")
+                        .bind(job_ids[0])
+                        .fetch_one(&db)
+                        .await
+                        .expect("failed to fetch job metadata");
+                assert_eq!(metadata.delivered_urls, vec![healthy_url.clone()]);
+            }
+        }
+
+        let status: JobStatus = sqlx::query_scalar("SELECT status FROM job_queue WHERE id = $1")
+            .bind(job_ids[0])
+            .fetch_one(&db)
+            .await
+            .expect("failed to fetch job status");
+
+        assert_eq!(job_ids[0], job_ids[1]);
+        assert_eq!(status, JobStatus::Completed);
+        // The retry only re-sent the webhook to the URL that failed.
+        assert_eq!(healthy_requests.load(Ordering::SeqCst), 1);
+        assert_eq!(flaky_requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_fan_out_already_delivered_is_completed_without_requests(db: PgPool) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let router = axum::Router::new().route(
+            "/",
+            axum::routing::post({
+                let requests = requests.clone();
+                move || async move {
+                    requests.fetch_add(1, Ordering::SeqCst);
+                    "OK"
+                }
+            }),
+        );
+        let url = serve_mock_destination(router).await;
+        let fan_out_url = format!("{}/?fan-out", url);
+        let queue = PgQueue::new_from_pool(
+            "test_fan_out_already_delivered_is_completed_without_requests",
+            db.clone(),
+        )
+        .await
+        .expect("failed to connect to PG");
+
+        let mut parameters = webhook_job_parameters(&url);
+        parameters.fan_out_urls = vec![fan_out_url.clone()];
+        let metadata = WebhookJobMetadata {
+            delivered_urls: vec![url.clone(), fan_out_url],
+            ..webhook_job_metadata()
+        };
+        enqueue_job(&queue, 2, parameters, metadata)
+            .await
+            .expect("failed to enqueue job");
+        let webhook_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = queue
+            .dequeue(&worker_id())
+            .await
+            .expect("failed to dequeue job")
+            .expect("didn't find a job to dequeue");
+        let job_id = webhook_job.id();
+
+        process_webhook_job(
+            reqwest::Client::new(),
+            webhook_job,
+            &sync::Semaphore::new(1),
+            &RetryPolicy::default(),
+            &RequestOptions::default(),
+            never_signaled(),
+            None,
+        )
+        .await
+        .expect("failed to process webhook job");
+
+        let (status, response_status): (JobStatus, Option<i32>) =
+            sqlx::query_as("SELECT status, response_status FROM job_queue WHERE id = $1")
+                .bind(job_id)
+                .fetch_one(&db)
+                .await
+                .expect("failed to fetch job");
+
+        assert_eq!(status, JobStatus::Completed);
+        // No request was made, so there is no response to store.
+        assert_eq!(response_status, None);
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_expired_job_is_discarded(db: PgPool) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let router = axum::Router::new().route(
+            "/",
+            axum::routing::post({
+                let requests = requests.clone();
+                move || async move {
+                    requests.fetch_add(1, Ordering::SeqCst);
+                }
+            }),
+        );
+        let url = serve_mock_destination(router).await;
+        let worker_id = worker_id();
+        let queue = PgQueue::new_from_pool("test_expired_job_is_discarded", db.clone())
+            .await
+            .expect("failed to connect to PG");
+
+        let new_job = NewJob::new(
+            3,
+            webhook_job_metadata(),
+            webhook_job_parameters(&url),
+            &url,
+        )
+        .expires_at(chrono::offset::Utc::now() - chrono::Duration::minutes(5));
+        queue.enqueue(new_job).await.expect("failed to enqueue job");
+
+        let webhook_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = queue
+            .dequeue(&worker_id)
+            .await
+            .expect("failed to dequeue job")
+            .expect("didn't find a job to dequeue");
+        let job_id = webhook_job.id();
+
+        process_webhook_job(
+            reqwest::Client::new(),
+            webhook_job,
+            &sync::Semaphore::new(1),
+            &RetryPolicy::default(),
+            &RequestOptions::default(),
+            never_signaled(),
+            None,
+        )
+        .await
+        .expect("failed to process webhook job");
+
+        let (status, errors): (JobStatus, Vec<sqlx::types::Json<WebhookJobError>>) =
+            sqlx::query_as("SELECT status, errors FROM job_queue WHERE id = $1")
+                .bind(job_id)
+                .fetch_one(&db)
+                .await
+                .expect("failed to fetch job row");
+
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+        // The job had attempts left, but it must not be retried.
+        assert_eq!(status, JobStatus::Failed);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].r#type,
+            hook_common::kafka_messages::app_metrics::ErrorType::ExpiredError
+        );
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_failed_job_stores_truncated_response_body(db: PgPool) {
+        let router = axum::Router::new().route(
+            "/fail",
+            axum::routing::post(|| async {
+                (axum::http::StatusCode::BAD_REQUEST, "a".repeat(4096))
+            }),
+        );
+        let url = format!("{}/fail", serve_mock_destination(router).await);
+        let worker_id = worker_id();
+        let queue =
+            PgQueue::new_from_pool("test_failed_job_stores_truncated_response_body", db.clone())
+                .await
+                .expect("failed to connect to PG");
+
+        enqueue_job(
+            &queue,
+            1,
+            webhook_job_parameters(&url),
+            webhook_job_metadata(),
+        )
+        .await
+        .expect("failed to enqueue job");
+
+        let webhook_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = queue
+            .dequeue(&worker_id)
+            .await
+            .expect("failed to dequeue job")
+            .expect("didn't find a job to dequeue");
+        let job_id = webhook_job.id();
+        let request_options = RequestOptions {
+            max_response_body_size: 100,
+            ..RequestOptions::default()
+        };
+
+        process_webhook_job(
+            reqwest::Client::new(),
+            webhook_job,
+            &sync::Semaphore::new(1),
+            &RetryPolicy::default(),
+            &request_options,
+            never_signaled(),
+            None,
+        )
+        .await
+        .expect("failed to process webhook job");
+
+        let (status, errors): (JobStatus, Vec<sqlx::types::Json<WebhookJobError>>) =
+            sqlx::query_as("SELECT status, errors FROM job_queue WHERE id = $1")
+                .bind(job_id)
+                .fetch_one(&db)
+                .await
+                .expect("failed to fetch job row");
+
+        assert_eq!(status, JobStatus::Failed);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].response, Some("a".repeat(100)));
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_failed_job_stores_response_digest(db: PgPool) {
+        let router = axum::Router::new().route(
+            "/fail",
+            axum::routing::post(|| async {
+                (axum::http::StatusCode::BAD_REQUEST, "a".repeat(4096))
+            }),
+        );
+        let url = format!("{}/fail", serve_mock_destination(router).await);
+        let worker_id = worker_id();
+        let queue = PgQueue::new_from_pool("test_failed_job_stores_response_digest", db.clone())
+            .await
+            .expect("failed to connect to PG");
+
+        enqueue_job(
+            &queue,
+            1,
+            webhook_job_parameters(&url),
+            webhook_job_metadata(),
+        )
+        .await
+        .expect("failed to enqueue job");
+
+        let webhook_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = queue
+            .dequeue(&worker_id)
+            .await
+            .expect("failed to dequeue job")
+            .expect("didn't find a job to dequeue");
+        let job_id = webhook_job.id();
+        let request_options = RequestOptions {
+            response_capture: ResponseCapture::Digest,
+            ..RequestOptions::default()
+        };
+
+        process_webhook_job(
+            reqwest::Client::new(),
+            webhook_job,
+            &sync::Semaphore::new(1),
+            &RetryPolicy::default(),
+            &request_options,
+            never_signaled(),
+            None,
+        )
+        .await
+        .expect("failed to process webhook job");
+
+        let errors: Vec<sqlx::types::Json<WebhookJobError>> =
+            sqlx::query_scalar("SELECT errors FROM job_queue WHERE id = $1")
+                .bind(job_id)
+                .fetch_one(&db)
+                .await
+                .expect("failed to fetch job row");
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].response, None);
+        assert_eq!(
+            errors[0].response_digest,
+            Some(ResponseDigest {
+                // The SHA-256 of 4096 'a' characters.
+                sha256: "c93eee2d0db02f10acc7460d9576e122dcf8cd53c4bf8dfcae1b3e74ebcfff5a"
+                    .to_owned(),
+                length: 4096,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_webhook_with_auth() {
+        let router = axum::Router::new().route(
+            "/auth",
+            axum::routing::post(|headers: axum::http::HeaderMap| async move {
+                headers
+                    .get(axum::http::header::AUTHORIZATION)
+                    .map(|value| value.to_str().unwrap().to_owned())
+                    .unwrap_or_default()
+            }),
+        );
+        let url = format!("{}/auth", serve_mock_destination(router).await);
+        // Typed auth takes precedence over any Authorization header in the job's headers.
+        let mut headers = collections::HashMap::new();
+        headers.insert("Authorization".to_owned(), "Bearer overridden".to_owned());
+
+        for (auth, expected) in [
+            (
+                WebhookAuth::Bearer {
+                    token: "a-token".to_owned(),
+                },
+                "Bearer a-token",
+            ),
+            (
+                WebhookAuth::Basic {
+                    username: "user".to_owned(),
+                    password: "pass".to_owned(),
+                },
+                "Basic dXNlcjpwYXNz",
+            ),
+        ] {
+            let response = send_webhook(
+                reqwest::Client::new(),
+                &WebhookJobParameters {
+                    body: String::new(),
+                    auth: Some(auth.clone()),
+                    ..webhook_job_parameters(&url)
+                },
+                &url,
+                &headers,
+                &RequestOptions::default(),
+            )
+            .await
+            .expect("send_webhook failed");
+
+            assert_eq!(
+                response.text().await.expect("failed to read response body"),
+                expected
+            );
+        }
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_retry_policy_is_selected_by_queue(db: PgPool) {
+        let router = axum::Router::new().route(
+            "/fail",
+            axum::routing::post(|| async { axum::http::StatusCode::INTERNAL_SERVER_ERROR }),
+        );
+        let url = format!("{}/fail", serve_mock_destination(router).await);
+        let worker_id = worker_id();
+        let default_policy = RetryPolicy::build(1, time::Duration::from_secs(10)).provide();
+        let degraded_policy = RetryPolicy::build(1, time::Duration::from_secs(600)).provide();
+        let mut queue_retry_policies = collections::HashMap::new();
+        queue_retry_policies.insert("webhooks-degraded".to_owned(), degraded_policy);
+
+        for (queue_name, expected_interval) in [("webhooks", 10.0), ("webhooks-degraded", 600.0)] {
+            let queue = PgQueue::new_from_pool(queue_name, db.clone())
+                .await
+                .expect("failed to connect to PG");
+            enqueue_job(
+                &queue,
+                2,
+                webhook_job_parameters(&url),
+                webhook_job_metadata(),
+            )
+            .await
+            .expect("failed to enqueue job");
+
+            let consumer = WebhookConsumer::new(
+                &worker_id,
+                &queue,
+                time::Duration::from_millis(10),
+                time::Duration::from_millis(5000),
+                10,
+                default_policy.clone(),
+            )
+            .queue_retry_policies(queue_retry_policies.clone());
+
+            let webhook_job = consumer
+                .wait_for_job(None, None, None)
+                .await
+                .expect("failed to wait and read job")
+                .expect("didn't find a job to dequeue");
+            let job_id = webhook_job.id();
+            let retry_policy = consumer.retry_policy_for(&webhook_job.job.queue).clone();
+
+            process_webhook_job(
+                reqwest::Client::new(),
+                webhook_job,
+                &sync::Semaphore::new(1),
+                &retry_policy,
+                &RequestOptions::default(),
+                never_signaled(),
+                None,
+            )
+            .await
+            .expect("failed to process webhook job");
+
+            let retry_in: f64 = sqlx::query_scalar(
+                "SELECT EXTRACT(EPOCH FROM scheduled_at - last_attempt_finished_at)::float8 FROM job_queue WHERE id = $1",
+            )
+            .bind(job_id)
+            .fetch_one(&db)
+            .await
+            .expect("failed to fetch job row");
+
+            assert!(
+                (retry_in - expected_interval).abs() < 1.0,
+                "job in {} was retried in {}s, expected {}s",
+                queue_name,
+                retry_in,
+                expected_interval
+            );
+        }
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_success_criteria_are_applied_by_status_and_body(db: PgPool) {
+        let router = axum::Router::new()
+            .route(
+                "/error",
+                axum::routing::post(|| async { r#"{"status":"error","message":"bad token"}"# }),
+            )
+            .route(
+                "/ok",
+                axum::routing::post(|| async { r#"{"status":"ok"}"# }),
+            )
+            .route(
+                "/accepted",
+                axum::routing::post(|| async { axum::http::StatusCode::ACCEPTED }),
+            )
+            .route(
+                "/gone",
+                axum::routing::post(|| async { axum::http::StatusCode::GONE }),
+            );
+        let base_url = serve_mock_destination(router).await;
+        let retry_policy = RetryPolicy::build(1, time::Duration::from_secs(10)).provide();
+        let request_options = RequestOptions {
+            success_criteria: r#"200~"status":"error"=failure,202=failure,410=success"#
+                .parse()
+                .unwrap(),
+            retry_classifier: "202=retry".parse().unwrap(),
+            ..RequestOptions::default()
+        };
+        let queue = PgQueue::new_from_pool(
+            "test_success_criteria_are_applied_by_status_and_body",
+            db.clone(),
+        )
+        .await
+        .expect("failed to connect to PG");
+
+        for (path, expected_status) in [
+            ("/error", JobStatus::Failed),
+            ("/ok", JobStatus::Completed),
+            ("/accepted", JobStatus::Available),
+            ("/gone", JobStatus::Completed),
+        ] {
+            enqueue_job(
+                &queue,
+                2,
+                webhook_job_parameters(&format!("{}{}", base_url, path)),
+                webhook_job_metadata(),
+            )
+            .await
+            .expect("failed to enqueue job");
+
+            let webhook_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = queue
+                .dequeue(&worker_id())
+                .await
+                .expect("failed to dequeue job")
+                .expect("didn't find a job to dequeue");
+            let job_id = webhook_job.id();
+
+            process_webhook_job(
+                reqwest::Client::new(),
+                webhook_job,
+                &sync::Semaphore::new(1),
+                &retry_policy,
+                &request_options,
+                never_signaled(),
+                None,
+            )
+            .await
+            .expect("failed to process webhook job");
+
+            let (status, errors): (JobStatus, Option<Vec<sqlx::types::Json<WebhookJobError>>>) =
+                sqlx::query_as("SELECT status, errors FROM job_queue WHERE id = $1")
+                    .bind(job_id)
+                    .fetch_one(&db)
+                    .await
+                    .expect("failed to fetch job row");
+            assert_eq!(status, expected_status, "unexpected status for {}", path);
+
+            if path == "/error" {
+                // A 200 isn't retried by default, and the error body is kept to tell why the job failed.
+                let errors = errors.expect("no errors were stored");
+                assert_eq!(errors.len(), 1);
+                assert_eq!(errors[0].r#type, app_metrics::ErrorType::BadHttpStatus(200));
+                assert_eq!(
+                    errors[0].response.as_deref(),
+                    Some(r#"{"status":"error","message":"bad token"}"#)
+                );
+            }
+        }
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_success_criteria_are_selected_by_queue(db: PgPool) {
+        let router = axum::Router::new().route(
+            "/error",
+            axum::routing::post(|| async { r#"{"status":"error","message":"bad token"}"# }),
+        );
+        let url = format!("{}/error", serve_mock_destination(router).await);
+        let retry_policy = RetryPolicy::build(1, time::Duration::from_secs(10)).provide();
+        let strict_queue_name = "test_success_criteria_are_selected_by_queue_strict";
+        let mut queue_success_criteria = collections::HashMap::new();
+        queue_success_criteria.insert(
+            strict_queue_name.to_owned(),
+            r#"200~"status":"error"=failure"#.parse().unwrap(),
+        );
+        let request_options = RequestOptions {
+            queue_success_criteria,
+            ..RequestOptions::default()
+        };
+
+        for (queue_name, expected_status) in [
+            (
+                "test_success_criteria_are_selected_by_queue",
+                JobStatus::Completed,
+            ),
+            (strict_queue_name, JobStatus::Failed),
+        ] {
+            let queue = PgQueue::new_from_pool(queue_name, db.clone())
+                .await
+                .expect("failed to connect to PG");
+            enqueue_job(
+                &queue,
+                1,
+                webhook_job_parameters(&url),
+                webhook_job_metadata(),
+            )
+            .await
+            .expect("failed to enqueue job");
+            let webhook_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = queue
+                .dequeue(&worker_id())
+                .await
+                .expect("failed to dequeue job")
+                .expect("didn't find a job to dequeue");
+
+            process_webhook_job(
+                reqwest::Client::new(),
+                webhook_job,
+                &sync::Semaphore::new(1),
+                &retry_policy,
+                &request_options,
+                never_signaled(),
+                None,
+            )
+            .await
+            .expect("failed to process webhook job");
+
+            let status: JobStatus =
+                sqlx::query_scalar("SELECT status FROM job_queue WHERE queue = $1")
+                    .bind(queue_name)
+                    .fetch_one(&db)
+                    .await
+                    .expect("failed to fetch job row");
+            assert_eq!(
+                status, expected_status,
+                "unexpected status in {}",
+                queue_name
+            );
+        }
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_retry_classifier_is_applied_by_status(db: PgPool) {
+        let router = axum::Router::new()
+            .route(
+                "/teapot",
+                axum::routing::post(|| async { axum::http::StatusCode::IM_A_TEAPOT }),
+            )
+            .route(
+                "/throttled",
+                axum::routing::post(|| async { axum::http::StatusCode::TOO_MANY_REQUESTS }),
+            )
+            .route(
+                "/bad",
+                axum::routing::post(|| async { axum::http::StatusCode::BAD_REQUEST }),
+            );
+        let base_url = serve_mock_destination(router).await;
+        let retry_policy = RetryPolicy::build(1, time::Duration::from_secs(10)).provide();
+        let request_options = RequestOptions {
+            retry_classifier: "418=discard,429=retry*4".parse().unwrap(),
+            ..RequestOptions::default()
+        };
+        let queue =
+            PgQueue::new_from_pool("test_retry_classifier_is_applied_by_status", db.clone())
+                .await
+                .expect("failed to connect to PG");
+
+        for (path, expected_status, expected_retry_in) in [
+            ("/teapot", JobStatus::Discarded, None),
+            ("/throttled", JobStatus::Available, Some(40.0)),
+            ("/bad", JobStatus::Failed, None),
+        ] {
+            enqueue_job(
+                &queue,
+                2,
+                webhook_job_parameters(&format!("{}{}", base_url, path)),
+                webhook_job_metadata(),
+            )
+            .await
+            .expect("failed to enqueue job");
+
+            let webhook_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = queue
+                .dequeue(&worker_id())
+                .await
+                .expect("failed to dequeue job")
+                .expect("didn't find a job to dequeue");
+            let job_id = webhook_job.id();
+
+            process_webhook_job(
+                reqwest::Client::new(),
+                webhook_job,
+                &sync::Semaphore::new(1),
+                &retry_policy,
+                &request_options,
+                never_signaled(),
+                None,
+            )
+            .await
+            .expect("failed to process webhook job");
+
+            let (status, retry_in): (JobStatus, f64) = sqlx::query_as(
+                "SELECT status, EXTRACT(EPOCH FROM scheduled_at - last_attempt_finished_at)::float8 FROM job_queue WHERE id = $1",
+            )
+            .bind(job_id)
+            .fetch_one(&db)
+            .await
+            .expect("failed to fetch job row");
+
+            assert_eq!(status, expected_status, "unexpected status for {}", path);
+            if let Some(expected_retry_in) = expected_retry_in {
+                assert!(
+                    (retry_in - expected_retry_in).abs() < 1.0,
+                    "job for {} was retried in {}s, expected {}s",
+                    path,
+                    retry_in,
+                    expected_retry_in
+                );
+            }
+        }
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_completed_job_stores_response(db: PgPool) {
+        let router = axum::Router::new().route(
+            "/",
+            axum::routing::post(|| async {
+                (
+                    axum::http::StatusCode::ACCEPTED,
+                    [("X-Request-Id", "a-request-id")],
+                )
+            }),
+        );
+        let url = serve_mock_destination(router).await;
+        let queue = PgQueue::new_from_pool("test_completed_job_stores_response", db.clone())
+            .await
+            .expect("failed to connect to PG");
+
+        enqueue_job(
+            &queue,
+            1,
+            webhook_job_parameters(&url),
+            webhook_job_metadata(),
+        )
+        .await
+        .expect("failed to enqueue job");
+
+        let webhook_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = queue
+            .dequeue(&worker_id())
+            .await
+            .expect("failed to dequeue job")
+            .expect("didn't find a job to dequeue");
+        let job_id = webhook_job.id();
+
+        process_webhook_job(
+            reqwest::Client::new(),
+            webhook_job,
+            &sync::Semaphore::new(1),
+            &RetryPolicy::default(),
+            &RequestOptions {
+                response_id_header: Some("x-request-id".to_owned()),
+                ..RequestOptions::default()
+            },
+            never_signaled(),
+            None,
+        )
+        .await
+        .expect("failed to process webhook job");
+
+        let (status, response_status, response_id): (JobStatus, Option<i32>, Option<String>) =
+            sqlx::query_as(
+                "SELECT status, response_status, response_id FROM job_queue WHERE id = $1",
+            )
+            .bind(job_id)
+            .fetch_one(&db)
+            .await
+            .expect("failed to fetch job row");
+
+        assert_eq!(status, JobStatus::Completed);
+        assert_eq!(response_status, Some(202));
+        assert_eq!(response_id, Some("a-request-id".to_owned()));
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_run_n(db: PgPool) {
+        let router = axum::Router::new().route("/", axum::routing::post(|| async { "OK" }));
+        let url = serve_mock_destination(router).await;
+        let queue = PgQueue::new_from_pool("test_run_n", db.clone())
+            .await
+            .expect("failed to connect to PG");
+
+        for _ in 0..3 {
+            enqueue_job(
+                &queue,
+                1,
+                webhook_job_parameters(&url),
+                webhook_job_metadata(),
+            )
+            .await
+            .expect("failed to enqueue job");
+        }
+
+        let consumer = WebhookConsumer::new(
+            &worker_id(),
+            &queue,
+            time::Duration::from_millis(10),
+            time::Duration::from_millis(5000),
+            2,
+            RetryPolicy::default(),
+        );
+
+        tokio::time::timeout(time::Duration::from_secs(10), consumer.run_n(false, 3))
+            .await
+            .expect("run_n didn't return")
+            .expect("failed to run consumer");
+
+        let statuses: Vec<JobStatus> =
+            sqlx::query_scalar("SELECT status FROM job_queue WHERE queue = 'test_run_n'")
+                .fetch_all(&db)
+                .await
+                .expect("failed to fetch job statuses");
+
+        assert_eq!(statuses.len(), 3);
+        assert!(statuses
+            .iter()
+            .all(|status| *status == JobStatus::Completed));
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_listening_picks_up_jobs_before_next_poll(db: PgPool) {
+        let router = axum::Router::new().route("/", axum::routing::post(|| async { "OK" }));
+        let url = serve_mock_destination(router).await;
+        let queue_name = "test_listening_picks_up_jobs_before_next_poll";
+        let queue = PgQueue::new_from_pool(queue_name, db.clone())
+            .await
+            .expect("failed to connect to PG");
+
+        let poll_interval = time::Duration::from_secs(30);
+        let consumer = WebhookConsumer::new(
+            &worker_id(),
+            &queue,
+            poll_interval,
+            time::Duration::from_secs(5),
+            10,
+            RetryPolicy::default(),
+        )
+        .listen_for_jobs(true);
+
+        // The job is enqueued after the consumer found the queue empty and started waiting for the next poll.
+        let started = tokio::time::Instant::now();
+        let (ran, _) = tokio::join!(
+            tokio::time::timeout(time::Duration::from_secs(5), consumer.run_n(false, 1)),
+            async {
+                tokio::time::sleep(time::Duration::from_millis(200)).await;
+                enqueue_job(
+                    &queue,
+                    1,
+                    webhook_job_parameters(&url),
+                    webhook_job_metadata(),
+                )
+                .await
+                .expect("failed to enqueue job");
+            }
+        );
+        ran.expect("job wasn't picked up before the next poll")
+            .expect("failed to run consumer");
+        assert!(started.elapsed() < poll_interval);
+
+        let status: JobStatus = sqlx::query_scalar("SELECT status FROM job_queue WHERE queue = $1")
+            .bind(queue_name)
+            .fetch_one(&db)
+            .await
+            .expect("failed to fetch job row");
+        assert_eq!(status, JobStatus::Completed);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_run_until_drained(db: PgPool) {
+        let router = axum::Router::new().route("/", axum::routing::post(|| async { "OK" }));
+        let url = serve_mock_destination(router).await;
+        let queue_name = "test_run_until_drained";
+        let queue = PgQueue::new_from_pool(queue_name, db.clone())
+            .await
+            .expect("failed to connect to PG");
+
+        for _ in 0..4 {
+            enqueue_job(
+                &queue,
+                1,
+                webhook_job_parameters(&url),
+                webhook_job_metadata(),
+            )
+            .await
+            .expect("failed to enqueue job");
+        }
+        // A job scheduled in the future, like a retry, isn't available yet so it doesn't keep the queue from draining.
+        sqlx::query(
+            "UPDATE job_queue SET scheduled_at = NOW() + interval '1 hour' WHERE id = (SELECT MAX(id) FROM job_queue WHERE queue = $1)",
+        )
+        .bind(queue_name)
+        .execute(&db)
+        .await
+        .expect("failed to schedule job");
+
+        let consumer = WebhookConsumer::new(
+            &worker_id(),
+            &queue,
+            time::Duration::from_millis(10),
+            time::Duration::from_millis(5000),
+            2,
+            RetryPolicy::default(),
+        );
+
+        let started = tokio::time::Instant::now();
+        tokio::time::timeout(
+            time::Duration::from_secs(10),
+            consumer.run_until_drained(false, 5),
+        )
+        .await
+        .expect("run_until_drained didn't return")
+        .expect("failed to run consumer");
+
+        let statuses: Vec<JobStatus> =
+            sqlx::query_scalar("SELECT status FROM job_queue WHERE queue = $1 ORDER BY id")
+                .bind(queue_name)
+                .fetch_all(&db)
+                .await
+                .expect("failed to fetch job statuses");
+
+        assert_eq!(
+            statuses,
+            vec![
+                JobStatus::Completed,
+                JobStatus::Completed,
+                JobStatus::Completed,
+                JobStatus::Available
+            ]
+        );
+        // The consumer only exits after the idle threshold, polling every 10ms.
+        assert!(started.elapsed() >= time::Duration::from_millis(40));
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_run_n_delivery_outcomes(db: PgPool) {
+        let router = axum::Router::new()
+            .route("/ok", axum::routing::post(|| async { "OK" }))
+            .route(
+                "/throttled",
+                axum::routing::post(|| async { axum::http::StatusCode::TOO_MANY_REQUESTS }),
+            )
+            .route(
+                "/error",
+                axum::routing::post(|| async { axum::http::StatusCode::INTERNAL_SERVER_ERROR }),
+            )
+            .route(
+                "/bad",
+                axum::routing::post(|| async { axum::http::StatusCode::BAD_REQUEST }),
+            );
+        let base_url = serve_mock_destination(router).await;
+        let outcomes = [
+            ("/ok", JobStatus::Completed),
+            ("/throttled", JobStatus::Available),
+            ("/error", JobStatus::Available),
+            ("/bad", JobStatus::Failed),
+        ];
+
+        for transactional in [false, true] {
+            let queue_name = format!("test_run_n_delivery_outcomes_{}", transactional);
+            let queue = PgQueue::new_from_pool(&queue_name, db.clone())
+                .await
+                .expect("failed to connect to PG");
+
+            for (path, _) in &outcomes {
+                enqueue_job(
+                    &queue,
+                    2,
+                    webhook_job_parameters(&format!("{}{}", base_url, path)),
+                    webhook_job_metadata(),
+                )
+                .await
+                .expect("failed to enqueue job");
+            }
+
+            // Retry far enough in the future that no job is dequeued twice.
+            let consumer = WebhookConsumer::new(
+                &worker_id(),
+                &queue,
+                time::Duration::from_millis(10),
+                time::Duration::from_millis(5000),
+                outcomes.len(),
+                RetryPolicy::build(1, time::Duration::from_secs(60)).provide(),
+            );
+
+            tokio::time::timeout(
+                time::Duration::from_secs(10),
+                consumer.run_n(transactional, outcomes.len()),
+            )
+            .await
+            .expect("run_n didn't return")
+            .expect("failed to run consumer");
 
-    /// Use process id as a worker id for tests.
-    #[allow(dead_code)]
-    fn worker_id() -> String {
-        std::process::id().to_string()
-    }
+            for (path, expected_status) in &outcomes {
+                let (status, attempt): (JobStatus, i32) = sqlx::query_as(
+                    "SELECT status, attempt FROM job_queue WHERE queue = $1 AND target = $2",
+                )
+                .bind(&queue_name)
+                .bind(format!("{}{}", base_url, path))
+                .fetch_one(&db)
+                .await
+                .expect("failed to fetch job row");
 
-    #[allow(dead_code)]
-    async fn enqueue_job(
-        queue: &PgQueue,
-        max_attempts: i32,
-        job_parameters: WebhookJobParameters,
-        job_metadata: WebhookJobMetadata,
-    ) -> Result<(), PgQueueError> {
-        let job_target = job_parameters.url.to_owned();
-        let new_job = NewJob::new(max_attempts, job_metadata, job_parameters, &job_target);
-        queue.enqueue(new_job).await?;
-        Ok(())
+                assert_eq!(
+                    status, *expected_status,
+                    "unexpected status for {} (transactional: {})",
+                    path, transactional
+                );
+                assert_eq!(attempt, 1);
+            }
+        }
     }
 
-    #[test]
-    fn test_is_retryable_status() {
-        assert!(!is_retryable_status(http::StatusCode::FORBIDDEN));
-        assert!(!is_retryable_status(http::StatusCode::BAD_REQUEST));
-        assert!(is_retryable_status(http::StatusCode::TOO_MANY_REQUESTS));
-        assert!(is_retryable_status(http::StatusCode::INTERNAL_SERVER_ERROR));
-    }
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_run_until_aborts_requests_after_grace_period(db: PgPool) {
+        let router = axum::Router::new().route(
+            "/hang",
+            axum::routing::post(|| async {
+                tokio::time::sleep(time::Duration::from_secs(60)).await;
+                "OK"
+            }),
+        );
+        let url = format!("{}/hang", serve_mock_destination(router).await);
+        let queue_name = "test_run_until_aborts_requests_after_grace_period";
+        let queue = PgQueue::new_from_pool(queue_name, db.clone())
+            .await
+            .expect("failed to connect to PG");
 
-    #[test]
-    fn test_parse_retry_after_header() {
-        let mut headers = reqwest::header::HeaderMap::new();
-        headers.insert(reqwest::header::RETRY_AFTER, "120".parse().unwrap());
+        enqueue_job(
+            &queue,
+            2,
+            webhook_job_parameters(&url),
+            webhook_job_metadata(),
+        )
+        .await
+        .expect("failed to enqueue job");
 
-        let duration = parse_retry_after_header(&headers).unwrap();
-        assert_eq!(duration, time::Duration::from_secs(120));
+        let consumer = WebhookConsumer::new(
+            &worker_id(),
+            &queue,
+            time::Duration::from_millis(10),
+            time::Duration::from_secs(30),
+            2,
+            RetryPolicy::default(),
+        );
 
-        headers.remove(reqwest::header::RETRY_AFTER);
+        let started = tokio::time::Instant::now();
+        tokio::time::timeout(
+            time::Duration::from_secs(5),
+            consumer.run_until(
+                false,
+                tokio::time::sleep(time::Duration::from_millis(200)),
+                time::Duration::from_millis(100),
+            ),
+        )
+        .await
+        .expect("run_until didn't return")
+        .expect("failed to run consumer");
+        assert!(started.elapsed() >= time::Duration::from_millis(300));
 
-        let duration = parse_retry_after_header(&headers);
-        assert_eq!(duration, None);
+        let (status, attempt): (JobStatus, i32) =
+            sqlx::query_as("SELECT status, attempt FROM job_queue WHERE queue = $1")
+                .bind(queue_name)
+                .fetch_one(&db)
+                .await
+                .expect("failed to fetch job row");
 
-        headers.insert(
-            reqwest::header::RETRY_AFTER,
-            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        assert_eq!(status, JobStatus::Available);
+        assert_eq!(attempt, 1);
+    }
+
+    /// Proxy connections from a random local port to the database server, dropping every connection accepted
+    /// before `refuse_until` as a database that is restarting would. Returns the port and the number of
+    /// connections dropped.
+    async fn flaky_database_proxy(refuse_until: tokio::time::Instant) -> (u16, Arc<AtomicUsize>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("failed to bind database proxy");
+        let port = listener
+            .local_addr()
+            .expect("failed to get database proxy address")
+            .port();
+        // The database of `db` is on the same server as the one in DATABASE_URL, used by `sqlx::test` to create it.
+        let url = url::Url::parse(&std::env::var("DATABASE_URL").expect("DATABASE_URL is not set"))
+            .expect("DATABASE_URL is not a valid URL");
+        let database = format!(
+            "{}:{}",
+            url.host_str().unwrap_or("localhost"),
+            url.port().unwrap_or(5432)
         );
+        let refused = Arc::new(AtomicUsize::new(0));
 
-        let duration = parse_retry_after_header(&headers);
-        assert_eq!(duration, None);
+        tokio::spawn({
+            let refused = refused.clone();
+            async move {
+                loop {
+                    let (mut inbound, _) = listener.accept().await.expect("failed to accept");
+                    if tokio::time::Instant::now() < refuse_until {
+                        refused.fetch_add(1, Ordering::SeqCst);
+                        continue;
+                    }
+
+                    let database = database.clone();
+                    tokio::spawn(async move {
+                        let mut outbound = tokio::net::TcpStream::connect(database)
+                            .await
+                            .expect("failed to connect to database");
+                        let _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await;
+                    });
+                }
+            }
+        });
+
+        (port, refused)
+    }
+
+    /// A `PgQueue` connecting to the database of `db` through a proxy started with `flaky_database_proxy`.
+    async fn flaky_queue(queue_name: &str, db: &PgPool, port: u16) -> PgQueue {
+        let options = (*db.connect_options()).clone().host("127.0.0.1").port(port);
+        // Time out quickly so that failing to connect is reported instead of retried by the pool itself.
+        let pool_options =
+            sqlx::postgres::PgPoolOptions::new().acquire_timeout(time::Duration::from_millis(100));
+
+        PgQueue::new_with_pool_options(queue_name, None, options, pool_options)
+            .await
+            .expect("failed to initialize queue")
     }
 
     #[sqlx::test(migrations = "../migrations")]
-    async fn test_wait_for_job(db: PgPool) {
-        let worker_id = worker_id();
-        let queue_name = "test_wait_for_job".to_string();
-        let queue = PgQueue::new_from_pool(&queue_name, db)
+    async fn test_wait_for_connection_retries_until_connected(db: PgPool) {
+        let refuse_until = tokio::time::Instant::now() + time::Duration::from_millis(500);
+        let (port, refused) = flaky_database_proxy(refuse_until).await;
+        let queue = flaky_queue(
+            "test_wait_for_connection_retries_until_connected",
+            &db,
+            port,
+        )
+        .await;
+        let consumer = WebhookConsumer::new(
+            &worker_id(),
+            &queue,
+            time::Duration::from_millis(10),
+            time::Duration::from_secs(5),
+            10,
+            RetryPolicy::default(),
+        )
+        .connection_retry_policy(
+            RetryPolicy::build(2, time::Duration::from_millis(50))
+                .maximum_interval(time::Duration::from_millis(100))
+                .provide(),
+        );
+
+        let error = consumer
+            .wait_for_connection(1)
             .await
-            .expect("failed to connect to PG");
+            .expect_err("connected while the database is unreachable");
+        assert!(error.is_connection_error());
 
-        let webhook_job_parameters = WebhookJobParameters {
-            body: "a webhook job body. much wow.".to_owned(),
-            headers: collections::HashMap::new(),
-            method: HttpMethod::POST,
-            url: "localhost".to_owned(),
-        };
-        let webhook_job_metadata = WebhookJobMetadata {
-            team_id: 1,
-            plugin_id: 2,
-            plugin_config_id: 3,
-        };
-        // enqueue takes ownership of the job enqueued to avoid bugs that can cause duplicate jobs.
-        // Normally, a separate application would be enqueueing jobs for us to consume, so no ownership
-        // conflicts would arise. However, in this test we need to do the enqueueing ourselves.
-        // So, we clone the job to keep it around and assert the values returned by wait_for_job.
+        consumer
+            .wait_for_connection(30)
+            .await
+            .expect("failed to connect once the database is reachable");
+        assert!(tokio::time::Instant::now() >= refuse_until);
+        assert!(refused.load(Ordering::SeqCst) > 0);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_run_n_backs_off_from_connection_errors(db: PgPool) {
+        let router = axum::Router::new().route("/", axum::routing::post(|| async { "OK" }));
+        let url = serve_mock_destination(router).await;
+        let queue_name = "test_run_n_backs_off_from_connection_errors";
         enqueue_job(
-            &queue,
+            &PgQueue::new_from_pool(queue_name, db.clone())
+                .await
+                .expect("failed to connect to PG"),
             1,
-            webhook_job_parameters.clone(),
-            webhook_job_metadata,
+            webhook_job_parameters(&url),
+            webhook_job_metadata(),
         )
         .await
         .expect("failed to enqueue job");
+
+        let refuse_until = tokio::time::Instant::now() + time::Duration::from_millis(500);
+        let (port, refused) = flaky_database_proxy(refuse_until).await;
+        let queue = flaky_queue(queue_name, &db, port).await;
         let consumer = WebhookConsumer::new(
-            &worker_id,
+            &worker_id(),
             &queue,
-            time::Duration::from_millis(100),
-            time::Duration::from_millis(5000),
+            time::Duration::from_millis(10),
+            time::Duration::from_secs(5),
             10,
             RetryPolicy::default(),
+        )
+        .connection_retry_policy(
+            RetryPolicy::build(2, time::Duration::from_millis(50))
+                .maximum_interval(time::Duration::from_millis(100))
+                .provide(),
         );
 
-        let consumed_job = consumer
-            .wait_for_job()
+        tokio::time::timeout(time::Duration::from_secs(10), consumer.run_n(false, 1))
             .await
-            .expect("failed to wait and read job");
+            .expect("run_n didn't return")
+            .expect("failed to run consumer");
+        assert!(refused.load(Ordering::SeqCst) > 0);
 
-        assert_eq!(consumed_job.job.attempt, 1);
-        assert!(consumed_job.job.attempted_by.contains(&worker_id));
-        assert_eq!(consumed_job.job.attempted_by.len(), 1);
-        assert_eq!(consumed_job.job.max_attempts, 1);
-        assert_eq!(
-            *consumed_job.job.parameters.as_ref(),
-            webhook_job_parameters
+        let status: JobStatus = sqlx::query_scalar("SELECT status FROM job_queue WHERE queue = $1")
+            .bind(queue_name)
+            .fetch_one(&db)
+            .await
+            .expect("failed to fetch job row");
+        assert_eq!(status, JobStatus::Completed);
+    }
+
+    /// Install a Prometheus recorder as the global recorder, shared by all tests, and return its handle.
+    /// Tests reading metrics from it should use a dedicated queue, as its label keeps them apart.
+    fn prometheus_handle() -> &'static metrics_exporter_prometheus::PrometheusHandle {
+        static HANDLE: std::sync::OnceLock<metrics_exporter_prometheus::PrometheusHandle> =
+            std::sync::OnceLock::new();
+
+        HANDLE.get_or_init(|| {
+            let recorder = metrics_exporter_prometheus::PrometheusBuilder::new().build_recorder();
+            let handle = recorder.handle();
+            metrics::set_boxed_recorder(Box::new(recorder)).expect("failed to install recorder");
+            handle
+        })
+    }
+
+    /// Find the value of the line rendered by `handle` for `metric` in `queue`.
+    fn rendered_metric(
+        handle: &metrics_exporter_prometheus::PrometheusHandle,
+        metric: &str,
+        queue: &str,
+    ) -> Option<f64> {
+        handle.render().lines().find_map(|line| {
+            let value = line.strip_prefix(&format!("{}{{queue=\"{}\"}} ", metric, queue))?;
+            value.parse().ok()
+        })
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_queue_latency_is_recorded(db: PgPool) {
+        let handle = prometheus_handle();
+        let router = axum::Router::new().route("/", axum::routing::post(|| async { "OK" }));
+        let url = serve_mock_destination(router).await;
+        let queue_name = "test_queue_latency_is_recorded";
+        let queue = PgQueue::new_from_pool(queue_name, db.clone())
+            .await
+            .expect("failed to connect to PG");
+
+        enqueue_job(
+            &queue,
+            1,
+            webhook_job_parameters(&url),
+            webhook_job_metadata(),
+        )
+        .await
+        .expect("failed to enqueue job");
+        // Pretend the job was created an hour ago, and scheduled to run a minute ago.
+        sqlx::query(
+            "UPDATE job_queue SET created_at = NOW() - interval '1 hour', scheduled_at = NOW() - interval '1 minute' WHERE queue = $1",
+        )
+        .bind(queue_name)
+        .execute(&db)
+        .await
+        .expect("failed to backdate job");
+
+        let webhook_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = queue
+            .dequeue(&worker_id())
+            .await
+            .expect("failed to dequeue job")
+            .expect("didn't find a job to dequeue");
+
+        process_webhook_job(
+            reqwest::Client::new(),
+            webhook_job,
+            &sync::Semaphore::new(1),
+            &RetryPolicy::default(),
+            &RequestOptions::default(),
+            never_signaled(),
+            None,
+        )
+        .await
+        .expect("failed to process webhook job");
+
+        let latency = rendered_metric(handle, "webhook_job_queue_latency_seconds_sum", queue_name)
+            .expect("queue latency was not recorded");
+        let lag = rendered_metric(handle, "webhook_job_scheduling_lag_seconds_sum", queue_name)
+            .expect("scheduling lag was not recorded");
+
+        assert!((3600.0..3660.0).contains(&latency));
+        assert!((60.0..120.0).contains(&lag));
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_remaining_attempts_are_recorded_on_failure(db: PgPool) {
+        let handle = prometheus_handle();
+        let router = axum::Router::new().route(
+            "/",
+            axum::routing::post(|| async { axum::http::StatusCode::BAD_REQUEST }),
         );
-        assert_eq!(consumed_job.job.status, JobStatus::Running);
-        assert_eq!(consumed_job.job.target, webhook_job_parameters.url);
+        let url = serve_mock_destination(router).await;
+        let queue_name = "test_remaining_attempts_are_recorded_on_failure";
+        let queue = PgQueue::new_from_pool(queue_name, db.clone())
+            .await
+            .expect("failed to connect to PG");
 
-        consumed_job
-            .complete()
+        enqueue_job(
+            &queue,
+            3,
+            webhook_job_parameters(&url),
+            webhook_job_metadata(),
+        )
+        .await
+        .expect("failed to enqueue job");
+        let webhook_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = queue
+            .dequeue(&worker_id())
             .await
-            .expect("job not successfully completed");
+            .expect("failed to dequeue job")
+            .expect("didn't find a job to dequeue");
+
+        process_webhook_job(
+            reqwest::Client::new(),
+            webhook_job,
+            &sync::Semaphore::new(1),
+            &RetryPolicy::default(),
+            &RequestOptions::default(),
+            never_signaled(),
+            None,
+        )
+        .await
+        .expect("failed to process webhook job");
+
+        // A 400 fails the job on its first attempt, with 2 attempts left.
+        let count = rendered_metric(
+            handle,
+            "webhook_jobs_failed_remaining_attempts_count",
+            queue_name,
+        );
+        let sum = rendered_metric(
+            handle,
+            "webhook_jobs_failed_remaining_attempts_sum",
+            queue_name,
+        );
+
+        assert_eq!(count, Some(1.0));
+        assert_eq!(sum, Some(2.0));
     }
 
     #[sqlx::test(migrations = "../migrations")]
-    async fn test_send_webhook(_: PgPool) {
-        let method = HttpMethod::POST;
-        let url = "http://localhost:18081/echo";
-        let headers = collections::HashMap::new();
-        let body = "a very relevant request body";
-        let client = reqwest::Client::new();
+    async fn test_stored_errors_are_recorded(db: PgPool) {
+        let handle = prometheus_handle();
+        let router = axum::Router::new().route(
+            "/",
+            axum::routing::post(|| async { axum::http::StatusCode::INTERNAL_SERVER_ERROR }),
+        );
+        let url = serve_mock_destination(router).await;
+        let queue_name = "test_stored_errors_are_recorded";
+        let queue = PgQueue::new_from_pool(queue_name, db.clone())
+            .await
+            .expect("failed to connect to PG");
+
+        enqueue_job(
+            &queue,
+            2,
+            webhook_job_parameters(&url),
+            webhook_job_metadata(),
+        )
+        .await
+        .expect("failed to enqueue job");
 
-        let response = send_webhook(client, &method, url, &headers, body.to_owned())
+        // The first attempt is retried with 1 error, and the second one fails with 2.
+        for _ in 0..2 {
+            sqlx::query("UPDATE job_queue SET scheduled_at = NOW() WHERE queue = $1")
+                .bind(queue_name)
+                .execute(&db)
+                .await
+                .expect("failed to reschedule job");
+            let webhook_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = queue
+                .dequeue(&worker_id())
+                .await
+                .expect("failed to dequeue job")
+                .expect("didn't find a job to dequeue");
+
+            process_webhook_job(
+                reqwest::Client::new(),
+                webhook_job,
+                &sync::Semaphore::new(1),
+                &RetryPolicy::default(),
+                &RequestOptions::default(),
+                never_signaled(),
+                None,
+            )
             .await
-            .expect("send_webhook failed");
+            .expect("failed to process webhook job");
+        }
 
-        assert_eq!(response.status(), StatusCode::OK);
-        assert_eq!(
-            response.text().await.expect("failed to read response body"),
-            body.to_owned(),
+        let count = rendered_metric(handle, "webhook_job_stored_errors_count", queue_name);
+        let sum = rendered_metric(handle, "webhook_job_stored_errors_sum", queue_name);
+
+        assert_eq!(count, Some(2.0));
+        assert_eq!(sum, Some(3.0));
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_dwell_time_is_recorded_by_queue_kind(db: PgPool) {
+        let handle = prometheus_handle();
+        // Fail the first attempt, so that the job is retried into the degraded queue.
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let router = axum::Router::new().route(
+            "/",
+            axum::routing::post(move || async move {
+                if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                    axum::http::StatusCode::SERVICE_UNAVAILABLE
+                } else {
+                    axum::http::StatusCode::OK
+                }
+            }),
+        );
+        let url = serve_mock_destination(router).await;
+        let queue_name = "test_dwell_time_is_recorded_by_queue_kind";
+        let degraded_queue_name = "test_dwell_time_is_recorded_by_queue_kind_degraded";
+        let moved_queue_name = "test_dwell_time_is_recorded_by_queue_kind_moved";
+        let queue = PgQueue::new_from_pool(queue_name, db.clone())
+            .await
+            .expect("failed to connect to PG");
+        let degraded_queue = PgQueue::new_from_pool(degraded_queue_name, db.clone())
+            .await
+            .expect("failed to connect to PG");
+        let moved_queue = PgQueue::new_from_pool(moved_queue_name, db.clone())
+            .await
+            .expect("failed to connect to PG");
+        let retry_policy = RetryPolicy::build(1, time::Duration::ZERO)
+            .queue(degraded_queue_name)
+            .provide();
+
+        enqueue_job(
+            &queue,
+            2,
+            webhook_job_parameters(&url),
+            webhook_job_metadata(),
+        )
+        .await
+        .expect("failed to enqueue job");
+        enqueue_job(
+            &moved_queue,
+            1,
+            webhook_job_parameters(&url),
+            webhook_job_metadata(),
+        )
+        .await
+        .expect("failed to enqueue job");
+        // Pretend the jobs were created an hour ago, which isn't time they spent in the degraded queue.
+        sqlx::query(
+            "UPDATE job_queue SET created_at = NOW() - interval '1 hour' WHERE queue = ANY($1)",
+        )
+        .bind([queue_name, moved_queue_name])
+        .execute(&db)
+        .await
+        .expect("failed to backdate jobs");
+
+        for queue in [&queue, &degraded_queue] {
+            let webhook_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = queue
+                .dequeue(&worker_id())
+                .await
+                .expect("failed to dequeue job")
+                .expect("didn't find a job to dequeue");
+
+            process_webhook_job(
+                reqwest::Client::new(),
+                webhook_job,
+                &sync::Semaphore::new(1),
+                &retry_policy,
+                &RequestOptions::default(),
+                never_signaled(),
+                None,
+            )
+            .await
+            .expect("failed to process webhook job");
+        }
+
+        // A job moved into the degraded queue also entered it when it was moved, not when it was created.
+        moved_queue
+            .move_jobs(degraded_queue_name, 1)
+            .await
+            .expect("failed to move jobs");
+        let webhook_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = degraded_queue
+            .dequeue(&worker_id())
+            .await
+            .expect("failed to dequeue job")
+            .expect("didn't find a job to dequeue");
+        process_webhook_job(
+            reqwest::Client::new(),
+            webhook_job,
+            &sync::Semaphore::new(1),
+            &retry_policy,
+            &RequestOptions::default(),
+            never_signaled(),
+            None,
+        )
+        .await
+        .expect("failed to process webhook job");
+
+        let rendered = handle.render();
+        let dwell_time = |suffix: &str, queue_name: &str, queue_kind: &str| {
+            let prefix = format!(
+                "webhook_job_dwell_time_seconds_{}{{queue=\"{}\",queue_kind=\"{}\"}} ",
+                suffix, queue_name, queue_kind
+            );
+            rendered
+                .lines()
+                .find_map(|line| line.strip_prefix(&prefix))
+                .and_then(|value| value.parse::<f64>().ok())
+        };
+
+        // The first job was only retried on its original queue, and both jobs completed on the degraded one.
+        assert_eq!(dwell_time("count", degraded_queue_name, "retry"), Some(2.0));
+        assert_eq!(dwell_time("count", queue_name, "primary"), None);
+        let retry_dwell_time = dwell_time("sum", degraded_queue_name, "retry")
+            .expect("retry dwell time was not recorded");
+        assert!(retry_dwell_time < 60.0, "{}", retry_dwell_time);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_dwell_time_is_recorded_by_queue_kind_when_retrying_in_the_same_queue(db: PgPool) {
+        let handle = prometheus_handle();
+        // Fail the first request only, so that one job is retried and the other completes on its first attempt.
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let router = axum::Router::new().route(
+            "/",
+            axum::routing::post(move || async move {
+                if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                    axum::http::StatusCode::SERVICE_UNAVAILABLE
+                } else {
+                    axum::http::StatusCode::OK
+                }
+            }),
         );
+        let url = serve_mock_destination(router).await;
+        let queue_name =
+            "test_dwell_time_is_recorded_by_queue_kind_when_retrying_in_the_same_queue";
+        let queue = PgQueue::new_from_pool(queue_name, db)
+            .await
+            .expect("failed to connect to PG");
+        // As with the default configuration, retries are sent to the queue jobs are dequeued from.
+        let retry_policy = RetryPolicy::build(1, time::Duration::ZERO)
+            .queue(queue_name)
+            .provide();
+
+        for _ in 0..2 {
+            enqueue_job(
+                &queue,
+                2,
+                webhook_job_parameters(&url),
+                webhook_job_metadata(),
+            )
+            .await
+            .expect("failed to enqueue job");
+        }
+
+        for _ in 0..3 {
+            let webhook_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = queue
+                .dequeue(&worker_id())
+                .await
+                .expect("failed to dequeue job")
+                .expect("didn't find a job to dequeue");
+
+            process_webhook_job(
+                reqwest::Client::new(),
+                webhook_job,
+                &sync::Semaphore::new(1),
+                &retry_policy,
+                &RequestOptions::default(),
+                never_signaled(),
+                None,
+            )
+            .await
+            .expect("failed to process webhook job");
+        }
+
+        let rendered = handle.render();
+        let dwell_time_count = |queue_kind: &str| {
+            let prefix = format!(
+                "webhook_job_dwell_time_seconds_count{{queue=\"{}\",queue_kind=\"{}\"}} ",
+                queue_name, queue_kind
+            );
+            rendered
+                .lines()
+                .find_map(|line| line.strip_prefix(&prefix))
+                .and_then(|value| value.parse::<f64>().ok())
+        };
+
+        assert_eq!(dwell_time_count("primary"), Some(1.0));
+        assert_eq!(dwell_time_count("retry"), Some(1.0));
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_job_metrics_are_labelled_by_queue(db: PgPool) {
+        let handle = prometheus_handle();
+        let router = axum::Router::new().route("/", axum::routing::post(|| async { "OK" }));
+        let url = serve_mock_destination(router).await;
+        let queue_names = [
+            "test_job_metrics_are_labelled_by_queue_webhooks",
+            "test_job_metrics_are_labelled_by_queue_webhooks_degraded",
+        ];
+
+        for (jobs, queue_name) in [(1, queue_names[0]), (2, queue_names[1])] {
+            let queue = PgQueue::new_from_pool(queue_name, db.clone())
+                .await
+                .expect("failed to connect to PG");
+
+            for _ in 0..jobs {
+                enqueue_job(
+                    &queue,
+                    1,
+                    webhook_job_parameters(&url),
+                    webhook_job_metadata(),
+                )
+                .await
+                .expect("failed to enqueue job");
+            }
+
+            let consumer = WebhookConsumer::new(
+                &worker_id(),
+                &queue,
+                time::Duration::from_millis(10),
+                time::Duration::from_millis(5000),
+                2,
+                RetryPolicy::default(),
+            );
+
+            tokio::time::timeout(time::Duration::from_secs(10), consumer.run_n(false, jobs))
+                .await
+                .expect("run_n didn't return")
+                .expect("failed to run consumer");
+        }
+
+        let rendered = handle.render();
+        let completed = |queue_name: &str| {
+            let prefix = format!("webhook_jobs_completed{{queue=\"{}\",", queue_name);
+            rendered
+                .lines()
+                .find(|line| line.starts_with(&prefix))
+                .and_then(|line| line.rsplit_once(' '))
+                .and_then(|(_, value)| value.parse::<f64>().ok())
+        };
+
+        assert_eq!(completed(queue_names[0]), Some(1.0));
+        assert_eq!(completed(queue_names[1]), Some(2.0));
     }
 }
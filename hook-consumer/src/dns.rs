@@ -0,0 +1,381 @@
+//! # DNS
+//!
+//! Custom DNS resolvers used by the reqwest client of the `WebhookConsumer`.
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use hook_common::pairs::key_value_pairs;
+use hook_common::webhook::ResolveError;
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+
+/// A resolver that uses the system's `getaddrinfo` via `tokio::net::lookup_host`.
+/// Unlike reqwest's default resolver, this one is public so it can be wrapped by other resolvers, and it fails with a
+/// `ResolveError` so that resolution failures are reported as such.
+pub struct SystemResolver;
+
+impl Resolve for SystemResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await
+                .map_err(|error| ResolveError {
+                    host: name.as_str().to_owned(),
+                    error,
+                })?
+                .collect();
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Weights assigned to specific IP addresses, as parsed from a comma-separated list of `ip=weight` pairs.
+/// Any IP address not present has a weight of 1.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IpWeights(pub HashMap<IpAddr, usize>);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseIpWeightsError(String);
+
+impl FromStr for IpWeights {
+    type Err = ParseIpWeightsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        key_value_pairs::<IpAddr, usize>(s)
+            .collect::<Result<_, _>>()
+            .map(IpWeights)
+            .map_err(|pair| ParseIpWeightsError(pair.to_owned()))
+    }
+}
+
+/// Addresses to resolve specific hostnames to, bypassing DNS, as parsed from a comma-separated list of
+/// `host=ip` pairs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostOverrides(pub HashMap<String, IpAddr>);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseHostOverridesError(String);
+
+impl FromStr for HostOverrides {
+    type Err = ParseHostOverridesError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        key_value_pairs::<String, IpAddr>(s)
+            .map(|pair| pair.map(|(host, ip)| (host.to_lowercase(), ip)))
+            .collect::<Result<_, _>>()
+            .map(HostOverrides)
+            .map_err(|pair| ParseHostOverridesError(pair.to_owned()))
+    }
+}
+
+impl HostOverrides {
+    /// Make a client built with `builder` resolve every overridden hostname to its address.
+    /// Overrides take precedence over any resolver set with `dns_resolver`. The port used is always the one of the
+    /// request's URL.
+    pub fn apply(&self, mut builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        for (host, ip) in &self.0 {
+            builder = builder.resolve(host, SocketAddr::new(*ip, 0));
+        }
+        builder
+    }
+}
+
+/// A resolver that distributes connections across all the addresses a hostname resolves to.
+///
+/// Every resolution rotates the addresses returned by the inner resolver, so that the address
+/// tried first changes following a weighted round-robin schedule. The remaining addresses are kept
+/// afterwards, in their original order, as fallbacks in case the preferred one fails to connect.
+///
+/// Resolution only happens when a new connection is opened, so pooled connections will keep
+/// using the address they connected to.
+pub struct RoundRobinResolver {
+    /// The resolver used to look up all the addresses of a hostname.
+    inner: Arc<dyn Resolve>,
+    /// Weights to prefer some IP addresses more than others.
+    weights: Arc<IpWeights>,
+    /// The number of resolutions done per hostname, used to pick the next preferred address.
+    counters: Mutex<HashMap<String, usize>>,
+}
+
+impl RoundRobinResolver {
+    pub fn new(inner: Arc<dyn Resolve>, weights: IpWeights) -> Self {
+        Self {
+            inner,
+            weights: Arc::new(weights),
+            counters: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Return the current counter for `host` and increment it for the next resolution.
+    fn next_count(&self, host: &str) -> usize {
+        let mut counters = self.counters.lock().expect("counters lock was poisoned");
+        let counter = counters.entry(host.to_owned()).or_insert(0);
+        let current = *counter;
+        *counter = counter.wrapping_add(1);
+        current
+    }
+}
+
+impl Default for RoundRobinResolver {
+    fn default() -> Self {
+        Self::new(Arc::new(SystemResolver), IpWeights::default())
+    }
+}
+
+impl Resolve for RoundRobinResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let count = self.next_count(name.as_str());
+        let weights = self.weights.clone();
+        let resolving = self.inner.resolve(name);
+
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = resolving.await?.collect();
+            Ok(Box::new(rotate_addrs(addrs, &weights, count).into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Move the preferred address for resolution number `count` to the front of `addrs`.
+/// Each address is preferred as many times per round as its weight, so an address with a weight of 0
+/// is never preferred, but can still be used as a fallback.
+/// The round is never materialized, so large weights don't cost anything.
+fn rotate_addrs(mut addrs: Vec<SocketAddr>, weights: &IpWeights, count: usize) -> Vec<SocketAddr> {
+    let address_weights: Vec<usize> = addrs
+        .iter()
+        .map(|addr| weights.0.get(&addr.ip()).copied().unwrap_or(1))
+        .collect();
+    let round = address_weights
+        .iter()
+        .fold(0usize, |total, weight| total.saturating_add(*weight));
+
+    if round == 0 {
+        return addrs;
+    }
+
+    let mut position = count % round;
+    let preferred = address_weights
+        .iter()
+        .position(|weight| {
+            if position < *weight {
+                true
+            } else {
+                position -= weight;
+                false
+            }
+        })
+        .expect("position is within the round");
+
+    let preferred = addrs.remove(preferred);
+    addrs.insert(0, preferred);
+    addrs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A resolver that always resolves to the same addresses.
+    struct StaticResolver(Vec<SocketAddr>);
+
+    impl Resolve for StaticResolver {
+        fn resolve(&self, _: Name) -> Resolving {
+            let addrs = self.0.clone();
+            Box::pin(async move { Ok(Box::new(addrs.into_iter()) as Addrs) })
+        }
+    }
+
+    fn addrs() -> Vec<SocketAddr> {
+        vec![
+            "10.0.0.1:0".parse().unwrap(),
+            "10.0.0.2:0".parse().unwrap(),
+            "10.0.0.3:0".parse().unwrap(),
+        ]
+    }
+
+    /// Resolve `host` `n` times and count how many times each address was the preferred one.
+    async fn count_preferred(
+        resolver: &RoundRobinResolver,
+        host: &str,
+        n: usize,
+    ) -> HashMap<SocketAddr, usize> {
+        let mut counts = HashMap::new();
+
+        for _ in 0..n {
+            let mut resolved = resolver
+                .resolve(Name::from_str(host).unwrap())
+                .await
+                .expect("failed to resolve");
+            let preferred = resolved.next().expect("no addresses resolved");
+            *counts.entry(preferred).or_insert(0) += 1;
+        }
+
+        counts
+    }
+
+    #[tokio::test]
+    async fn test_round_robin_distributes_across_addresses() {
+        let resolver =
+            RoundRobinResolver::new(Arc::new(StaticResolver(addrs())), IpWeights::default());
+
+        let counts = count_preferred(&resolver, "example.com", 300).await;
+
+        for addr in addrs() {
+            assert_eq!(counts.get(&addr), Some(&100));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_round_robin_keeps_all_addresses_as_fallbacks() {
+        let resolver =
+            RoundRobinResolver::new(Arc::new(StaticResolver(addrs())), IpWeights::default());
+
+        for _ in 0..3 {
+            let mut resolved: Vec<SocketAddr> = resolver
+                .resolve(Name::from_str("example.com").unwrap())
+                .await
+                .expect("failed to resolve")
+                .collect();
+            resolved.sort();
+
+            assert_eq!(resolved, addrs());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_weighted_round_robin() {
+        let weights: IpWeights = "10.0.0.1=3, 10.0.0.2=1, 10.0.0.3=0".parse().unwrap();
+        let resolver = RoundRobinResolver::new(Arc::new(StaticResolver(addrs())), weights);
+
+        let counts = count_preferred(&resolver, "example.com", 400).await;
+
+        assert_eq!(counts.get(&addrs()[0]), Some(&300));
+        assert_eq!(counts.get(&addrs()[1]), Some(&100));
+        assert_eq!(counts.get(&addrs()[2]), None);
+    }
+
+    #[test]
+    fn test_rotate_addrs_with_large_weights() {
+        let half = usize::MAX / 2;
+        let weights: IpWeights = format!("10.0.0.1={}, 10.0.0.2={}, 10.0.0.3=1", half, half)
+            .parse()
+            .unwrap();
+
+        assert_eq!(rotate_addrs(addrs(), &weights, half - 1)[0], addrs()[0]);
+        assert_eq!(rotate_addrs(addrs(), &weights, half)[0], addrs()[1]);
+        assert_eq!(rotate_addrs(addrs(), &weights, 2 * half)[0], addrs()[2]);
+        assert_eq!(rotate_addrs(addrs(), &weights, 2 * half + 1)[0], addrs()[0]);
+    }
+
+    #[tokio::test]
+    async fn test_requests_distribute_across_addresses() {
+        // Serve the same port on two loopback addresses, counting the requests each one receives.
+        let first = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = first.local_addr().unwrap().port();
+        let second = tokio::net::TcpListener::bind(("127.0.0.2", port))
+            .await
+            .unwrap();
+        let mut counters = Vec::new();
+
+        for listener in [first, second] {
+            let counter = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+            let router = axum::Router::new().route(
+                "/",
+                axum::routing::post({
+                    let counter = counter.clone();
+                    move || async move {
+                        counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    }
+                }),
+            );
+            tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+            counters.push(counter);
+        }
+
+        let resolver = RoundRobinResolver::new(
+            Arc::new(StaticResolver(vec![
+                "127.0.0.1:0".parse().unwrap(),
+                "127.0.0.2:0".parse().unwrap(),
+            ])),
+            IpWeights::default(),
+        );
+        // Disable connection pooling so that every request has to resolve the hostname again.
+        let client = reqwest::Client::builder()
+            .dns_resolver(Arc::new(resolver))
+            .pool_max_idle_per_host(0)
+            .build()
+            .unwrap();
+
+        for _ in 0..10 {
+            client
+                .post(format!("http://webhooks.test:{}/", port))
+                .send()
+                .await
+                .expect("failed to send request")
+                .error_for_status()
+                .expect("request failed");
+        }
+
+        for counter in counters {
+            assert_eq!(counter.load(std::sync::atomic::Ordering::SeqCst), 5);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_host_overrides_route_requests() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let router = axum::Router::new().route("/", axum::routing::post(|| async { "overridden" }));
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        let overrides: HostOverrides = "prod.example.com=127.0.0.1".parse().unwrap();
+        let client = overrides.apply(reqwest::Client::builder()).build().unwrap();
+
+        let body = client
+            .post(format!("http://prod.example.com:{}/", port))
+            .send()
+            .await
+            .expect("failed to send request")
+            .error_for_status()
+            .expect("request failed")
+            .text()
+            .await
+            .expect("failed to read response body");
+
+        assert_eq!(body, "overridden");
+    }
+
+    #[test]
+    fn test_parse_host_overrides() {
+        let overrides: HostOverrides = "prod.example.com=127.0.0.1, Other.Example.com=::1"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            overrides.0.get("prod.example.com"),
+            Some(&"127.0.0.1".parse().unwrap())
+        );
+        assert_eq!(
+            overrides.0.get("other.example.com"),
+            Some(&"::1".parse().unwrap())
+        );
+        assert_eq!("".parse::<HostOverrides>(), Ok(HostOverrides::default()));
+        assert!("prod.example.com".parse::<HostOverrides>().is_err());
+        assert!("=127.0.0.1".parse::<HostOverrides>().is_err());
+        assert!("prod.example.com=not-an-ip"
+            .parse::<HostOverrides>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_parse_ip_weights() {
+        let weights: IpWeights = "10.0.0.1=3,::1=2".parse().unwrap();
+
+        assert_eq!(weights.0.get(&"10.0.0.1".parse().unwrap()), Some(&3));
+        assert_eq!(weights.0.get(&"::1".parse().unwrap()), Some(&2));
+        assert_eq!("".parse::<IpWeights>(), Ok(IpWeights::default()));
+        assert!("10.0.0.1".parse::<IpWeights>().is_err());
+        assert!("not-an-ip=1".parse::<IpWeights>().is_err());
+    }
+}
@@ -1,8 +1,40 @@
+use std::fmt;
 use std::time;
 
-use hook_common::pgqueue;
+use hook_common::{
+    pgqueue,
+    retry::{RetryAction, RetryClassifier},
+    webhook::{ResponseDigest, WebhookRequestErrorKind},
+};
 use thiserror::Error;
 
+/// What is kept of an error response body returned by a webhook destination.
+#[derive(Debug, PartialEq, Eq)]
+pub enum CapturedResponse {
+    /// The beginning of the body, up to a maximum size.
+    Body(String),
+    /// Only a digest of the body.
+    Digest(ResponseDigest),
+}
+
+/// The parts of a response that a webhook destination may take too long to send.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponsePhase {
+    /// The status line and headers, i.e. the time to first byte.
+    Headers,
+    /// The whole body, once headers have been received.
+    Body,
+}
+
+impl fmt::Display for ResponsePhase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResponsePhase::Headers => write!(f, "response headers"),
+            ResponsePhase::Body => write!(f, "response body"),
+        }
+    }
+}
+
 /// Enumeration of errors related to webhook job processing in the WebhookConsumer.
 #[derive(Error, Debug)]
 pub enum WebhookError {
@@ -12,13 +44,140 @@ pub enum WebhookError {
     ParseHeadersError(http::Error),
     #[error("error parsing webhook url")]
     ParseUrlError(url::ParseError),
+    #[error("{0} is not within the base URL of its team")]
+    EscapedBaseUrlError(String),
+    #[error("{0} is not a valid content type")]
+    InvalidContentTypeError(String),
+    #[error("{0} is not an allowed webhook destination")]
+    DisallowedHostError(String),
+    #[error("webhook body of {size} bytes is larger than the maximum of {max_body_bytes} bytes")]
+    BodyTooLargeError { size: usize, max_body_bytes: usize },
+    #[error("webhook has both a body and multipart parts, but only one of them can be sent")]
+    MultipartWithBodyError,
+    #[error("webhook destination redirected with {status}{}, but the redirect was not followed", location.as_ref().map(|location| format!(" to {}", location)).unwrap_or_default())]
+    RedirectError {
+        status: http::StatusCode,
+        location: Option<String>,
+    },
+    #[error("webhook destination responded with {status}, but the response doesn't meet the success criteria")]
+    UnsuccessfulResponseError {
+        status: http::StatusCode,
+        response: Option<CapturedResponse>,
+    },
     #[error("a webhook could not be delivered but it could be retried later: {error}")]
     RetryableRequestError {
         error: reqwest::Error,
+        response: Option<CapturedResponse>,
         retry_after: Option<time::Duration>,
     },
-    #[error("a webhook could not be delivered and it cannot be retried further: {0}")]
-    NonRetryableRetryableRequestError(reqwest::Error),
+    #[error("a webhook could not be delivered but it could be retried later: timed out after {timeout:?} waiting for {phase}")]
+    RetryableTimeoutError {
+        phase: ResponsePhase,
+        timeout: time::Duration,
+    },
+    #[error("a webhook could not be delivered and it cannot be retried further: {error}")]
+    NonRetryableRequestError {
+        error: reqwest::Error,
+        response: Option<CapturedResponse>,
+    },
+}
+
+/// A webhook destination redirected a request to a host webhooks can't be sent to, see `RedirectPolicy`.
+#[derive(Error, Debug)]
+#[error("redirected to {0}, which is not an allowed webhook destination")]
+pub struct DisallowedRedirectError(pub String);
+
+/// A webhook destination redirected a request more times than `RedirectPolicy` allows.
+#[derive(Error, Debug)]
+#[error("redirected more than {0} times")]
+pub struct TooManyRedirectsError(pub usize);
+
+/// Whether a failed webhook request may succeed if it's tried again later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retryability {
+    /// The request may succeed later, like after a timeout or a connection failure.
+    Retryable,
+    /// The request would fail the same way every time, like when it couldn't be built.
+    NonRetryable,
+}
+
+/// Classify an error a webhook request failed with by whether the request can be retried.
+/// Status errors are classified as by the default `RetryClassifier`: 429 and 5XX can be retried. Any configured
+/// rules have to be applied to the status by the caller.
+pub fn classify_reqwest_error(error: &reqwest::Error) -> Retryability {
+    if error.is_builder() || error.is_decode() || error.is_redirect() {
+        // Being redirected too many times will just happen again.
+        return Retryability::NonRetryable;
+    }
+
+    match error.status() {
+        Some(status) => match RetryClassifier::default().classify(status.as_u16()) {
+            RetryAction::Retry { .. } => Retryability::Retryable,
+            RetryAction::Fail | RetryAction::Discard => Retryability::NonRetryable,
+        },
+        None => Retryability::Retryable,
+    }
+}
+
+impl WebhookError {
+    /// Wrap an error a webhook request failed with before getting a response, as retryable or not as decided by
+    /// `classify_reqwest_error`.
+    /// Redirects to hosts that aren't allowed are reported like requests to them, see `RedirectPolicy`.
+    pub fn from_request_error(error: reqwest::Error) -> Self {
+        if let Some(DisallowedRedirectError(host)) = std::error::Error::source(&error)
+            .and_then(|source| source.downcast_ref::<DisallowedRedirectError>())
+        {
+            return WebhookError::DisallowedHostError(host.to_owned());
+        }
+
+        match classify_reqwest_error(&error) {
+            Retryability::Retryable => WebhookError::RetryableRequestError {
+                error,
+                response: None,
+                retry_after: None,
+            },
+            Retryability::NonRetryable => WebhookError::NonRetryableRequestError {
+                error,
+                response: None,
+            },
+        }
+    }
+
+    /// The kind of failure of the webhook request, if this error comes from sending one.
+    pub fn request_error_kind(&self) -> Option<WebhookRequestErrorKind> {
+        match self {
+            WebhookError::RetryableRequestError { error, .. }
+            | WebhookError::NonRetryableRequestError { error, .. } => Some(error.into()),
+            WebhookError::RetryableTimeoutError { .. } => Some(WebhookRequestErrorKind::Timeout),
+            WebhookError::RedirectError { .. } | WebhookError::UnsuccessfulResponseError { .. } => {
+                Some(WebhookRequestErrorKind::Status)
+            }
+            _ => None,
+        }
+    }
+
+    /// The HTTP status code returned by the destination, if the request got a response at all.
+    pub fn status(&self) -> Option<http::StatusCode> {
+        match self {
+            WebhookError::RetryableRequestError { error, .. }
+            | WebhookError::NonRetryableRequestError { error, .. } => error.status(),
+            WebhookError::RedirectError { status, .. }
+            | WebhookError::UnsuccessfulResponseError { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// Whether this error is a sign of an overloaded destination: the request timed out, couldn't connect, or got a
+    /// 429 or 5xx response. Other errors, like 4xx responses to a bad payload, come from destinations that are up.
+    pub fn is_overload(&self) -> bool {
+        match self.request_error_kind() {
+            Some(WebhookRequestErrorKind::Timeout | WebhookRequestErrorKind::Connect) => true,
+            Some(WebhookRequestErrorKind::Status) => self.status().is_some_and(|status| {
+                status == http::StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            }),
+            _ => false,
+        }
+    }
 }
 
 /// Enumeration of errors related to initialization and consumption of webhook jobs.
@@ -28,6 +187,164 @@ pub enum ConsumerError {
     TimeoutError,
     #[error("an error occurred in the underlying queue")]
     QueueError(#[from] pgqueue::PgQueueError),
-    #[error("an error occurred in the underlying job")]
-    PgJobError(String),
+    #[error("retry is an invalid state for the underlying job: {0}")]
+    RetryInvalidError(String),
+    #[error("{command} query of the underlying job failed with: {error}")]
+    JobQueryError { command: String, error: sqlx::Error },
+    #[error("transaction {command} of the underlying job failed with: {error}")]
+    JobTransactionError { command: String, error: sqlx::Error },
+}
+
+/// Enumeration of errors loading the client certificate presented to webhook destinations that require mutual TLS.
+#[derive(Error, Debug)]
+pub enum ClientIdentityError {
+    #[error("a client certificate and its key must be set together")]
+    IncompleteError,
+    #[error("failed to read {path}: {error}")]
+    ReadError { path: String, error: std::io::Error },
+    #[error("invalid client certificate or key: {0}")]
+    InvalidIdentityError(#[from] reqwest::Error),
+}
+
+impl ConsumerError {
+    /// Whether this error means the database couldn't be reached, so the operation can be retried once it's back.
+    pub fn is_connection_error(&self) -> bool {
+        matches!(self, ConsumerError::QueueError(error) if error.is_connection_error())
+    }
+}
+
+/// Keep the kind of a `PgJobError`, dropping the job it carries, so that callers can match on it.
+impl<T> From<pgqueue::PgJobError<T>> for ConsumerError {
+    fn from(error: pgqueue::PgJobError<T>) -> Self {
+        match error {
+            pgqueue::PgJobError::RetryInvalidError { error, .. } => {
+                ConsumerError::RetryInvalidError(error)
+            }
+            pgqueue::PgJobError::QueryError { command, error } => {
+                ConsumerError::JobQueryError { command, error }
+            }
+            pgqueue::PgJobError::TransactionError { command, error } => {
+                ConsumerError::JobTransactionError { command, error }
+            }
+            pgqueue::PgJobError::TimeoutError { timeout } => {
+                ConsumerError::QueueError(pgqueue::PgQueueError::TimeoutError { timeout })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_consumer_error_from_pg_job_error() {
+        let error = ConsumerError::from(pgqueue::PgJobError::RetryInvalidError {
+            job: (),
+            error: "no attempts left".to_owned(),
+        });
+        assert!(
+            matches!(error, ConsumerError::RetryInvalidError(error) if error == "no attempts left")
+        );
+
+        let error = ConsumerError::from(pgqueue::PgJobError::<()>::QueryError {
+            command: "UPDATE".to_owned(),
+            error: sqlx::Error::RowNotFound,
+        });
+        assert!(matches!(
+            error,
+            ConsumerError::JobQueryError { command, error: sqlx::Error::RowNotFound } if command == "UPDATE"
+        ));
+
+        let error = ConsumerError::from(pgqueue::PgJobError::<()>::TransactionError {
+            command: "COMMIT".to_owned(),
+            error: sqlx::Error::PoolTimedOut,
+        });
+        assert!(matches!(
+            error,
+            ConsumerError::JobTransactionError { command, error: sqlx::Error::PoolTimedOut } if command == "COMMIT"
+        ));
+
+        let error = ConsumerError::from(pgqueue::PgJobError::<()>::TimeoutError {
+            timeout: std::time::Duration::from_secs(1),
+        });
+        assert!(error.is_connection_error());
+    }
+
+    #[test]
+    fn test_webhook_error_request_error_kind() {
+        let error = WebhookError::RetryableTimeoutError {
+            phase: ResponsePhase::Headers,
+            timeout: time::Duration::from_secs(1),
+        };
+        assert_eq!(
+            error.request_error_kind(),
+            Some(WebhookRequestErrorKind::Timeout)
+        );
+
+        let error = WebhookError::DisallowedHostError("example.com".to_owned());
+        assert_eq!(error.request_error_kind(), None);
+    }
+
+    #[test]
+    fn test_webhook_error_is_overload() {
+        let status_error = |status| WebhookError::UnsuccessfulResponseError {
+            status,
+            response: None,
+        };
+
+        assert!(status_error(http::StatusCode::SERVICE_UNAVAILABLE).is_overload());
+        assert!(status_error(http::StatusCode::TOO_MANY_REQUESTS).is_overload());
+        assert!(!status_error(http::StatusCode::BAD_REQUEST).is_overload());
+        assert!(!status_error(http::StatusCode::NOT_FOUND).is_overload());
+        assert!(WebhookError::RetryableTimeoutError {
+            phase: ResponsePhase::Headers,
+            timeout: time::Duration::from_secs(1),
+        }
+        .is_overload());
+        assert!(!WebhookError::DisallowedHostError("example.com".to_owned()).is_overload());
+    }
+
+    #[tokio::test]
+    async fn test_classify_reqwest_error() {
+        let client = reqwest::Client::builder()
+            .timeout(time::Duration::from_millis(100))
+            .build()
+            .expect("failed to build client");
+
+        // Connections are accepted into the backlog, but nothing ever responds.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("failed to bind listener");
+        let error = client
+            .get(format!("http://{}", listener.local_addr().unwrap()))
+            .send()
+            .await
+            .expect_err("request didn't time out");
+        assert!(error.is_timeout());
+        assert_eq!(classify_reqwest_error(&error), Retryability::Retryable);
+
+        // Nothing listens on the port once the listener is dropped.
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let error = client
+            .get(format!("http://{}", addr))
+            .send()
+            .await
+            .expect_err("request didn't fail to connect");
+        assert!(error.is_connect());
+        assert_eq!(classify_reqwest_error(&error), Retryability::Retryable);
+
+        let error = client
+            .get("not a url")
+            .send()
+            .await
+            .expect_err("request was built");
+        assert!(error.is_builder());
+        assert_eq!(classify_reqwest_error(&error), Retryability::NonRetryable);
+        assert!(matches!(
+            WebhookError::from_request_error(error),
+            WebhookError::NonRetryableRequestError { response: None, .. }
+        ));
+    }
 }
@@ -1,3 +1,6 @@
 pub mod config;
 pub mod consumer;
+pub mod dns;
 pub mod error;
+#[cfg(feature = "otel")]
+pub mod otel;
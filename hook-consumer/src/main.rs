@@ -1,46 +1,155 @@
 //! Consume `PgQueue` jobs to run webhook calls.
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::time;
+
 use envconfig::Envconfig;
+use tracing::Instrument;
 
 use hook_common::{
-    metrics::serve, metrics::setup_metrics_router, pgqueue::PgQueue, retry::RetryPolicy,
+    metrics::serve, metrics::setup_metrics_router_with_labels, pgqueue::PgQueue, retry::RetryPolicy,
 };
 use hook_consumer::config::Config;
-use hook_consumer::consumer::WebhookConsumer;
+use hook_consumer::consumer::{client_builder, client_identity, proxies, WebhookConsumer};
+use hook_consumer::dns::{RoundRobinResolver, SystemResolver};
 use hook_consumer::error::ConsumerError;
 
 #[tokio::main]
 async fn main() -> Result<(), ConsumerError> {
     let config = Config::init_from_env().expect("Invalid configuration:");
+    config.logging.init().expect("failed to initialize logging");
+
+    #[cfg(feature = "otel")]
+    hook_consumer::otel::init(&config.otlp_logs_endpoint, &config.otlp_traces_endpoint)
+        .expect("failed to initialize OpenTelemetry");
 
-    let retry_policy = RetryPolicy::build(
-        config.retry_policy.backoff_coefficient,
-        config.retry_policy.initial_interval.0,
+    let retry_policy = config.retry_policy.retry_policy();
+    let connect_options = config
+        .connection
+        .connect_options(&config.database_url)
+        .expect("invalid database connection options");
+    let mut queue = PgQueue::new_with_pool_options(
+        &config.queue_name,
+        Some(&config.table_name),
+        connect_options,
+        config.connection.pool_options(),
     )
-    .maximum_interval(config.retry_policy.maximum_interval.0)
-    .queue(&config.retry_policy.retry_queue_name)
-    .provide();
-    let queue = PgQueue::new(&config.queue_name, &config.database_url)
-        .await
-        .expect("failed to initialize queue");
-
-    let consumer = WebhookConsumer::new(
+    .await
+    .expect("failed to initialize queue")
+    .interleave_teams(config.interleave_teams)
+    .team_weights(config.team_weights.0.clone())
+    .dequeue_order(config.dequeue_order);
+    if !config.query_timeout.0.is_zero() {
+        queue = queue.query_timeout(config.query_timeout.0);
+    }
+    if let Some(max_attempted_by) = NonZeroUsize::new(config.max_attempted_by) {
+        queue = queue.max_attempted_by(max_attempted_by);
+    }
+    if let Some(max_errors) = NonZeroUsize::new(config.max_errors) {
+        queue = queue.max_errors(max_errors);
+    }
+
+    let mut client_builder = client_builder(config.request_timeout.0);
+    if config.round_robin_dns {
+        client_builder = client_builder.dns_resolver(Arc::new(RoundRobinResolver::new(
+            Arc::new(SystemResolver),
+            config.dns_ip_weights.clone(),
+        )));
+    }
+    client_builder = config.dns_host_overrides.apply(client_builder);
+    client_builder = config
+        .redirect_policy
+        .apply(client_builder, &config.allowed_hosts);
+    for proxy in proxies(&config.http_proxy, &config.https_proxy, &config.no_proxy)
+        .expect("invalid proxy configuration")
+    {
+        client_builder = client_builder.proxy(proxy);
+    }
+    if let Some(identity) = client_identity(&config.client_cert_path, &config.client_key_path)
+        .expect("invalid client certificate configuration")
+    {
+        client_builder = client_builder.identity(identity);
+    }
+    let client = client_builder
+        .build()
+        .expect("failed to construct reqwest client for webhook consumer");
+
+    let mut consumer = WebhookConsumer::new(
         &config.consumer_name,
         &queue,
         config.poll_interval.0,
         config.request_timeout.0,
         config.max_concurrent_jobs,
         retry_policy,
+    )
+    .queue_retry_policies(config.queue_retry_policies())
+    .client(client)
+    .request_options(config.request_options())
+    .max_concurrent_requests(config.max_concurrent_requests)
+    .max_poll_interval(config.max_poll_interval.0)
+    .listen_for_jobs(config.listen_for_jobs)
+    .team_max_concurrent_jobs(config.team_max_concurrent_jobs.0.clone())
+    .connection_retry_policy(
+        RetryPolicy::build(2, time::Duration::from_millis(100))
+            .maximum_interval(config.database_connect_max_interval.0)
+            .provide(),
     );
+    if config.max_concurrent_jobs_per_team > 0 {
+        consumer = consumer.max_concurrent_jobs_per_team(config.max_concurrent_jobs_per_team);
+    }
+    if config.failure_rate_threshold > 0.0 {
+        consumer = consumer
+            .failure_rate_backpressure(config.failure_rate_threshold, config.failure_rate_window);
+    }
+    if config.max_memory_bytes > 0 {
+        consumer = consumer.max_memory_bytes(config.max_memory_bytes);
+    }
 
     let bind = config.bind();
+    let deployment_labels = config.deployment_labels();
     tokio::task::spawn(async move {
-        let router = setup_metrics_router();
+        let router = setup_metrics_router_with_labels(&deployment_labels);
         serve(router, &bind)
             .await
             .expect("failed to start serving metrics");
     });
 
-    consumer.run(config.transactional).await?;
+    // All logs emitted while consuming carry the deployment labels as span fields.
+    let span = tracing::info_span!(
+        "consumer",
+        region = tracing::field::Empty,
+        zone = tracing::field::Empty
+    );
+    for (key, value) in config.deployment_labels() {
+        span.record(key.as_str(), value.as_str());
+    }
+
+    consumer
+        .wait_for_connection(config.database_connect_attempts)
+        .instrument(span.clone())
+        .await?;
+    consumer
+        .run_until(
+            config.transactional,
+            shutdown_signal(),
+            config.shutdown_grace_period.0,
+        )
+        .instrument(span)
+        .await?;
+
+    #[cfg(feature = "otel")]
+    hook_consumer::otel::shutdown();
 
     Ok(())
 }
+
+/// Wait for a SIGINT or SIGTERM to shut down.
+async fn shutdown_signal() {
+    let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .expect("failed to install SIGTERM handler");
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {},
+        _ = sigterm.recv() => {},
+    }
+}
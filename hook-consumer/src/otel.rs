@@ -0,0 +1,350 @@
+//! # OpenTelemetry
+//!
+//! Trace every webhook delivery as a span continuing the trace it was enqueued in, emit its outcome as an OTLP log
+//! record correlated to that span, and propagate the span's trace context to its destination.
+//! Only available with the `otel` feature. These records complement, and do not replace, the fmt logger.
+use std::collections;
+use std::time;
+
+use opentelemetry::logs::{LogError, LogRecord, Logger, Severity};
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::{TraceContextExt, TraceError, Tracer};
+use opentelemetry::{global, Context, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use thiserror::Error;
+
+use crate::error::WebhookError;
+
+/// The name of the logger and tracer used to emit delivery records.
+const INSTRUMENTATION_NAME: &str = "hook-consumer";
+
+/// Errors that can occur when setting up OpenTelemetry.
+#[derive(Error, Debug)]
+pub enum OtelError {
+    #[error("failed to initialize OTLP log pipeline: {0}")]
+    LogError(#[from] LogError),
+    #[error("failed to initialize OTLP trace pipeline: {0}")]
+    TraceError(#[from] TraceError),
+}
+
+/// Install the global tracer and logger providers.
+/// A tracer provider is always installed, so that delivery spans have valid trace context to propagate and to
+/// correlate delivery records with, even if spans are not exported because `traces_endpoint` is empty.
+/// No delivery records are exported if `logs_endpoint` is empty.
+pub fn init(logs_endpoint: &str, traces_endpoint: &str) -> Result<(), OtelError> {
+    init_tracer_provider(traces_endpoint)?;
+    if !logs_endpoint.is_empty() {
+        init_logger_provider(logs_endpoint)?;
+    }
+
+    Ok(())
+}
+
+/// Flush and shut down the global tracer and logger providers.
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+    global::shutdown_logger_provider();
+}
+
+/// Install a batching OTLP trace pipeline exporting to `endpoint` as the global tracer provider, or a provider that
+/// doesn't export spans if `endpoint` is empty.
+pub fn init_tracer_provider(endpoint: &str) -> Result<(), TraceError> {
+    if endpoint.is_empty() {
+        global::set_tracer_provider(opentelemetry_sdk::trace::TracerProvider::builder().build());
+        return Ok(());
+    }
+
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+
+    Ok(())
+}
+
+/// Install a batching OTLP log pipeline exporting to `endpoint` as the global logger provider.
+pub fn init_logger_provider(endpoint: &str) -> Result<(), LogError> {
+    opentelemetry_otlp::new_pipeline()
+        .logging()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+
+    Ok(())
+}
+
+/// The outcome of a single webhook delivery attempt.
+pub struct Delivery<'a> {
+    pub job_id: i64,
+    pub team_id: u32,
+    pub url: &'a str,
+    pub attempt: i32,
+    pub duration: time::Duration,
+    pub result: &'a Result<reqwest::Response, WebhookError>,
+}
+
+impl Delivery<'_> {
+    /// A short description of the outcome, used as the `status` attribute.
+    fn status(&self) -> &'static str {
+        match self.result {
+            Ok(_) => "success",
+            Err(WebhookError::RetryableRequestError { .. })
+            | Err(WebhookError::RetryableTimeoutError { .. }) => "retryable_error",
+            Err(WebhookError::NonRetryableRequestError { .. }) => "non_retryable_error",
+            Err(WebhookError::ParseHeadersError(_))
+            | Err(WebhookError::ParseHttpMethodError(_))
+            | Err(WebhookError::InvalidContentTypeError(_))
+            | Err(WebhookError::MultipartWithBodyError)
+            | Err(WebhookError::ParseUrlError(_))
+            | Err(WebhookError::EscapedBaseUrlError(_)) => "parse_error",
+            Err(WebhookError::DisallowedHostError(_)) => "disallowed_host",
+            Err(WebhookError::BodyTooLargeError { .. }) => "body_too_large",
+            Err(WebhookError::RedirectError { .. }) => "redirect",
+            Err(WebhookError::UnsuccessfulResponseError { .. }) => "unsuccessful_response",
+        }
+    }
+
+    /// The HTTP status code returned by the destination, if we got a response at all.
+    fn status_code(&self) -> Option<u16> {
+        match self.result {
+            Ok(response) => Some(response.status().as_u16()),
+            Err(WebhookError::RetryableRequestError { error, .. })
+            | Err(WebhookError::NonRetryableRequestError { error, .. }) => {
+                error.status().map(|status| status.as_u16())
+            }
+            Err(WebhookError::RedirectError { status, .. })
+            | Err(WebhookError::UnsuccessfulResponseError { status, .. }) => Some(status.as_u16()),
+            _ => None,
+        }
+    }
+
+    fn target_host(&self) -> Option<String> {
+        reqwest::Url::parse(self.url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_owned))
+    }
+}
+
+/// Start a span for a webhook delivery, returning a `Context` with the span active.
+/// The span is a child of the span in the W3C `trace_context` of the job, if any, and starts a new trace otherwise.
+/// Delivery records emitted with this `Context` attached carry the span's trace id.
+pub fn start_delivery_span(trace_context: &collections::HashMap<String, String>) -> Context {
+    let parent_cx = TraceContextPropagator::new().extract(trace_context);
+    let span =
+        global::tracer(INSTRUMENTATION_NAME).start_with_context("webhook_delivery", &parent_cx);
+    Context::current_with_span(span)
+}
+
+/// Add W3C trace context headers (`traceparent` and `tracestate`) for the span in `cx` to a webhook's `headers`,
+/// so that destinations can correlate the webhook with our traces.
+/// Nothing is added if `cx` has no valid span, and headers already set by the job are kept as they are.
+pub fn inject_trace_context(
+    cx: &Context,
+    mut headers: collections::HashMap<String, String>,
+) -> collections::HashMap<String, String> {
+    let mut trace_headers = collections::HashMap::new();
+    TraceContextPropagator::new().inject_context(cx, &mut trace_headers);
+
+    for (name, value) in trace_headers {
+        if !headers.keys().any(|key| key.eq_ignore_ascii_case(&name)) {
+            headers.insert(name, value);
+        }
+    }
+
+    headers
+}
+
+/// Emit a delivery record with the global logger, correlated to the delivery span in `cx`.
+pub fn emit_delivery_log(cx: &Context, delivery: &Delivery) {
+    let _guard = cx.clone().attach();
+    emit(&global::logger(INSTRUMENTATION_NAME.into()), delivery);
+    cx.span().end();
+}
+
+/// Emit a delivery record with `logger`.
+/// The trace context is taken by the logger from the current `Context`.
+pub fn emit(logger: &impl Logger, delivery: &Delivery) {
+    let status = delivery.status();
+    let severity = match delivery.result {
+        Ok(_) => Severity::Info,
+        Err(_) => Severity::Warn,
+    };
+
+    let mut attributes = vec![
+        KeyValue::new("job_id", delivery.job_id),
+        KeyValue::new("team_id", delivery.team_id as i64),
+        KeyValue::new("status", status),
+        KeyValue::new("attempt", delivery.attempt as i64),
+        KeyValue::new("duration_ms", delivery.duration.as_millis() as i64),
+    ];
+    if let Some(host) = delivery.target_host() {
+        attributes.push(KeyValue::new("target_host", host));
+    }
+    if let Some(status_code) = delivery.status_code() {
+        attributes.push(KeyValue::new("http_status_code", status_code as i64));
+    }
+
+    let record = LogRecord::builder()
+        .with_observed_timestamp(time::SystemTime::now())
+        .with_severity_number(severity)
+        .with_severity_text(severity.name())
+        .with_body(format!("webhook delivery {}", status).into())
+        .with_attributes(
+            attributes
+                .into_iter()
+                .map(|kv| (kv.key, kv.value.into()))
+                .collect(),
+        )
+        .build();
+
+    logger.emit(record);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::logs::{AnyValue, LoggerProvider as _};
+    use opentelemetry::trace::{Span as _, TracerProvider as _};
+    use opentelemetry::Key;
+    use opentelemetry_sdk::logs::LoggerProvider;
+    use opentelemetry_sdk::testing::logs::InMemoryLogsExporter;
+
+    /// Find the value of the attribute `key`, formatted as a string as `AnyValue` can't be compared.
+    fn attribute(attributes: &[(Key, AnyValue)], key: &str) -> Option<String> {
+        attributes
+            .iter()
+            .find(|(k, _)| k.as_str() == key)
+            .map(|(_, value)| match value {
+                AnyValue::Int(value) => value.to_string(),
+                AnyValue::String(value) => value.to_string(),
+                value => format!("{:?}", value),
+            })
+    }
+
+    #[test]
+    fn test_emits_delivery_record() {
+        let exporter = InMemoryLogsExporter::default();
+        let logger_provider = LoggerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let logger = logger_provider.logger("test");
+        let tracer_provider = opentelemetry_sdk::trace::TracerProvider::builder().build();
+        let span = tracer_provider.tracer("test").start("webhook_delivery");
+        let trace_id = span.span_context().trace_id();
+
+        let result = Err(WebhookError::ParseHttpMethodError("TRACE".to_owned()));
+        let delivery = Delivery {
+            job_id: 42,
+            team_id: 1,
+            url: "https://webhooks.example.com/hook",
+            attempt: 2,
+            duration: time::Duration::from_millis(150),
+            result: &result,
+        };
+
+        {
+            let _guard = Context::current_with_span(span).attach();
+            emit(&logger, &delivery);
+        }
+
+        // The simple processor exports from a background thread, so wait for the record to arrive.
+        let mut logs = Vec::new();
+        for _ in 0..100 {
+            logs = exporter.get_emitted_logs().expect("failed to get logs");
+            if !logs.is_empty() {
+                break;
+            }
+            std::thread::sleep(time::Duration::from_millis(10));
+        }
+        assert_eq!(logs.len(), 1);
+
+        let record = &logs[0].record;
+        let attributes = record.attributes.as_ref().expect("no attributes");
+        assert_eq!(attribute(attributes, "job_id"), Some("42".to_owned()));
+        assert_eq!(attribute(attributes, "team_id"), Some("1".to_owned()));
+        assert_eq!(
+            attribute(attributes, "target_host"),
+            Some("webhooks.example.com".to_owned())
+        );
+        assert_eq!(
+            attribute(attributes, "status"),
+            Some("parse_error".to_owned())
+        );
+        assert_eq!(attribute(attributes, "attempt"), Some("2".to_owned()));
+        assert_eq!(attribute(attributes, "duration_ms"), Some("150".to_owned()));
+        assert_eq!(attribute(attributes, "http_status_code"), None);
+        assert_eq!(
+            record
+                .trace_context
+                .as_ref()
+                .expect("record is not correlated to a span")
+                .trace_id,
+            trace_id
+        );
+    }
+
+    #[test]
+    fn test_injects_trace_context() {
+        let tracer_provider = opentelemetry_sdk::trace::TracerProvider::builder().build();
+        let span = tracer_provider.tracer("test").start("webhook_delivery");
+        let span_context = span.span_context().clone();
+        let cx = Context::current_with_span(span);
+
+        let headers = inject_trace_context(&cx, collections::HashMap::new());
+        let traceparent = headers.get("traceparent").expect("traceparent is missing");
+
+        assert_eq!(
+            traceparent,
+            &format!(
+                "00-{}-{}-01",
+                span_context.trace_id(),
+                span_context.span_id()
+            )
+        );
+        assert_eq!(traceparent.len(), 55);
+
+        // Without a valid span there is no trace context to propagate.
+        let headers = inject_trace_context(&Context::new(), collections::HashMap::new());
+        assert!(headers.is_empty());
+
+        // Trace context set by the job itself is kept.
+        let job_headers = collections::HashMap::from([(
+            "Traceparent".to_owned(),
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01".to_owned(),
+        )]);
+        let headers = inject_trace_context(&cx, job_headers.clone());
+        assert_eq!(headers.get("Traceparent"), job_headers.get("Traceparent"));
+        assert_eq!(headers.get("traceparent"), None);
+    }
+
+    #[test]
+    fn test_init_traces_deliveries() {
+        init("", "").expect("failed to initialize OpenTelemetry");
+
+        let cx = start_delivery_span(&collections::HashMap::new());
+        assert!(cx.span().span_context().is_valid());
+        let headers = inject_trace_context(&cx, collections::HashMap::new());
+        assert!(headers.contains_key("traceparent"));
+
+        // A delivery continues the trace the job was enqueued in.
+        let trace_context = collections::HashMap::from([(
+            "traceparent".to_owned(),
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01".to_owned(),
+        )]);
+        let cx = start_delivery_span(&trace_context);
+        let span_context = cx.span().span_context().clone();
+        assert_eq!(
+            span_context.trace_id().to_string(),
+            "0af7651916cd43dd8448eb211c80319c"
+        );
+        assert_ne!(span_context.span_id().to_string(), "b7ad6b7169203331");
+    }
+}
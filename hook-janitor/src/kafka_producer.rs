@@ -17,10 +17,13 @@ pub async fn create_kafka_producer(
     client_config
         .set("bootstrap.servers", &config.kafka_hosts)
         .set("statistics.interval.ms", "10000")
-        .set("linger.ms", config.kafka_producer_linger_ms.to_string())
+        .set(
+            "linger.ms",
+            config.kafka_producer_linger_ms.0.as_millis().to_string(),
+        )
         .set(
             "message.timeout.ms",
-            config.kafka_message_timeout_ms.to_string(),
+            config.kafka_message_timeout_ms.0.as_millis().to_string(),
         )
         .set(
             "compression.codec",
@@ -4,16 +4,19 @@ use config::Config;
 use envconfig::Envconfig;
 use eyre::Result;
 use futures::future::{select, Either};
+#[cfg(feature = "kafka")]
 use kafka_producer::create_kafka_producer;
+use sqlx::types::chrono::Utc;
 use std::{str::FromStr, time::Duration};
 use tokio::sync::Semaphore;
 use webhooks::WebhookCleaner;
 
-use hook_common::metrics;
+use hook_common::{metrics, pgqueue::PgQueue, webhook::WebhookJobError};
 
 mod cleanup;
 mod config;
 mod handlers;
+#[cfg(feature = "kafka")]
 mod kafka_producer;
 mod webhooks;
 
@@ -25,9 +28,9 @@ async fn listen(app: Router, bind: String) -> Result<()> {
     Ok(())
 }
 
-async fn cleanup_loop(cleaner: Box<dyn Cleaner>, interval_secs: u64) {
+async fn cleanup_loop(cleaner: Box<dyn Cleaner>, interval: Duration) {
     let semaphore = Semaphore::new(1);
-    let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+    let mut interval = tokio::time::interval(interval);
 
     loop {
         let _permit = semaphore.acquire().await;
@@ -37,46 +40,139 @@ async fn cleanup_loop(cleaner: Box<dyn Cleaner>, interval_secs: u64) {
     }
 }
 
+async fn recurring_jobs_loop(queue: PgQueue, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+
+    loop {
+        interval.tick().await;
+
+        match queue.materialize_recurring(Utc::now()).await {
+            Ok(materialized) => tracing::debug!("materialized {} recurring jobs", materialized),
+            Err(e) => tracing::error!("failed to materialize recurring jobs, {}", e),
+        }
+    }
+}
+
+async fn running_jobs_loop(queue: PgQueue, interval: Duration, timeout: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    let error = WebhookJobError::new_timeout(&format!(
+        "job was still running after {} seconds",
+        timeout.as_secs()
+    ));
+
+    loop {
+        interval.tick().await;
+
+        match queue.expire_running(timeout, &error).await {
+            Ok(expired) => tracing::debug!("recovered {} running jobs", expired),
+            Err(e) => tracing::error!("failed to recover running jobs, {}", e),
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt::init();
-
     let config = Config::init_from_env().expect("failed to load configuration from env");
+    config.logging.init().expect("failed to initialize logging");
 
     let mode_name = CleanerModeName::from_str(&config.mode)
         .unwrap_or_else(|_| panic!("invalid cleaner mode: {}", config.mode));
 
+    let connect_options = config
+        .connection
+        .connect_options(&config.database_url)
+        .expect("invalid database connection options");
+
     let cleaner = match mode_name {
         CleanerModeName::Webhooks => {
-            let kafka_producer = create_kafka_producer(&config.kafka)
-                .await
-                .expect("failed to create kafka producer");
-
-            Box::new(
-                WebhookCleaner::new(
-                    &config.queue_name,
-                    &config.database_url,
-                    kafka_producer,
-                    config.kafka.app_metrics_topic.to_owned(),
-                )
-                .expect("unable to create webhook cleaner"),
+            let mut cleaner = WebhookCleaner::new(
+                &config.queue_name,
+                connect_options.clone(),
+                config.connection.pool_options(),
             )
+            .and_then(|cleaner| cleaner.table_name(&config.table_name))
+            .expect("unable to create webhook cleaner");
+            if !config.query_timeout.0.is_zero() {
+                cleaner = cleaner.query_timeout(config.query_timeout.0);
+            }
+
+            #[cfg(feature = "kafka")]
+            let cleaner = {
+                let kafka_producer = create_kafka_producer(&config.kafka)
+                    .await
+                    .expect("failed to create kafka producer");
+
+                let mut cleaner = cleaner
+                    .kafka(kafka_producer, config.kafka.app_metrics_topic.to_owned())
+                    .plugin_log_entries(
+                        config.kafka.plugin_log_entries_topic.to_owned(),
+                        config.kafka.plugin_log_entries_level,
+                        config.kafka.plugin_log_entries_max_message_length,
+                    )
+                    .expect("unable to produce plugin log entries");
+                if !config.kafka.janitor_runs_topic.is_empty() {
+                    cleaner = cleaner
+                        .janitor_runs_topic(config.kafka.janitor_runs_topic.to_owned())
+                        .expect("unable to produce janitor runs");
+                }
+                cleaner
+            };
+
+            Box::new(cleaner)
         }
     };
 
-    let cleanup_loop = Box::pin(cleanup_loop(cleaner, config.cleanup_interval_secs));
+    let cleanup_loop = Box::pin(cleanup_loop(cleaner, config.cleanup_interval_secs.0));
+
+    let mut queue = PgQueue::new_with_pool_options(
+        &config.queue_name,
+        Some(&config.table_name),
+        connect_options,
+        config.connection.pool_options(),
+    )
+    .await
+    .expect("failed to initialize queue");
+    if !config.query_timeout.0.is_zero() {
+        queue = queue.query_timeout(config.query_timeout.0);
+    }
+    let running_jobs_loop = Box::pin(async {
+        if config.running_job_timeout_secs.0.is_zero() {
+            return std::future::pending().await;
+        }
+        running_jobs_loop(
+            queue.clone(),
+            config.running_jobs_interval_secs.0,
+            config.running_job_timeout_secs.0,
+        )
+        .await
+    });
+    let recurring_jobs_loop = Box::pin(recurring_jobs_loop(
+        queue.clone(),
+        config.recurring_jobs_interval_secs.0,
+    ));
 
     let recorder_handle = metrics::setup_metrics_recorder();
     let app = handlers::app(Some(recorder_handle));
     let http_server = Box::pin(listen(app, config.bind()));
 
-    match select(http_server, cleanup_loop).await {
+    match select(
+        http_server,
+        select(cleanup_loop, select(recurring_jobs_loop, running_jobs_loop)),
+    )
+    .await
+    {
         Either::Left((listen_result, _)) => match listen_result {
             Ok(_) => {}
             Err(e) => tracing::error!("failed to start hook-janitor http server, {}", e),
         },
-        Either::Right((_, _)) => {
+        Either::Right((Either::Left(_), _)) => {
             tracing::error!("hook-janitor cleanup task exited")
         }
+        Either::Right((Either::Right((Either::Left(_), _)), _)) => {
+            tracing::error!("hook-janitor recurring jobs task exited")
+        }
+        Either::Right((Either::Right((Either::Right(_), _)), _)) => {
+            tracing::error!("hook-janitor running jobs task exited")
+        }
     };
 }
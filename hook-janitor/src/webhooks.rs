@@ -1,52 +1,99 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
+#[cfg(feature = "kafka")]
 use futures::future::join_all;
 use hook_common::webhook::WebhookJobError;
+#[cfg(feature = "kafka")]
 use rdkafka::error::KafkaError;
+#[cfg(feature = "kafka")]
 use rdkafka::producer::{FutureProducer, FutureRecord};
+#[cfg(feature = "kafka")]
 use serde_json::error::Error as SerdeError;
-use sqlx::postgres::{PgPool, PgPoolOptions, Postgres};
-use sqlx::types::{chrono, Uuid};
+use sqlx::postgres::{PgConnectOptions, PgPool, PgPoolOptions, Postgres};
+use sqlx::types::{chrono, Json, Uuid};
 use sqlx::Transaction;
 use thiserror::Error;
 use tracing::{debug, error};
 
 use crate::cleanup::Cleaner;
+#[cfg(feature = "kafka")]
 use crate::kafka_producer::KafkaContext;
 
-use hook_common::kafka_messages::app_metrics::{AppMetric, AppMetricCategory};
+use hook_common::kafka_messages::app_metrics::{AppMetric, AppMetricCategory, ErrorType};
+use hook_common::kafka_messages::janitor_runs::JanitorRun;
+#[cfg(feature = "kafka")]
+use hook_common::kafka_messages::plugin_logs::{
+    PluginLogEntry, PluginLogEntrySource, PluginLogEntryType,
+};
+use hook_common::pgqueue::{is_valid_table_name, JobStatus, DEFAULT_TABLE};
 
 #[derive(Error, Debug)]
+// Only the Kafka variants break the pattern.
+#[cfg_attr(not(feature = "kafka"), allow(clippy::enum_variant_names))]
 pub enum WebhookCleanerError {
-    #[error("failed to create postgres pool: {error}")]
-    PoolCreationError { error: sqlx::Error },
     #[error("failed to acquire conn and start txn: {error}")]
     StartTxnError { error: sqlx::Error },
     #[error("failed to get completed rows: {error}")]
     GetCompletedRowsError { error: sqlx::Error },
     #[error("failed to get failed rows: {error}")]
     GetFailedRowsError { error: sqlx::Error },
+    #[error("failed to get discarded rows: {error}")]
+    GetDiscardedRowsError { error: sqlx::Error },
+    #[cfg(feature = "kafka")]
     #[error("failed to serialize rows: {error}")]
     SerializeRowsError { error: SerdeError },
+    #[cfg(feature = "kafka")]
     #[error("failed to produce to kafka: {error}")]
     KafkaProduceError { error: KafkaError },
+    #[cfg(feature = "kafka")]
     #[error("failed to produce to kafka (timeout)")]
     KafkaProduceCanceled,
     #[error("failed to delete rows: {error}")]
     DeleteRowsError { error: sqlx::Error },
     #[error("failed to commit txn: {error}")]
     CommitTxnError { error: sqlx::Error },
+    #[error("{0} is not a valid table name")]
+    InvalidTableName(String),
+    #[error("timed out after {timeout:?} waiting for the database")]
+    TimeoutError { timeout: Duration },
+    #[cfg(feature = "kafka")]
+    #[error("{0} can't be produced without a Kafka producer, set one with `kafka` first")]
+    KafkaNotSet(&'static str),
 }
 
 type Result<T, E = WebhookCleanerError> = std::result::Result<T, E>;
 
 pub struct WebhookCleaner {
     queue_name: String,
+    // The table jobs are cleaned up from, only ever set to a name checked by `is_valid_table_name`.
+    table: String,
     pg_pool: PgPool,
-    kafka_producer: FutureProducer<KafkaContext>,
+    // Any query, or acquiring a connection for one, gives up after this long if set.
+    query_timeout: Option<Duration>,
+    // Without Kafka, rows are still cleaned up, but nothing is reported about them.
+    #[cfg(feature = "kafka")]
+    kafka: Option<KafkaOutput>,
+}
+
+#[cfg(feature = "kafka")]
+struct KafkaOutput {
+    producer: FutureProducer<KafkaContext>,
     app_metrics_topic: String,
+    janitor_runs_topic: Option<String>,
+    plugin_log_entries: Option<PluginLogEntriesOutput>,
+}
+
+#[cfg(feature = "kafka")]
+struct PluginLogEntriesOutput {
+    topic: String,
+    // The type of the log entries produced for failed jobs, i.e. the level they are shown at.
+    level: PluginLogEntryType,
+    // Error messages are truncated to this many characters.
+    max_message_length: usize,
+    // Identifies this janitor as the source of the log entries.
+    instance_id: Uuid,
 }
 
 #[derive(sqlx::FromRow, Debug)]
@@ -95,14 +142,19 @@ struct FailedRow {
     team_id: u32,
     #[sqlx(try_from = "i64")]
     plugin_config_id: u32,
-    #[sqlx(json)]
-    last_error: WebhookJobError,
+    // Jobs can be failed without storing an error, e.g. if they were failed before errors were kept.
+    last_error: Option<Json<WebhookJobError>>,
     #[sqlx(try_from = "i64")]
     failures: u32,
 }
 
 impl From<FailedRow> for AppMetric {
     fn from(row: FailedRow) -> Self {
+        let (error_type, error_details) = match row.last_error {
+            Some(Json(error)) => (Some(error.r#type), Some(error.details)),
+            None => (None, None),
+        };
+
         AppMetric {
             timestamp: row.hour,
             team_id: row.team_id,
@@ -113,8 +165,128 @@ impl From<FailedRow> for AppMetric {
             successes_on_retry: 0,
             failures: row.failures,
             error_uuid: Some(Uuid::now_v7()),
-            error_type: Some(row.last_error.r#type),
-            error_details: Some(row.last_error.details),
+            error_type,
+            error_details,
+        }
+    }
+}
+
+#[derive(sqlx::FromRow, Debug)]
+struct DiscardedRow {
+    // App Metrics truncates/aggregates rows on the hour, so we take advantage of that to GROUP BY
+    // and aggregate to select fewer rows.
+    hour: DateTime<Utc>,
+    #[sqlx(try_from = "i64")]
+    team_id: u32,
+    #[sqlx(try_from = "i64")]
+    plugin_config_id: u32,
+    // The status code the target rejected the jobs with.
+    response_status: Option<i32>,
+    #[sqlx(try_from = "i64")]
+    discards: u32,
+}
+
+// Discarded jobs were not delivered, so they are reported as failures, but with the status code
+// they were rejected with instead of an error, as the consumer doesn't store one for them.
+impl From<DiscardedRow> for AppMetric {
+    fn from(row: DiscardedRow) -> Self {
+        AppMetric {
+            timestamp: row.hour,
+            team_id: row.team_id,
+            plugin_config_id: row.plugin_config_id,
+            job_id: None,
+            category: AppMetricCategory::Webhook,
+            successes: 0,
+            successes_on_retry: 0,
+            failures: row.discards,
+            error_uuid: Some(Uuid::now_v7()),
+            error_type: row
+                .response_status
+                .map(|status| ErrorType::BadHttpStatus(status as u16)),
+            error_details: None,
+        }
+    }
+}
+
+// Cleanups delete jobs in every terminal status (see `JobStatus::is_terminal`).
+// All cleanup queries filter on `COALESCE(last_attempt_finished_at, created_at) <= NOW()`, where
+// `NOW()` is the start of the cleanup transaction, so jobs that reached a terminal status without
+// finishing an attempt are cleaned up too. Together with the status and queue filters, this matches
+// the partial index `idx_queue_status_last_attempt_finished_at`, which is on the same expression, so
+// they don't need to scan the whole table. Jobs that finish while a cleanup is running are left for
+// the next one.
+// Deleted rows are counted by status, so that what's reported as deleted is what was deleted.
+fn delete_observed_rows_query(table: &str) -> String {
+    let statuses = JobStatus::ALL
+        .iter()
+        .filter(|status| status.is_terminal())
+        .map(|status| format!("'{}'", status.as_str()))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    format!(
+        r#"
+    WITH deleted AS (
+        DELETE FROM {table}
+        WHERE status IN ({statuses})
+          AND queue = $1
+          AND COALESCE(last_attempt_finished_at, created_at) <= NOW()
+        RETURNING status
+    )
+    SELECT status, count(*) FROM deleted GROUP BY status;
+"#
+    )
+}
+
+// The number of rows deleted by a cleanup, by status.
+#[derive(Debug, Default)]
+struct DeletedRows {
+    completed: u64,
+    failed: u64,
+    discarded: u64,
+    cancelled: u64,
+}
+
+impl DeletedRows {
+    fn total(&self) -> u64 {
+        self.completed + self.failed + self.discarded + self.cancelled
+    }
+}
+
+#[cfg(feature = "kafka")]
+#[derive(sqlx::FromRow, Debug)]
+struct FailedJobRow {
+    finished_at: DateTime<Utc>,
+    #[sqlx(try_from = "i64")]
+    team_id: u32,
+    #[sqlx(try_from = "i64")]
+    plugin_id: u32,
+    #[sqlx(try_from = "i64")]
+    plugin_config_id: u32,
+    last_error: Option<Json<WebhookJobError>>,
+}
+
+#[cfg(feature = "kafka")]
+impl FailedJobRow {
+    fn into_plugin_log_entry(self, output: &PluginLogEntriesOutput) -> PluginLogEntry {
+        let message = match self.last_error {
+            Some(Json(error)) => match error.details.error.message {
+                Some(message) => format!("{}: {}", error.details.error.name, message),
+                None => error.details.error.name,
+            },
+            None => "Unknown Error".to_owned(),
+        };
+
+        PluginLogEntry {
+            source: PluginLogEntrySource::System,
+            type_: output.level,
+            id: Uuid::now_v7(),
+            team_id: self.team_id,
+            plugin_id: self.plugin_id,
+            plugin_config_id: self.plugin_config_id,
+            timestamp: self.finished_at,
+            message: message.chars().take(output.max_message_length).collect(),
+            instance_id: output.instance_id,
         }
     }
 }
@@ -125,49 +297,124 @@ struct SerializableTxn<'a>(Transaction<'a, Postgres>);
 
 struct CleanupStats {
     rows_processed: u64,
+    rows_deleted: DeletedRows,
     completed_agg_row_count: usize,
     failed_agg_row_count: usize,
+    // The number of job rows aggregated, as opposed to the number of aggregated rows above.
+    completed_row_count: u64,
+    failed_row_count: u64,
+    discarded_agg_row_count: usize,
+    discarded_row_count: u64,
 }
 
 impl WebhookCleaner {
+    /// Create a `WebhookCleaner` with a pool built from `pool_options`, like `PgQueue::new_with_pool_options`.
     pub fn new(
         queue_name: &str,
-        database_url: &str,
-        kafka_producer: FutureProducer<KafkaContext>,
-        app_metrics_topic: String,
+        options: PgConnectOptions,
+        pool_options: PgPoolOptions,
     ) -> Result<Self> {
+        let pg_pool = pool_options.connect_lazy_with(options);
+
+        Self::new_from_pool(queue_name, pg_pool)
+    }
+
+    pub fn new_from_pool(queue_name: &str, pg_pool: PgPool) -> Result<Self> {
         let queue_name = queue_name.to_owned();
-        let pg_pool = PgPoolOptions::new()
-            .acquire_timeout(Duration::from_secs(10))
-            .connect_lazy(database_url)
-            .map_err(|error| WebhookCleanerError::PoolCreationError { error })?;
 
         Ok(Self {
             queue_name,
+            table: DEFAULT_TABLE.to_owned(),
             pg_pool,
-            kafka_producer,
-            app_metrics_topic,
+            query_timeout: None,
+            #[cfg(feature = "kafka")]
+            kafka: None,
         })
     }
 
-    #[allow(dead_code)] // This is used in tests.
-    pub fn new_from_pool(
-        queue_name: &str,
-        pg_pool: PgPool,
+    /// Clean up jobs from `table_name` instead of the default `job_queue` table, like `PgQueue::table_name`.
+    pub fn table_name(mut self, table_name: &str) -> Result<Self> {
+        if !is_valid_table_name(table_name) {
+            return Err(WebhookCleanerError::InvalidTableName(table_name.to_owned()));
+        }
+
+        self.table = table_name.to_owned();
+        Ok(self)
+    }
+
+    /// Give up on any query of a cleanup after `query_timeout`, returning a `WebhookCleanerError::TimeoutError`,
+    /// like `PgQueue::query_timeout`. Producing to Kafka isn't covered, as it has timeouts of its own.
+    pub fn query_timeout(mut self, query_timeout: Duration) -> Self {
+        self.query_timeout = Some(query_timeout);
+        self
+    }
+
+    /// Produce `AppMetric`s for the rows we clean up to `app_metrics_topic`.
+    #[cfg(feature = "kafka")]
+    pub fn kafka(
+        mut self,
         kafka_producer: FutureProducer<KafkaContext>,
         app_metrics_topic: String,
+    ) -> Self {
+        self.kafka = Some(KafkaOutput {
+            producer: kafka_producer,
+            app_metrics_topic,
+            janitor_runs_topic: None,
+            plugin_log_entries: None,
+        });
+        self
+    }
+
+    /// Produce a `PluginLogEntry` of type `level` to `topic` for every failed job we clean up, with the
+    /// job's last error truncated to `max_message_length` characters.
+    /// Fails if no Kafka producer was set with `kafka` first.
+    #[cfg(feature = "kafka")]
+    pub fn plugin_log_entries(
+        mut self,
+        topic: String,
+        level: PluginLogEntryType,
+        max_message_length: usize,
     ) -> Result<Self> {
-        let queue_name = queue_name.to_owned();
+        let kafka = self
+            .kafka
+            .as_mut()
+            .ok_or(WebhookCleanerError::KafkaNotSet("plugin log entries"))?;
+        kafka.plugin_log_entries = Some(PluginLogEntriesOutput {
+            topic,
+            level,
+            max_message_length,
+            instance_id: Uuid::now_v7(),
+        });
+        Ok(self)
+    }
 
-        Ok(Self {
-            queue_name,
-            pg_pool,
-            kafka_producer,
-            app_metrics_topic,
-        })
+    /// Produce a `JanitorRun` summary to `janitor_runs_topic` at the end of every cleanup run.
+    /// Fails if no Kafka producer was set with `kafka` first.
+    #[cfg(feature = "kafka")]
+    pub fn janitor_runs_topic(mut self, janitor_runs_topic: String) -> Result<Self> {
+        let kafka = self
+            .kafka
+            .as_mut()
+            .ok_or(WebhookCleanerError::KafkaNotSet("janitor runs"))?;
+        kafka.janitor_runs_topic = Some(janitor_runs_topic);
+        Ok(self)
+    }
+
+    /// Run a database operation, failing with a `WebhookCleanerError::TimeoutError` if it takes longer than the
+    /// `query_timeout`. Dropping the operation on timeout drops the connection it was using.
+    async fn with_timeout<T>(
+        &self,
+        operation: impl std::future::Future<Output = Result<T>>,
+    ) -> Result<T> {
+        match self.query_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, operation)
+                .await
+                .map_err(|_| WebhookCleanerError::TimeoutError { timeout })?,
+            None => operation.await,
+        }
     }
 
-    async fn start_serializable_txn(&self) -> Result<SerializableTxn> {
+    async fn start_serializable_txn(&self) -> Result<SerializableTxn<'_>> {
         let mut tx = self
             .pg_pool
             .begin()
@@ -189,19 +436,23 @@ impl WebhookCleaner {
     }
 
     async fn get_completed_rows(&self, tx: &mut SerializableTxn<'_>) -> Result<Vec<CompletedRow>> {
-        let base_query = r#"
-            SELECT DATE_TRUNC('hour', last_attempt_finished_at) AS hour,
+        let table = &self.table;
+        let base_query = format!(
+            r#"
+            SELECT DATE_TRUNC('hour', COALESCE(last_attempt_finished_at, created_at)) AS hour,
                 (metadata->>'team_id')::bigint AS team_id,
                 (metadata->>'plugin_config_id')::bigint AS plugin_config_id,
                 count(*) as successes
-            FROM job_queue
+            FROM {table}
             WHERE status = 'completed'
                 AND queue = $1
+                AND COALESCE(last_attempt_finished_at, created_at) <= NOW()
             GROUP BY hour, team_id, plugin_config_id
             ORDER BY hour, team_id, plugin_config_id;
-        "#;
+        "#
+        );
 
-        let rows = sqlx::query_as::<_, CompletedRow>(base_query)
+        let rows = sqlx::query_as::<_, CompletedRow>(&base_query)
             .bind(&self.queue_name)
             .fetch_all(&mut *tx.0)
             .await
@@ -211,20 +462,24 @@ impl WebhookCleaner {
     }
 
     async fn get_failed_rows(&self, tx: &mut SerializableTxn<'_>) -> Result<Vec<FailedRow>> {
-        let base_query = r#"
-            SELECT DATE_TRUNC('hour', last_attempt_finished_at) AS hour,
+        let table = &self.table;
+        let base_query = format!(
+            r#"
+            SELECT DATE_TRUNC('hour', COALESCE(last_attempt_finished_at, created_at)) AS hour,
                    (metadata->>'team_id')::bigint AS team_id,
                    (metadata->>'plugin_config_id')::bigint AS plugin_config_id,
                    errors[array_upper(errors, 1)] AS last_error,
                    count(*) as failures
-            FROM job_queue
+            FROM {table}
             WHERE status = 'failed'
               AND queue = $1
+              AND COALESCE(last_attempt_finished_at, created_at) <= NOW()
             GROUP BY hour, team_id, plugin_config_id, last_error
             ORDER BY hour, team_id, plugin_config_id, last_error;
-        "#;
+        "#
+        );
 
-        let rows = sqlx::query_as::<_, FailedRow>(base_query)
+        let rows = sqlx::query_as::<_, FailedRow>(&base_query)
             .bind(&self.queue_name)
             .fetch_all(&mut *tx.0)
             .await
@@ -233,10 +488,93 @@ impl WebhookCleaner {
         Ok(rows)
     }
 
+    async fn get_discarded_rows(&self, tx: &mut SerializableTxn<'_>) -> Result<Vec<DiscardedRow>> {
+        let table = &self.table;
+        let base_query = format!(
+            r#"
+            SELECT DATE_TRUNC('hour', COALESCE(last_attempt_finished_at, created_at)) AS hour,
+                   (metadata->>'team_id')::bigint AS team_id,
+                   (metadata->>'plugin_config_id')::bigint AS plugin_config_id,
+                   response_status,
+                   count(*) as discards
+            FROM {table}
+            WHERE status = 'discarded'
+              AND queue = $1
+              AND COALESCE(last_attempt_finished_at, created_at) <= NOW()
+            GROUP BY hour, team_id, plugin_config_id, response_status
+            ORDER BY hour, team_id, plugin_config_id, response_status;
+        "#
+        );
+
+        let rows = sqlx::query_as::<_, DiscardedRow>(&base_query)
+            .bind(&self.queue_name)
+            .fetch_all(&mut *tx.0)
+            .await
+            .map_err(|e| WebhookCleanerError::GetDiscardedRowsError { error: e })?;
+
+        Ok(rows)
+    }
+
+    #[cfg(feature = "kafka")]
+    async fn get_failed_jobs(&self, tx: &mut SerializableTxn<'_>) -> Result<Vec<FailedJobRow>> {
+        let table = &self.table;
+        let base_query = format!(
+            r#"
+            SELECT COALESCE(last_attempt_finished_at, created_at) AS finished_at,
+                   (metadata->>'team_id')::bigint AS team_id,
+                   (metadata->>'plugin_id')::bigint AS plugin_id,
+                   (metadata->>'plugin_config_id')::bigint AS plugin_config_id,
+                   errors[array_upper(errors, 1)] AS last_error
+            FROM {table}
+            WHERE status = 'failed'
+              AND queue = $1
+              AND COALESCE(last_attempt_finished_at, created_at) <= NOW()
+            ORDER BY finished_at;
+        "#
+        );
+
+        let rows = sqlx::query_as::<_, FailedJobRow>(&base_query)
+            .bind(&self.queue_name)
+            .fetch_all(&mut *tx.0)
+            .await
+            .map_err(|e| WebhookCleanerError::GetFailedRowsError { error: e })?;
+
+        Ok(rows)
+    }
+
+    #[cfg(feature = "kafka")]
+    async fn send_plugin_log_entries_to_kafka(&self, tx: &mut SerializableTxn<'_>) -> Result<()> {
+        let (producer, output) = match &self.kafka {
+            Some(KafkaOutput {
+                producer,
+                plugin_log_entries: Some(output),
+                ..
+            }) => (producer, output),
+            _ => return Ok(()),
+        };
+
+        let payloads: Vec<String> = self
+            .with_timeout(self.get_failed_jobs(tx))
+            .await?
+            .into_iter()
+            .map(|row| serde_json::to_string(&row.into_plugin_log_entry(output)))
+            .collect::<Result<Vec<String>, SerdeError>>()
+            .map_err(|e| WebhookCleanerError::SerializeRowsError { error: e })?;
+
+        produce_payloads(producer, &output.topic, payloads).await
+    }
+
+    #[cfg(not(feature = "kafka"))]
+    async fn send_plugin_log_entries_to_kafka(&self, _tx: &mut SerializableTxn<'_>) -> Result<()> {
+        Ok(())
+    }
+
+    #[cfg(feature = "kafka")]
     async fn send_metrics_to_kafka(&self, metrics: Vec<AppMetric>) -> Result<()> {
-        if metrics.is_empty() {
-            return Ok(());
-        }
+        let kafka = match &self.kafka {
+            Some(kafka) if !metrics.is_empty() => kafka,
+            _ => return Ok(()),
+        };
 
         let payloads: Vec<String> = metrics
             .into_iter()
@@ -244,54 +582,38 @@ impl WebhookCleaner {
             .collect::<Result<Vec<String>, SerdeError>>()
             .map_err(|e| WebhookCleanerError::SerializeRowsError { error: e })?;
 
-        let mut delivery_futures = Vec::new();
-
-        for payload in payloads {
-            match self.kafka_producer.send_result(FutureRecord {
-                topic: self.app_metrics_topic.as_str(),
-                payload: Some(&payload),
-                partition: None,
-                key: None::<&str>,
-                timestamp: None,
-                headers: None,
-            }) {
-                Ok(future) => delivery_futures.push(future),
-                Err((error, _)) => return Err(WebhookCleanerError::KafkaProduceError { error }),
-            }
-        }
-
-        for result in join_all(delivery_futures).await {
-            match result {
-                Ok(Ok(_)) => {}
-                Ok(Err((error, _))) => {
-                    return Err(WebhookCleanerError::KafkaProduceError { error })
-                }
-                Err(_) => {
-                    // Cancelled due to timeout while retrying
-                    return Err(WebhookCleanerError::KafkaProduceCanceled);
-                }
-            }
-        }
+        produce_payloads(&kafka.producer, &kafka.app_metrics_topic, payloads).await
+    }
 
+    #[cfg(not(feature = "kafka"))]
+    async fn send_metrics_to_kafka(&self, _metrics: Vec<AppMetric>) -> Result<()> {
         Ok(())
     }
 
-    async fn delete_observed_rows(&self, tx: &mut SerializableTxn<'_>) -> Result<u64> {
+    async fn delete_observed_rows(&self, tx: &mut SerializableTxn<'_>) -> Result<DeletedRows> {
         // This DELETE is only safe because we are in serializable isolation mode, see the note
         // in `start_serializable_txn`.
-        let base_query = r#"
-            DELETE FROM job_queue
-            WHERE status IN ('failed', 'completed')
-              AND queue = $1;
-        "#;
+        let base_query = delete_observed_rows_query(&self.table);
 
-        let result = sqlx::query(base_query)
+        let counts: Vec<(JobStatus, i64)> = sqlx::query_as(&base_query)
             .bind(&self.queue_name)
-            .execute(&mut *tx.0)
+            .fetch_all(&mut *tx.0)
             .await
             .map_err(|e| WebhookCleanerError::DeleteRowsError { error: e })?;
 
-        Ok(result.rows_affected())
+        let mut deleted = DeletedRows::default();
+        for (status, count) in counts {
+            match status {
+                JobStatus::Completed => deleted.completed = count as u64,
+                JobStatus::Failed => deleted.failed = count as u64,
+                JobStatus::Discarded => deleted.discarded = count as u64,
+                JobStatus::Cancelled => deleted.cancelled = count as u64,
+                // Only terminal statuses are deleted.
+                JobStatus::Available | JobStatus::Running => {}
+            }
+        }
+
+        Ok(deleted)
     }
 
     async fn commit_txn(&self, tx: SerializableTxn<'_>) -> Result<()> {
@@ -313,50 +635,160 @@ impl WebhookCleaner {
         // of rows in memory. It seems unlikely we'll need to paginate, but that can be added in the
         // future if necessary.
 
-        let mut tx = self.start_serializable_txn().await?;
+        let mut tx = self.with_timeout(self.start_serializable_txn()).await?;
 
-        let completed_agg_row_count = {
-            let completed_rows = self.get_completed_rows(&mut tx).await?;
+        let (completed_agg_row_count, completed_row_count) = {
+            let completed_rows = self.with_timeout(self.get_completed_rows(&mut tx)).await?;
             let row_count = completed_rows.len();
+            let job_count = completed_rows.iter().map(|row| row.successes as u64).sum();
             let completed_app_metrics: Vec<AppMetric> =
                 completed_rows.into_iter().map(Into::into).collect();
             self.send_metrics_to_kafka(completed_app_metrics).await?;
-            row_count
+            (row_count, job_count)
         };
 
-        let failed_agg_row_count = {
-            let failed_rows = self.get_failed_rows(&mut tx).await?;
+        let (failed_agg_row_count, failed_row_count) = {
+            let failed_rows = self.with_timeout(self.get_failed_rows(&mut tx)).await?;
             let row_count = failed_rows.len();
+            let job_count = failed_rows.iter().map(|row| row.failures as u64).sum();
             let failed_app_metrics: Vec<AppMetric> =
                 failed_rows.into_iter().map(Into::into).collect();
             self.send_metrics_to_kafka(failed_app_metrics).await?;
-            row_count
+            (row_count, job_count)
         };
 
-        let mut rows_processed = 0;
-        if completed_agg_row_count + failed_agg_row_count != 0 {
-            rows_processed = self.delete_observed_rows(&mut tx).await?;
-            self.commit_txn(tx).await?;
+        if failed_agg_row_count != 0 {
+            self.send_plugin_log_entries_to_kafka(&mut tx).await?;
+        }
+
+        let (discarded_agg_row_count, discarded_row_count) = {
+            let discarded_rows = self.with_timeout(self.get_discarded_rows(&mut tx)).await?;
+            let row_count = discarded_rows.len();
+            let job_count = discarded_rows.iter().map(|row| row.discards as u64).sum();
+            let discarded_app_metrics: Vec<AppMetric> =
+                discarded_rows.into_iter().map(Into::into).collect();
+            self.send_metrics_to_kafka(discarded_app_metrics).await?;
+            (row_count, job_count)
+        };
+
+        let mut rows_deleted = DeletedRows::default();
+        if completed_agg_row_count + failed_agg_row_count + discarded_agg_row_count != 0 {
+            rows_deleted = self
+                .with_timeout(self.delete_observed_rows(&mut tx))
+                .await?;
+            self.with_timeout(self.commit_txn(tx)).await?;
         }
 
         Ok(CleanupStats {
-            rows_processed,
+            rows_processed: rows_deleted.total(),
+            rows_deleted,
             completed_agg_row_count,
             failed_agg_row_count,
+            completed_row_count,
+            failed_row_count,
+            discarded_agg_row_count,
+            discarded_row_count,
         })
     }
+
+    #[cfg(feature = "kafka")]
+    async fn send_janitor_run_to_kafka(&self, janitor_run: &JanitorRun) -> Result<()> {
+        let (producer, topic) = match &self.kafka {
+            Some(KafkaOutput {
+                producer,
+                janitor_runs_topic: Some(topic),
+                ..
+            }) => (producer, topic.as_str()),
+            _ => return Ok(()),
+        };
+        let payload = serde_json::to_string(janitor_run)
+            .map_err(|e| WebhookCleanerError::SerializeRowsError { error: e })?;
+
+        produce_payloads(producer, topic, vec![payload]).await
+    }
+
+    #[cfg(not(feature = "kafka"))]
+    async fn send_janitor_run_to_kafka(&self, _janitor_run: &JanitorRun) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Produce every payload in `payloads` to `topic`, waiting until all of them are delivered.
+#[cfg(feature = "kafka")]
+async fn produce_payloads(
+    producer: &FutureProducer<KafkaContext>,
+    topic: &str,
+    payloads: Vec<String>,
+) -> Result<()> {
+    let mut delivery_futures = Vec::new();
+
+    for payload in payloads {
+        match producer.send_result(FutureRecord {
+            topic,
+            payload: Some(&payload),
+            partition: None,
+            key: None::<&str>,
+            timestamp: None,
+            headers: None,
+        }) {
+            Ok(future) => delivery_futures.push(future),
+            Err((error, _)) => return Err(WebhookCleanerError::KafkaProduceError { error }),
+        }
+    }
+
+    for result in join_all(delivery_futures).await {
+        match result {
+            Ok(Ok(_)) => {}
+            Ok(Err((error, _))) => return Err(WebhookCleanerError::KafkaProduceError { error }),
+            Err(_) => {
+                // Cancelled due to timeout while retrying
+                return Err(WebhookCleanerError::KafkaProduceCanceled);
+            }
+        }
+    }
+
+    Ok(())
 }
 
 #[async_trait]
 impl Cleaner for WebhookCleaner {
     async fn cleanup(&self) {
-        match self.cleanup_impl().await {
+        let start = Instant::now();
+        let result = self.cleanup_impl().await;
+
+        let mut janitor_run = JanitorRun {
+            timestamp: Utc::now(),
+            queue: self.queue_name.to_owned(),
+            rows_scanned: 0,
+            completed_rows_deleted: 0,
+            failed_rows_deleted: 0,
+            discarded_rows_deleted: 0,
+            duration_ms: start.elapsed().as_millis() as u64,
+            error: None,
+        };
+        match &result {
+            Ok(stats) => {
+                janitor_run.rows_scanned =
+                    stats.completed_row_count + stats.failed_row_count + stats.discarded_row_count;
+                janitor_run.completed_rows_deleted = stats.rows_deleted.completed;
+                janitor_run.failed_rows_deleted = stats.rows_deleted.failed;
+                janitor_run.discarded_rows_deleted = stats.rows_deleted.discarded;
+            }
+            Err(error) => janitor_run.error = Some(error.to_string()),
+        }
+
+        if let Err(error) = self.send_janitor_run_to_kafka(&janitor_run).await {
+            error!(error = ?error, "WebhookCleaner failed to produce janitor run summary");
+        }
+
+        match result {
             Ok(stats) => {
                 if stats.rows_processed > 0 {
                     debug!(
                         rows_processed = stats.rows_processed,
                         completed_agg_row_count = stats.completed_agg_row_count,
                         failed_agg_row_count = stats.failed_agg_row_count,
+                        discarded_agg_row_count = stats.discarded_agg_row_count,
                         "WebhookCleaner::cleanup finished"
                     );
                 } else {
@@ -373,23 +805,37 @@ impl Cleaner for WebhookCleaner {
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(feature = "kafka")]
     use crate::config;
+    #[cfg(feature = "kafka")]
     use crate::kafka_producer::{create_kafka_producer, KafkaContext};
+    #[cfg(feature = "kafka")]
+    use hook_common::duration::EnvMsDuration;
+    #[cfg(feature = "kafka")]
     use hook_common::kafka_messages::app_metrics::{
         Error as WebhookError, ErrorDetails, ErrorType,
     };
+    #[cfg(feature = "kafka")]
+    use hook_common::kafka_messages::plugin_logs::PluginLogEntryType;
     use hook_common::pgqueue::{NewJob, PgJob, PgQueue, PgQueueJob};
     use hook_common::webhook::{HttpMethod, WebhookJobMetadata, WebhookJobParameters};
+    #[cfg(feature = "kafka")]
     use rdkafka::consumer::{Consumer, StreamConsumer};
+    #[cfg(feature = "kafka")]
     use rdkafka::mocking::MockCluster;
+    #[cfg(feature = "kafka")]
     use rdkafka::producer::{DefaultProducerContext, FutureProducer};
+    #[cfg(feature = "kafka")]
     use rdkafka::{ClientConfig, Message};
     use sqlx::{PgPool, Row};
     use std::collections::HashMap;
+    #[cfg(feature = "kafka")]
     use std::str::FromStr;
 
+    #[cfg(feature = "kafka")]
     const APP_METRICS_TOPIC: &str = "app_metrics";
 
+    #[cfg(feature = "kafka")]
     async fn create_mock_kafka() -> (
         MockCluster<'static, DefaultProducerContext>,
         FutureProducer<KafkaContext>,
@@ -397,13 +843,16 @@ mod tests {
         let cluster = MockCluster::new(1).expect("failed to create mock brokers");
 
         let config = config::KafkaConfig {
-            kafka_producer_linger_ms: 0,
+            kafka_producer_linger_ms: EnvMsDuration(Duration::ZERO),
             kafka_producer_queue_mib: 50,
-            kafka_message_timeout_ms: 5000,
+            kafka_message_timeout_ms: EnvMsDuration(Duration::from_secs(5)),
             kafka_compression_codec: "none".to_string(),
             kafka_hosts: cluster.bootstrap_servers(),
             app_metrics_topic: APP_METRICS_TOPIC.to_string(),
+            janitor_runs_topic: "".to_string(),
             plugin_log_entries_topic: "plugin_log_entries".to_string(),
+            plugin_log_entries_level: PluginLogEntryType::Error,
+            plugin_log_entries_max_message_length: 1000,
             kafka_tls: false,
         };
 
@@ -415,6 +864,7 @@ mod tests {
         )
     }
 
+    #[cfg(feature = "kafka")]
     fn check_app_metric_vector_equality(v1: &[AppMetric], v2: &[AppMetric]) {
         // Ignores `error_uuid`s.
         assert_eq!(v1.len(), v2.len());
@@ -427,6 +877,7 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "kafka")]
     #[sqlx::test(migrations = "../migrations", fixtures("webhook_cleanup"))]
     async fn test_cleanup_impl(db: PgPool) {
         let (mock_cluster, mock_producer) = create_mock_kafka().await;
@@ -442,13 +893,9 @@ mod tests {
             .expect("failed to create mock consumer");
         consumer.subscribe(&[APP_METRICS_TOPIC]).unwrap();
 
-        let webhook_cleaner = WebhookCleaner::new_from_pool(
-            &"webhooks",
-            db,
-            mock_producer,
-            APP_METRICS_TOPIC.to_owned(),
-        )
-        .expect("unable to create webhook cleaner");
+        let webhook_cleaner = WebhookCleaner::new_from_pool("webhooks", db)
+            .expect("unable to create webhook cleaner")
+            .kafka(mock_producer, APP_METRICS_TOPIC.to_owned());
 
         let cleanup_stats = webhook_cleaner
             .cleanup_impl()
@@ -620,16 +1067,406 @@ mod tests {
         check_app_metric_vector_equality(&expected_app_metrics, &received_app_metrics);
     }
 
+    #[cfg(feature = "kafka")]
     #[sqlx::test(migrations = "../migrations", fixtures("webhook_cleanup"))]
-    async fn test_serializable_isolation(db: PgPool) {
-        let (_, mock_producer) = create_mock_kafka().await;
-        let webhook_cleaner = WebhookCleaner::new_from_pool(
-            &"webhooks",
-            db.clone(),
-            mock_producer,
-            APP_METRICS_TOPIC.to_owned(),
+    async fn test_cleanup_produces_janitor_run(db: PgPool) {
+        const JANITOR_RUNS_TOPIC: &str = "janitor_runs";
+
+        let (mock_cluster, mock_producer) = create_mock_kafka().await;
+        mock_cluster
+            .create_topic(APP_METRICS_TOPIC, 1, 1)
+            .expect("failed to create mock app_metrics topic");
+        mock_cluster
+            .create_topic(JANITOR_RUNS_TOPIC, 1, 1)
+            .expect("failed to create mock janitor_runs topic");
+
+        let consumer: StreamConsumer = ClientConfig::new()
+            .set("bootstrap.servers", mock_cluster.bootstrap_servers())
+            .set("group.id", "mock")
+            .set("auto.offset.reset", "earliest")
+            .create()
+            .expect("failed to create mock consumer");
+        consumer.subscribe(&[JANITOR_RUNS_TOPIC]).unwrap();
+
+        let webhook_cleaner = WebhookCleaner::new_from_pool("webhooks", db)
+            .expect("unable to create webhook cleaner")
+            .kafka(mock_producer, APP_METRICS_TOPIC.to_owned())
+            .janitor_runs_topic(JANITOR_RUNS_TOPIC.to_owned())
+            .expect("unable to produce janitor runs");
+
+        webhook_cleaner.cleanup().await;
+        // A second run doesn't find anything left to clean up, but it's still summarized.
+        webhook_cleaner.cleanup().await;
+
+        let mut janitor_runs = Vec::new();
+        for _ in 0..2 {
+            let kafka_msg = consumer.recv().await.unwrap();
+            let payload_str = String::from_utf8(kafka_msg.payload().unwrap().to_vec()).unwrap();
+            let janitor_run: JanitorRun = serde_json::from_str(&payload_str).unwrap();
+            janitor_runs.push(janitor_run);
+        }
+
+        assert_eq!(janitor_runs[0].queue, "webhooks");
+        assert_eq!(janitor_runs[0].rows_scanned, 11);
+        assert_eq!(janitor_runs[0].completed_rows_deleted, 5);
+        assert_eq!(janitor_runs[0].failed_rows_deleted, 6);
+        assert_eq!(janitor_runs[0].error, None);
+
+        assert_eq!(janitor_runs[1].rows_scanned, 0);
+        assert_eq!(janitor_runs[1].completed_rows_deleted, 0);
+        assert_eq!(janitor_runs[1].failed_rows_deleted, 0);
+        assert_eq!(janitor_runs[1].error, None);
+    }
+
+    #[cfg(feature = "kafka")]
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_cleanup_produces_plugin_log_entries(db: PgPool) {
+        const PLUGIN_LOG_ENTRIES_TOPIC: &str = "plugin_log_entries";
+
+        let (mock_cluster, mock_producer) = create_mock_kafka().await;
+        mock_cluster
+            .create_topic(APP_METRICS_TOPIC, 1, 1)
+            .expect("failed to create mock app_metrics topic");
+        mock_cluster
+            .create_topic(PLUGIN_LOG_ENTRIES_TOPIC, 1, 1)
+            .expect("failed to create mock plugin_log_entries topic");
+
+        let consumer: StreamConsumer = ClientConfig::new()
+            .set("bootstrap.servers", mock_cluster.bootstrap_servers())
+            .set("group.id", "mock")
+            .set("auto.offset.reset", "earliest")
+            .create()
+            .expect("failed to create mock consumer");
+        consumer.subscribe(&[PLUGIN_LOG_ENTRIES_TOPIC]).unwrap();
+
+        // Only the last error of a failed job is logged.
+        sqlx::query(
+            r#"
+            INSERT INTO job_queue (errors, metadata, last_attempt_finished_at, parameters, queue, status, target)
+            VALUES (
+                ARRAY [
+                    '{"type":"TimeoutError","details":{"error":{"name":"Timeout","message":"first attempt"}}}'::jsonb,
+                    '{"type":{"BadHttpStatus":500},"details":{"error":{"name":"Bad Http Status","message":"a long error message"}}}'::jsonb
+                ],
+                '{"team_id": 1, "plugin_id": 99, "plugin_config_id": 2}',
+                '2023-12-19 20:01:18.799371+00',
+                '{}',
+                'webhooks',
+                'failed',
+                'https://myhost/endpoint'
+            )
+            "#,
         )
-        .expect("unable to create webhook cleaner");
+        .execute(&db)
+        .await
+        .expect("failed to insert failed job");
+
+        let webhook_cleaner = WebhookCleaner::new_from_pool("webhooks", db)
+            .expect("unable to create webhook cleaner")
+            .kafka(mock_producer, APP_METRICS_TOPIC.to_owned())
+            .plugin_log_entries(
+                PLUGIN_LOG_ENTRIES_TOPIC.to_owned(),
+                PluginLogEntryType::Warn,
+                "Bad Http Status: a long".len(),
+            )
+            .expect("unable to produce plugin log entries");
+
+        webhook_cleaner
+            .cleanup_impl()
+            .await
+            .expect("webbook cleanup_impl failed");
+
+        let kafka_msg = consumer.recv().await.unwrap();
+        let payload_str = String::from_utf8(kafka_msg.payload().unwrap().to_vec()).unwrap();
+        let log_entry: serde_json::Value = serde_json::from_str(&payload_str).unwrap();
+
+        assert_eq!(log_entry["source"], "SYSTEM");
+        assert_eq!(log_entry["type"], "WARN");
+        assert_eq!(log_entry["team_id"], 1);
+        assert_eq!(log_entry["plugin_id"], 99);
+        assert_eq!(log_entry["plugin_config_id"], 2);
+        assert_eq!(log_entry["timestamp"], "2023-12-19 20:01:18");
+        assert_eq!(log_entry["message"], "Bad Http Status: a long");
+    }
+
+    #[cfg(feature = "kafka")]
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_cleanup_impl_reports_discarded_jobs_as_failures(db: PgPool) {
+        let (mock_cluster, mock_producer) = create_mock_kafka().await;
+        mock_cluster
+            .create_topic(APP_METRICS_TOPIC, 1, 1)
+            .expect("failed to create mock app_metrics topic");
+
+        let consumer: StreamConsumer = ClientConfig::new()
+            .set("bootstrap.servers", mock_cluster.bootstrap_servers())
+            .set("group.id", "mock")
+            .set("auto.offset.reset", "earliest")
+            .create()
+            .expect("failed to create mock consumer");
+        consumer.subscribe(&[APP_METRICS_TOPIC]).unwrap();
+
+        for _ in 0..2 {
+            sqlx::query(
+                r#"
+            INSERT INTO job_queue (metadata, last_attempt_finished_at, parameters, queue, status, target, response_status)
+            VALUES ('{"team_id": 1, "plugin_id": 99, "plugin_config_id": 2}', '2023-12-19 20:01:18.799371+00', '{}', 'webhooks', 'discarded', 'https://myhost/endpoint', 409)
+                "#,
+            )
+            .execute(&db)
+            .await
+            .expect("failed to insert discarded job");
+        }
+
+        let webhook_cleaner = WebhookCleaner::new_from_pool("webhooks", db.clone())
+            .expect("unable to create webhook cleaner")
+            .kafka(mock_producer, APP_METRICS_TOPIC.to_owned());
+
+        let cleanup_stats = webhook_cleaner
+            .cleanup_impl()
+            .await
+            .expect("webbook cleanup_impl failed");
+        assert_eq!(cleanup_stats.rows_processed, 2);
+        assert_eq!(cleanup_stats.completed_row_count, 0);
+        assert_eq!(cleanup_stats.discarded_agg_row_count, 1);
+        assert_eq!(cleanup_stats.discarded_row_count, 2);
+
+        let kafka_msg = consumer.recv().await.unwrap();
+        let payload_str = String::from_utf8(kafka_msg.payload().unwrap().to_vec()).unwrap();
+        let app_metric: serde_json::Value = serde_json::from_str(&payload_str).unwrap();
+        assert_eq!(app_metric["team_id"], 1);
+        assert_eq!(app_metric["plugin_config_id"], 2);
+        assert_eq!(app_metric["successes"], 0);
+        assert_eq!(app_metric["failures"], 2);
+        assert_eq!(app_metric["error_type"], "Bad HTTP Status: 409");
+        assert!(app_metric.get("error_details").is_none());
+    }
+
+    #[sqlx::test(migrations = "../migrations", fixtures("webhook_cleanup"))]
+    async fn test_cleanup_impl_with_custom_table(db: PgPool) {
+        sqlx::query("CREATE TABLE custom_job_queue (LIKE job_queue INCLUDING ALL)")
+            .execute(&db)
+            .await
+            .expect("failed to create custom table");
+        sqlx::query("INSERT INTO custom_job_queue SELECT * FROM job_queue")
+            .execute(&db)
+            .await
+            .expect("failed to copy fixture rows");
+        let webhook_cleaner = WebhookCleaner::new_from_pool("webhooks", db.clone())
+            .expect("unable to create webhook cleaner")
+            .table_name("custom_job_queue")
+            .expect("failed to set table name");
+
+        let cleanup_stats = webhook_cleaner
+            .cleanup_impl()
+            .await
+            .expect("webbook cleanup_impl failed");
+        assert_eq!(cleanup_stats.rows_processed, 11);
+
+        let count_rows = |table: &'static str| {
+            let db = db.clone();
+            async move {
+                sqlx::query_scalar::<_, i64>(&format!(
+                    "SELECT count(*) FROM {table} WHERE queue = 'webhooks' AND status IN ('completed', 'failed')"
+                ))
+                .fetch_one(&db)
+                .await
+                .expect("failed to count rows")
+            }
+        };
+        assert_eq!(count_rows("custom_job_queue").await, 0);
+        assert_eq!(count_rows("job_queue").await, 11);
+
+        assert!(matches!(
+            WebhookCleaner::new_from_pool("webhooks", db.clone())
+                .expect("unable to create webhook cleaner")
+                .table_name("jobs; DROP TABLE job_queue"),
+            Err(WebhookCleanerError::InvalidTableName(_))
+        ));
+    }
+
+    #[cfg(feature = "kafka")]
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_kafka_outputs_require_a_producer(db: PgPool) {
+        let new_cleaner = || {
+            WebhookCleaner::new_from_pool("webhooks", db.clone())
+                .expect("unable to create webhook cleaner")
+        };
+
+        assert!(matches!(
+            new_cleaner().plugin_log_entries(
+                "plugin_log_entries".to_owned(),
+                PluginLogEntryType::Warn,
+                100,
+            ),
+            Err(WebhookCleanerError::KafkaNotSet(_))
+        ));
+        assert!(matches!(
+            new_cleaner().janitor_runs_topic("janitor_runs".to_owned()),
+            Err(WebhookCleanerError::KafkaNotSet(_))
+        ));
+    }
+
+    #[sqlx::test(migrations = "../migrations", fixtures("webhook_cleanup"))]
+    async fn test_cleanup_impl_without_kafka(db: PgPool) {
+        let webhook_cleaner = WebhookCleaner::new_from_pool("webhooks", db.clone())
+            .expect("unable to create webhook cleaner");
+
+        let cleanup_stats = webhook_cleaner
+            .cleanup_impl()
+            .await
+            .expect("webbook cleanup_impl failed");
+
+        assert_eq!(cleanup_stats.rows_processed, 11);
+        assert_eq!(cleanup_stats.completed_agg_row_count, 4);
+        assert_eq!(cleanup_stats.completed_row_count, 5);
+        assert_eq!(cleanup_stats.failed_agg_row_count, 5);
+        assert_eq!(cleanup_stats.failed_row_count, 6);
+
+        let remaining: i64 = sqlx::query_scalar(
+            "SELECT count(*) FROM job_queue WHERE queue = 'webhooks' AND status IN ('completed', 'failed')",
+        )
+        .fetch_one(&db)
+        .await
+        .expect("failed to count rows");
+        assert_eq!(remaining, 0);
+    }
+
+    #[sqlx::test(migrations = "../migrations", fixtures("webhook_cleanup"))]
+    async fn test_cleanup_impl_times_out(db: PgPool) {
+        let webhook_cleaner = WebhookCleaner::new_from_pool("webhooks", db.clone())
+            .expect("unable to create webhook cleaner")
+            .query_timeout(Duration::from_millis(100));
+
+        // Hold a lock on the table so that the cleanup's queries hang.
+        let mut lock = db.begin().await.expect("failed to start transaction");
+        sqlx::query("LOCK TABLE job_queue IN ACCESS EXCLUSIVE MODE")
+            .execute(&mut *lock)
+            .await
+            .expect("failed to lock table");
+
+        let result = webhook_cleaner.cleanup_impl().await;
+        assert!(matches!(
+            result,
+            Err(WebhookCleanerError::TimeoutError { .. })
+        ));
+
+        lock.rollback().await.expect("failed to roll back");
+        let remaining: i64 = sqlx::query_scalar(
+            "SELECT count(*) FROM job_queue WHERE queue = 'webhooks' AND status IN ('completed', 'failed')",
+        )
+        .fetch_one(&db)
+        .await
+        .expect("failed to count rows");
+        assert_eq!(remaining, 11);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_cleanup_impl_with_expired_jobs(db: PgPool) {
+        let queue = PgQueue::new_from_pool("webhooks", db.clone())
+            .await
+            .expect("failed to connect to local test postgresql database");
+        let job_metadata = WebhookJobMetadata {
+            team_id: 1,
+            plugin_id: 2,
+            plugin_config_id: 3,
+            precondition: None,
+            delivered_urls: Vec::new(),
+            recurrence: None,
+            trace_context: Default::default(),
+        };
+        let new_job = NewJob::new(1, job_metadata, HashMap::<String, String>::new(), "target");
+        queue.enqueue(new_job).await.expect("failed to enqueue job");
+        let _job: PgJob<HashMap<String, String>, WebhookJobMetadata> = queue
+            .dequeue("worker_id")
+            .await
+            .expect("failed to dequeue job")
+            .expect("didn't find a job to dequeue");
+        let expired = queue
+            .expire_running(
+                Duration::ZERO,
+                WebhookJobError::new_timeout("job was running for too long"),
+            )
+            .await
+            .expect("failed to expire running jobs");
+        assert_eq!(expired, 1);
+
+        // Failed jobs without any error, which can't be reported with their error but must still be cleaned up,
+        // including one that never finished an attempt.
+        sqlx::query(
+            r#"
+            INSERT INTO job_queue (metadata, last_attempt_finished_at, parameters, queue, status, target)
+            VALUES ('{"team_id": 1, "plugin_id": 2, "plugin_config_id": 3}', NOW(), '{}', 'webhooks', 'failed', 'target'),
+                   ('{"team_id": 1, "plugin_id": 2, "plugin_config_id": 3}', NULL, '{}', 'webhooks', 'failed', 'target')
+            "#,
+        )
+        .execute(&db)
+        .await
+        .expect("failed to insert failed jobs");
+
+        let webhook_cleaner = WebhookCleaner::new_from_pool("webhooks", db.clone())
+            .expect("unable to create webhook cleaner");
+        let cleanup_stats = webhook_cleaner
+            .cleanup_impl()
+            .await
+            .expect("webbook cleanup_impl failed");
+
+        assert_eq!(cleanup_stats.rows_processed, 3);
+        assert_eq!(cleanup_stats.failed_agg_row_count, 2);
+        assert_eq!(cleanup_stats.failed_row_count, 3);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn test_delete_observed_rows_deletes_cancelled_jobs(db: PgPool) {
+        sqlx::query(
+            r#"
+        INSERT INTO job_queue (metadata, last_attempt_finished_at, parameters, queue, status, target)
+        VALUES ('{"team_id": 1, "plugin_id": 99, "plugin_config_id": 2}', '2023-12-19 20:01:18.799371+00', '{}', 'webhooks', 'cancelled', 'https://myhost/endpoint')
+            "#,
+        )
+        .execute(&db)
+        .await
+        .expect("failed to insert cancelled job");
+        let webhook_cleaner = WebhookCleaner::new_from_pool("webhooks", db.clone())
+            .expect("unable to create webhook cleaner");
+
+        let mut tx = webhook_cleaner.start_serializable_txn().await.unwrap();
+        let rows_deleted = webhook_cleaner.delete_observed_rows(&mut tx).await.unwrap();
+        webhook_cleaner.commit_txn(tx).await.unwrap();
+
+        assert_eq!(rows_deleted.total(), 1);
+        assert_eq!(rows_deleted.cancelled, 1);
+    }
+
+    #[sqlx::test(migrations = "../migrations", fixtures("webhook_cleanup"))]
+    async fn test_delete_observed_rows_uses_index(db: PgPool) {
+        let mut conn = db.acquire().await.expect("failed to acquire connection");
+        // The fixture table is tiny, so discourage sequential scans like a large table would.
+        sqlx::query("SET enable_seqscan = off")
+            .execute(&mut *conn)
+            .await
+            .expect("failed to disable sequential scans");
+
+        let plan: Vec<String> = sqlx::query_scalar(&format!(
+            "EXPLAIN {}",
+            delete_observed_rows_query(DEFAULT_TABLE)
+        ))
+        .bind("webhooks")
+        .fetch_all(&mut *conn)
+        .await
+        .expect("failed to explain query");
+
+        assert!(
+            plan.iter()
+                .any(|line| line.contains("idx_queue_status_last_attempt_finished_at")),
+            "query plan doesn't use the index: {:#?}",
+            plan
+        );
+    }
+
+    #[sqlx::test(migrations = "../migrations", fixtures("webhook_cleanup"))]
+    async fn test_serializable_isolation(db: PgPool) {
+        let webhook_cleaner = WebhookCleaner::new_from_pool("webhooks", db.clone())
+            .expect("unable to create webhook cleaner");
 
         let queue = PgQueue::new_from_pool("webhooks", db.clone())
             .await
@@ -640,7 +1477,7 @@ mod tests {
             let count: i64 = sqlx::query(
                 "SELECT count(*) FROM job_queue WHERE queue = 'webhooks' AND status = $1::job_status",
             )
-            .bind(&status)
+            .bind(status)
             .fetch_one(&mut *conn)
             .await
             .unwrap()
@@ -663,7 +1500,7 @@ mod tests {
         {
             // The fixtures include an available job, so let's complete it while the txn is open.
             let webhook_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = queue
-                .dequeue(&"worker_id")
+                .dequeue("worker_id")
                 .await
                 .expect("failed to dequeue job")
                 .expect("didn't find a job to dequeue");
@@ -676,20 +1513,29 @@ mod tests {
         {
             // Enqueue and complete another job while the txn is open.
             let job_parameters = WebhookJobParameters {
+                auth: None,
                 body: "foo".to_owned(),
+                fallback_urls: Vec::new(),
+                fan_out_urls: Vec::new(),
                 headers: HashMap::new(),
                 method: HttpMethod::POST,
+                multipart: Vec::new(),
+                timeout_ms: None,
                 url: "http://example.com".to_owned(),
             };
             let job_metadata = WebhookJobMetadata {
                 team_id: 1,
                 plugin_id: 2,
                 plugin_config_id: 3,
+                precondition: None,
+                delivered_urls: Vec::new(),
+                recurrence: None,
+                trace_context: Default::default(),
             };
-            let new_job = NewJob::new(1, job_metadata, job_parameters, &"target");
+            let new_job = NewJob::new(1, job_metadata, job_parameters, "target");
             queue.enqueue(new_job).await.expect("failed to enqueue job");
             let webhook_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = queue
-                .dequeue(&"worker_id")
+                .dequeue("worker_id")
                 .await
                 .expect("failed to dequeue job")
                 .expect("didn't find a job to dequeue");
@@ -702,17 +1548,26 @@ mod tests {
         {
             // Enqueue another available job while the txn is open.
             let job_parameters = WebhookJobParameters {
+                auth: None,
                 body: "foo".to_owned(),
+                fallback_urls: Vec::new(),
+                fan_out_urls: Vec::new(),
                 headers: HashMap::new(),
                 method: HttpMethod::POST,
+                multipart: Vec::new(),
+                timeout_ms: None,
                 url: "http://example.com".to_owned(),
             };
             let job_metadata = WebhookJobMetadata {
                 team_id: 1,
                 plugin_id: 2,
                 plugin_config_id: 3,
+                precondition: None,
+                delivered_urls: Vec::new(),
+                recurrence: None,
+                trace_context: Default::default(),
             };
-            let new_job = NewJob::new(1, job_metadata, job_parameters, &"target");
+            let new_job = NewJob::new(1, job_metadata, job_parameters, "target");
             queue.enqueue(new_job).await.expect("failed to enqueue job");
         }
 
@@ -720,9 +1575,11 @@ mod tests {
         assert_eq!(get_count_from_new_conn(&db, "completed").await, 7);
         assert_eq!(get_count_from_new_conn(&db, "available").await, 1);
 
-        let rows_processed = webhook_cleaner.delete_observed_rows(&mut tx).await.unwrap();
+        let rows_deleted = webhook_cleaner.delete_observed_rows(&mut tx).await.unwrap();
         // The 11 rows that were in the queue when the txn started should be deleted.
-        assert_eq!(rows_processed, 11);
+        assert_eq!(rows_deleted.total(), 11);
+        assert_eq!(rows_deleted.completed, 5);
+        assert_eq!(rows_deleted.failed, 6);
 
         // We haven't committed, so the rows are still visible from outside the txn.
         assert_eq!(get_count_from_new_conn(&db, "completed").await, 7);
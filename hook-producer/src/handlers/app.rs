@@ -1,12 +1,38 @@
-use axum::{routing, Router};
+use std::sync::Arc;
+
+use axum::{extract::FromRef, routing, Router};
 use metrics_exporter_prometheus::PrometheusHandle;
 
 use hook_common::metrics;
 use hook_common::pgqueue::PgQueue;
+use hook_common::webhook::TenantBaseUrls;
+
+use super::{jobs, webhook};
+
+/// The state shared by the handlers of the producer's routes.
+#[derive(Clone)]
+pub struct AppState {
+    pub queue: PgQueue,
+    /// Base URLs relative webhook URLs are resolved against, to validate them and to derive their target.
+    pub tenant_base_urls: Arc<TenantBaseUrls>,
+}
 
-use super::webhook;
+impl FromRef<AppState> for PgQueue {
+    fn from_ref(state: &AppState) -> Self {
+        state.queue.clone()
+    }
+}
+
+pub fn app(
+    pg_queue: PgQueue,
+    tenant_base_urls: TenantBaseUrls,
+    metrics: Option<PrometheusHandle>,
+) -> Router {
+    let state = AppState {
+        queue: pg_queue,
+        tenant_base_urls: Arc::new(tenant_base_urls),
+    };
 
-pub fn app(pg_pool: PgQueue, metrics: Option<PrometheusHandle>) -> Router {
     Router::new()
         .route("/", routing::get(index))
         .route(
@@ -16,8 +42,11 @@ pub fn app(pg_pool: PgQueue, metrics: Option<PrometheusHandle>) -> Router {
                 None => std::future::ready("no metrics recorder installed".to_owned()),
             }),
         )
-        .route("/webhook", routing::post(webhook::post).with_state(pg_pool))
+        .route("/webhook", routing::post(webhook::post))
+        .route("/jobs", routing::post(jobs::post))
+        .route("/jobs/:id", routing::get(jobs::get))
         .layer(axum::middleware::from_fn(metrics::track_metrics))
+        .with_state(state)
 }
 
 pub async fn index() -> &'static str {
@@ -42,7 +71,7 @@ mod tests {
             .await
             .expect("failed to construct pg_queue");
 
-        let app = app(pg_queue, None);
+        let app = app(pg_queue, TenantBaseUrls::default(), None);
 
         let response = app
             .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
@@ -0,0 +1,75 @@
+use axum::{http::StatusCode, Json};
+use hook_common::webhook::{
+    validate_max_attempts, TenantBaseUrls, ValidationError, WebhookJobMetadata,
+    WebhookJobParameters,
+};
+use serde::Serialize;
+use serde_derive::Deserialize;
+use tracing::{debug, error};
+
+pub const MAX_BODY_SIZE: usize = 1_000_000;
+
+/// The body of any response to a request that failed.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+pub fn default_max_attempts() -> u32 {
+    3
+}
+
+/// Check that a request would enqueue a webhook job that can be sent, so that we fail fast on it.
+/// Relative URLs are accepted if the job's team has a base URL in `tenant_base_urls` to resolve them against.
+/// After validating, `max_attempts` is known to fit in an `i32`, and the hostname of `parameters.url` to exist.
+pub fn validate(
+    parameters: &WebhookJobParameters,
+    metadata: &WebhookJobMetadata,
+    max_attempts: u32,
+    tenant_base_urls: &TenantBaseUrls,
+) -> Result<(), ValidationError> {
+    if parameters.body.len() > MAX_BODY_SIZE {
+        return Err(ValidationError::BodyTooLarge);
+    }
+
+    parameters.validate(tenant_base_urls.0.get(&metadata.team_id))?;
+    metadata.validate()?;
+    validate_max_attempts(max_attempts as i64)
+}
+
+/// The hostname of `url`, which webhook jobs are grouped by unless they set a target.
+/// Relative URLs are resolved against the base URL of the job's team in `tenant_base_urls` first.
+pub fn get_hostname(
+    url: &str,
+    team_id: u32,
+    tenant_base_urls: &TenantBaseUrls,
+) -> Result<String, ValidationError> {
+    tenant_base_urls
+        .resolve(url, team_id)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_owned))
+        .ok_or_else(|| ValidationError::InvalidUrl(url.to_owned()))
+}
+
+pub fn bad_request(err: ValidationError) -> (StatusCode, Json<ErrorResponse>) {
+    debug!("invalid payload: {}", err);
+    (
+        StatusCode::BAD_REQUEST,
+        Json(ErrorResponse {
+            error: err.to_string(),
+        }),
+    )
+}
+
+pub fn internal_error<E>(err: E) -> (StatusCode, Json<ErrorResponse>)
+where
+    E: std::error::Error,
+{
+    error!("internal error: {}", err);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrorResponse {
+            error: err.to_string(),
+        }),
+    )
+}
@@ -0,0 +1,368 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use hook_common::webhook::{
+    TenantBaseUrls, ValidationError, WebhookJobMetadata, WebhookJobParameters,
+};
+use serde::Serialize;
+use serde_derive::Deserialize;
+use tracing::debug;
+
+use hook_common::pgqueue::{Job, JobStatus, NewJob, PgQueue};
+
+use super::app::AppState;
+use super::common::{
+    bad_request, default_max_attempts, get_hostname, internal_error, validate, ErrorResponse,
+};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JobPostResponse {
+    /// The id of the new job, to look it up later.
+    id: i64,
+}
+
+/// The body of a request made to enqueue a webhook Job.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+pub struct JobPostRequestBody {
+    parameters: WebhookJobParameters,
+    metadata: WebhookJobMetadata,
+
+    #[serde(default = "default_max_attempts")]
+    max_attempts: u32,
+
+    /// The target the job is grouped by, like the hostname of its URL, which is used if not set.
+    #[serde(default)]
+    target: Option<String>,
+}
+
+impl JobPostRequestBody {
+    /// Check that this request would enqueue a webhook job that can be sent, so that we fail fast on it.
+    fn validate(&self, tenant_base_urls: &TenantBaseUrls) -> Result<(), ValidationError> {
+        if self
+            .target
+            .as_ref()
+            .is_some_and(|target| target.trim().is_empty())
+        {
+            return Err(ValidationError::EmptyTarget);
+        }
+
+        validate(
+            &self.parameters,
+            &self.metadata,
+            self.max_attempts,
+            tenant_base_urls,
+        )
+    }
+
+    /// The target of the job, defaulting to the hostname of its URL.
+    fn target(&self, tenant_base_urls: &TenantBaseUrls) -> Result<String, ValidationError> {
+        match &self.target {
+            Some(target) => Ok(target.to_owned()),
+            None => get_hostname(
+                &self.parameters.url,
+                self.metadata.team_id,
+                tenant_base_urls,
+            ),
+        }
+    }
+}
+
+pub async fn post(
+    State(state): State<AppState>,
+    Json(payload): Json<JobPostRequestBody>,
+) -> Result<Json<JobPostResponse>, (StatusCode, Json<ErrorResponse>)> {
+    debug!("received payload: {:?}", payload);
+
+    payload
+        .validate(&state.tenant_base_urls)
+        .map_err(bad_request)?;
+    let target = payload
+        .target(&state.tenant_base_urls)
+        .map_err(bad_request)?;
+
+    let job = NewJob::new(
+        payload.max_attempts as i32,
+        payload.metadata,
+        payload.parameters,
+        target.as_str(),
+    );
+
+    match state.queue.enqueue_returning_id(job).await {
+        Ok(Some(id)) => Ok(Json(JobPostResponse { id })),
+        Ok(None) => Err((
+            StatusCode::CONFLICT,
+            Json(ErrorResponse {
+                error: "job is a duplicate".to_owned(),
+            }),
+        )),
+        Err(err) => Err(internal_error(err)),
+    }
+}
+
+/// The state of a job, for producers to follow up on its delivery.
+#[derive(Serialize, Debug)]
+pub struct JobGetResponse {
+    id: i64,
+    status: JobStatus,
+    attempt: i32,
+    max_attempts: i32,
+    /// The error the last failed attempt was recorded with, if any.
+    last_error: Option<serde_json::Value>,
+    /// When the job is next scheduled to run, if it's available, or when it last was otherwise.
+    scheduled_at: chrono::DateTime<chrono::offset::Utc>,
+    priority: i16,
+}
+
+pub async fn get(
+    State(pg_queue): State<PgQueue>,
+    Path(id): Path<i64>,
+) -> Result<Json<JobGetResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let job: Job<serde_json::Value, serde_json::Value> = pg_queue
+        .get_job(id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "job not found".to_owned(),
+                }),
+            )
+        })?;
+    let last_error = pg_queue.last_error(id).await.map_err(internal_error)?;
+
+    Ok(Json(JobGetResponse {
+        id: job.id,
+        status: job.status,
+        attempt: job.attempt,
+        max_attempts: job.max_attempts,
+        last_error,
+        scheduled_at: job.scheduled_at,
+        priority: job.priority,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use axum::{
+        body::Body,
+        http::{self, Request, StatusCode},
+    };
+    use hook_common::pgqueue::{PgJob, PgQueue};
+    use hook_common::webhook::{HttpMethod, WebhookJobParameters};
+    use http_body_util::BodyExt; // for `collect`
+    use sqlx::PgPool;
+    use std::collections;
+    use tower::ServiceExt; // for `call`, `oneshot`, and `ready`
+
+    use crate::handlers::app;
+
+    fn request_body(url: &str, target: Option<&str>) -> JobPostRequestBody {
+        JobPostRequestBody {
+            parameters: WebhookJobParameters {
+                auth: None,
+                fallback_urls: Vec::new(),
+                fan_out_urls: Vec::new(),
+                headers: collections::HashMap::new(),
+                method: HttpMethod::POST,
+                multipart: Vec::new(),
+                timeout_ms: None,
+                url: url.to_owned(),
+                body: r#"{"a": "b"}"#.to_owned(),
+            },
+            metadata: WebhookJobMetadata {
+                team_id: 1,
+                plugin_id: 2,
+                plugin_config_id: 3,
+                precondition: None,
+                delivered_urls: Vec::new(),
+                recurrence: None,
+                trace_context: Default::default(),
+            },
+            max_attempts: 2,
+            target: target.map(str::to_owned),
+        }
+    }
+
+    async fn post_job(pg_queue: &PgQueue, body: &JobPostRequestBody) -> axum::response::Response {
+        app(pg_queue.clone(), TenantBaseUrls::default(), None)
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/jobs")
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(serde_json::to_string(body).unwrap()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn jobs_post_returns_id(db: PgPool) {
+        let pg_queue = PgQueue::new_from_pool("test_jobs_post", db)
+            .await
+            .expect("failed to construct pg_queue");
+
+        let response = post_job(
+            &pg_queue,
+            &request_body("http://example.com/", Some("example")),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let id = serde_json::from_slice::<JobPostResponse>(&body).unwrap().id;
+
+        let pg_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = pg_queue
+            .dequeue("test")
+            .await
+            .expect("failed to dequeue job")
+            .expect("job was not enqueued");
+        assert_eq!(pg_job.job.id, id);
+        assert_eq!(pg_job.job.target, "example");
+        assert_eq!(pg_job.job.max_attempts, 2);
+        assert_eq!(pg_job.job.parameters.url, "http://example.com/");
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn jobs_post_invalid_payload(db: PgPool) {
+        let pg_queue = PgQueue::new_from_pool("test_jobs_post_invalid", db)
+            .await
+            .expect("failed to construct pg_queue");
+
+        let mut no_attempts = request_body("http://example.com/", None);
+        no_attempts.max_attempts = 0;
+
+        for (body, error) in [
+            (request_body("invalid", None), "invalid is not a valid url"),
+            (
+                request_body("http://example.com/", Some(" ")),
+                "target must not be empty",
+            ),
+            (no_attempts, "0 is not a valid number of max attempts"),
+        ] {
+            let response = post_job(&pg_queue, &body).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let body: ErrorResponse = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body.error, error);
+        }
+
+        let pg_job: Option<PgJob<WebhookJobParameters, WebhookJobMetadata>> = pg_queue
+            .dequeue("test")
+            .await
+            .expect("failed to dequeue job");
+        assert!(pg_job.is_none());
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn jobs_post_relative_url(db: PgPool) {
+        let pg_queue = PgQueue::new_from_pool("test_jobs_post_relative_url", db)
+            .await
+            .expect("failed to construct pg_queue");
+        let tenant_base_urls: TenantBaseUrls =
+            "1=https://tenant.example.com/hooks/".parse().unwrap();
+
+        let response = app(pg_queue.clone(), tenant_base_urls, None)
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/jobs")
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        serde_json::to_string(&request_body("/events", None)).unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The URL is resolved when the job is sent, but the job is grouped by the host it resolves to.
+        let pg_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = pg_queue
+            .dequeue("test")
+            .await
+            .expect("failed to dequeue job")
+            .expect("job was not enqueued");
+        assert_eq!(pg_job.job.target, "tenant.example.com");
+        assert_eq!(pg_job.job.parameters.url, "/events");
+
+        // Teams without a base URL can only use absolute URLs.
+        let response = post_job(&pg_queue, &request_body("/events", None)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: ErrorResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body.error, "/events is not a valid url");
+    }
+
+    async fn get_job(pg_queue: &PgQueue, id: i64) -> (StatusCode, serde_json::Value) {
+        let response = app(pg_queue.clone(), TenantBaseUrls::default(), None)
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/jobs/{}", id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn jobs_get_returns_status(db: PgPool) {
+        let pg_queue = PgQueue::new_from_pool("test_jobs_get", db)
+            .await
+            .expect("failed to construct pg_queue");
+
+        let response = post_job(&pg_queue, &request_body("http://example.com/", None)).await;
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let id = serde_json::from_slice::<JobPostResponse>(&body).unwrap().id;
+
+        let (status, job) = get_job(&pg_queue, id).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(job["id"], id);
+        assert_eq!(job["status"], "available");
+        assert_eq!(job["attempt"], 0);
+        assert_eq!(job["max_attempts"], 2);
+        assert_eq!(job["last_error"], serde_json::Value::Null);
+        assert!(job["scheduled_at"].is_string());
+        assert_eq!(job["priority"], 0);
+
+        let _pg_job: PgJob<WebhookJobParameters, WebhookJobMetadata> = pg_queue
+            .dequeue("test")
+            .await
+            .expect("failed to dequeue job")
+            .expect("job was not enqueued");
+        pg_queue
+            .fail_running(id, "destination is down")
+            .await
+            .expect("failed to fail job");
+
+        let (status, job) = get_job(&pg_queue, id).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(job["status"], "failed");
+        assert_eq!(job["attempt"], 1);
+        assert_eq!(job["last_error"], "destination is down");
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn jobs_get_missing_job(db: PgPool) {
+        let pg_queue = PgQueue::new_from_pool("test_jobs_get_missing", db)
+            .await
+            .expect("failed to construct pg_queue");
+
+        let (status, body) = get_job(&pg_queue, 42).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"], "job not found");
+    }
+}
@@ -1,4 +1,6 @@
 mod app;
+mod common;
+mod jobs;
 mod webhook;
 
 pub use app::app;
@@ -1,19 +1,18 @@
 use axum::{extract::State, http::StatusCode, Json};
 use hook_common::webhook::{WebhookJobMetadata, WebhookJobParameters};
 use serde_derive::Deserialize;
-use url::Url;
 
-use hook_common::pgqueue::{NewJob, PgQueue};
+use hook_common::pgqueue::NewJob;
 use serde::Serialize;
-use tracing::{debug, error};
+use tracing::debug;
 
-const MAX_BODY_SIZE: usize = 1_000_000;
+use super::app::AppState;
+use super::common::{
+    bad_request, default_max_attempts, get_hostname, internal_error, validate, ErrorResponse,
+};
 
 #[derive(Serialize, Deserialize)]
-pub struct WebhookPostResponse {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    error: Option<String>,
-}
+pub struct WebhookPostResponse {}
 
 /// The body of a request made to create a webhook Job.
 #[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
@@ -25,35 +24,27 @@ pub struct WebhookPostRequestBody {
     max_attempts: u32,
 }
 
-fn default_max_attempts() -> u32 {
-    3
-}
-
 pub async fn post(
-    State(pg_queue): State<PgQueue>,
+    State(state): State<AppState>,
     Json(payload): Json<WebhookPostRequestBody>,
-) -> Result<Json<WebhookPostResponse>, (StatusCode, Json<WebhookPostResponse>)> {
+) -> Result<Json<WebhookPostResponse>, (StatusCode, Json<ErrorResponse>)> {
     debug!("received payload: {:?}", payload);
 
-    if payload.parameters.body.len() > MAX_BODY_SIZE {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(WebhookPostResponse {
-                error: Some("body too large".to_owned()),
-            }),
-        ));
-    }
+    validate(
+        &payload.parameters,
+        &payload.metadata,
+        payload.max_attempts,
+        &state.tenant_base_urls,
+    )
+    .map_err(bad_request)?;
 
-    let url_hostname = get_hostname(&payload.parameters.url)?;
-    // We could cast to i32, but this ensures we are not wrapping.
-    let max_attempts = i32::try_from(payload.max_attempts).map_err(|_| {
-        (
-            StatusCode::BAD_REQUEST,
-            Json(WebhookPostResponse {
-                error: Some("invalid number of max attempts".to_owned()),
-            }),
-        )
-    })?;
+    let url_hostname = get_hostname(
+        &payload.parameters.url,
+        payload.metadata.team_id,
+        &state.tenant_base_urls,
+    )
+    .map_err(bad_request)?;
+    let max_attempts = payload.max_attempts as i32;
     let job = NewJob::new(
         max_attempts,
         payload.metadata,
@@ -61,43 +52,9 @@ pub async fn post(
         url_hostname.as_str(),
     );
 
-    pg_queue.enqueue(job).await.map_err(internal_error)?;
-
-    Ok(Json(WebhookPostResponse { error: None }))
-}
-
-fn internal_error<E>(err: E) -> (StatusCode, Json<WebhookPostResponse>)
-where
-    E: std::error::Error,
-{
-    error!("internal error: {}", err);
-    (
-        StatusCode::INTERNAL_SERVER_ERROR,
-        Json(WebhookPostResponse {
-            error: Some(err.to_string()),
-        }),
-    )
-}
+    state.queue.enqueue(job).await.map_err(internal_error)?;
 
-fn get_hostname(url_str: &str) -> Result<String, (StatusCode, Json<WebhookPostResponse>)> {
-    let url = Url::parse(url_str).map_err(|_| {
-        (
-            StatusCode::BAD_REQUEST,
-            Json(WebhookPostResponse {
-                error: Some("could not parse url".to_owned()),
-            }),
-        )
-    })?;
-
-    match url.host_str() {
-        Some(hostname) => Ok(hostname.to_owned()),
-        None => Err((
-            StatusCode::BAD_REQUEST,
-            Json(WebhookPostResponse {
-                error: Some("couldn't extract hostname from url".to_owned()),
-            }),
-        )),
-    }
+    Ok(Json(WebhookPostResponse {}))
 }
 
 #[cfg(test)]
@@ -108,8 +65,8 @@ mod tests {
         body::Body,
         http::{self, Request, StatusCode},
     };
-    use hook_common::pgqueue::PgQueue;
-    use hook_common::webhook::{HttpMethod, WebhookJobParameters};
+    use hook_common::pgqueue::{PgQueue, Recurrence};
+    use hook_common::webhook::{HttpMethod, TenantBaseUrls, WebhookJobParameters};
     use http_body_util::BodyExt;
     use sqlx::PgPool; // for `collect`
     use std::collections;
@@ -123,7 +80,7 @@ mod tests {
             .await
             .expect("failed to construct pg_queue");
 
-        let app = app(pg_queue, None);
+        let app = app(pg_queue, TenantBaseUrls::default(), None);
 
         let mut headers = collections::HashMap::new();
         headers.insert("Content-Type".to_owned(), "application/json".to_owned());
@@ -136,8 +93,13 @@ mod tests {
                     .body(Body::from(
                         serde_json::to_string(&WebhookPostRequestBody {
                             parameters: WebhookJobParameters {
+                                auth: None,
+                                fallback_urls: Vec::new(),
+                                fan_out_urls: Vec::new(),
                                 headers,
                                 method: HttpMethod::POST,
+                                multipart: Vec::new(),
+                                timeout_ms: None,
                                 url: "http://example.com/".to_owned(),
                                 body: r#"{"a": "b"}"#.to_owned(),
                             },
@@ -145,6 +107,10 @@ mod tests {
                                 team_id: 1,
                                 plugin_id: 2,
                                 plugin_config_id: 3,
+                                precondition: None,
+                                delivered_urls: Vec::new(),
+                                recurrence: None,
+                                trace_context: Default::default(),
                             },
                             max_attempts: 1,
                         })
@@ -167,7 +133,7 @@ mod tests {
             .await
             .expect("failed to construct pg_queue");
 
-        let app = app(pg_queue, None);
+        let app = app(pg_queue, TenantBaseUrls::default(), None);
 
         let response = app
             .oneshot(
@@ -178,8 +144,13 @@ mod tests {
                     .body(Body::from(
                         serde_json::to_string(&WebhookPostRequestBody {
                             parameters: WebhookJobParameters {
+                                auth: None,
+                                fallback_urls: Vec::new(),
+                                fan_out_urls: Vec::new(),
                                 headers: collections::HashMap::new(),
                                 method: HttpMethod::POST,
+                                multipart: Vec::new(),
+                                timeout_ms: None,
                                 url: "invalid".to_owned(),
                                 body: r#"{"a": "b"}"#.to_owned(),
                             },
@@ -187,6 +158,122 @@ mod tests {
                                 team_id: 1,
                                 plugin_id: 2,
                                 plugin_config_id: 3,
+                                precondition: None,
+                                delivered_urls: Vec::new(),
+                                recurrence: None,
+                                trace_context: Default::default(),
+                            },
+                            max_attempts: 1,
+                        })
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn webhook_invalid_parameters(db: PgPool) {
+        let pg_queue = PgQueue::new_from_pool("test_index", db)
+            .await
+            .expect("failed to construct pg_queue");
+
+        let mut headers = collections::HashMap::new();
+        headers.insert("Not A Token".to_owned(), "value".to_owned());
+
+        for (headers, max_attempts, error) in [
+            (headers, 1, "Not A Token is not a valid header name"),
+            (
+                collections::HashMap::new(),
+                0,
+                "0 is not a valid number of max attempts",
+            ),
+        ] {
+            let app = app(pg_queue.clone(), TenantBaseUrls::default(), None);
+
+            let response = app
+                .oneshot(
+                    Request::builder()
+                        .method(http::Method::POST)
+                        .uri("/webhook")
+                        .header(http::header::CONTENT_TYPE, "application/json")
+                        .body(Body::from(
+                            serde_json::to_string(&WebhookPostRequestBody {
+                                parameters: WebhookJobParameters {
+                                    auth: None,
+                                    fallback_urls: Vec::new(),
+                                    fan_out_urls: Vec::new(),
+                                    headers,
+                                    method: HttpMethod::POST,
+                                    multipart: Vec::new(),
+                                    timeout_ms: None,
+                                    url: "http://example.com".to_owned(),
+                                    body: r#"{"a": "b"}"#.to_owned(),
+                                },
+                                metadata: WebhookJobMetadata {
+                                    team_id: 1,
+                                    plugin_id: 2,
+                                    plugin_config_id: 3,
+                                    precondition: None,
+                                    delivered_urls: Vec::new(),
+                                    recurrence: None,
+                                    trace_context: Default::default(),
+                                },
+                                max_attempts,
+                            })
+                            .unwrap(),
+                        ))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let body: ErrorResponse = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body.error, error);
+        }
+    }
+
+    #[sqlx::test(migrations = "../migrations")]
+    async fn webhook_invalid_recurrence(db: PgPool) {
+        let pg_queue = PgQueue::new_from_pool("test_index", db)
+            .await
+            .expect("failed to construct pg_queue");
+
+        let app = app(pg_queue, TenantBaseUrls::default(), None);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/webhook")
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        serde_json::to_string(&WebhookPostRequestBody {
+                            parameters: WebhookJobParameters {
+                                auth: None,
+                                fallback_urls: Vec::new(),
+                                fan_out_urls: Vec::new(),
+                                headers: collections::HashMap::new(),
+                                method: HttpMethod::POST,
+                                multipart: Vec::new(),
+                                timeout_ms: None,
+                                url: "http://example.com".to_owned(),
+                                body: r#"{"a": "b"}"#.to_owned(),
+                            },
+                            metadata: WebhookJobMetadata {
+                                team_id: 1,
+                                plugin_id: 2,
+                                plugin_config_id: 3,
+                                precondition: None,
+                                delivered_urls: Vec::new(),
+                                recurrence: Some(Recurrence::Cron("not a cron".to_owned())),
+                                trace_context: Default::default(),
                             },
                             max_attempts: 1,
                         })
@@ -198,6 +285,10 @@ mod tests {
             .unwrap();
 
         assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: ErrorResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body.error, "not a cron is not a valid cron expression");
     }
 
     #[sqlx::test(migrations = "../migrations")]
@@ -206,7 +297,7 @@ mod tests {
             .await
             .expect("failed to construct pg_queue");
 
-        let app = app(pg_queue, None);
+        let app = app(pg_queue, TenantBaseUrls::default(), None);
 
         let response = app
             .oneshot(
@@ -229,7 +320,7 @@ mod tests {
             .await
             .expect("failed to construct pg_queue");
 
-        let app = app(pg_queue, None);
+        let app = app(pg_queue, TenantBaseUrls::default(), None);
 
         let response = app
             .oneshot(
@@ -252,7 +343,7 @@ mod tests {
             .await
             .expect("failed to construct pg_queue");
 
-        let app = app(pg_queue, None);
+        let app = app(pg_queue, TenantBaseUrls::default(), None);
 
         let bytes: Vec<u8> = vec![b'a'; 1_000_000 * 2];
         let long_string = String::from_utf8_lossy(&bytes);
@@ -266,8 +357,13 @@ mod tests {
                     .body(Body::from(
                         serde_json::to_string(&WebhookPostRequestBody {
                             parameters: WebhookJobParameters {
+                                auth: None,
+                                fallback_urls: Vec::new(),
+                                fan_out_urls: Vec::new(),
                                 headers: collections::HashMap::new(),
                                 method: HttpMethod::POST,
+                                multipart: Vec::new(),
+                                timeout_ms: None,
                                 url: "http://example.com".to_owned(),
                                 body: long_string.to_string(),
                             },
@@ -275,6 +371,10 @@ mod tests {
                                 team_id: 1,
                                 plugin_id: 2,
                                 plugin_config_id: 3,
+                                precondition: None,
+                                delivered_urls: Vec::new(),
+                                recurrence: None,
+                                trace_context: Default::default(),
                             },
                             max_attempts: 1,
                         })
@@ -19,22 +19,34 @@ async fn listen(app: Router, bind: String) -> Result<()> {
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt::init();
-
     let config = Config::init_from_env().expect("failed to load configuration from env");
+    config.logging.init().expect("failed to initialize logging");
 
-    let pg_queue = PgQueue::new(
+    let connect_options = config
+        .connection
+        .connect_options(&config.database_url)
+        .expect("invalid database connection options");
+    let mut pg_queue = PgQueue::new_with_pool_options(
         // TODO: Coupling the queue name to the PgQueue object doesn't seem ideal from the producer
         // side, but we don't need more than one queue for now.
         &config.queue_name,
-        &config.database_url,
+        Some(&config.table_name),
+        connect_options,
+        config.connection.pool_options(),
     )
     .await
     .expect("failed to initialize queue");
+    if !config.query_timeout.0.is_zero() {
+        pg_queue = pg_queue.query_timeout(config.query_timeout.0);
+    }
 
     let recorder_handle = metrics::setup_metrics_recorder();
 
-    let app = handlers::app(pg_queue, Some(recorder_handle));
+    let app = handlers::app(
+        pg_queue,
+        config.tenant_base_urls.clone(),
+        Some(recorder_handle),
+    );
 
     match listen(app, config.bind()).await {
         Ok(_) => {}
@@ -0,0 +1,5 @@
+CREATE TABLE queue_control(
+    queue TEXT PRIMARY KEY,
+    paused BOOLEAN NOT NULL DEFAULT FALSE,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,13 @@
+CREATE TABLE recurring_jobs(
+    id BIGSERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    queue TEXT NOT NULL DEFAULT 'default' :: text,
+    cron_expr TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_fired_at TIMESTAMPTZ DEFAULT NULL,
+    max_attempts INT NOT NULL DEFAULT 1,
+    metadata JSONB,
+    parameters JSONB,
+    target TEXT NOT NULL,
+    UNIQUE (queue, name)
+);
@@ -0,0 +1,3 @@
+-- Jobs that are not worth delivering after some time can set an expiration.
+-- Consumers discard these jobs instead of running them once it's past.
+ALTER TABLE job_queue ADD COLUMN expires_at TIMESTAMPTZ DEFAULT NULL;
@@ -0,0 +1,4 @@
+-- Jobs that completed after their target responded keep what it responded with,
+-- as an audit trail of deliveries.
+ALTER TABLE job_queue ADD COLUMN response_status INTEGER DEFAULT NULL;
+ALTER TABLE job_queue ADD COLUMN response_id TEXT DEFAULT NULL;
@@ -0,0 +1,6 @@
+-- Jobs can set a deduplication key so that the same job isn't enqueued twice, e.g. by a double-fired plugin.
+-- Only one pending ('available' or 'running') job can have a given key at a time.
+ALTER TABLE job_queue ADD COLUMN dedup_key TEXT DEFAULT NULL;
+
+CREATE UNIQUE INDEX idx_job_queue_dedup_key ON job_queue(dedup_key)
+WHERE dedup_key IS NOT NULL AND status IN ('available', 'running');
@@ -0,0 +1,14 @@
+-- Jobs with a higher priority are dequeued before any job with a lower priority in the same queue.
+ALTER TABLE job_queue ADD COLUMN priority SMALLINT NOT NULL DEFAULT 0;
+
+-- Needed for `dequeue` queries, which lock the first due 'available' job of a queue in dequeue order.
+-- * It only covers 'available' jobs: most rows are running, or finished and waiting for the janitor, and dequeue
+--   queries never look at those, so a partial index stays small however large the table grows.
+-- * `queue` comes first as dequeue queries match it by equality, which makes the next columns sorted within it.
+-- * The rest are the ORDER BY columns of `DequeueOrder::Fifo`, so jobs are read from the index in dequeue order
+--   and the scan stops at the first job that's due and not locked, instead of sorting every available job.
+--   `scheduled_at` is checked on the rows read, as jobs in the future are rare compared to jobs ready to run.
+--   `DequeueOrder::Lifo` still uses the index to find the queue's available jobs, but has to sort them.
+CREATE INDEX idx_queue_available_priority ON job_queue(queue, priority DESC, attempt, created_at)
+WHERE
+    status = 'available';
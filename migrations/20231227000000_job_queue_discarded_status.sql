@@ -0,0 +1,3 @@
+-- Jobs whose target rejected them in a way that retrying won't change are discarded, so that they are neither
+-- counted as delivered like completed jobs nor reported as errors like failed jobs.
+ALTER TYPE job_status ADD VALUE IF NOT EXISTS 'discarded';
@@ -0,0 +1,16 @@
+-- Needed for the janitor's cleanup queries, which only ever look at jobs in a terminal status
+-- that finished before the cleanup transaction started.
+-- * A partial index keeps it small, as jobs are deleted shortly after reaching a terminal status. It covers
+--   discarded jobs too, which is why it's created here: a new enum value can't be used in the transaction that
+--   added it, so this can't be done in the migration adding the 'discarded' status.
+-- * Jobs can reach a terminal status without ever finishing an attempt, e.g. when they are imported as failed, so
+--   the cleanup queries fall back to when the job was created. The index uses the same expression for them to
+--   match it.
+CREATE INDEX idx_queue_status_last_attempt_finished_at ON job_queue(queue, status, COALESCE(last_attempt_finished_at, created_at))
+WHERE
+    status IN ('completed', 'failed', 'discarded');
+
+-- Needed for the janitor to recover jobs that have been running for too long, which only ever looks at running jobs.
+CREATE INDEX idx_queue_running_attempted_at ON job_queue(queue, attempted_at)
+WHERE
+    status = 'running';
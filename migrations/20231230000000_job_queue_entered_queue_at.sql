@@ -0,0 +1,3 @@
+-- When a job entered the queue it's in, if it was moved there from the queue it was created in, like when it's
+-- retried into a retry queue. Jobs still in the queue they were created in entered it at `created_at`.
+ALTER TABLE job_queue ADD COLUMN entered_queue_at TIMESTAMPTZ DEFAULT NULL;
@@ -0,0 +1,9 @@
+-- The table a queue's jobs are stored in, so that queues sharing a name in different tables are paused and have
+-- recurring jobs of their own. Rows from before the table was configurable are for the default table.
+ALTER TABLE queue_control ADD COLUMN job_table TEXT NOT NULL DEFAULT 'job_queue';
+ALTER TABLE queue_control DROP CONSTRAINT queue_control_pkey;
+ALTER TABLE queue_control ADD PRIMARY KEY (job_table, queue);
+
+ALTER TABLE recurring_jobs ADD COLUMN job_table TEXT NOT NULL DEFAULT 'job_queue';
+ALTER TABLE recurring_jobs DROP CONSTRAINT recurring_jobs_queue_name_key;
+ALTER TABLE recurring_jobs ADD CONSTRAINT recurring_jobs_job_table_queue_name_key UNIQUE (job_table, queue, name);
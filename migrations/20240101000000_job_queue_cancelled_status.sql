@@ -0,0 +1,3 @@
+-- Every status a `JobStatus` can be in can be stored, so that the janitor can clean up every terminal status without
+-- keeping its own list of the ones the database has.
+ALTER TYPE job_status ADD VALUE IF NOT EXISTS 'cancelled';
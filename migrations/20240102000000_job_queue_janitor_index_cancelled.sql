@@ -0,0 +1,7 @@
+-- The janitor's cleanup queries look at cancelled jobs too, now that they can be stored. This is done separately from
+-- the migration adding the 'cancelled' status, as a new enum value can't be used in the transaction that added it.
+DROP INDEX idx_queue_status_last_attempt_finished_at;
+
+CREATE INDEX idx_queue_status_last_attempt_finished_at ON job_queue(queue, status, COALESCE(last_attempt_finished_at, created_at))
+WHERE
+    status IN ('completed', 'failed', 'discarded', 'cancelled');